@@ -0,0 +1,137 @@
+use crate::{
+    TileImage, TilePixel,
+    palette::{average_color, composite_over_black},
+};
+use anyhow::{Result, bail};
+#[cfg(feature = "webp")]
+use image::codecs::webp::WebPDecoder;
+use image::{
+    AnimationDecoder, Frame, ImageFormat, ImageReader, RgbaImage, codecs::png::PngDecoder,
+};
+use std::{fs::File, io::BufReader, path::Path, str::FromStr};
+
+// How palette images with several frames (animated PNG and WebP images) are turned into tiles, for `--animated`
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum AnimatedFrames {
+    // The image's first frame, as for any other palette image
+    #[default]
+    First,
+    // The frame whose average color is nearest to the average color of every frame
+    Representative,
+    // Every frame, averaged pixel by pixel
+    Average,
+    // Every frame, each as a palette image of its own
+    Each,
+}
+
+impl FromStr for AnimatedFrames {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "first" => Ok(Self::First),
+            "representative" => Ok(Self::Representative),
+            "average" => Ok(Self::Average),
+            "each" => Ok(Self::Each),
+            _ => bail!("expected \"first\", \"representative\", \"average\", or \"each\""),
+        }
+    }
+}
+
+// Decodes a palette image into the images its tiles are resized from: a single image, unless the palette image is
+// animated and `animated_frames` is `Each`, in which case there is one image per frame.
+pub(crate) fn decode_palette_image(
+    path: &Path,
+    animated_frames: AnimatedFrames,
+) -> Result<Vec<TileImage>> {
+    let reader = ImageReader::open(path)?;
+    if animated_frames != AnimatedFrames::First
+        && let Some(mut frames) = decode_frames(path, reader.format())?
+        && frames.len() > 1
+    {
+        return Ok(match animated_frames {
+            AnimatedFrames::First | AnimatedFrames::Each => frames,
+            AnimatedFrames::Representative => {
+                vec![frames.swap_remove(representative_frame(&frames))]
+            }
+            AnimatedFrames::Average => vec![average_frames(&frames)],
+        });
+    }
+    Ok(vec![reader.decode()?.into_rgba8()])
+}
+
+// Decodes every frame of an animated image in `format`, each composed onto the image's full canvas.
+// Returns `None` if the image isn't animated.
+fn decode_frames(path: &Path, format: Option<ImageFormat>) -> Result<Option<Vec<RgbaImage>>> {
+    let reader = BufReader::new(File::open(path)?);
+    let frames = match format {
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(reader)?;
+            if !decoder.is_apng()? {
+                return Ok(None);
+            }
+            decoder.apng()?.into_frames().collect_frames()?
+        }
+        #[cfg(feature = "webp")]
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(reader)?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            decoder.into_frames().collect_frames()?
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(frames.into_iter().map(Frame::into_buffer).collect()))
+}
+
+// Finds the index of the frame whose average color (as a tile's is calculated) is nearest to the average of every
+// frame's, so a single frame stands for the whole animation.
+fn representative_frame(frames: &[RgbaImage]) -> usize {
+    let colors: Vec<_> = frames
+        .iter()
+        .map(|frame| {
+            let mut pixels = frame.as_raw().clone();
+            composite_over_black(&mut pixels);
+            average_color::<TilePixel>(&pixels, false)
+        })
+        .collect();
+    let mut mean = [0.; 3];
+    for color in &colors {
+        for (sum, c) in mean.iter_mut().zip(color) {
+            *sum += c / colors.len() as f32;
+        }
+    }
+
+    let distance = |color: &[f32; 3]| {
+        color
+            .iter()
+            .zip(mean)
+            .map(|(a, b)| (a - b).powi(2))
+            .sum::<f32>()
+    };
+    colors
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+        .unwrap()
+        .0
+}
+
+// Averages every frame pixel by pixel, including their opacity
+fn average_frames(frames: &[RgbaImage]) -> RgbaImage {
+    let (width, height) = frames[0].dimensions();
+    let mut sums = vec![0u32; frames[0].as_raw().len()];
+    for frame in frames {
+        for (sum, &component) in sums.iter_mut().zip(frame.as_raw()) {
+            *sum += u32::from(component);
+        }
+    }
+
+    let frame_count = frames.len() as u32;
+    let averaged = sums
+        .into_iter()
+        .map(|sum| ((sum + frame_count / 2) / frame_count) as u8)
+        .collect();
+    RgbaImage::from_raw(width, height, averaged).unwrap()
+}
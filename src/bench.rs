@@ -0,0 +1,217 @@
+use crate::{
+    InputImage, TileImage,
+    animation::AnimatedFrames,
+    environment::fingerprint,
+    geometry::{TileSize, parse_tile_size},
+    layout::grid_cells,
+    mosaic::{MAX_QUANTIZED_COLOR_COUNT, MatchOptions, Quantizer, Records, dither, render_image},
+    palette::{LoadOptions, LoadedImage, Palette, UpscalePolicy, process_image},
+    quality::QualityFilters,
+    rng::hash,
+};
+use anyhow::{Result, bail};
+use image::{ImageFormat, Rgb, Rgba};
+use pico_args::Arguments;
+use rayon::{ThreadPoolBuilder, prelude::*};
+use std::{
+    io::Cursor,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+// Runs `tessera bench`, which measures the throughput of each stage of the pipeline
+// on a synthetic palette and input image generated in memory.
+pub(crate) fn run(mut args: Arguments) -> Result<()> {
+    if args.contains(["-h", "--help"]) {
+        println!(
+            "tessera bench: measure the throughput of each pipeline stage on synthetic data
+-h, --help           print this message
+--palette-size       number of images in the synthetic palette; default is 1000
+--source-size        width and height of each synthetic palette image, in pixels; default is 256
+--input-size         width and height of the synthetic input image, in pixels; default is 256
+-s, --tile-size      width and height of each tile in the output image, in pixels (e.g. \"16\" or \"16x24\"); default is 16
+-d, --dither         \"true\" to enable or \"false\" to disable dithering when processing input image; default is \"true\"
+--quantizer          quantizer to dither with: \"quantette\" or \"median-cut\"; default is \"quantette\"
+-t, --threads        number of threads to use; default is the number of logical CPUs"
+        );
+        return Ok(());
+    }
+
+    let palette_size: u32 = args.opt_value_from_str("--palette-size")?.unwrap_or(1000);
+    let source_size: u32 = args.opt_value_from_str("--source-size")?.unwrap_or(256);
+    let input_size: u32 = args.opt_value_from_str("--input-size")?.unwrap_or(256);
+    let tile_size = args
+        .opt_value_from_fn(["-s", "--tile-size"], parse_tile_size)?
+        .unwrap_or(TileSize::square(16));
+    let dither_enabled: bool = args.opt_value_from_str(["-d", "--dither"])?.unwrap_or(true);
+    let quantizer: Quantizer = args.opt_value_from_str("--quantizer")?.unwrap_or_default();
+    let thread_count: Option<usize> = args.opt_value_from_str(["-t", "--threads"])?;
+
+    if palette_size == 0
+        || source_size == 0
+        || input_size == 0
+        || tile_size.width == 0
+        || tile_size.height == 0
+    {
+        bail!("palette size, source size, input size, and tile size must be greater than zero");
+    }
+    if thread_count == Some(0) {
+        bail!("`-t`/`--threads`: thread count cannot be zero");
+    }
+
+    if let Some(thread_count) = thread_count {
+        ThreadPoolBuilder::new()
+            .num_threads(thread_count)
+            .build_global()?;
+    }
+
+    for (name, value) in fingerprint() {
+        println!("{name}: {value}");
+    }
+    println!(
+        "palette: {palette_size} images of {source_size}x{source_size}, input: {input_size}x{input_size}, tile size: {}x{}",
+        tile_size.width, tile_size.height
+    );
+
+    let palette_images: Vec<_> = (0..palette_size)
+        .into_par_iter()
+        .map(|i| synthetic_palette_image(i, source_size))
+        .collect();
+    let input_image = synthetic_input_image(input_size);
+
+    let source_megapixels = f64::from(palette_size) * f64::from(source_size).powi(2) / 1e6;
+    let tile_megapixels =
+        f64::from(palette_size) * f64::from(tile_size.width * tile_size.height) / 1e6;
+    let input_megapixels = f64::from(input_size).powi(2) / 1e6;
+    let output_megapixels = input_megapixels * f64::from(tile_size.width * tile_size.height);
+
+    // Resize stage
+    let spans = vec![1];
+    let load_options = LoadOptions {
+        tile_size,
+        spans: &spans,
+        min_tile_source: None,
+        allow_upscale: false,
+        quality_filters: QualityFilters::default(),
+        upscale_policy: UpscalePolicy::default(),
+        animated_frames: AnimatedFrames::First,
+        max_source_pixels: None,
+        cache: None,
+    };
+    let start = Instant::now();
+    let tile_sets = palette_images
+        .par_iter()
+        .map(|image| match process_image(image, &load_options)? {
+            LoadedImage::Kept(mut resized_images, _, _) => Ok(resized_images.remove(0)),
+            LoadedImage::Rejected(reason) => bail!("synthetic palette image rejected: {reason}"),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    report(
+        "resize",
+        start.elapsed(),
+        f64::from(palette_size),
+        "tiles",
+        source_megapixels,
+    );
+
+    // Index stage
+    let paths = (0..palette_size)
+        .map(|i| PathBuf::from(format!("synthetic-{i}")))
+        .collect();
+    let start = Instant::now();
+    let palette = Palette::new(paths, tile_size, spans, vec![tile_sets], false, None, None)?;
+    report(
+        "index",
+        start.elapsed(),
+        f64::from(palette_size),
+        "tiles",
+        tile_megapixels,
+    );
+
+    // Dither stage
+    let input_image = if dither_enabled {
+        let start = Instant::now();
+        let dithered_image = dither(&input_image, quantizer, MAX_QUANTIZED_COLOR_COUNT)?;
+        report(
+            "dither",
+            start.elapsed(),
+            f64::from(input_size).powi(2),
+            "pixels",
+            input_megapixels,
+        );
+        dithered_image
+    } else {
+        input_image
+    };
+
+    // Match and compose stage
+    let cells = grid_cells(&input_image);
+    let start = Instant::now();
+    let (output_image, _) = render_image(
+        &palette,
+        &cells,
+        input_size,
+        input_size,
+        MatchOptions::default(),
+        Records::default(),
+    )?;
+    report(
+        "match+compose",
+        start.elapsed(),
+        cells.len() as f64,
+        "cells",
+        output_megapixels,
+    );
+
+    // Encode stage
+    let start = Instant::now();
+    let mut encoded_image = Cursor::new(Vec::new());
+    output_image.write_to(&mut encoded_image, ImageFormat::Png)?;
+    report(
+        "encode (PNG)",
+        start.elapsed(),
+        output_megapixels,
+        "megapixels",
+        output_megapixels,
+    );
+
+    Ok(())
+}
+
+// Prints the time taken by a stage along with its throughput in items and megapixels per second.
+fn report(stage: &str, elapsed: Duration, items: f64, unit: &str, megapixels: f64) {
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    println!(
+        "{stage:>14}: {:>9.3} s, {:>12.1} {unit}/s, {:>9.1} MP/s",
+        elapsed.as_secs_f64(),
+        items / seconds,
+        megapixels / seconds
+    );
+}
+
+// Generates a palette image: a gradient from a pseudorandom base color, with some texture so resizing isn't trivial.
+pub(crate) fn synthetic_palette_image(index: u32, size: u32) -> TileImage {
+    let [r, g, b, ..] = hash(u64::from(index)).to_le_bytes();
+
+    TileImage::from_fn(size, size, |x, y| {
+        let texture = ((x ^ y) & 15) as u8;
+        let shade = ((x + y) * 64 / (2 * size)) as u8;
+        Rgba([
+            r.saturating_add(shade).saturating_sub(texture),
+            g.saturating_add(texture),
+            b.saturating_sub(shade),
+            u8::MAX,
+        ])
+    })
+}
+
+// Generates an input image: smooth gradients across the whole color range.
+pub(crate) fn synthetic_input_image(size: u32) -> InputImage {
+    InputImage::from_fn(size, size, |x, y| {
+        Rgb([
+            (x * 255 / size) as u8,
+            (y * 255 / size) as u8,
+            ((x + y) * 255 / (2 * size)) as u8,
+        ])
+    })
+}
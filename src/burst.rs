@@ -0,0 +1,145 @@
+use crate::InputImage;
+use anyhow::Result;
+use image::{
+    GrayImage, ImageReader,
+    imageops::{FilterType, grayscale, resize},
+};
+use rayon::prelude::*;
+use std::{ops::Range, path::PathBuf};
+
+// Width or height (in pixels) below which the coarsest level of the image pyramid used to align frames is not
+// shrunk further
+const ALIGN_MIN_SIZE: u32 = 64;
+
+// Distance (in pixels) searched around no shift at the coarsest level of the image pyramid. Each finer level
+// doubles the shift found so far and searches 1 pixel around it.
+const ALIGN_COARSE_RADIUS: i32 = 4;
+
+// Averages a burst of photos of the same scene into a single input image with less noise, for `--burst`.
+// Every frame is shifted to line up with the reference frame (the image given with `-i`) before averaging, to make
+// up for the camera moving slightly between shots. Parts of the reference frame that a shifted frame doesn't cover
+// are averaged from the frames that do. Every frame must be the same size as the reference frame.
+pub(crate) fn merge_burst(reference: &InputImage, frame_paths: &[PathBuf]) -> Result<InputImage> {
+    let (width, height) = reference.dimensions();
+    let reference_pyramid = pyramid(&grayscale(reference));
+
+    let mut sums: Vec<[f32; 3]> = reference
+        .pixels()
+        .map(|pixel| pixel.0.map(f32::from))
+        .collect();
+    let mut counts = vec![1u32; sums.len()];
+
+    for path in frame_paths {
+        let frame = ImageReader::open(path)?.decode()?.into_rgb8();
+        let (dx, dy) = align(&reference_pyramid, &pyramid(&grayscale(&frame)));
+
+        sums.par_chunks_mut(width as usize)
+            .zip(counts.par_chunks_mut(width as usize))
+            .enumerate()
+            .for_each(|(y, (sum_row, count_row))| {
+                let Some(frame_y) = (y as u32)
+                    .checked_add_signed(dy)
+                    .filter(|&frame_y| frame_y < height)
+                else {
+                    return;
+                };
+                for (x, (sum, count)) in sum_row.iter_mut().zip(count_row).enumerate() {
+                    let Some(frame_x) = (x as u32)
+                        .checked_add_signed(dx)
+                        .filter(|&frame_x| frame_x < width)
+                    else {
+                        continue;
+                    };
+                    let pixel = frame.get_pixel(frame_x, frame_y);
+                    for (c, frame_c) in sum.iter_mut().zip(pixel.0) {
+                        *c += f32::from(frame_c);
+                    }
+                    *count += 1;
+                }
+            });
+    }
+
+    let mut merged = InputImage::new(width, height);
+    for ((pixel, sum), &count) in merged.pixels_mut().zip(&sums).zip(&counts) {
+        pixel.0 = sum.map(|c| (c / count as f32).round() as u8);
+    }
+    Ok(merged)
+}
+
+// Builds an image pyramid of a grayscale image, from the full-size image to the coarsest level
+fn pyramid(image: &GrayImage) -> Vec<GrayImage> {
+    let mut levels = vec![image.clone()];
+    while let Some(level) = levels.last()
+        && level.width() / 2 >= ALIGN_MIN_SIZE
+        && level.height() / 2 >= ALIGN_MIN_SIZE
+    {
+        let level = resize(
+            level,
+            level.width() / 2,
+            level.height() / 2,
+            FilterType::Triangle,
+        );
+        levels.push(level);
+    }
+    levels
+}
+
+// Finds the shift (in pixels of the full-size image) that best lines up a frame with the reference frame, so that
+// pixel (x, y) of the reference frame shows the same point as pixel (x + dx, y + dy) of the frame
+fn align(reference: &[GrayImage], frame: &[GrayImage]) -> (i32, i32) {
+    let mut shift = (0, 0);
+    for (level, (reference, frame)) in reference.iter().zip(frame).rev().enumerate() {
+        let radius = if level == 0 {
+            ALIGN_COARSE_RADIUS
+        } else {
+            shift = (shift.0 * 2, shift.1 * 2);
+            1
+        };
+        let center = shift;
+        shift = (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (center.0 + dx, center.1 + dy)))
+            .map(|shift| (shift, difference(reference, frame, shift)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap()
+            .0;
+    }
+    shift
+}
+
+// Mean absolute difference between the reference frame and a shifted frame, over the pixels they both cover
+fn difference(reference: &GrayImage, frame: &GrayImage, (dx, dy): (i32, i32)) -> f32 {
+    let (width, height) = reference.dimensions();
+    let x_range = covered(width, dx);
+    let y_range = covered(height, dy);
+    if x_range.is_empty() || y_range.is_empty() {
+        return f32::MAX;
+    }
+
+    let total: u64 = y_range
+        .clone()
+        .into_par_iter()
+        .map(|y| {
+            x_range
+                .clone()
+                .map(|x| {
+                    let a = reference.get_pixel(x, y).0[0];
+                    let b = frame
+                        .get_pixel(x.wrapping_add_signed(dx), y.wrapping_add_signed(dy))
+                        .0[0];
+                    u64::from(a.abs_diff(b))
+                })
+                .sum::<u64>()
+        })
+        .sum();
+    total as f32 / (x_range.len() * y_range.len()) as f32
+}
+
+// Range of the rows or columns of the reference frame that a frame shifted by `shift` pixels covers
+fn covered(length: u32, shift: i32) -> Range<u32> {
+    let distance = shift.unsigned_abs().min(length);
+    if shift < 0 {
+        distance..length
+    } else {
+        0..length - distance
+    }
+}
@@ -0,0 +1,295 @@
+use crate::{
+    geometry::TileSize,
+    logging::{StatsFormat, format_count, log, stats_format},
+    paths::encode_path,
+};
+use anyhow::{Context, Result, bail};
+use pico_args::Arguments;
+use std::{
+    fs::{File, create_dir_all, metadata, read, remove_file, rename},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use walkdir::WalkDir;
+
+// Identifies the format of cache entries; entries in any other format are treated as missing
+const ENTRY_MAGIC: &[u8; 4] = b"TSRT";
+const ENTRY_VERSION: u8 = 1;
+
+// A directory of resized tiles, so palette images don't need to be decoded and resized again on later runs.
+// Each entry is one palette image resized for one tile size, cell span, upscaling algorithm, and limit on the size
+// that the palette image was decoded at (with `--max-source-megapixels`).
+// Entries are keyed by the palette image's path, size, and modification time, so changed images are resized again.
+// An entry's modification time is updated whenever it is used, so the least recently used entries can be evicted.
+#[derive(Clone)]
+pub(crate) struct TileCache {
+    dir: PathBuf,
+}
+
+// A palette image's original dimensions and its resized tile
+pub(crate) struct CacheEntry {
+    pub(crate) dimensions: (u32, u32),
+    pub(crate) tile: Vec<u8>,
+}
+
+// What a cache entry was resized for
+pub(crate) struct CacheKey<'a> {
+    pub(crate) tile_size: TileSize,
+    pub(crate) span: u32,
+    // Name of the algorithm used when upscaling
+    pub(crate) upscale_algorithm: &'a str,
+    // Number of pixels that the palette image was downsampled to fit within while decoding it, if any
+    pub(crate) max_source_pixels: Option<u64>,
+}
+
+impl TileCache {
+    pub(crate) fn new(dir: PathBuf) -> Result<Self> {
+        create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    // Returns the original dimensions of a palette image and its resized tile, if they are cached.
+    pub(crate) fn load(&self, image_path: &Path, key: &CacheKey<'_>) -> Result<Option<CacheEntry>> {
+        let key = entry_key(image_path, key)?;
+        let entry_path = self.entry_path(&key);
+        let Ok(entry) = read(&entry_path) else {
+            return Ok(None);
+        };
+
+        let entry = parse_entry(&entry, &key);
+        if entry.is_some() {
+            // Failing to mark the entry as used only affects the order of eviction, so errors are ignored
+            File::options()
+                .write(true)
+                .open(&entry_path)
+                .and_then(|file| file.set_modified(SystemTime::now()))
+                .ok();
+        }
+
+        Ok(entry)
+    }
+
+    // Stores the original dimensions of a palette image and its resized tile.
+    pub(crate) fn store(
+        &self,
+        image_path: &Path,
+        key: &CacheKey<'_>,
+        dimensions: (u32, u32),
+        tile: &[u8],
+    ) -> Result<()> {
+        let key = entry_key(image_path, key)?;
+        let entry_path = self.entry_path(&key);
+        create_dir_all(entry_path.parent().unwrap())?;
+
+        let mut entry = Vec::with_capacity(key.len() + tile.len() + 32);
+        entry.extend_from_slice(ENTRY_MAGIC);
+        entry.push(ENTRY_VERSION);
+        entry.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        entry.extend_from_slice(key.as_bytes());
+        entry.extend_from_slice(&dimensions.0.to_le_bytes());
+        entry.extend_from_slice(&dimensions.1.to_le_bytes());
+        entry.extend_from_slice(tile);
+
+        // Write to a temporary file first so concurrent runs never see a partially written entry
+        let temporary_path = entry_path.with_extension(format!("tmp{}", std::process::id()));
+        File::create(&temporary_path)?.write_all(&entry)?;
+        rename(temporary_path, entry_path)?;
+
+        Ok(())
+    }
+
+    // Lists every entry in the cache, with its size in bytes and when it was last used.
+    fn entries(&self) -> Result<Vec<(PathBuf, u64, SystemTime)>> {
+        let mut entries = Vec::new();
+
+        for entry in WalkDir::new(&self.dir) {
+            let entry = entry?;
+            if !entry.file_type().is_file()
+                || entry.path().extension().is_none_or(|ext| ext != "tile")
+            {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            entries.push((entry.into_path(), metadata.len(), metadata.modified()?));
+        }
+
+        Ok(entries)
+    }
+
+    // Returns the number of entries in the cache and their total size, in bytes.
+    pub(crate) fn usage(&self) -> Result<(usize, u64)> {
+        let entries = self.entries()?;
+        Ok((entries.len(), entries.iter().map(|(_, size, _)| size).sum()))
+    }
+
+    // Removes the least recently used entries until the cache is no larger than `max_size` bytes.
+    // Returns the number of entries removed and their total size, in bytes.
+    pub(crate) fn evict(&self, max_size: u64) -> Result<(usize, u64)> {
+        let mut entries = self.entries()?;
+        let mut total_size: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        let (mut removed_count, mut removed_size) = (0, 0);
+
+        entries.sort_unstable_by_key(|&(_, _, last_used)| last_used);
+        for (path, size, _) in entries {
+            if total_size <= max_size {
+                break;
+            }
+            remove_file(path)?;
+            total_size -= size;
+            removed_count += 1;
+            removed_size += size;
+        }
+
+        Ok((removed_count, removed_size))
+    }
+
+    // Entries are spread across subdirectories by the first two digits of their hash,
+    // so no single directory gets too large.
+    fn entry_path(&self, key: &str) -> PathBuf {
+        let hash = format!("{:016x}", fnv1a(key.as_bytes()));
+        self.dir.join(&hash[..2]).join(format!("{hash}.tile"))
+    }
+}
+
+// Describes a cache entry, including the identity of the palette image it was resized from.
+fn entry_key(image_path: &Path, key: &CacheKey<'_>) -> Result<String> {
+    let image_path = image_path.canonicalize()?;
+    let metadata = metadata(&image_path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    // Entries decoded without a limit keep the keys they had before the limit was added
+    let limit = key
+        .max_source_pixels
+        .map_or_else(String::new, |max_source_pixels| {
+            format!("\n{max_source_pixels}")
+        });
+
+    Ok(format!(
+        "{}\n{}\n{modified}\n{}x{}\n{}\n{}{limit}",
+        encode_path(&image_path),
+        metadata.len(),
+        key.tile_size.width,
+        key.tile_size.height,
+        key.span,
+        key.upscale_algorithm
+    ))
+}
+
+// Parses a cache entry, returning `None` if it is malformed or was stored for a different key (a hash collision).
+fn parse_entry(entry: &[u8], key: &str) -> Option<CacheEntry> {
+    let rest = entry.strip_prefix(ENTRY_MAGIC)?;
+    let (&version, rest) = rest.split_first()?;
+    if version != ENTRY_VERSION {
+        return None;
+    }
+
+    let (key_len, rest) = rest.split_first_chunk::<4>()?;
+    let (entry_key, rest) = rest.split_at_checked(u32::from_le_bytes(*key_len) as usize)?;
+    if entry_key != key.as_bytes() {
+        return None;
+    }
+
+    let (width, rest) = rest.split_first_chunk::<4>()?;
+    let (height, tile) = rest.split_first_chunk::<4>()?;
+
+    Some(CacheEntry {
+        dimensions: (u32::from_le_bytes(*width), u32::from_le_bytes(*height)),
+        tile: tile.to_vec(),
+    })
+}
+
+// 64-bit FNV-1a hash, used for entry file names since it is stable across platforms and versions
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Parses a size in bytes, optionally with a binary unit suffix (e.g. "4096", "512M", "2GiB").
+pub(crate) fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let digits_end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits_end);
+    let number: u64 = number
+        .parse()
+        .context("expected a size such as \"512M\" or \"2G\"")?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        unit => bail!("unknown size unit \"{unit}\""),
+    };
+
+    number.checked_mul(multiplier).context("size is too large")
+}
+
+// Formats a size in bytes for display, e.g. "1.5 MiB", or as a plain number of bytes with `--stats-format raw`.
+pub(crate) fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = size as f64;
+    let mut unit_idx = 0;
+    while value >= 1024. && unit_idx < UNITS.len() - 1 {
+        value /= 1024.;
+        unit_idx += 1;
+    }
+
+    if unit_idx == 0 || stats_format() == StatsFormat::Raw {
+        format!("{size} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit_idx])
+    }
+}
+
+// Runs `tessera cache`, which reports the size of a tile cache and optionally shrinks or clears it.
+pub(crate) fn run(mut args: Arguments) -> Result<()> {
+    if args.contains(["-h", "--help"]) {
+        println!(
+            "tessera cache: inspect, shrink, or clear a tile cache
+-h, --help           print this message
+--cache-dir          directory of the tile cache
+--max-size           evict the least recently used entries until the cache is no larger than this size, e.g. \"2G\"
+--clear              remove every entry from the cache"
+        );
+        return Ok(());
+    }
+
+    let cache_dir_path: PathBuf = args.value_from_str("--cache-dir")?;
+    let max_size: Option<u64> = args.opt_value_from_fn("--max-size", parse_size)?;
+    let clear = args.contains("--clear");
+
+    if !cache_dir_path.is_dir() {
+        bail!("`--cache-dir`: path does not point to a directory");
+    }
+    if clear && max_size.is_some() {
+        bail!("`--clear` and `--max-size` cannot be used together");
+    }
+
+    let cache = TileCache::new(cache_dir_path)?;
+
+    if let Some(max_size) = max_size.or(clear.then_some(0)) {
+        let (removed_count, removed_size) = cache.evict(max_size)?;
+        log!(
+            "removed {} entries ({})",
+            format_count(removed_count as u64),
+            format_size(removed_size)
+        );
+    }
+
+    let (entry_count, total_size) = cache.usage()?;
+    println!(
+        "{} entries ({})",
+        format_count(entry_count as u64),
+        format_size(total_size)
+    );
+
+    Ok(())
+}
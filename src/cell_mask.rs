@@ -0,0 +1,50 @@
+use crate::geometry::TileSize;
+use image::{
+    DynamicImage, GrayImage, Luma,
+    imageops::{FilterType, resize},
+};
+
+// A shape that every tile is cut to before it is drawn, for `--cell-mask`, e.g. a star or a rounded square.
+// Where the mask is transparent, the grout color shows through instead of the tile.
+pub(crate) struct CellMask {
+    // Every span of cell that tiles are drawn for, in ascending order
+    spans: Vec<u32>,
+    // Coverage of each pixel of a tile (from 0 for grout to 255 for the tile) for cells of each span in `spans`
+    masks: Vec<GrayImage>,
+}
+
+impl CellMask {
+    // Resizes a mask image to the tiles of each span. Images with an alpha channel are masked by it; images without
+    // one are masked by their lightness, with white showing the tile and black showing the grout.
+    pub(crate) fn new(image: &DynamicImage, tile_size: TileSize, spans: &[u32]) -> Self {
+        let coverage = if image.color().has_alpha() {
+            let rgba = image.to_rgba8();
+            GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+                Luma([rgba.get_pixel(x, y).0[3]])
+            })
+        } else {
+            image.to_luma8()
+        };
+
+        let masks = spans
+            .iter()
+            .map(|span| {
+                resize(
+                    &coverage,
+                    span * tile_size.width,
+                    span * tile_size.height,
+                    FilterType::Triangle,
+                )
+            })
+            .collect();
+        Self {
+            spans: spans.to_vec(),
+            masks,
+        }
+    }
+
+    // Coverage of each pixel of a tile for a cell of the given span
+    pub(crate) fn coverage(&self, span: u32) -> &GrayImage {
+        &self.masks[self.spans.iter().position(|&s| s == span).unwrap()]
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::{Result, bail};
+use image::Rgb;
+
+// Converts a (R, G, B) triple in linear sRGB space (i.e. every component's value is from 0.0 to 1.0)
+// to its corresponding (L, a, b) triple in Oklab space.
+// From https://bottosson.github.io/posts/oklab/
+pub(crate) fn linear_srgb_to_oklab(r: f32, g: f32, b: f32) -> [f32; 3] {
+    let lp = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let mp = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let sp = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    let l = 0.2104542553 * lp + 0.7936177850 * mp - 0.0040720468 * sp;
+    let a = 1.9779984951 * lp - 2.4285922050 * mp + 0.4505937099 * sp;
+    let b = 0.0259040371 * lp + 0.7827717662 * mp - 0.8086757660 * sp;
+
+    [l, a, b]
+}
+
+// Converts an (L, a, b) triple in Oklab space to its corresponding (R, G, B) triple in linear sRGB space.
+// The result may fall outside of the sRGB gamut, so callers should clamp components as needed.
+// From https://bottosson.github.io/posts/oklab/
+pub(crate) fn oklab_to_linear_srgb(l: f32, a: f32, b: f32) -> [f32; 3] {
+    let lp = l + 0.3963377774 * a + 0.2158037573 * b;
+    let mp = l - 0.1055613458 * a - 0.0638541728 * b;
+    let sp = l - 0.0894841775 * a - 1.2914855480 * b;
+
+    let lp = lp * lp * lp;
+    let mp = mp * mp * mp;
+    let sp = sp * sp * sp;
+
+    let r = 4.0767416621 * lp - 3.3077115913 * mp + 0.2309699292 * sp;
+    let g = -1.2684380046 * lp + 2.6097574011 * mp - 0.3413193965 * sp;
+    let b = -0.0041960863 * lp - 0.7034186147 * mp + 1.7076147010 * sp;
+
+    [r, g, b]
+}
+
+// Parses a hex color such as "#1e1e1e".
+pub(crate) fn parse_hex_color(s: &str) -> Result<Rgb<u8>> {
+    let Some(hex) = s
+        .strip_prefix('#')
+        .filter(|hex| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+    else {
+        bail!("expected a hex color such as \"#1e1e1e\"");
+    };
+    let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    Ok(Rgb([component(0), component(2), component(4)]))
+}
@@ -0,0 +1,233 @@
+use anyhow::{Result, bail};
+use rayon::prelude::*;
+use std::str::FromStr;
+
+// Color space output images are written in, for `--output-colorspace`. Palette images and the input image are
+// treated as sRGB throughout, so output pixels are converted from sRGB just before being written.
+// Every supported color space has a gamut containing sRGB's, so no colors are clipped.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputColorSpace {
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+}
+
+impl FromStr for OutputColorSpace {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "srgb" => Ok(Self::Srgb),
+            "display-p3" => Ok(Self::DisplayP3),
+            "adobe-rgb" => Ok(Self::AdobeRgb),
+            _ => bail!("expected \"srgb\", \"display-p3\", or \"adobe-rgb\""),
+        }
+    }
+}
+
+// Gamma of Adobe RGB (1998)'s transfer function, exactly 563/256
+const ADOBE_RGB_GAMMA: f32 = 2.19921875;
+
+impl OutputColorSpace {
+    // Whether pixels need to be converted from sRGB at all
+    pub(crate) fn needs_conversion(self) -> bool {
+        self != Self::Srgb
+    }
+
+    // Converts RGB pixels (3 bytes each) from sRGB to this color space, in place.
+    pub(crate) fn convert(self, pixels: &mut [u8]) {
+        // Matrices from linear sRGB to each color space's linear RGB, derived from their primaries (all share D65)
+        let matrix = match self {
+            Self::Srgb => return,
+            Self::DisplayP3 => [
+                [0.8224619687, 0.1775380313, 0.],
+                [0.0331941989, 0.9668058011, 0.],
+                [0.0170826307, 0.0723974407, 0.9105199286],
+            ],
+            Self::AdobeRgb => [
+                [0.7151256069, 0.2848743931, 0.],
+                [0., 1., 0.],
+                [0., 0.0411619485, 0.9588380515],
+            ],
+        };
+        // Display P3 shares sRGB's transfer function
+        let encode = |c: f32| match self {
+            Self::AdobeRgb => c.powf(ADOBE_RGB_GAMMA.recip()),
+            Self::Srgb | Self::DisplayP3 => srgb_encode(c),
+        };
+        let decode: [f32; 256] = std::array::from_fn(|c| srgb_decode(c as f32 / 255.));
+
+        pixels.par_chunks_mut(3 * 1024).for_each(|chunk| {
+            for pixel in chunk.as_chunks_mut::<3>().0 {
+                let linear = pixel.map(|c| decode[usize::from(c)]);
+                for (c, row) in pixel.iter_mut().zip(&matrix) {
+                    let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+                    *c = (encode(value.clamp(0., 1.)) * 255.).round() as u8;
+                }
+            }
+        });
+    }
+
+    // Builds an ICC profile (version 2.1, for the widest support) describing this color space, to embed in outputs.
+    // It is a matrix/TRC display profile: the XYZ colorants of the primaries (adapted to D50 with the Bradford
+    // transform, as ICC profiles require) and a tone curve shared by every channel.
+    pub(crate) fn icc_profile(self) -> Vec<u8> {
+        let (description, colorants) = match self {
+            Self::Srgb => (
+                "sRGB",
+                [
+                    [0.4360412516, 0.2224845402, 0.0139201875],
+                    [0.3851129108, 0.7169050786, 0.0970672387],
+                    [0.1430458376, 0.0606103812, 0.7139125738],
+                ],
+            ),
+            Self::DisplayP3 => (
+                "Display P3",
+                [
+                    [0.5151187009, 0.2411891678, -0.0010504527],
+                    [0.2919778320, 0.6922440506, 0.0418791201],
+                    [0.1571034670, 0.0665667815, 0.7840713326],
+                ],
+            ),
+            Self::AdobeRgb => (
+                "Adobe RGB (1998) compatible",
+                [
+                    [0.6097407888, 0.3111125348, 0.0194653741],
+                    [0.2052725604, 0.6256751417, 0.0608745068],
+                    [0.1491866508, 0.0632123236, 0.7445601191],
+                ],
+            ),
+        };
+
+        let curve = match self {
+            // A curve with a single entry is a pure gamma, as an unsigned 8.8 fixed-point number
+            Self::AdobeRgb => curve_tag(&[(ADOBE_RGB_GAMMA * 256.).round() as u16]),
+            Self::Srgb | Self::DisplayP3 => {
+                let table: Vec<_> = (0..1024)
+                    .map(|i| (srgb_decode(i as f32 / 1023.) * 65535.).round() as u16)
+                    .collect();
+                curve_tag(&table)
+            }
+        };
+
+        let tags = [
+            (*b"desc", description_tag(description)),
+            (*b"cprt", text_tag("No copyright, use freely")),
+            (*b"wtpt", xyz_tag(D50)),
+            (*b"rXYZ", xyz_tag(colorants[0])),
+            (*b"gXYZ", xyz_tag(colorants[1])),
+            (*b"bXYZ", xyz_tag(colorants[2])),
+            (*b"rTRC", curve.clone()),
+            (*b"gTRC", curve.clone()),
+            (*b"bTRC", curve),
+        ];
+
+        // Tag data follows the header and tag table, each tag aligned to 4 bytes.
+        // Identical consecutive tags (the tone curves) share their data.
+        let mut data = Vec::new();
+        let mut table = Vec::new();
+        let data_start = HEADER_SIZE + 4 + 12 * tags.len();
+        table.extend((tags.len() as u32).to_be_bytes());
+        let mut previous: Option<(Vec<u8>, usize)> = None;
+        for (signature, tag) in tags {
+            let offset = match &previous {
+                Some((previous_tag, offset)) if *previous_tag == tag => *offset,
+                _ => {
+                    let offset = data_start + data.len();
+                    data.extend(&tag);
+                    data.resize(data.len().next_multiple_of(4), 0);
+                    offset
+                }
+            };
+            table.extend(signature);
+            table.extend((offset as u32).to_be_bytes());
+            table.extend((tag.len() as u32).to_be_bytes());
+            previous = Some((tag, offset));
+        }
+
+        let size = data_start + data.len();
+        let mut profile = Vec::with_capacity(size);
+        profile.extend((size as u32).to_be_bytes());
+        profile.extend([0; 4]); // preferred CMM
+        profile.extend(0x0210_0000_u32.to_be_bytes()); // version 2.1
+        profile.extend(b"mntr"); // display device profile
+        profile.extend(b"RGB ");
+        profile.extend(b"XYZ "); // profile connection space
+        for field in [2025_u16, 1, 1, 0, 0, 0] {
+            profile.extend(field.to_be_bytes()); // creation date, fixed so outputs are reproducible
+        }
+        profile.extend(b"acsp");
+        profile.extend([0; 24]); // platform, flags, manufacturer, model, and attributes
+        profile.extend([0; 4]); // perceptual rendering intent
+        profile.extend(xyz_number(D50)); // illuminant of the profile connection space
+        profile.extend([0; 4]); // creator
+        profile.resize(HEADER_SIZE, 0);
+        profile.extend(table);
+        profile.extend(data);
+
+        profile
+    }
+}
+
+// Size of an ICC profile's header, in bytes
+const HEADER_SIZE: usize = 128;
+
+// The D50 white point, in XYZ space, which ICC profiles' colorants are relative to
+const D50: [f32; 3] = [0.9642, 1., 0.8249];
+
+// Converts an sRGB component (from 0.0 to 1.0) to linear sRGB.
+fn srgb_decode(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Converts a linear sRGB component (from 0.0 to 1.0) to sRGB.
+fn srgb_encode(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(2.4_f32.recip()) - 0.055
+    }
+}
+
+// Encodes XYZ components as signed 15.16 fixed-point numbers.
+fn xyz_number(xyz: [f32; 3]) -> Vec<u8> {
+    xyz.iter()
+        .flat_map(|c| ((c * 65536.).round() as i32).to_be_bytes())
+        .collect()
+}
+
+fn xyz_tag(xyz: [f32; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    tag.extend(xyz_number(xyz));
+    tag
+}
+
+fn curve_tag(entries: &[u16]) -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    tag.extend((entries.len() as u32).to_be_bytes());
+    tag.extend(entries.iter().flat_map(|entry| entry.to_be_bytes()));
+    tag
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+// A version 2 profile description, with only the ASCII form filled in
+fn description_tag(description: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend((description.len() as u32 + 1).to_be_bytes());
+    tag.extend(description.as_bytes());
+    tag.push(0);
+    // Empty Unicode description (language code and length) and ScriptCode description (code, length, and 67 bytes)
+    tag.extend([0; 8]);
+    tag.extend([0; 3 + 67]);
+    tag
+}
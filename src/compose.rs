@@ -0,0 +1,336 @@
+use crate::{
+    animation::AnimatedFrames,
+    cache::TileCache,
+    color::parse_hex_color,
+    geometry::{TileSize, parse_tile_size},
+    layout::{Cell, Pinned},
+    mosaic::{MatchOptions, Records, render},
+    output::{Outputs, check_output_path},
+    palette::{LoadOptions, LoadedPalette, Palette, UpscalePolicy, load_images},
+    paths::decode_path,
+    placements::split_preamble,
+    quality::QualityFilters,
+};
+use anyhow::{Context, Result, bail};
+use foldhash::HashMap;
+use image::Rgb;
+use pico_args::Arguments;
+use std::{fs::read_to_string, path::PathBuf};
+
+// Runs `tessera compose`, which draws an output image from a placements file (as written by `--placements`),
+// placing each cell's `source` palette image as-is. Editing a placements file and composing it again
+// changes individual cells without matching the whole input image again.
+pub(crate) fn run(mut args: Arguments) -> Result<()> {
+    if args.contains(["-h", "--help"]) {
+        println!(
+            "tessera compose: draw an output image from a (possibly edited) --placements file
+usage: tessera compose [OPTIONS] PLACEMENTS
+
+-h, --help           print this message
+-s, --tile-size      width and height of each tile in the output image, in pixels, e.g. \"64\" or \"64x96\"
+--upscale-policy     how to handle palette images smaller than a tile: \"bilinear\", \"nearest\", \"lanczos\", or \"skip\";
+                     default is \"bilinear\"
+--cache-dir          directory of a cache of resized palette images, which is reused and added to
+--grout              hex color showing around tiles rotated as in the placements file's `rotation` column, e.g. \"#d8d2c4\";
+                     default is \"#000000\"
+--cell-count         draw only the first this many cells, in the order of the placements file's `order` column
+                     (see `tessera --order`) or else in the order they are listed, leaving the rest black
+-o, --output         output image path; may be given more than once"
+        );
+        return Ok(());
+    }
+
+    let tile_size = args.value_from_fn(["-s", "--tile-size"], parse_tile_size)?;
+    let upscale_policy: UpscalePolicy = args
+        .opt_value_from_str("--upscale-policy")?
+        .unwrap_or_default();
+    let cache_dir_path: Option<PathBuf> = args.opt_value_from_str("--cache-dir")?;
+    let grout = args.opt_value_from_fn("--grout", parse_hex_color)?;
+    let cell_count: Option<u32> = args.opt_value_from_str("--cell-count")?;
+    let output_image_paths: Vec<PathBuf> = args.values_from_str(["-o", "--output"])?;
+    let placements_path: PathBuf = args.free_from_str()?;
+
+    if tile_size.width == 0 || tile_size.height == 0 {
+        bail!("`-s`/`--tile-size`: tile size cannot be zero");
+    }
+    if output_image_paths.is_empty() {
+        bail!("`-o`/`--output`: at least one output path is required");
+    }
+    for path in &output_image_paths {
+        check_output_path(path)?;
+    }
+    if !placements_path.is_file() {
+        bail!("placements path does not point to a file");
+    }
+
+    let cache = cache_dir_path.map(TileCache::new).transpose()?;
+    compose(
+        &read_to_string(&placements_path)?,
+        tile_size,
+        upscale_policy,
+        cache.as_ref(),
+        grout.map_or([0; 3], |grout| grout.0),
+        cell_count,
+        output_image_paths,
+    )
+}
+
+// Draws output images from the contents of a placements file, optionally drawing only the first `cell_count` cells.
+// Tiles are rotated as recorded in the `rotation` column, with the `grout` color showing around them.
+pub(crate) fn compose(
+    placements: &str,
+    tile_size: TileSize,
+    upscale_policy: UpscalePolicy,
+    cache: Option<&TileCache>,
+    grout: [u8; 3],
+    cell_count: Option<u32>,
+    output_image_paths: Vec<PathBuf>,
+) -> Result<()> {
+    let Placed {
+        mut cells,
+        ranks,
+        rotations,
+        sources,
+        width,
+        height,
+    } = read_placements(placements)?;
+    // Rotations are keyed by the position of each cell, so they still apply after cells are dropped for `cell_count`
+    let rotations: HashMap<_, _> = cells
+        .iter()
+        .zip(rotations)
+        .filter(|&(_, rotation)| rotation != 0.)
+        .map(|(cell, rotation)| ((cell.x, cell.y), rotation))
+        .collect();
+    if let Some(cell_count) = cell_count {
+        let mut ranks = ranks.into_iter();
+        cells.retain(|_| ranks.next().unwrap() < cell_count);
+    }
+    if sources.is_empty() {
+        bail!("placements file places no palette images");
+    }
+    if let Some(source) = sources.iter().find(|source| !source.is_file()) {
+        bail!(
+            "placements file: source {} does not point to a file",
+            source.display()
+        );
+    }
+
+    // Every span of cell in the placements file; tiles are resized once for each of these
+    let mut spans: Vec<_> = cells.iter().map(|cell| cell.span).collect();
+    spans.sort_unstable();
+    spans.dedup();
+
+    let load_options = LoadOptions {
+        tile_size,
+        spans: &spans,
+        min_tile_source: None,
+        allow_upscale: false,
+        quality_filters: QualityFilters::default(),
+        upscale_policy,
+        animated_frames: AnimatedFrames::First,
+        max_source_pixels: None,
+        cache,
+    };
+    let LoadedPalette {
+        paths: palette_paths,
+        tile_sets,
+        source_dimensions: _,
+    } = load_images(sources.clone(), &load_options)?;
+
+    // Kept palette images are in the same order as the sources, so each source's tile index is found in one pass.
+    // Sources rejected by `--upscale-policy skip` are drawn as solid color.
+    let mut kept_paths = palette_paths.iter().enumerate().peekable();
+    let tile_indices: Vec<_> = sources
+        .iter()
+        .map(|source| {
+            kept_paths
+                .next_if(|(_, path)| *path == source)
+                .map(|(tile_idx, _)| tile_idx)
+        })
+        .collect();
+    for cell in &mut cells {
+        if let Some(Pinned::Tile(source_idx)) = cell.pinned {
+            cell.pinned = Some(tile_indices[source_idx].map_or(Pinned::Solid, Pinned::Tile));
+        }
+    }
+
+    let palette = Palette::new(
+        palette_paths,
+        tile_size,
+        spans,
+        tile_sets,
+        false,
+        None,
+        None,
+    )?;
+
+    let mut outputs = Outputs::new(
+        output_image_paths,
+        width * tile_size.width,
+        height * tile_size.height,
+        false,
+        None,
+    )?;
+    render(
+        &palette,
+        &cells,
+        width,
+        height,
+        MatchOptions {
+            rotations: (!rotations.is_empty()).then_some(&rotations),
+            grout,
+            ..MatchOptions::default()
+        },
+        Records::default(),
+        |band| outputs.write_band(&band),
+    )?;
+    outputs.finish()?;
+
+    Ok(())
+}
+
+// Cells read from a placements file, with the palette images placed in them
+pub(crate) struct Placed {
+    // Cells, each pinned to a palette image (by index into `sources`) or to solid color
+    pub(crate) cells: Vec<Cell>,
+    // When each cell is placed, from the `order` column if there is one, or else the order cells are listed in
+    pub(crate) ranks: Vec<u32>,
+    // Angle (in degrees) that each cell's tile is rotated by, from the `rotation` column if there is one, or else 0
+    pub(crate) rotations: Vec<f32>,
+    // Path of each palette image placed in any cell, without duplicates
+    pub(crate) sources: Vec<PathBuf>,
+    // Dimensions of the grid the cells cover
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+// Parses a placements file, as written by `--placements`. Columns are found by name, so extra columns
+// (such as alternates) and columns in a different order are fine.
+pub(crate) fn read_placements(contents: &str) -> Result<Placed> {
+    let (preamble, contents) = split_preamble(contents)?;
+    let mut lines = contents.lines().zip(preamble.lines().count() + 1..);
+    let Some((header, _)) = lines.next() else {
+        bail!("placements file is empty");
+    };
+    let header = split_csv_line(header);
+    let column_idx = |name: &str| {
+        header
+            .iter()
+            .position(|column| column == name)
+            .with_context(|| format!("placements file has no `{name}` column"))
+    };
+    let row_idx = column_idx("row")?;
+    let col_idx = column_idx("column")?;
+    let span_idx = column_idx("span")?;
+    let source_idx = column_idx("source")?;
+    let color_idx = column_idx("color")?;
+    let order_idx = column_idx("order").ok();
+    let rotation_idx = column_idx("rotation").ok();
+
+    let mut cells = Vec::new();
+    let mut ranks = Vec::new();
+    let mut rotations = Vec::new();
+    let mut sources = Vec::new();
+    let mut source_indices = HashMap::default();
+    let (mut width, mut height) = (0, 0);
+
+    for (line, line_number) in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields = split_csv_line(line);
+        let field = |idx: usize| fields.get(idx).map_or("", |field| field.trim());
+        let parse = |idx: usize, name: &str| -> Result<u32> {
+            field(idx)
+                .parse()
+                .with_context(|| format!("placements file: line {line_number}: invalid {name}"))
+        };
+
+        let y = parse(row_idx, "row")?;
+        let x = parse(col_idx, "column")?;
+        let span = parse(span_idx, "span")?;
+        if span == 0 {
+            bail!("placements file: line {line_number}: span cannot be zero");
+        }
+
+        let color = field(color_idx);
+        let hex = color.strip_prefix('#').unwrap_or(color);
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("placements file: line {line_number}: expected a hex color such as \"#1e1e1e\"");
+        }
+        let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+
+        // Cells without a source are drawn as solid color
+        let source = field(source_idx);
+        let pinned = if source.is_empty() {
+            Pinned::Solid
+        } else {
+            let source_idx = if let Some(&source_idx) = source_indices.get(source) {
+                source_idx
+            } else {
+                sources.push(decode_path(source).with_context(|| {
+                    format!("placements file: line {line_number}: invalid source path")
+                })?);
+                source_indices.insert(source.to_owned(), sources.len() - 1);
+                sources.len() - 1
+            };
+            Pinned::Tile(source_idx)
+        };
+
+        let rank = match order_idx {
+            Some(order_idx) => parse(order_idx, "order")?,
+            None => cells.len() as u32,
+        };
+        let rotation = match rotation_idx.map(field) {
+            Some(rotation) if !rotation.is_empty() => rotation.parse().with_context(|| {
+                format!("placements file: line {line_number}: invalid rotation")
+            })?,
+            _ => 0.,
+        };
+
+        width = width.max(x + span);
+        height = height.max(y + span);
+        cells.push(Cell {
+            x,
+            y,
+            span,
+            color: Rgb([component(0), component(2), component(4)]),
+            pinned: Some(pinned),
+        });
+        ranks.push(rank);
+        rotations.push(rotation);
+    }
+
+    Ok(Placed {
+        cells,
+        ranks,
+        rotations,
+        sources,
+        width,
+        height,
+    })
+}
+
+// Splits a line of a CSV file into fields, unquoting quoted fields.
+pub(crate) fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut in_quotes = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+
+    fields
+}
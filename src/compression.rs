@@ -0,0 +1,65 @@
+use crate::{decoder::RecentTiles, palette::TileSet};
+use anyhow::{Result, anyhow};
+use miniz_oxide::{deflate::compress_to_vec, inflate::decompress_to_vec};
+use rayon::prelude::*;
+use std::sync::{Arc, Mutex};
+
+// DEFLATE compression level of kept tiles, the fastest one, since tiles are decompressed whenever they are drawn
+const COMPRESSION_LEVEL: u8 = 1;
+
+// Tiles kept compressed in memory, for `--compress-tiles`, and decompressed whenever they are drawn. Tiles compress
+// well (especially upscaled ones and ones with flat backgrounds), and most cells are drawn with a small share of the
+// palette's tiles, so recently drawn tiles are kept decompressed in a small least-recently-used cache.
+pub(crate) struct CompressedTiles {
+    // One set of compressed tiles per span, each indexed the same way as the palette's paths
+    tile_sets: Vec<Vec<Box<[u8]>>>,
+    recent: Mutex<RecentTiles>,
+}
+
+impl CompressedTiles {
+    // Compresses tiles, freeing each one as it is compressed. Recently drawn tiles are kept decompressed up to a
+    // total size of `capacity` bytes.
+    pub(crate) fn new(tile_sets: Vec<TileSet>, capacity: u64) -> Self {
+        let tile_sets = tile_sets
+            .into_iter()
+            .map(|tile_set| {
+                tile_set
+                    .into_par_iter()
+                    .map(|tile| compress_to_vec(&tile, COMPRESSION_LEVEL).into_boxed_slice())
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            tile_sets,
+            recent: Mutex::new(RecentTiles::new(capacity)),
+        }
+    }
+
+    // Returns the tile of the palette image by index `tile_idx` for cells with the given span, which is the
+    // `span_idx`th span of the palette, decompressing it unless it was drawn recently.
+    pub(crate) fn tile(&self, tile_idx: usize, span_idx: usize, span: u32) -> Result<Arc<Vec<u8>>> {
+        if let Some(tile) = self.recent.lock().unwrap().get((tile_idx, span)) {
+            return Ok(tile);
+        }
+
+        let tile = decompress_to_vec(&self.tile_sets[span_idx][tile_idx])
+            .map_err(|err| anyhow!("a compressed tile could not be decompressed: {err}"))?;
+
+        let tile = Arc::new(tile);
+        self.recent
+            .lock()
+            .unwrap()
+            .insert((tile_idx, span), Arc::clone(&tile));
+        Ok(tile)
+    }
+
+    // Total size of every compressed tile, in bytes
+    pub(crate) fn memory(&self) -> u64 {
+        self.tile_sets
+            .iter()
+            .flatten()
+            .map(|tile| tile.len() as u64)
+            .sum()
+    }
+}
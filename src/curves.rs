@@ -0,0 +1,177 @@
+use crate::{
+    INPUT_PIXEL_COMPONENT_MAX, InputImage, color::oklab_to_linear_srgb, mosaic::cell_oklab,
+};
+use anyhow::{Context, Result, bail};
+use image::Rgb;
+use std::str::FromStr;
+
+// Color space that curves are applied in
+#[derive(Clone, Copy, Default)]
+pub(crate) enum CurveSpace {
+    // Red, green, and blue components, each from 0 to 255
+    #[default]
+    Rgb,
+    // Oklab lightness (from 0 to 1) and a and b components (roughly from -0.5 to 0.5)
+    Oklab,
+}
+
+impl FromStr for CurveSpace {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rgb" => Ok(Self::Rgb),
+            "oklab" => Ok(Self::Oklab),
+            _ => bail!("expected \"rgb\" or \"oklab\""),
+        }
+    }
+}
+
+// A tone curve through a list of points, sorted by input value.
+// Values between points are interpolated linearly, and values beyond the first or last point are clamped to them.
+struct Curve {
+    points: Vec<(f32, f32)>,
+}
+
+impl Curve {
+    fn apply(&self, x: f32) -> f32 {
+        let idx = self.points.partition_point(|&(point_x, _)| point_x < x);
+        if idx == 0 {
+            return self.points[0].1;
+        }
+        let Some(&(x1, y1)) = self.points.get(idx) else {
+            return self.points[idx - 1].1;
+        };
+        let (x0, y0) = self.points[idx - 1];
+        y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+    }
+}
+
+// Curves for each channel of a color space, applied to the input image before matching
+pub(crate) struct Curves {
+    space: CurveSpace,
+    // Curve for each channel, in the order of the color space's components; channels without a curve are unchanged
+    channels: [Option<Curve>; 3],
+}
+
+impl Curves {
+    // Parses a curves specification: curves for one or more channels, separated by semicolons.
+    // Each curve is a channel name, "=", and a list of "input:output" points separated by commas,
+    // e.g. "r=0:0,128:150,255:255;b=0:20,255:255" in RGB or "l=0:0.05,0.5:0.55,1:1" in Oklab.
+    // RGB channels are "r", "g", "b", or "rgb" for all three; Oklab channels are "l", "a", and "b".
+    pub(crate) fn parse(s: &str, space: CurveSpace) -> Result<Self> {
+        let mut channels = [None, None, None];
+
+        for spec in s.split(';').map(str::trim).filter(|spec| !spec.is_empty()) {
+            let Some((channel, points)) = spec.split_once('=') else {
+                bail!(
+                    "`--curves`: expected a channel name and points separated by \"=\" in \"{spec}\""
+                );
+            };
+            let channel_indices: &[usize] = match (space, channel.trim()) {
+                (CurveSpace::Rgb, "r") | (CurveSpace::Oklab, "l") => &[0],
+                (CurveSpace::Rgb, "g") | (CurveSpace::Oklab, "a") => &[1],
+                (_, "b") => &[2],
+                (CurveSpace::Rgb, "rgb") => &[0, 1, 2],
+                (CurveSpace::Rgb, channel) => {
+                    bail!(
+                        "`--curves`: unknown channel \"{channel}\"; expected \"r\", \"g\", \"b\", or \"rgb\""
+                    )
+                }
+                (CurveSpace::Oklab, channel) => {
+                    bail!(
+                        "`--curves`: unknown channel \"{channel}\"; expected \"l\", \"a\", or \"b\""
+                    )
+                }
+            };
+
+            let mut curve_points = Vec::new();
+            for point in points.split(',') {
+                let Some((x, y)) = point.split_once(':') else {
+                    bail!("`--curves`: expected a point as \"input:output\", found \"{point}\"");
+                };
+                let parse = |s: &str| -> Result<f32> {
+                    let value: f32 = s.trim().parse().with_context(|| {
+                        format!("`--curves`: invalid value in point \"{point}\"")
+                    })?;
+                    if !value.is_finite() {
+                        bail!("`--curves`: invalid value in point \"{point}\"");
+                    }
+                    Ok(value)
+                };
+                curve_points.push((parse(x)?, parse(y)?));
+            }
+
+            curve_points.sort_by(|(x1, _), (x2, _)| x1.total_cmp(x2));
+            if curve_points
+                .windows(2)
+                .any(|points| points[0].0.total_cmp(&points[1].0).is_eq())
+            {
+                bail!(
+                    "`--curves`: curve for \"{}\" has two points with the same input value",
+                    channel.trim()
+                );
+            }
+
+            for &idx in channel_indices {
+                channels[idx] = Some(Curve {
+                    points: curve_points.clone(),
+                });
+            }
+        }
+
+        if channels.iter().all(Option::is_none) {
+            bail!("`--curves`: no curves given");
+        }
+
+        Ok(Self { space, channels })
+    }
+
+    // A curve for Oklab lightness alone through points sorted by input value, with no two sharing an input value
+    pub(crate) fn lightness(points: Vec<(f32, f32)>) -> Self {
+        Self {
+            space: CurveSpace::Oklab,
+            channels: [Some(Curve { points }), None, None],
+        }
+    }
+
+    // Applies the curves to each pixel of an image.
+    pub(crate) fn apply(&self, image: &mut InputImage) {
+        let apply_channel = |idx: usize, value: f32| {
+            self.channels[idx]
+                .as_ref()
+                .map_or(value, |curve| curve.apply(value))
+        };
+
+        match self.space {
+            CurveSpace::Rgb => {
+                // Every input value is known ahead of time, so the curves are tabulated once
+                let tables: [[u8; 256]; 3] = std::array::from_fn(|idx| {
+                    std::array::from_fn(|value| {
+                        apply_channel(idx, value as f32)
+                            .round()
+                            .clamp(0., INPUT_PIXEL_COMPONENT_MAX) as u8
+                    })
+                });
+                for pixel in image.pixels_mut() {
+                    for (component, table) in pixel.0.iter_mut().zip(&tables) {
+                        *component = table[usize::from(*component)];
+                    }
+                }
+            }
+            CurveSpace::Oklab => {
+                for pixel in image.pixels_mut() {
+                    let [l, a, b] = cell_oklab(*pixel);
+                    let rgb = oklab_to_linear_srgb(
+                        apply_channel(0, l),
+                        apply_channel(1, a),
+                        apply_channel(2, b),
+                    );
+                    *pixel =
+                        Rgb(rgb
+                            .map(|c| (c.clamp(0., 1.) * INPUT_PIXEL_COMPONENT_MAX).round() as u8));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::{Error, Result, bail};
+use image::RgbImage;
+use rayon::prelude::*;
+use std::str::FromStr;
+
+// A type of color vision deficiency (i.e. color blindness)
+#[derive(Clone, Copy)]
+pub(crate) enum Deficiency {
+    Protan,
+    Deutan,
+    Tritan,
+}
+
+impl Deficiency {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Protan => "protan",
+            Self::Deutan => "deutan",
+            Self::Tritan => "tritan",
+        }
+    }
+
+    // Matrix simulating the deficiency at full severity, applied to colors in linear sRGB space.
+    // From Machado, Oliveira, and Fernandes (2009), "A Physiologically-based Model for Simulation of Color Vision Deficiency"
+    fn matrix(self) -> [[f32; 3]; 3] {
+        match self {
+            Self::Protan => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            Self::Deutan => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            Self::Tritan => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        }
+    }
+}
+
+impl FromStr for Deficiency {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "protan" => Self::Protan,
+            "deutan" => Self::Deutan,
+            "tritan" => Self::Tritan,
+            _ => bail!(
+                "unknown color vision deficiency `{s}`; expected one of protan, deutan, tritan"
+            ),
+        })
+    }
+}
+
+// Parses a comma-separated list of color vision deficiencies (e.g. "protan,tritan").
+pub(crate) fn parse_deficiencies(s: &str) -> Result<Vec<Deficiency>> {
+    s.split(',').map(|s| s.trim().parse()).collect()
+}
+
+// Produces a copy of an image simulating how it looks to viewers with the given color vision deficiency.
+pub(crate) fn simulate(image: &RgbImage, deficiency: Deficiency) -> RgbImage {
+    // Lookup table from sRGB-encoded components to linear components
+    let to_linear: [f32; 256] = std::array::from_fn(|c| {
+        let c = c as f32 / 255.;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    let matrix = deficiency.matrix();
+
+    let mut simulated_image = image.clone();
+
+    simulated_image.par_chunks_mut(3).for_each(|px| {
+        let linear = [0, 1, 2].map(|i| to_linear[usize::from(px[i])]);
+
+        for (component, row) in px.iter_mut().zip(matrix) {
+            let c = (row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2]).clamp(0., 1.);
+            let c = if c <= 0.0031308 {
+                c * 12.92
+            } else {
+                1.055 * c.powf(1. / 2.4) - 0.055
+            };
+            *component = (c * 255.).round() as u8;
+        }
+    });
+
+    simulated_image
+}
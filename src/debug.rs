@@ -0,0 +1,100 @@
+use crate::{InputImage, TILE_PIXEL_SIZE, paths::encode_path};
+use anyhow::Result;
+use image::{Rgb, RgbImage};
+use std::{
+    fmt::Write as _,
+    fs::{create_dir_all, write},
+    path::{Path, PathBuf},
+};
+
+// Intermediate outputs that explain how the output image was produced, written to the `--debug-dir` directory:
+// - `quantized.png`: the input image after dithering/quantization; each pixel is the target color of one tile
+// - `matched.png`: the average color of the tile placed for each pixel of the input image
+// - `tile-indices.csv`: the index of the palette image placed for each pixel of the input image,
+//   or an empty field if no palette image was placed (e.g. the pixel was drawn as solid color)
+// - `palette.csv`: the path of each palette image, by index
+pub(crate) struct DebugOutput {
+    dir: PathBuf,
+    matched_image: RgbImage,
+    tile_indices: Vec<Option<usize>>,
+}
+
+impl DebugOutput {
+    pub(crate) fn new(dir: PathBuf, width: u32, height: u32) -> Self {
+        Self {
+            dir,
+            matched_image: RgbImage::new(width, height),
+            tile_indices: vec![None; (width * height) as usize],
+        }
+    }
+
+    // Records the tile placed for the pixel of the input image at (`x`, `y`).
+    pub(crate) fn record(&mut self, x: u32, y: u32, tile_index: Option<usize>, tile: &[u8]) {
+        let mut sums = [0u64; 3];
+
+        for px in tile.as_chunks::<TILE_PIXEL_SIZE>().0 {
+            for (sum, &component) in sums.iter_mut().zip(px) {
+                *sum += u64::from(component);
+            }
+        }
+
+        let px_count = (tile.len() / TILE_PIXEL_SIZE).max(1) as u64;
+        let width = self.matched_image.width();
+
+        self.matched_image
+            .put_pixel(x, y, Rgb(sums.map(|sum| (sum / px_count) as u8)));
+        self.tile_indices[(y * width + x) as usize] = tile_index;
+    }
+
+    pub(crate) fn save(
+        self,
+        quantized_image: &InputImage,
+        palette_paths: &[PathBuf],
+    ) -> Result<()> {
+        create_dir_all(&self.dir)?;
+
+        quantized_image.save(self.dir.join("quantized.png"))?;
+        self.matched_image.save(self.dir.join("matched.png"))?;
+
+        let width = self.matched_image.width() as usize;
+        let mut tile_indices = String::new();
+
+        for row in self.tile_indices.chunks(width) {
+            for (tile_index, i) in row.iter().zip(0..) {
+                if i > 0 {
+                    tile_indices.push(',');
+                }
+                if let Some(tile_index) = tile_index {
+                    write!(tile_indices, "{tile_index}")?;
+                }
+            }
+            tile_indices.push('\n');
+        }
+
+        write(self.dir.join("tile-indices.csv"), tile_indices)?;
+
+        let mut palette = String::from("index,path\n");
+
+        for (path, i) in palette_paths.iter().zip(0..) {
+            writeln!(palette, "{i},{}", csv_field(path))?;
+        }
+
+        write(self.dir.join("palette.csv"), palette)?;
+
+        Ok(())
+    }
+}
+
+// Formats a path as a CSV field (losslessly, with `encode_path`), quoting it if necessary.
+pub(crate) fn csv_field(path: &Path) -> String {
+    csv_quote(&encode_path(path))
+}
+
+// Quotes a CSV field if it contains a separator, quote, or line break.
+pub(crate) fn csv_quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
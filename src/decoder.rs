@@ -0,0 +1,150 @@
+use crate::{
+    animation::AnimatedFrames,
+    cache::TileCache,
+    geometry::TileSize,
+    palette::{LoadOptions, LoadedImage, UpscalePolicy, composite_over_black, load_image},
+    quality::QualityFilters,
+};
+use anyhow::{Result, bail};
+use foldhash::{HashMap, HashMapExt};
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+// Maximum total size of the recently drawn tiles that are kept (decoded with `--descriptors-only`, or decompressed with
+// `--compress-tiles`), in bytes, unless set with `--recent-tiles-size`
+pub(crate) const DEFAULT_RECENT_TILES_SIZE: u64 = 64 << 20;
+
+// Decodes tiles from their palette images whenever they are drawn, for `--descriptors-only`, so the palette keeps
+// only each palette image's average color and tiles never all have to fit in memory at once. Recently drawn tiles are
+// kept in a small least-recently-used cache, since neighboring cells of similar colors often reuse the same tiles.
+pub(crate) struct TileDecoder {
+    tile_size: TileSize,
+    upscale_policy: UpscalePolicy,
+    animated_frames: AnimatedFrames,
+    max_source_pixels: Option<u64>,
+    // Tile cache to read resized tiles from instead of decoding palette images, if any
+    cache: Option<TileCache>,
+    recent: Mutex<RecentTiles>,
+}
+
+// Recently decoded tiles, by palette image index and span, evicting the least recently used ones beyond a total size
+pub(crate) struct RecentTiles {
+    // Maximum total size of the tiles, in bytes
+    capacity: u64,
+    size: u64,
+    tiles: HashMap<TileKey, RecentTile>,
+    // Key of each tile by the time it was last used, oldest first
+    uses: BTreeMap<u64, TileKey>,
+    // Number of uses of any tile so far, which times each use
+    clock: u64,
+}
+
+// Palette image index and span of a tile
+type TileKey = (usize, u32);
+
+struct RecentTile {
+    tile: Arc<Vec<u8>>,
+    last_use: u64,
+}
+
+impl TileDecoder {
+    pub(crate) fn new(
+        tile_size: TileSize,
+        upscale_policy: UpscalePolicy,
+        animated_frames: AnimatedFrames,
+        max_source_pixels: Option<u64>,
+        cache: Option<TileCache>,
+        capacity: u64,
+    ) -> Self {
+        Self {
+            tile_size,
+            upscale_policy,
+            animated_frames,
+            max_source_pixels,
+            cache,
+            recent: Mutex::new(RecentTiles::new(capacity)),
+        }
+    }
+
+    // Returns the tile (composited over black) of the palette image at `path`, by index `tile_idx`, for cells with
+    // the given span, decoding and resizing it unless it was drawn recently.
+    pub(crate) fn tile(&self, path: &Path, tile_idx: usize, span: u32) -> Result<Arc<Vec<u8>>> {
+        if let Some(tile) = self.recent.lock().unwrap().get((tile_idx, span)) {
+            return Ok(tile);
+        }
+
+        // The palette image already passed every filter when the palette was loaded, so none are applied again
+        let options = LoadOptions {
+            tile_size: self.tile_size,
+            spans: &[span],
+            min_tile_source: None,
+            allow_upscale: true,
+            quality_filters: QualityFilters::default(),
+            upscale_policy: self.upscale_policy,
+            animated_frames: self.animated_frames,
+            max_source_pixels: self.max_source_pixels,
+            cache: self.cache.as_ref(),
+        };
+        let mut tile = match load_image(path, &options)? {
+            LoadedImage::Kept(mut resized_images, ..) => resized_images.pop().unwrap(),
+            LoadedImage::Rejected(reason) => bail!(
+                "palette image {} could not be loaded again: {reason}",
+                path.display()
+            ),
+        };
+        composite_over_black(&mut tile);
+
+        let tile = Arc::new(tile);
+        self.recent
+            .lock()
+            .unwrap()
+            .insert((tile_idx, span), Arc::clone(&tile));
+        Ok(tile)
+    }
+}
+
+impl RecentTiles {
+    pub(crate) fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            size: 0,
+            tiles: HashMap::new(),
+            uses: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub(crate) fn get(&mut self, key: TileKey) -> Option<Arc<Vec<u8>>> {
+        let recent_tile = self.tiles.get_mut(&key)?;
+        self.uses.remove(&recent_tile.last_use);
+        self.clock += 1;
+        recent_tile.last_use = self.clock;
+        self.uses.insert(self.clock, key);
+        Some(Arc::clone(&recent_tile.tile))
+    }
+
+    // Adds a tile, evicting the least recently used tiles until the rest fit within the capacity.
+    // The newest tile is always kept, even if it is larger than the capacity on its own.
+    pub(crate) fn insert(&mut self, key: TileKey, tile: Arc<Vec<u8>>) {
+        self.clock += 1;
+        self.size += tile.len() as u64;
+        let recent_tile = RecentTile {
+            tile,
+            last_use: self.clock,
+        };
+        if let Some(replaced) = self.tiles.insert(key, recent_tile) {
+            self.size -= replaced.tile.len() as u64;
+            self.uses.remove(&replaced.last_use);
+        }
+        self.uses.insert(self.clock, key);
+
+        while self.size > self.capacity && self.uses.len() > 1 {
+            let (_, oldest_key) = self.uses.pop_first().unwrap();
+            let evicted = self.tiles.remove(&oldest_key).unwrap();
+            self.size -= evicted.tile.len() as u64;
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{
+    InputImage,
+    animation::AnimatedFrames,
+    bench::{synthetic_input_image, synthetic_palette_image},
+    geometry::{TileSize, parse_tile_size},
+    layout::grid_cells,
+    logging::{format_count, log},
+    mosaic::{MAX_QUANTIZED_COLOR_COUNT, MatchOptions, Quantizer, Records, dither, render_image},
+    output::save_image,
+    palette::{LoadOptions, LoadedPalette, Palette, UpscalePolicy, find_images, load_images},
+    placements::Placements,
+    quality::QualityFilters,
+};
+use anyhow::{Result, bail};
+use image::ImageReader;
+use pico_args::Arguments;
+use rayon::prelude::*;
+use std::{fs::create_dir_all, path::PathBuf};
+
+// Runs `tessera demo`, which writes a synthetic palette and input image to a directory, then generates a small mosaic
+// and its placements file from them, going through the same steps as `tessera` does with its default options.
+// It doubles as a working reference for driving the pipeline from code, one step after another.
+pub(crate) fn run(mut args: Arguments) -> Result<()> {
+    if args.contains(["-h", "--help"]) {
+        println!(
+            "tessera demo: generate a mosaic from a synthetic palette and input image, written to a directory
+usage: tessera demo [OPTIONS] DIR
+
+-h, --help           print this message
+--palette-size       number of images in the synthetic palette; default is 64
+--source-size        width and height of each synthetic palette image, in pixels; default is 64
+--input-size         width and height of the synthetic input image, in pixels; default is 32
+-s, --tile-size      width and height of each tile in the output image, in pixels (e.g. \"16\" or \"16x24\"); default is 8"
+        );
+        return Ok(());
+    }
+
+    let palette_size: u32 = args.opt_value_from_str("--palette-size")?.unwrap_or(64);
+    let source_size: u32 = args.opt_value_from_str("--source-size")?.unwrap_or(64);
+    let input_size: u32 = args.opt_value_from_str("--input-size")?.unwrap_or(32);
+    let tile_size = args
+        .opt_value_from_fn(["-s", "--tile-size"], parse_tile_size)?
+        .unwrap_or(TileSize::square(8));
+    let dir_path: PathBuf = args.free_from_str()?;
+
+    if palette_size == 0
+        || source_size == 0
+        || input_size == 0
+        || tile_size.width == 0
+        || tile_size.height == 0
+    {
+        bail!("palette size, source size, input size, and tile size must be greater than zero");
+    }
+    if dir_path.is_file() {
+        bail!("output directory path points to a file");
+    }
+
+    // Write the synthetic palette and input image, as a palette directory and input image given to `tessera` would be
+    let palette_dir_path = dir_path.join("palette");
+    let input_image_path = dir_path.join("input.png");
+    create_dir_all(&palette_dir_path)?;
+    (0..palette_size).into_par_iter().try_for_each(|i| {
+        synthetic_palette_image(i, source_size)
+            .save(palette_dir_path.join(format!("synthetic-{i:04}.png")))
+            .map_err(anyhow::Error::from)
+    })?;
+    synthetic_input_image(input_size).save(&input_image_path)?;
+    log!(
+        "wrote {} palette images to {} and an input image to {}",
+        format_count(u64::from(palette_size)),
+        palette_dir_path.display(),
+        input_image_path.display()
+    );
+
+    // Load the palette: find its images, resize each to a tile, and index the tiles by color
+    let spans = vec![1];
+    let load_options = LoadOptions {
+        tile_size,
+        spans: &spans,
+        min_tile_source: None,
+        allow_upscale: false,
+        quality_filters: QualityFilters::default(),
+        upscale_policy: UpscalePolicy::default(),
+        animated_frames: AnimatedFrames::First,
+        max_source_pixels: None,
+        cache: None,
+    };
+    let LoadedPalette {
+        paths, tile_sets, ..
+    } = load_images(
+        find_images(&palette_dir_path, &["png".to_owned()])?,
+        &load_options,
+    )?;
+    let palette = Palette::new(paths, tile_size, spans, tile_sets, false, None, None)?;
+
+    // Load the input image and dither it, so each of its pixels is the target color of one cell
+    let input_image: InputImage = ImageReader::open(&input_image_path)?.decode()?.into_rgb8();
+    let (width, height) = input_image.dimensions();
+    let input_image = dither(
+        &input_image,
+        Quantizer::default(),
+        MAX_QUANTIZED_COLOR_COUNT,
+    )?;
+    let cells = grid_cells(&input_image);
+
+    // Match every cell with a tile and draw the output image, recording each cell's palette image in a placements file
+    let placements_path = dir_path.join("placements.csv");
+    let mut placements = Placements::new(&placements_path, 0, None, None)?;
+    let records = Records {
+        placements: Some(&mut placements),
+        ..Records::default()
+    };
+    let (output_image, stats) = render_image(
+        &palette,
+        &cells,
+        width,
+        height,
+        MatchOptions::default(),
+        records,
+    )?;
+    placements.finish()?;
+
+    let output_image_path = dir_path.join("mosaic.png");
+    save_image(&output_image, &output_image_path, None)?;
+    log!(
+        "wrote a mosaic of {} cells using {} distinct palette images to {}, and its placements to {}",
+        format_count(cells.len() as u64),
+        format_count(stats.tile_uses.iter().filter(|&&uses| uses > 0).count() as u64),
+        output_image_path.display(),
+        placements_path.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use crate::{
+    compose::{Placed, read_placements},
+    layout::Pinned,
+    logging::{format_count, log},
+    output::{Outputs, check_output_path},
+};
+use anyhow::{Result, bail};
+use foldhash::{HashMap, HashMapExt};
+use image::{Pixel, Rgb, RgbImage};
+use pico_args::Arguments;
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+// Default width and height of each cell in the difference image, in pixels
+const DEFAULT_CELL_SIZE: u32 = 8;
+
+// Color that cells whose palette image changed are blended halfway toward
+const CHANGED_COLOR: [u8; 3] = [255, 0, 0];
+
+// Gray that cells whose palette image is unchanged are blended halfway toward, after removing their color, so
+// changed cells stand out
+const UNCHANGED_GRAY: u8 = 128;
+
+// Runs `tessera diff`, which draws an image highlighting the cells whose palette image differs between two
+// placements files (as written by `--placements`), e.g. to see what a tweaked parameter or an updated palette
+// changed. Each cell is drawn as its target color in the second placements file: changed cells are tinted red,
+// and unchanged cells are drawn in faded gray.
+pub(crate) fn run(mut args: Arguments) -> Result<()> {
+    if args.contains(["-h", "--help"]) {
+        println!(
+            "tessera diff: draw an image highlighting the cells whose palette image changed between two --placements files
+usage: tessera diff [OPTIONS] PLACEMENTS_A PLACEMENTS_B
+
+-h, --help           print this message
+--cell-size          width and height of each cell in the difference image, in pixels; default is {DEFAULT_CELL_SIZE}
+-o, --output         output image path; may be given more than once
+
+Changed cells are tinted red and unchanged cells are drawn in faded gray, each in its target color in PLACEMENTS_B."
+        );
+        return Ok(());
+    }
+
+    let cell_size: u32 = args
+        .opt_value_from_str("--cell-size")?
+        .unwrap_or(DEFAULT_CELL_SIZE);
+    let output_image_paths: Vec<PathBuf> = args.values_from_str(["-o", "--output"])?;
+    let placements_paths: Vec<PathBuf> = args.finish().into_iter().map(PathBuf::from).collect();
+
+    if cell_size == 0 {
+        bail!("`--cell-size`: cell size cannot be zero");
+    }
+    if output_image_paths.is_empty() {
+        bail!("`-o`/`--output`: at least one output path is required");
+    }
+    for path in &output_image_paths {
+        check_output_path(path)?;
+    }
+    let [before_path, after_path] = placements_paths.as_slice() else {
+        bail!("expected two placements files");
+    };
+    if let Some(path) = [before_path, after_path]
+        .into_iter()
+        .find(|path| !path.is_file())
+    {
+        bail!(
+            "placements path {} does not point to a file",
+            path.display()
+        );
+    }
+
+    let before = read_placements(&read_to_string(before_path)?)?;
+    let after = read_placements(&read_to_string(after_path)?)?;
+    if (before.width, before.height) != (after.width, after.height) {
+        bail!(
+            "the placements files cover grids of different sizes ({}x{} and {}x{} cells)",
+            before.width,
+            before.height,
+            after.width,
+            after.height
+        );
+    }
+
+    // Palette image placed in each cell of the first placements file, keyed by the cell's position and span.
+    // Cells that were split or merged differently (e.g. with `--depth-map`) count as changed.
+    let mut before_sources = HashMap::with_capacity(before.cells.len());
+    for (cell_idx, cell) in before.cells.iter().enumerate() {
+        before_sources.insert((cell.x, cell.y, cell.span), source(&before, cell_idx));
+    }
+
+    let mut image = RgbImage::new(after.width * cell_size, after.height * cell_size);
+    let mut changed_count = 0;
+    for (cell_idx, cell) in after.cells.iter().enumerate() {
+        let changed =
+            before_sources.get(&(cell.x, cell.y, cell.span)) != Some(&source(&after, cell_idx));
+        let color = if changed {
+            changed_count += 1;
+            cell.color.map2(&Rgb(CHANGED_COLOR), u8::midpoint)
+        } else {
+            let gray = cell.color.to_luma().0[0].midpoint(UNCHANGED_GRAY);
+            Rgb([gray; 3])
+        };
+
+        let span = cell.span * cell_size;
+        for y in cell.y * cell_size..(cell.y * cell_size + span).min(image.height()) {
+            for x in cell.x * cell_size..(cell.x * cell_size + span).min(image.width()) {
+                image.put_pixel(x, y, color);
+            }
+        }
+    }
+
+    let mut outputs = Outputs::new(
+        output_image_paths,
+        image.width(),
+        image.height(),
+        false,
+        None,
+    )?;
+    outputs.write_band(&image)?;
+    outputs.finish()?;
+
+    log!(
+        "{} of {} cells changed their palette image ({:.1}%)",
+        format_count(changed_count),
+        format_count(after.cells.len() as u64),
+        changed_count as f64 / after.cells.len().max(1) as f64 * 100.
+    );
+    Ok(())
+}
+
+// Path of the palette image placed in a cell, or `None` if it is drawn as solid color
+fn source(placed: &Placed, cell_idx: usize) -> Option<&Path> {
+    match placed.cells[cell_idx].pinned {
+        Some(Pinned::Tile(source_idx)) => Some(&placed.sources[source_idx]),
+        _ => None,
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::{Result, bail};
+use image::{
+    Pixel, RgbImage,
+    imageops::{FilterType, resize},
+};
+use rayon::prelude::*;
+use std::str::FromStr;
+
+// Lightness difference (out of 255) from the average of a block at which a pixel of the block counts for 4 times as
+// much as a pixel at the average, with `--grid-filter detail`. Smaller values preserve fainter details.
+const DETAIL_SCALE: f32 = 16.;
+
+// How the input image is resized to the grid of cells, for `--grid-filter`
+#[derive(Clone, Copy, Default)]
+pub(crate) enum GridFilter {
+    // Average each block of the input image that becomes a cell, which blurs away features thinner than a cell
+    #[default]
+    Average,
+    // Weight each pixel of a block by how much its lightness stands out from the block's average, so thin features
+    // such as text strokes and line art survive into the cells they cross. Only used when shrinking the image; when
+    // the grid is larger than the input image, it is enlarged as with `average`.
+    Detail,
+}
+
+impl FromStr for GridFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "average" => Ok(Self::Average),
+            "detail" => Ok(Self::Detail),
+            _ => bail!("expected \"average\" or \"detail\""),
+        }
+    }
+}
+
+impl GridFilter {
+    // Resizes the input image to the grid, so each of its pixels becomes one cell
+    pub(crate) fn resize(self, image: &RgbImage, width: u32, height: u32) -> RgbImage {
+        match self {
+            Self::Detail if width <= image.width() && height <= image.height() => {
+                detail_downscale(image, width, height)
+            }
+            _ => resize(image, width, height, FilterType::Triangle),
+        }
+    }
+}
+
+// Shrinks an image by averaging each block of pixels that becomes one output pixel, weighting every pixel by
+// (1 + d / DETAIL_SCALE)², where d is the difference between its lightness and the block's average lightness.
+// A block crossed by a thin dark stroke on a light background thus comes out dark rather than light gray, while
+// nearly flat blocks come out close to their plain average.
+fn detail_downscale(image: &RgbImage, width: u32, height: u32) -> RgbImage {
+    let (source_width, source_height) = image.dimensions();
+    // Range of source pixels covered by output pixel `i` of `count`, out of `length` source pixels
+    let block = |i: u32, count: u32, length: u32| {
+        let start = u64::from(i) * u64::from(length) / u64::from(count);
+        let end = (u64::from(i + 1) * u64::from(length) / u64::from(count)).max(start + 1);
+        start as u32..end as u32
+    };
+
+    let mut output = RgbImage::new(width, height);
+    output
+        .par_chunks_exact_mut(width as usize * 3)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let rows = block(y as u32, height, source_height);
+            for (x, pixel) in row.chunks_exact_mut(3).enumerate() {
+                let columns = block(x as u32, width, source_width);
+                let pixels = || {
+                    rows.clone().flat_map(|source_y| {
+                        columns
+                            .clone()
+                            .map(move |source_x| image.get_pixel(source_x, source_y))
+                    })
+                };
+
+                let count = (rows.len() * columns.len()) as f32;
+                let mean_luma = pixels()
+                    .map(|pixel| f32::from(pixel.to_luma().0[0]))
+                    .sum::<f32>()
+                    / count;
+
+                let mut sum = [0f32; 3];
+                let mut weight_sum = 0.;
+                for source in pixels() {
+                    let deviation = (f32::from(source.to_luma().0[0]) - mean_luma).abs();
+                    let weight = (1. + deviation / DETAIL_SCALE).powi(2);
+                    for (c, source_c) in sum.iter_mut().zip(source.0) {
+                        *c += weight * f32::from(source_c);
+                    }
+                    weight_sum += weight;
+                }
+                for (c, sum_c) in pixel.iter_mut().zip(sum) {
+                    *c = (sum_c / weight_sum).round() as u8;
+                }
+            }
+        });
+    output
+}
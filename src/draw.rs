@@ -0,0 +1,191 @@
+use crate::{
+    TILE_PIXEL_COMPONENT_MAX, TILE_PIXEL_COMPONENT_MAX_INT, TILE_PIXEL_SIZE, mosaic::MatchOptions,
+};
+use anyhow::{Context, Result};
+
+// A drop shadow that every tile casts on the grout showing around it, for `--shadow`
+#[derive(Clone, Copy)]
+pub(crate) struct Shadow {
+    // Darkness of the shadow under the tile, from 0 (none) to 1 (black)
+    pub(crate) opacity: f32,
+    // Blur radius of the shadow's edges, in pixels
+    pub(crate) radius: f32,
+    // Offset of the shadow from the tile, in pixels (positive toward the bottom right)
+    pub(crate) offset: (i32, i32),
+}
+
+// Parses the offset of a shadow, as "X,Y" in pixels
+pub(crate) fn parse_shadow_offset(s: &str) -> Result<(i32, i32)> {
+    let (x, y) = s.split_once(',').context("expected \"X,Y\"")?;
+    Ok((x.trim().parse()?, y.trim().parse()?))
+}
+
+// Draws tiles over the grout, cut to `cell_mask`, rotated by `rotate_jitter`, and casting a `shadow` (see
+// `MatchOptions`). Tiles never leave their cell, so neither do their shadows. Holds buffers large enough for a cell of
+// any span, so it is set up once for a whole render.
+pub(crate) struct TileDrawer {
+    // How much of each pixel of the cell the tile covers, from 0 to 1
+    coverage: Vec<f32>,
+    // Color of the tile at each pixel of the cell, premultiplied by its coverage
+    premultiplied: Vec<[f32; 3]>,
+    // How much of each pixel of the cell is in shadow, from 0 to 1, and a buffer for blurring it
+    shadow: Vec<f32>,
+    blurred: Vec<f32>,
+}
+
+impl TileDrawer {
+    pub(crate) fn new(max_pixel_count: usize) -> Self {
+        Self {
+            coverage: vec![0.; max_pixel_count],
+            premultiplied: vec![[0.; 3]; max_pixel_count],
+            shadow: vec![0.; max_pixel_count],
+            blurred: vec![0.; max_pixel_count],
+        }
+    }
+
+    // Draws a tile for a cell of the given span (`width` by `height` pixels) into `drawn`, which has the same
+    // dimensions, rotated about its center by an angle (in radians). Pixels are sampled bilinearly, with everything
+    // outside the tile (or cut away by the mask) uncovered, so the edges of rotated and cut tiles are antialiased.
+    pub(crate) fn draw(
+        &mut self,
+        tile: &[u8],
+        (width, height, span): (u32, u32, u32),
+        angle: f32,
+        options: MatchOptions<'_>,
+        drawn: &mut [u8],
+    ) {
+        let pixel_count = (width * height) as usize;
+        let mask = options.cell_mask.map(|cell_mask| cell_mask.coverage(span));
+        // Coverage and premultiplied color of a pixel of the unrotated tile
+        let pixel = |x: i64, y: i64| -> (f32, [f32; 3]) {
+            if (0..i64::from(width)).contains(&x) && (0..i64::from(height)).contains(&y) {
+                let px_idx = y as usize * width as usize + x as usize;
+                let coverage = mask.map_or(1., |mask| {
+                    f32::from(mask.as_raw()[px_idx]) / TILE_PIXEL_COMPONENT_MAX
+                });
+                let px_idx = px_idx * TILE_PIXEL_SIZE;
+                (
+                    coverage,
+                    [0, 1, 2].map(|c| f32::from(tile[px_idx + c]) * coverage),
+                )
+            } else {
+                (0., [0.; 3])
+            }
+        };
+
+        let (sin, cos) = angle.sin_cos();
+        let (center_x, center_y) = (width as f32 / 2., height as f32 / 2.);
+        for ((coverage, premultiplied), px_idx) in self.coverage[..pixel_count]
+            .iter_mut()
+            .zip(&mut self.premultiplied[..pixel_count])
+            .zip(0..)
+        {
+            // Offset of the pixel's center from the tile's center, rotated back into the unrotated tile
+            let dx = (px_idx % width) as f32 + 0.5 - center_x;
+            let dy = (px_idx / width) as f32 + 0.5 - center_y;
+            let x = cos * dx + sin * dy + center_x - 0.5;
+            let y = cos * dy - sin * dx + center_y - 0.5;
+
+            let (x0, y0) = (x.floor(), y.floor());
+            let (fx, fy) = (x - x0, y - y0);
+            let (x0, y0) = (x0 as i64, y0 as i64);
+            let [top_left, top_right, bottom_left, bottom_right] = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                .map(|(offset_x, offset_y)| pixel(x0 + offset_x, y0 + offset_y));
+            let lerp = |top_left: f32, top_right: f32, bottom_left: f32, bottom_right: f32| {
+                let top = top_left + (top_right - top_left) * fx;
+                let bottom = bottom_left + (bottom_right - bottom_left) * fx;
+                top + (bottom - top) * fy
+            };
+            *coverage = lerp(top_left.0, top_right.0, bottom_left.0, bottom_right.0);
+            *premultiplied = [0, 1, 2].map(|c| {
+                lerp(
+                    top_left.1[c],
+                    top_right.1[c],
+                    bottom_left.1[c],
+                    bottom_right.1[c],
+                )
+            });
+        }
+
+        let shadow = &mut self.shadow[..pixel_count];
+        match options.shadow {
+            Some(options) => {
+                cast_shadow(
+                    &self.coverage[..pixel_count],
+                    width,
+                    height,
+                    options,
+                    shadow,
+                    &mut self.blurred[..pixel_count],
+                );
+            }
+            None => shadow.fill(0.),
+        }
+
+        for (((px, coverage), premultiplied), shadow) in drawn
+            .as_chunks_mut::<TILE_PIXEL_SIZE>()
+            .0
+            .iter_mut()
+            .zip(&self.coverage)
+            .zip(&self.premultiplied)
+            .zip(&*shadow)
+        {
+            for c in 0..3 {
+                let grout = f32::from(options.grout[c]) * (1. - shadow);
+                px[c] = (premultiplied[c] + grout * (1. - coverage)).round() as u8;
+            }
+            px[3] = TILE_PIXEL_COMPONENT_MAX_INT;
+        }
+    }
+}
+
+// Fills `shadow` with the darkness of a tile's shadow at each pixel of its cell: its coverage, offset and blurred
+// by a Gaussian blur (in two passes, one along each axis, using `blurred` in between), scaled by the opacity.
+fn cast_shadow(
+    coverage: &[f32],
+    width: u32,
+    height: u32,
+    options: Shadow,
+    shadow: &mut [f32],
+    blurred: &mut [f32],
+) {
+    let (width, height) = (i64::from(width), i64::from(height));
+    // Gaussian weights for offsets from -reach to reach pixels
+    let sigma = (options.radius / 2.).max(f32::EPSILON);
+    let reach = options.radius.ceil() as i64;
+    let weights: Vec<f32> = (-reach..=reach)
+        .map(|offset| (-(offset * offset) as f32 / (2. * sigma * sigma)).exp())
+        .collect();
+    let weight_sum: f32 = weights.iter().sum();
+
+    let (offset_x, offset_y) = (i64::from(options.offset.0), i64::from(options.offset.1));
+    // Horizontal pass, with the shadow moved by its offset; everything outside the cell is uncovered
+    for y in 0..height {
+        for x in 0..width {
+            let source_y = y - offset_y;
+            let mut sum = 0.;
+            if (0..height).contains(&source_y) {
+                for (weight, offset) in weights.iter().zip(-reach..) {
+                    let source_x = x - offset_x + offset;
+                    if (0..width).contains(&source_x) {
+                        sum += weight * coverage[(source_y * width + source_x) as usize];
+                    }
+                }
+            }
+            blurred[(y * width + x) as usize] = sum / weight_sum;
+        }
+    }
+    // Vertical pass
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.;
+            for (weight, offset) in weights.iter().zip(-reach..) {
+                let source_y = y + offset;
+                if (0..height).contains(&source_y) {
+                    sum += weight * blurred[(source_y * width + x) as usize];
+                }
+            }
+            shadow[(y * width + x) as usize] = sum / weight_sum * options.opacity;
+        }
+    }
+}
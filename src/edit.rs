@@ -0,0 +1,352 @@
+use crate::{
+    cache::TileCache,
+    color::parse_hex_color,
+    compose::{compose, split_csv_line},
+    debug::csv_quote,
+    geometry::{TileSize, parse_tile_size},
+    output::check_output_path,
+    palette::UpscalePolicy,
+    placements::split_preamble,
+};
+use anyhow::{Context, Result, anyhow, bail};
+use pico_args::Arguments;
+use std::{
+    fs::{read_to_string, write},
+    io::{Write, stdin, stdout},
+    path::PathBuf,
+};
+
+const COMMANDS: &str = "commands:
+  show ROW COLUMN      print the palette image placed in the cell covering a position, and its alternates
+  next ROW COLUMN      place the cell's first alternate, moving the current palette image to the end of the alternates
+  use ROW COLUMN N     swap the cell's palette image with its Nth alternate
+  save                 write the placements file and draw the output images
+  help                 print this message
+  quit                 exit; unsaved changes are discarded";
+
+// Runs `tessera edit`, which swaps palette images in individual cells of a placements file for their alternates
+// (as written with `--placement-alternates`), then saves the placements file and redraws the output images.
+// Cells are chosen by row and column in the grid of cells, which are read from standard input along with commands.
+pub(crate) fn run(mut args: Arguments) -> Result<()> {
+    if args.contains(["-h", "--help"]) {
+        println!(
+            "tessera edit: interactively swap palette images in a --placements file for their alternates
+usage: tessera edit [OPTIONS] PLACEMENTS
+
+-h, --help           print this message
+-s, --tile-size      width and height of each tile in the output image, in pixels, e.g. \"64\" or \"64x96\"
+--upscale-policy     how to handle palette images smaller than a tile: \"bilinear\", \"nearest\", \"lanczos\", or \"skip\";
+                     default is \"bilinear\"
+--cache-dir          directory of a cache of resized palette images, which is reused and added to
+--grout              hex color showing around tiles rotated as in the placements file's `rotation` column, e.g. \"#d8d2c4\";
+                     default is \"#000000\"
+-o, --output         output image path, drawn on each save; may be given more than once
+
+{COMMANDS}"
+        );
+        return Ok(());
+    }
+
+    let tile_size = args.value_from_fn(["-s", "--tile-size"], parse_tile_size)?;
+    let upscale_policy: UpscalePolicy = args
+        .opt_value_from_str("--upscale-policy")?
+        .unwrap_or_default();
+    let cache_dir_path: Option<PathBuf> = args.opt_value_from_str("--cache-dir")?;
+    let grout = args.opt_value_from_fn("--grout", parse_hex_color)?;
+    let output_image_paths: Vec<PathBuf> = args.values_from_str(["-o", "--output"])?;
+    let placements_path: PathBuf = args.free_from_str()?;
+
+    if tile_size.width == 0 || tile_size.height == 0 {
+        bail!("`-s`/`--tile-size`: tile size cannot be zero");
+    }
+    if output_image_paths.is_empty() {
+        bail!("`-o`/`--output`: at least one output path is required");
+    }
+    for path in &output_image_paths {
+        check_output_path(path)?;
+    }
+    if !placements_path.is_file() {
+        bail!("placements path does not point to a file");
+    }
+
+    let mut manifest = Manifest::parse(&read_to_string(&placements_path)?)?;
+    if manifest.alternates.is_empty() {
+        bail!("placements file lists no alternates; write it with `--placement-alternates`");
+    }
+
+    let editor = Editor {
+        placements_path,
+        tile_size,
+        upscale_policy,
+        cache: cache_dir_path.map(TileCache::new).transpose()?,
+        grout: grout.map_or([0; 3], |grout| grout.0),
+        output_image_paths,
+    };
+    editor.run(&mut manifest)
+}
+
+// Where an editing session saves to
+struct Editor {
+    placements_path: PathBuf,
+    tile_size: TileSize,
+    upscale_policy: UpscalePolicy,
+    cache: Option<TileCache>,
+    grout: [u8; 3],
+    output_image_paths: Vec<PathBuf>,
+}
+
+impl Editor {
+    // Reads and runs commands until `quit` or the end of standard input.
+    fn run(&self, manifest: &mut Manifest) -> Result<()> {
+        println!("{COMMANDS}");
+        let mut unsaved = false;
+        let mut lines = stdin().lines();
+
+        loop {
+            print!("> ");
+            stdout().flush()?;
+            let Some(line) = lines.next().transpose()? else {
+                if unsaved {
+                    eprintln!("discarded unsaved changes");
+                }
+                return Ok(());
+            };
+
+            let words: Vec<_> = line.split_whitespace().collect();
+            let result = match words.as_slice() {
+                [] => Ok(()),
+                ["show", position @ ..] => manifest
+                    .find_cell(position)
+                    .map(|row_idx| manifest.print_cell(row_idx)),
+                ["next", position @ ..] => manifest.find_cell(position).map(|row_idx| {
+                    manifest.cycle(row_idx);
+                    manifest.print_cell(row_idx);
+                    unsaved = true;
+                }),
+                ["use", position @ .., n] => manifest.find_cell(position).and_then(|row_idx| {
+                    manifest.swap(row_idx, n)?;
+                    manifest.print_cell(row_idx);
+                    unsaved = true;
+                    Ok(())
+                }),
+                ["save"] => self.save(manifest).map(|()| unsaved = false),
+                ["help"] => {
+                    println!("{COMMANDS}");
+                    Ok(())
+                }
+                ["quit"] if unsaved => {
+                    unsaved = false;
+                    Err(anyhow!(
+                        "there are unsaved changes; `save` them, or `quit` again to discard them"
+                    ))
+                }
+                ["quit"] => return Ok(()),
+                _ => Err(anyhow!("unknown command; `help` lists commands")),
+            };
+
+            if let Err(error) = result {
+                eprintln!("{error:#}");
+            }
+        }
+    }
+
+    // Writes the placements file and draws the output images from it.
+    fn save(&self, manifest: &Manifest) -> Result<()> {
+        let contents = manifest.to_csv();
+        write(&self.placements_path, &contents)?;
+        compose(
+            &contents,
+            self.tile_size,
+            self.upscale_policy,
+            self.cache.as_ref(),
+            self.grout,
+            None,
+            self.output_image_paths.clone(),
+        )?;
+        eprintln!("saved {}", self.placements_path.display());
+
+        Ok(())
+    }
+}
+
+// The fields of a placements file, kept as written so columns the editor doesn't change are saved as-is
+struct Manifest {
+    // Comment lines before the header, such as the version of the pseudorandom number generator
+    preamble: String,
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+    // Position and span of the cell in each row
+    cells: Vec<(u32, u32, u32)>,
+    source_idx: usize,
+    error_idx: usize,
+    // Indices of the source and error columns of each alternate, in order
+    alternates: Vec<(usize, usize)>,
+}
+
+impl Manifest {
+    fn parse(contents: &str) -> Result<Self> {
+        let (preamble, contents) = split_preamble(contents)?;
+        let mut lines = contents.lines().zip(preamble.lines().count() + 1..);
+        let Some((header, _)) = lines.next() else {
+            bail!("placements file is empty");
+        };
+        let header = split_csv_line(header);
+        let column_idx = |name: &str| {
+            header
+                .iter()
+                .position(|column| column == name)
+                .with_context(|| format!("placements file has no `{name}` column"))
+        };
+        let row_idx = column_idx("row")?;
+        let col_idx = column_idx("column")?;
+        let span_idx = column_idx("span")?;
+        let source_idx = column_idx("source")?;
+        let error_idx = column_idx("error")?;
+
+        let mut alternates = Vec::new();
+        for n in 1.. {
+            let (Ok(alternate_idx), Ok(alternate_error_idx)) = (
+                column_idx(&format!("alternate_{n}")),
+                column_idx(&format!("alternate_{n}_error")),
+            ) else {
+                break;
+            };
+            alternates.push((alternate_idx, alternate_error_idx));
+        }
+
+        let mut rows = Vec::new();
+        let mut cells = Vec::new();
+        for (line, line_number) in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut fields = split_csv_line(line);
+            fields.resize(header.len(), String::new());
+            let parse = |idx: usize, name: &str| -> Result<u32> {
+                fields[idx]
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("placements file: line {line_number}: invalid {name}"))
+            };
+            cells.push((
+                parse(row_idx, "row")?,
+                parse(col_idx, "column")?,
+                parse(span_idx, "span")?,
+            ));
+            rows.push(fields);
+        }
+
+        Ok(Self {
+            preamble: preamble.to_owned(),
+            header,
+            rows,
+            cells,
+            source_idx,
+            error_idx,
+            alternates,
+        })
+    }
+
+    // Finds the row of the cell covering a position, given as the words "ROW COLUMN".
+    fn find_cell(&self, position: &[&str]) -> Result<usize> {
+        let [y, x] = position else {
+            bail!("expected a row and a column");
+        };
+        let y: u32 = y.parse().context("invalid row")?;
+        let x: u32 = x.parse().context("invalid column")?;
+
+        self.cells
+            .iter()
+            .position(|&(cell_y, cell_x, span)| {
+                (cell_y..cell_y + span).contains(&y) && (cell_x..cell_x + span).contains(&x)
+            })
+            .with_context(|| format!("no cell covers row {y}, column {x}"))
+    }
+
+    // Prints the palette image placed in a cell and its alternates.
+    fn print_cell(&self, row_idx: usize) {
+        let row = &self.rows[row_idx];
+        let (y, x, span) = self.cells[row_idx];
+        let describe = |source: &str, error: &str| {
+            let source = if source.is_empty() {
+                "(solid color)"
+            } else {
+                source
+            };
+            format!("{source} (error {error})")
+        };
+
+        println!(
+            "row {y}, column {x}, span {span}: {}",
+            describe(&row[self.source_idx], &row[self.error_idx])
+        );
+        for (n, &(alternate_idx, alternate_error_idx)) in (1..).zip(&self.alternates) {
+            println!(
+                "  {n}: {}",
+                describe(&row[alternate_idx], &row[alternate_error_idx])
+            );
+        }
+    }
+
+    // Places a cell's first alternate, moving every other alternate up and the current palette image to the end,
+    // so repeatedly cycling a cell visits each alternate in turn before returning to the original.
+    fn cycle(&mut self, row_idx: usize) {
+        let row = &mut self.rows[row_idx];
+        let columns: Vec<_> = [(self.source_idx, self.error_idx)]
+            .into_iter()
+            .chain(self.alternates.iter().copied())
+            .collect();
+        let mut candidates: Vec<_> = columns
+            .iter()
+            .map(|&(idx, error_idx)| {
+                (
+                    std::mem::take(&mut row[idx]),
+                    std::mem::take(&mut row[error_idx]),
+                )
+            })
+            .collect();
+        candidates.rotate_left(1);
+
+        for ((idx, error_idx), (source, error)) in columns.into_iter().zip(candidates) {
+            row[idx] = source;
+            row[error_idx] = error;
+        }
+    }
+
+    // Swaps the palette image placed in a cell with its `n`th alternate, counting from 1.
+    fn swap(&mut self, row_idx: usize, n: &str) -> Result<()> {
+        let n: usize = n.parse().context("invalid alternate number")?;
+        let Some(&(alternate_idx, alternate_error_idx)) =
+            n.checked_sub(1).and_then(|idx| self.alternates.get(idx))
+        else {
+            bail!(
+                "alternate number must be between 1 and {}",
+                self.alternates.len()
+            );
+        };
+
+        let row = &mut self.rows[row_idx];
+        row.swap(self.source_idx, alternate_idx);
+        row.swap(self.error_idx, alternate_error_idx);
+
+        Ok(())
+    }
+
+    fn to_csv(&self) -> String {
+        let line = |fields: &[String]| {
+            let mut line = fields
+                .iter()
+                .map(|field| csv_quote(field))
+                .collect::<Vec<_>>()
+                .join(",");
+            line.push('\n');
+            line
+        };
+
+        std::iter::once(self.preamble.clone())
+            .chain(std::iter::once(line(&self.header)))
+            .chain(self.rows.iter().map(|row| line(row)))
+            .collect()
+    }
+}
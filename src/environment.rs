@@ -0,0 +1,72 @@
+use fast_image_resize::Resizer;
+use image::ImageFormat;
+use rayon::current_num_threads;
+use std::env::consts::{ARCH, OS};
+
+// Describes the build of tessera and the machine it runs on, as (name, value) pairs, so timings from
+// `tessera bench` and `--report` can be compared between machines and builds. It is only shown locally; nothing
+// is sent anywhere.
+pub(crate) fn fingerprint() -> Vec<(&'static str, String)> {
+    let build = if cfg!(debug_assertions) {
+        "debug"
+    } else {
+        "release"
+    };
+
+    vec![
+        (
+            "tessera",
+            format!("{} ({build} build)", env!("CARGO_PKG_VERSION")),
+        ),
+        ("platform", format!("{OS} {ARCH}")),
+        ("threads", current_num_threads().to_string()),
+        ("SIMD", simd_features()),
+        (
+            "resize CPU extensions",
+            format!("{:?}", Resizer::new().cpu_extensions()),
+        ),
+        ("codecs", codecs()),
+    ]
+}
+
+// Lists the SIMD instruction sets detected on the CPU that tessera's dependencies can use
+fn simd_features() -> String {
+    let mut features: Vec<&str> = Vec::new();
+    #[cfg(target_arch = "x86_64")]
+    for (name, detected) in [
+        ("sse4.1", is_x86_feature_detected!("sse4.1")),
+        ("avx2", is_x86_feature_detected!("avx2")),
+        ("fma", is_x86_feature_detected!("fma")),
+        ("avx512f", is_x86_feature_detected!("avx512f")),
+    ] {
+        if detected {
+            features.push(name);
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        features.push("neon");
+    }
+
+    if features.is_empty() {
+        "none".to_owned()
+    } else {
+        features.join(", ")
+    }
+}
+
+// Lists the image formats this build can read or write, e.g. "png (read/write)"
+fn codecs() -> String {
+    ImageFormat::all()
+        .filter_map(|format| {
+            let support = match (format.reading_enabled(), format.writing_enabled()) {
+                (true, true) => "read/write",
+                (true, false) => "read",
+                (false, true) => "write",
+                (false, false) => return None,
+            };
+            Some(format!("{} ({support})", format.extensions_str()[0]))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,111 @@
+use crate::InputImage;
+use anyhow::{Context, Result, bail};
+use image::{
+    GrayImage, Luma,
+    imageops::{FilterType, blur, resize},
+};
+use quantette::palette::{IntoColor, Oklab, Srgb};
+use std::str::FromStr;
+
+// Largest width or height (in pixels) that the input image is shrunk to before finding its focal point
+const SALIENCY_SIZE: u32 = 128;
+
+// Standard deviation of the blur applied to the saliency map, as a fraction of its larger dimension, so the focal point
+// lands in the largest salient region rather than on a single salient pixel
+const SALIENCY_BLUR: f32 = 0.08;
+
+// How much less the saliency at the corners of the image counts than at its center, so that among equally salient
+// regions, the one nearer the center is chosen
+const CENTER_BIAS: f32 = 0.25;
+
+// A point of the output image that a layout or ordering is centered on, for `--layout-center` and `--order-center`
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Focus {
+    // A point given as fractions of the output image's width and height
+    Point(f32, f32),
+    // The focal point of the input image, found by `focal_point`
+    Auto,
+}
+
+impl FromStr for Focus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "auto" {
+            return Ok(Self::Auto);
+        }
+        let (x, y) = s.split_once(',').context("expected \"X,Y\" or \"auto\"")?;
+        let (x, y): (f32, f32) = (x.trim().parse()?, y.trim().parse()?);
+        if !(0. ..=1.).contains(&x) || !(0. ..=1.).contains(&y) {
+            bail!("coordinates must be between 0 and 1");
+        }
+        Ok(Self::Point(x, y))
+    }
+}
+
+// Finds the most salient region of an image, such as a face or the subject of a photo against its background, as
+// fractions of the image's width and height. Each pixel's saliency is how far its color (in Oklab space, after a
+// slight blur) is from the image's mean color, following Achanta et al.'s frequency-tuned salient region detection;
+// the focal point is the peak of the saliency map after a wide blur.
+pub(crate) fn focal_point(image: &InputImage) -> (f32, f32) {
+    let (width, height) = image.dimensions();
+    let scale = (SALIENCY_SIZE as f32 / width.max(height) as f32).min(1.);
+    let small = resize(
+        image,
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+        FilterType::Triangle,
+    );
+    let small = blur(&small, 1.);
+    let (width, height) = small.dimensions();
+
+    let colors: Vec<[f32; 3]> = small
+        .pixels()
+        .map(|pixel| {
+            let oklab: Oklab = Srgb::from(pixel.0).into_linear().into_color();
+            [oklab.l, oklab.a, oklab.b]
+        })
+        .collect();
+    let mut mean = [0.; 3];
+    for color in &colors {
+        for (m, c) in mean.iter_mut().zip(color) {
+            *m += c / colors.len() as f32;
+        }
+    }
+    let saliency: Vec<f32> = colors
+        .iter()
+        .map(|color| {
+            color
+                .iter()
+                .zip(mean)
+                .map(|(c, m)| (c - m).powi(2))
+                .sum::<f32>()
+                .sqrt()
+        })
+        .collect();
+
+    // The saliency map is scaled to the range of a grayscale image, to be blurred
+    let max_saliency = saliency.iter().copied().fold(f32::MIN_POSITIVE, f32::max);
+    let map = GrayImage::from_fn(width, height, |x, y| {
+        Luma([(saliency[(y * width + x) as usize] / max_saliency * 255.).round() as u8])
+    });
+    let map = blur(&map, SALIENCY_BLUR * width.max(height) as f32);
+
+    let (center_x, center_y) = (width as f32 / 2., height as f32 / 2.);
+    let half_diagonal = center_x.hypot(center_y);
+    let (x, y, _) = map
+        .enumerate_pixels()
+        .map(|(x, y, pixel)| {
+            let distance =
+                (x as f32 + 0.5 - center_x).hypot(y as f32 + 0.5 - center_y) / half_diagonal;
+            let weight = f32::from(pixel.0[0]) * (1. - CENTER_BIAS * distance * distance);
+            (x, y, weight)
+        })
+        .max_by(|(_, _, a), (_, _, b)| a.total_cmp(b))
+        .unwrap();
+
+    (
+        (x as f32 + 0.5) / width as f32,
+        (y as f32 + 0.5) / height as f32,
+    )
+}
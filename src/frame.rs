@@ -0,0 +1,143 @@
+use crate::{TILE_PIXEL_SIZE, color::parse_hex_color, palette::Palette};
+use anyhow::Result;
+use image::{
+    ImageReader, Rgb, RgbImage,
+    imageops::{FilterType, resize},
+};
+use std::{path::PathBuf, str::FromStr};
+
+// What a frame around the output image is filled with
+#[derive(Clone)]
+pub(crate) enum FrameStyle {
+    // A solid color, written as a hex color such as "#1e1e1e"
+    Solid(Rgb<u8>),
+    // Tiles from the palette, cycling through the palette in order
+    Tiles,
+    // An image, stretched to the dimensions of the framed output image
+    Image(PathBuf),
+}
+
+impl FromStr for FrameStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "tiles" {
+            return Ok(Self::Tiles);
+        }
+
+        if s.starts_with('#') {
+            return Ok(Self::Solid(parse_hex_color(s)?));
+        }
+
+        Ok(Self::Image(PathBuf::from(s)))
+    }
+}
+
+// Pixels of a frame, ready to draw
+enum FrameFill<'a> {
+    Solid(Rgb<u8>),
+    Tiles(&'a Palette),
+    Image(RgbImage),
+}
+
+// A frame of constant width around the output image.
+// Coordinates are in pixels of the framed output image, whose top-left corner is the frame's top-left corner.
+pub(crate) struct Frame<'a> {
+    width: u32,
+    // Dimensions of the framed output image
+    outer_width: u32,
+    outer_height: u32,
+    fill: FrameFill<'a>,
+}
+
+impl<'a> Frame<'a> {
+    // Prepares a frame of the given width around an output image of the given dimensions.
+    pub(crate) fn new(
+        style: FrameStyle,
+        width: u32,
+        palette: &'a Palette,
+        inner_width: u32,
+        inner_height: u32,
+    ) -> Result<Self> {
+        let outer_width = inner_width + 2 * width;
+        let outer_height = inner_height + 2 * width;
+
+        let fill = match style {
+            FrameStyle::Solid(color) => FrameFill::Solid(color),
+            FrameStyle::Tiles => FrameFill::Tiles(palette),
+            FrameStyle::Image(path) => {
+                let image = ImageReader::open(path)?.decode()?.into_rgb8();
+                FrameFill::Image(resize(
+                    &image,
+                    outer_width,
+                    outer_height,
+                    FilterType::Triangle,
+                ))
+            }
+        };
+
+        Ok(Self {
+            width,
+            outer_width,
+            outer_height,
+            fill,
+        })
+    }
+
+    pub(crate) fn width(&self) -> u32 {
+        self.width
+    }
+
+    // Dimensions of the framed output image
+    pub(crate) fn outer_dimensions(&self) -> (u32, u32) {
+        (self.outer_width, self.outer_height)
+    }
+
+    // Draws the top edge of the frame.
+    pub(crate) fn top(&self) -> RgbImage {
+        RgbImage::from_fn(self.outer_width, self.width, |x, y| self.pixel(x, y))
+    }
+
+    // Draws the bottom edge of the frame.
+    pub(crate) fn bottom(&self) -> RgbImage {
+        let y_offset = self.outer_height - self.width;
+        RgbImage::from_fn(self.outer_width, self.width, |x, y| {
+            self.pixel(x, y + y_offset)
+        })
+    }
+
+    // Draws the left and right edges of the frame around a band of the output image,
+    // whose top row is row `y` of the framed output image.
+    pub(crate) fn surround(&self, band: &RgbImage, y: u32) -> RgbImage {
+        let right_x = self.outer_width - self.width;
+
+        RgbImage::from_fn(self.outer_width, band.height(), |x, band_y| {
+            if x < self.width || x >= right_x {
+                self.pixel(x, y + band_y)
+            } else {
+                *band.get_pixel(x - self.width, band_y)
+            }
+        })
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> Rgb<u8> {
+        match &self.fill {
+            FrameFill::Solid(color) => *color,
+            FrameFill::Tiles(palette) => {
+                // Tiles are laid out in a grid anchored at the top-left corner, clipped at the inner edges
+                let tile_size = palette.tile_size;
+                let tiles = palette.tiles(1);
+                let columns = self.outer_width.div_ceil(tile_size.width);
+                let tile_x = x / tile_size.width;
+                let tile_y = y / tile_size.height;
+                let tile = &tiles[(tile_y * columns + tile_x) as usize % tiles.len()];
+
+                let px_idx = ((y % tile_size.height) * tile_size.width + x % tile_size.width)
+                    as usize
+                    * TILE_PIXEL_SIZE;
+                Rgb(*tile[px_idx..].first_chunk().unwrap())
+            }
+            FrameFill::Image(image) => *image.get_pixel(x, y),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use crate::remainder::FitRemainder;
+use anyhow::{Result, bail};
+use std::str::FromStr;
+
+// Width and height of each tile in the output image, in pixels
+#[derive(Clone, Copy)]
+pub(crate) struct TileSize {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+impl TileSize {
+    pub(crate) fn square(size: u32) -> Self {
+        Self {
+            width: size,
+            height: size,
+        }
+    }
+}
+
+// Parses a tile size: either a single number for square tiles (e.g. "64") or a width and height (e.g. "64x96").
+pub(crate) fn parse_tile_size(s: &str) -> Result<TileSize> {
+    Ok(match s.split_once(['x', 'X']) {
+        Some((width, height)) => TileSize {
+            width: width.trim().parse()?,
+            height: height.trim().parse()?,
+        },
+        None => TileSize::square(s.trim().parse()?),
+    })
+}
+
+// Parses a comma-separated list of tile sizes (e.g. "32,64x96").
+pub(crate) fn parse_tile_sizes(s: &str) -> Result<Vec<TileSize>> {
+    s.split(',').map(parse_tile_size).collect()
+}
+
+// A dimension of a geometry: either an absolute number of pixels or a percentage of the original dimension
+#[derive(Clone, Copy)]
+enum Dimension {
+    Pixels(u32),
+    Percent(f64),
+}
+
+// How a geometry's dimensions are applied, following ImageMagick's geometry flags
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Flag {
+    // Fit within the given width and height, preserving aspect ratio
+    None,
+    // `!`: use the given width and height exactly, ignoring aspect ratio
+    Exact,
+    // `^`: fill the given width and height (as a minimum), preserving aspect ratio
+    Fill,
+    // `>`: only shrink dimensions that are larger than the given width and height
+    ShrinkOnly,
+    // `<`: only enlarge dimensions that are smaller than the given width and height
+    EnlargeOnly,
+}
+
+// An ImageMagick-style geometry describing a size relative to some original size, such as:
+// - "50%": half the original width and height
+// - "50%x25%": half the original width and a quarter of the original height
+// - "120" or "120x": a width of 120, with the height scaled to preserve aspect ratio
+// - "x80": a height of 80, with the width scaled to preserve aspect ratio
+// - "120x80": the largest size that fits within 120x80 while preserving aspect ratio
+// - "120x80!", "120x80^", "120x80>", "120x80<": exactly 120x80, the smallest size that covers 120x80,
+//   only shrinking to fit within 120x80, or only enlarging to fit within 120x80, respectively
+#[derive(Clone, Copy)]
+pub(crate) struct Geometry {
+    width: Option<Dimension>,
+    height: Option<Dimension>,
+    flag: Flag,
+}
+
+impl FromStr for Geometry {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+
+        let (s, flag) = match s.chars().last() {
+            Some('!') => (&s[..s.len() - 1], Flag::Exact),
+            Some('^') => (&s[..s.len() - 1], Flag::Fill),
+            Some('>') => (&s[..s.len() - 1], Flag::ShrinkOnly),
+            Some('<') => (&s[..s.len() - 1], Flag::EnlargeOnly),
+            _ => (s, Flag::None),
+        };
+
+        // A single trailing percent sign (e.g. "50%" or "50x25%") applies to both dimensions
+        let (s, all_percent) = match s.strip_suffix('%') {
+            Some(s) if !s.contains('%') => (s, true),
+            _ => (s, false),
+        };
+
+        let parse_dimension = |s: &str| -> Result<Option<Dimension>> {
+            let s = s.trim();
+            if s.is_empty() {
+                return Ok(None);
+            }
+            let dimension = match s.strip_suffix('%') {
+                Some(percent) => Dimension::Percent(percent.parse()?),
+                None if all_percent => Dimension::Percent(s.parse()?),
+                None => Dimension::Pixels(s.parse()?),
+            };
+            match dimension {
+                Dimension::Pixels(0) => bail!("dimensions cannot be zero"),
+                Dimension::Percent(percent) if percent.is_nan() || percent <= 0. => {
+                    bail!("percentages must be positive")
+                }
+                _ => Ok(Some(dimension)),
+            }
+        };
+
+        let (width, height) = if let Some((width, height)) = s.split_once(['x', 'X']) {
+            (parse_dimension(width)?, parse_dimension(height)?)
+        } else {
+            // A single percentage scales both dimensions; a single number of pixels is a width
+            let dimension = parse_dimension(s)?;
+            if matches!(dimension, Some(Dimension::Percent(_))) {
+                (dimension, dimension)
+            } else {
+                (dimension, None)
+            }
+        };
+
+        if width.is_none() && height.is_none() {
+            bail!("geometry must have a width or a height");
+        }
+
+        Ok(Self {
+            width,
+            height,
+            flag,
+        })
+    }
+}
+
+impl Geometry {
+    // Applies the geometry to an original size, returning the new size (which is never zero in either dimension).
+    pub(crate) fn apply(self, width: f64, height: f64) -> (u32, u32) {
+        let resolve = |dimension, original: f64| match dimension {
+            Some(Dimension::Pixels(pixels)) => Some(f64::from(pixels)),
+            Some(Dimension::Percent(percent)) => Some(original * percent / 100.),
+            None => None,
+        };
+        let target_width = resolve(self.width, width);
+        let target_height = resolve(self.height, height);
+
+        let is_percent = matches!(self.width, Some(Dimension::Percent(_)))
+            || matches!(self.height, Some(Dimension::Percent(_)));
+
+        let (new_width, new_height) = match (target_width, target_height) {
+            // Percentages scale each dimension independently, like ImageMagick
+            (Some(target_width), Some(target_height)) if is_percent || self.flag == Flag::Exact => {
+                (target_width, target_height)
+            }
+            (Some(target_width), Some(target_height)) => {
+                let width_scale = target_width / width;
+                let height_scale = target_height / height;
+                let scale = if self.flag == Flag::Fill {
+                    width_scale.max(height_scale)
+                } else {
+                    width_scale.min(height_scale)
+                };
+                (width * scale, height * scale)
+            }
+            (Some(target_width), None) => (target_width, height * target_width / width),
+            (None, Some(target_height)) => (width * target_height / height, target_height),
+            (None, None) => (width, height),
+        };
+
+        let (new_width, new_height) = match self.flag {
+            Flag::ShrinkOnly if new_width >= width && new_height >= height => (width, height),
+            Flag::EnlargeOnly if new_width <= width && new_height <= height => (width, height),
+            _ => (new_width, new_height),
+        };
+
+        (
+            (new_width.round() as u32).max(1),
+            (new_height.round() as u32).max(1),
+        )
+    }
+}
+
+// Decides the number of columns and rows of cells from `--grid` or `--fit`, or `None` to keep one cell per pixel of
+// the input image. Dimensions left for the geometry to choose preserve the input image's aspect ratio in the output image.
+// With `--fit`, the grid is rounded down to whole tiles, unless the remainder is filled with a partial row and column.
+pub(crate) fn grid_dimensions(
+    grid: Option<Geometry>,
+    fit: Option<Geometry>,
+    fit_remainder: Option<FitRemainder>,
+    (input_width, input_height): (u32, u32),
+    tile_size: TileSize,
+) -> Option<(u32, u32)> {
+    match (grid, fit) {
+        (Some(grid), _) => Some(grid.apply(
+            f64::from(input_width),
+            f64::from(input_height) * f64::from(tile_size.width) / f64::from(tile_size.height),
+        )),
+        (None, Some(fit)) => {
+            let (output_width, output_height) =
+                fit.apply(f64::from(input_width), f64::from(input_height));
+            Some(match fit_remainder {
+                Some(FitRemainder::Crop) => (
+                    output_width.div_ceil(tile_size.width),
+                    output_height.div_ceil(tile_size.height),
+                ),
+                _ => (
+                    (output_width / tile_size.width).max(1),
+                    (output_height / tile_size.height).max(1),
+                ),
+            })
+        }
+        (None, None) => None,
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{
+    TILE_PIXEL_COMPONENT_MAX_INT, TILE_PIXEL_SIZE, geometry::TileSize, layout::Cell,
+    palette::LoadedPalette,
+};
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont, point};
+use anyhow::{Context, Result, bail};
+use rayon::prelude::*;
+use std::{
+    fs::{read, write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+// Characters whose glyphs make up the palette, for `--charset`
+#[derive(Default)]
+pub(crate) enum Charset {
+    // Printable ASCII characters, including the space
+    #[default]
+    Ascii,
+    // Braille patterns, whose dots form a 2x4 grid in each character
+    Braille,
+    // CJK unified ideographs
+    Cjk,
+    // Characters listed explicitly, as "chars:..."
+    Chars(Vec<char>),
+}
+
+impl FromStr for Charset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ascii" => Ok(Self::Ascii),
+            "braille" => Ok(Self::Braille),
+            "cjk" => Ok(Self::Cjk),
+            _ => match s.strip_prefix("chars:") {
+                Some(chars) if !chars.is_empty() => Ok(Self::Chars(chars.chars().collect())),
+                _ => bail!(
+                    "expected \"ascii\", \"braille\", \"cjk\", or a list of characters such as \"chars: .:-=+*#%@\""
+                ),
+            },
+        }
+    }
+}
+
+impl Charset {
+    fn chars(&self) -> Vec<char> {
+        match self {
+            Self::Ascii => (' '..='~').collect(),
+            Self::Braille => ('\u{2800}'..='\u{28FF}').collect(),
+            Self::Cjk => ('\u{4E00}'..='\u{9FFF}').collect(),
+            Self::Chars(chars) => {
+                let mut chars = chars.clone();
+                chars.sort_unstable();
+                chars.dedup();
+                chars
+            }
+        }
+    }
+}
+
+// A font's glyphs for the characters of a charset, for `--glyphs`. Each glyph is rendered as a tile of light
+// text on black, so glyphs are matched by how much of their tile they cover.
+pub(crate) struct Glyphs {
+    font: FontVec,
+    font_path: PathBuf,
+    // Characters of the charset that the font has glyphs for, in the order of the palette
+    pub(crate) chars: Vec<char>,
+}
+
+impl Glyphs {
+    pub(crate) fn new(font_path: &Path, charset: &Charset) -> Result<Self> {
+        let font = FontVec::try_from_vec(read(font_path)?)
+            .ok()
+            .context("`--glyphs`: path does not point to a TrueType or OpenType font")?;
+        // Characters without a glyph map to glyph 0, which is drawn as a placeholder box
+        let chars: Vec<_> = charset
+            .chars()
+            .into_iter()
+            .filter(|&c| font.glyph_id(c).0 != 0)
+            .collect();
+        if chars.is_empty() {
+            bail!("`--glyphs`: font has no glyphs for any character of the charset");
+        }
+
+        Ok(Self {
+            font,
+            font_path: font_path.to_owned(),
+            chars,
+        })
+    }
+
+    // Renders every glyph as a tile for each span, as if it were a palette image. Each glyph's path is its code point
+    // joined to the font's path (e.g. "font.ttf/U+0041"), so it is listed by code point in placements files and reports.
+    pub(crate) fn load(&self, tile_size: TileSize, spans: &[u32]) -> LoadedPalette {
+        let tile_sets = spans
+            .iter()
+            .map(|&span| {
+                self.chars
+                    .par_iter()
+                    .map(|&c| self.render(c, tile_size.width * span, tile_size.height * span))
+                    .collect()
+            })
+            .collect();
+
+        LoadedPalette {
+            paths: self
+                .chars
+                .iter()
+                .map(|&c| self.font_path.join(format!("U+{:04X}", u32::from(c))))
+                .collect(),
+            tile_sets,
+            source_dimensions: vec![(tile_size.width, tile_size.height); self.chars.len()],
+        }
+    }
+
+    // Renders a glyph centered horizontally in an opaque tile, scaled so the font's ascent and descent fill its height.
+    fn render(&self, c: char, width: u32, height: u32) -> Vec<u8> {
+        let mut tile = vec![0; (width * height) as usize * TILE_PIXEL_SIZE];
+        for px in tile.as_chunks_mut::<TILE_PIXEL_SIZE>().0 {
+            px[3] = TILE_PIXEL_COMPONENT_MAX_INT;
+        }
+
+        let font = self.font.as_scaled(PxScale::from(height as f32));
+        let id = font.glyph_id(c);
+        let x = (width as f32 - font.h_advance(id)) / 2.;
+        let glyph = id.with_scale_and_position(font.scale(), point(x, font.ascent()));
+
+        if let Some(outlined_glyph) = font.outline_glyph(glyph) {
+            let bounds = outlined_glyph.px_bounds();
+            outlined_glyph.draw(|glyph_x, glyph_y, coverage| {
+                // Parts of glyphs wider or taller than the tile are cut off
+                let x = bounds.min.x as i64 + i64::from(glyph_x);
+                let y = bounds.min.y as i64 + i64::from(glyph_y);
+                if (0..i64::from(width)).contains(&x) && (0..i64::from(height)).contains(&y) {
+                    let px_idx = (y as usize * width as usize + x as usize) * TILE_PIXEL_SIZE;
+                    let value = (coverage.clamp(0., 1.) * f32::from(TILE_PIXEL_COMPONENT_MAX_INT))
+                        .round() as u8;
+                    tile[px_idx..px_idx + 3].fill(value);
+                }
+            });
+        }
+
+        tile
+    }
+}
+
+// The character placed in each cell, for `--text`, written as a plain-text version of the output image
+pub(crate) struct TextOutput {
+    width: u32,
+    // Index of the glyph placed at each position of the grid, if any
+    glyph_indices: Vec<Option<usize>>,
+}
+
+impl TextOutput {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            glyph_indices: vec![None; (width * height) as usize],
+        }
+    }
+
+    // Records the glyph placed in a cell, or `None` for a cell drawn as solid color.
+    pub(crate) fn record(&mut self, cell: &Cell, glyph_idx: Option<usize>) {
+        for y in cell.y..cell.y + cell.span {
+            for x in cell.x..cell.x + cell.span {
+                self.glyph_indices[(y * self.width + x) as usize] = glyph_idx;
+            }
+        }
+    }
+
+    // Writes one line of characters per row of cells. Cells drawn as solid color are written as spaces.
+    pub(crate) fn write(&self, path: &Path, chars: &[char]) -> Result<()> {
+        let mut text = String::new();
+        for row in self.glyph_indices.chunks(self.width as usize) {
+            text.extend(
+                row.iter()
+                    .map(|glyph_idx| glyph_idx.map_or(' ', |idx| chars[idx])),
+            );
+            text.push('\n');
+        }
+        write(path, text)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{INPUT_PIXEL_COMPONENT_MAX, geometry::TileSize, layout::Cell};
+use image::{Rgb, RgbImage};
+
+// Softens visible seams between neighboring tiles, for `--harmonize`: along every edge between two cells,
+// the pixels of each tile within `edge_width` of the edge are shifted toward the average of the two tiles' facing edge
+// pixels, most at the edge and fading linearly away from it. Tile texture is kept, since each pixel is shifted
+// by the same amount as the edge pixel beside it rather than blended. This is much cheaper than blending in the
+// gradient domain, and works on bands of the output image as they are rendered.
+pub(crate) struct Harmonizer {
+    tile_size: TileSize,
+    // Number of cells across the grid
+    width: u32,
+    // Index of the cell covering each position of the grid, so edges inside cells with a span above 1 are left alone
+    cell_indices: Vec<u32>,
+    // Fraction of the difference between two facing edge pixels that each is shifted by, from 0.0 to 0.5
+    strength: f32,
+    edge_width: u32,
+    // The previous band, held back until the edge between it and the next band is harmonized
+    previous_band: Option<RgbImage>,
+    // Row of the grid where the next band starts
+    next_row: u32,
+}
+
+impl Harmonizer {
+    // `strength` is from 0.0 (no change) to 1.0 (facing edge pixels meet at their average).
+    // `edge_width` is limited to half of the smaller tile dimension, so the shifts from opposite edges don't overlap.
+    pub(crate) fn new(
+        cells: &[Cell],
+        width: u32,
+        height: u32,
+        tile_size: TileSize,
+        strength: f32,
+        edge_width: u32,
+    ) -> Self {
+        let mut cell_indices = vec![0; (width * height) as usize];
+        for (cell, idx) in cells.iter().zip(0..) {
+            for y in cell.y..cell.y + cell.span {
+                for x in cell.x..cell.x + cell.span {
+                    cell_indices[(y * width + x) as usize] = idx;
+                }
+            }
+        }
+
+        Self {
+            tile_size,
+            width,
+            cell_indices,
+            strength: strength / 2.,
+            edge_width: edge_width
+                .min(tile_size.width / 2)
+                .min(tile_size.height / 2)
+                .max(1),
+            previous_band: None,
+            next_row: 0,
+        }
+    }
+
+    // Takes the next band of the output image (without any frame), returning the previous band once it is harmonized.
+    pub(crate) fn push(&mut self, mut band: RgbImage) -> Option<RgbImage> {
+        let first_row = self.next_row;
+        let row_count = band.height() / self.tile_size.height;
+        self.next_row += row_count;
+
+        for row in first_row..first_row + row_count {
+            let y = (row - first_row) * self.tile_size.height;
+            for column in 1..self.width {
+                if self.cell_index(column - 1, row) != self.cell_index(column, row) {
+                    self.harmonize_vertical_edge(&mut band, column * self.tile_size.width, y);
+                }
+            }
+            if row > first_row {
+                self.harmonize_horizontal_edge(None, &mut band, row, y);
+            }
+        }
+
+        let previous_band = self.previous_band.take();
+        let previous_band = previous_band.map(|mut previous_band| {
+            self.harmonize_horizontal_edge(Some(&mut previous_band), &mut band, first_row, 0);
+            previous_band
+        });
+        self.previous_band = Some(band);
+        previous_band
+    }
+
+    // Returns the last band, which has no band below it.
+    pub(crate) fn finish(self) -> Option<RgbImage> {
+        self.previous_band
+    }
+
+    fn cell_index(&self, column: u32, row: u32) -> u32 {
+        self.cell_indices[(row * self.width + column) as usize]
+    }
+
+    // Harmonizes the edge at column `x` of pixels between two horizontally neighboring cells,
+    // for the row of cells starting at row `y` of pixels in the band.
+    fn harmonize_vertical_edge(&self, band: &mut RgbImage, x: u32, y: u32) {
+        for y in y..y + self.tile_size.height {
+            let mut left: Vec<_> = (0..self.edge_width)
+                .map(|d| band.get_pixel(x - 1 - d, y).0)
+                .collect();
+            let mut right: Vec<_> = (0..self.edge_width)
+                .map(|d| band.get_pixel(x + d, y).0)
+                .collect();
+            self.shift(&mut left, &mut right);
+            for (d, (left, right)) in (0..).zip(left.into_iter().zip(right)) {
+                band.put_pixel(x - 1 - d, y, Rgb(left));
+                band.put_pixel(x + d, y, Rgb(right));
+            }
+        }
+    }
+
+    // Harmonizes the edges between cells in grid row `row` and the row above, at row `y` of pixels in `band`.
+    // The row above is in `upper_band` (at its bottom), or in `band` itself if it is `None`.
+    fn harmonize_horizontal_edge(
+        &self,
+        mut upper_band: Option<&mut RgbImage>,
+        band: &mut RgbImage,
+        row: u32,
+        y: u32,
+    ) {
+        for column in 0..self.width {
+            if self.cell_index(column, row - 1) == self.cell_index(column, row) {
+                continue;
+            }
+
+            for x in column * self.tile_size.width..(column + 1) * self.tile_size.width {
+                let mut below: Vec<_> = (0..self.edge_width)
+                    .map(|d| band.get_pixel(x, y + d).0)
+                    .collect();
+                let mut above: Vec<_> = match &upper_band {
+                    Some(upper_band) => (0..self.edge_width)
+                        .map(|d| upper_band.get_pixel(x, upper_band.height() - 1 - d).0)
+                        .collect(),
+                    None => (0..self.edge_width)
+                        .map(|d| band.get_pixel(x, y - 1 - d).0)
+                        .collect(),
+                };
+                self.shift(&mut above, &mut below);
+                for (d, (above, below)) in (0..).zip(above.into_iter().zip(below)) {
+                    band.put_pixel(x, y + d, Rgb(below));
+                    match &mut upper_band {
+                        Some(upper_band) => {
+                            let upper_y = upper_band.height() - 1 - d;
+                            upper_band.put_pixel(x, upper_y, Rgb(above));
+                        }
+                        None => band.put_pixel(x, y - 1 - d, Rgb(above)),
+                    }
+                }
+            }
+        }
+    }
+
+    // Shifts two strips of pixels on either side of an edge (each starting at the pixel beside the edge)
+    // toward each other, by a fraction of the difference between the two pixels beside the edge.
+    fn shift(&self, a: &mut [[u8; 3]], b: &mut [[u8; 3]]) {
+        let (edge_a, edge_b) = (a[0], b[0]);
+        let edge_width = a.len() as f32;
+
+        for (d, (a, b)) in (0..).zip(a.iter_mut().zip(b)) {
+            let weight = self.strength * (1. - d as f32 / edge_width);
+            for c in 0..3 {
+                let difference = f32::from(edge_b[c]) - f32::from(edge_a[c]);
+                a[c] = (f32::from(a[c]) + difference * weight)
+                    .round()
+                    .clamp(0., INPUT_PIXEL_COMPONENT_MAX) as u8;
+                b[c] = (f32::from(b[c]) - difference * weight)
+                    .round()
+                    .clamp(0., INPUT_PIXEL_COMPONENT_MAX) as u8;
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::{Result, bail};
+use foldhash::{HashMap, HashMapExt};
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+
+// Name of the file in a directory of the palette tree carrying matching hints for the palette images in it
+const HINTS_FILE_NAME: &str = "palette.toml";
+
+// Matching hints from one directory's palette.toml, which apply to the directory and every directory below it
+#[derive(Default)]
+struct DirHints {
+    // Desirability weight of its palette images, as for `--weights`
+    weight: Option<f32>,
+    // Tags of its palette images, for `--tags`
+    tags: Vec<String>,
+    // Whether its palette images are left out of the palette
+    exclude: bool,
+}
+
+impl DirHints {
+    // Reads a directory's palette.toml, if it has one, e.g. "weight = 2", "tags = [\"beach\"]", or "exclude = true".
+    fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(HINTS_FILE_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let table: Table = match read_to_string(&path)?.parse() {
+            Ok(table) => table,
+            Err(err) => bail!("`{}`: {err}", path.display()),
+        };
+        let mut hints = Self::default();
+        for (key, value) in table {
+            match (key.as_str(), value) {
+                ("weight", Value::Float(weight)) => hints.weight = Some(weight as f32),
+                ("weight", Value::Integer(weight)) => hints.weight = Some(weight as f32),
+                ("tags", Value::Array(tags)) => {
+                    for tag in tags {
+                        let Value::String(tag) = tag else {
+                            bail!("`{}`: `tags` must be a list of strings", path.display());
+                        };
+                        hints.tags.push(tag);
+                    }
+                }
+                ("exclude", Value::Boolean(exclude)) => hints.exclude = exclude,
+                ("weight", _) => bail!("`{}`: `weight` must be a number", path.display()),
+                ("tags", _) => bail!("`{}`: `tags` must be a list of strings", path.display()),
+                ("exclude", _) => bail!("`{}`: `exclude` must be true or false", path.display()),
+                _ => bail!(
+                    "`{}`: unknown key `{key}`; expected `weight`, `tags`, or `exclude`",
+                    path.display()
+                ),
+            }
+        }
+        if hints
+            .weight
+            .is_some_and(|weight| !weight.is_finite() || weight < 0.)
+        {
+            bail!("`{}`: weight must be a non-negative number", path.display());
+        }
+
+        Ok(Some(hints))
+    }
+}
+
+// Whether a directory's palette.toml leaves it (and every directory below it) out of the palette
+pub(crate) fn is_excluded(dir: &Path) -> Result<bool> {
+    Ok(DirHints::read(dir)?.is_some_and(|hints| hints.exclude))
+}
+
+// Matching hints for palette images from the palette.toml files in the palette tree.
+// Each palette image gets the weight from the nearest palette.toml in its directory or a directory above it
+// (up to the palette directory), and the tags from all of them.
+pub(crate) struct PaletteHints {
+    // Hints in effect in each directory containing a palette image, combined with those of every directory above it
+    dirs: HashMap<PathBuf, DirHints>,
+}
+
+impl PaletteHints {
+    pub(crate) fn load(palette_dir: &Path, palette_paths: &[PathBuf]) -> Result<Self> {
+        let mut hints = Self {
+            dirs: HashMap::new(),
+        };
+        for path in palette_paths {
+            if let Some(dir) = path.parent() {
+                hints.resolve(palette_dir, dir)?;
+            }
+        }
+        Ok(hints)
+    }
+
+    // Combines the hints of a directory with those of every directory above it, up to the palette directory
+    fn resolve(&mut self, palette_dir: &Path, dir: &Path) -> Result<()> {
+        if self.dirs.contains_key(dir) {
+            return Ok(());
+        }
+
+        let mut combined = DirHints::default();
+        if dir != palette_dir
+            && let Some(parent) = dir
+                .parent()
+                .filter(|parent| parent.starts_with(palette_dir))
+        {
+            self.resolve(palette_dir, parent)?;
+            let parent_hints = &self.dirs[parent];
+            combined.weight = parent_hints.weight;
+            combined.tags.clone_from(&parent_hints.tags);
+        }
+        if let Some(own) = DirHints::read(dir)? {
+            combined.weight = own.weight.or(combined.weight);
+            for tag in own.tags {
+                if !combined.tags.contains(&tag) {
+                    combined.tags.push(tag);
+                }
+            }
+        }
+
+        self.dirs.insert(dir.to_owned(), combined);
+        Ok(())
+    }
+
+    fn get(&self, path: &Path) -> Option<&DirHints> {
+        path.parent().and_then(|dir| self.dirs.get(dir))
+    }
+
+    // Whether a palette image is tagged with any of the given tags
+    pub(crate) fn has_any_tag(&self, path: &Path, tags: &[String]) -> bool {
+        self.get(path)
+            .is_some_and(|hints| hints.tags.iter().any(|tag| tags.contains(tag)))
+    }
+
+    // Weight of each palette image, or `None` if no palette.toml assigns any weight
+    pub(crate) fn weights(&self, palette_paths: &[PathBuf]) -> Option<Vec<f32>> {
+        self.dirs
+            .values()
+            .any(|hints| hints.weight.is_some())
+            .then(|| {
+                palette_paths
+                    .iter()
+                    .map(|path| self.get(path).and_then(|hints| hints.weight).unwrap_or(1.))
+                    .collect()
+            })
+    }
+}
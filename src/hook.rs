@@ -0,0 +1,77 @@
+use anyhow::{Result, bail};
+use std::{
+    io::stderr,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str::FromStr,
+};
+
+// A shell command to run when rendering finishes, for `--on-complete`, e.g. "upload.sh {output}".
+// Before it runs, "{output}" is replaced with the paths of the outputs written (suffixed with their tile size, with
+// several tile sizes), "{report}" with the `--report` path (or nothing), and "{status}" with the exit status of the
+// run: 0 if it succeeded or 1 if it failed.
+pub(crate) struct CompletionHook {
+    command: String,
+}
+
+impl FromStr for CompletionHook {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.trim().is_empty() {
+            bail!("command cannot be empty");
+        }
+        Ok(Self {
+            command: s.to_owned(),
+        })
+    }
+}
+
+impl CompletionHook {
+    // Runs the command in the platform's shell, waiting for it to finish.
+    // Its output goes to stderr, so it doesn't mix with anything piped from stdout.
+    pub(crate) fn run(
+        &self,
+        output_paths: &[PathBuf],
+        report_path: Option<&Path>,
+        succeeded: bool,
+    ) -> Result<()> {
+        let outputs: Vec<_> = output_paths.iter().map(|path| quote(path)).collect();
+        let command = self
+            .command
+            .replace("{output}", &outputs.join(" "))
+            .replace("{report}", &report_path.map(quote).unwrap_or_default())
+            .replace("{status}", if succeeded { "0" } else { "1" });
+
+        let status = shell(&command).stdout(Stdio::from(stderr())).status()?;
+        if !status.success() {
+            bail!("`--on-complete`: command failed ({status})");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.args(["/C", command]);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.args(["-c", command]);
+    shell
+}
+
+// Quotes a path as a single argument for the shell
+#[cfg(windows)]
+fn quote(path: &Path) -> String {
+    format!("\"{}\"", path.to_string_lossy())
+}
+
+#[cfg(not(windows))]
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
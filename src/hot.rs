@@ -0,0 +1,85 @@
+use crate::{
+    logging::{format_count, log},
+    palette::{
+        LoadOptions, LoadedImage, LoadedPalette, collect_loaded_images, load_image_for_sizes,
+    },
+};
+use anyhow::Result;
+use foldhash::{HashMap, HashSet};
+use rayon::prelude::*;
+use std::{
+    fs::metadata,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// Palette images loaded by earlier renders of a `--watch` run, kept in memory so that when palette images arrive,
+// only the new (or changed) ones are decoded and resized, and the palette is indexed again from the tiles already
+// loaded. Palette images are assumed to be loaded with the same options every time, as they are within one run.
+#[derive(Default)]
+pub(crate) struct HotPalette {
+    images: HashMap<PathBuf, HotImage>,
+}
+
+// A palette image's size and modification time when it was loaded, and the outcome of loading it for each tile size
+struct HotImage {
+    identity: (u64, Option<SystemTime>),
+    loaded_images: Vec<LoadedImage>,
+}
+
+impl HotPalette {
+    // Like `load_images_for_sizes`, but only loads the palette images that weren't loaded before or have changed since,
+    // and forgets those that are no longer in `paths`.
+    pub(crate) fn load(
+        &mut self,
+        paths: Vec<PathBuf>,
+        options: &[LoadOptions<'_>],
+    ) -> Result<Vec<LoadedPalette>> {
+        let mut stale = Vec::new();
+        for path in &paths {
+            let identity = identity(path)?;
+            if self
+                .images
+                .get(path)
+                .is_none_or(|image| image.identity != identity)
+            {
+                stale.push((path, identity));
+            }
+        }
+
+        let loaded_images = stale
+            .par_iter()
+            .map(|&(path, _)| load_image_for_sizes(path, options))
+            .collect::<Result<Vec<_>>>()?;
+        let listed: HashSet<_> = paths.iter().collect();
+        self.images.retain(|path, _| listed.contains(path));
+        if !self.images.is_empty() {
+            log!(
+                "loaded {} new or changed palette images, reusing the tiles of the other {}",
+                format_count(stale.len() as u64),
+                format_count((paths.len() - stale.len()) as u64)
+            );
+        }
+        for ((path, identity), loaded_images) in stale.into_iter().zip(loaded_images) {
+            self.images.insert(
+                path.clone(),
+                HotImage {
+                    identity,
+                    loaded_images,
+                },
+            );
+        }
+
+        let loaded_images = paths
+            .iter()
+            .map(|path| self.images[path].loaded_images.clone())
+            .collect();
+        Ok(collect_loaded_images(paths, loaded_images, options))
+    }
+}
+
+// Identifies a version of a palette image by its size and modification time
+fn identity(path: &Path) -> Result<(u64, Option<SystemTime>)> {
+    let metadata = metadata(path)?;
+    Ok((metadata.len(), metadata.modified().ok()))
+}
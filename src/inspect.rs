@@ -0,0 +1,367 @@
+use crate::{
+    INPUT_PIXEL_COMPONENT_MAX,
+    animation::AnimatedFrames,
+    cache::TileCache,
+    color::oklab_to_linear_srgb,
+    default_extensions,
+    geometry::{TileSize, parse_tile_size},
+    logging::{format_count, log},
+    palette::{LoadOptions, LoadedPalette, Palette, UpscalePolicy, find_images, load_images},
+    parse_extensions,
+    paths::encode_relative_path,
+    quality::QualityFilters,
+    rng::Rng,
+};
+use anyhow::{Result, bail};
+use kiddo::{KdTree, SquaredEuclidean};
+use pico_args::Arguments;
+use rayon::ThreadPoolBuilder;
+use std::{
+    fs::write,
+    path::{Path, PathBuf},
+};
+
+// Number of clusters palette images are grouped into by default
+const DEFAULT_CLUSTER_COUNT: usize = 8;
+
+// Number of palette images listed as exemplars of each cluster
+const EXEMPLAR_COUNT: usize = 3;
+
+// Maximum number of rounds of k-means clustering; clustering usually settles well before this
+const MAX_KMEANS_ROUNDS: usize = 100;
+
+// Color distance from a kept palette image within which another palette image is suggested for pruning by default
+const DEFAULT_PRUNE_DISTANCE: f32 = 0.01;
+
+// Runs `tessera inspect`, which summarizes a palette: palette images are clustered by average color (k-means in
+// Oklab space), and each cluster's size, mean color, and most typical palette images are reported,
+// so it is easy to see what a palette is dominated by.
+pub(crate) fn run(mut args: Arguments) -> Result<()> {
+    if args.contains(["-h", "--help"]) {
+        println!(
+            "tessera inspect: summarize a palette by clustering its images by color
+usage: tessera inspect [OPTIONS]
+
+-h, --help           print this message
+-p, --palette-dir    path to directory containing palette images
+-s, --tile-size      width and height of the tiles palette images' colors are calculated from, in pixels; default is \"16\"
+-k, --clusters       number of clusters to group palette images into; default is 8
+-a, --alpha-weighted compute the average color of each tile from its visible pixels only, weighted by opacity
+-x, --extensions     comma-separated list of file extensions of palette images to process, replacing the default list;
+                     prefix the list with \"+\" to add to the default list instead; default is \"{}\"
+--cache-dir          directory of a cache of resized palette images, which is reused and added to
+-t, --threads        number of threads to use; default is the number of logical CPUs
+--suggest-prune      list redundant palette images instead, whose removal would barely change the palette's gamut:
+                     each has a color within --prune-distance of a palette image that is kept
+--prune-distance     color distance within which palette images count as redundant, for --suggest-prune; default is 0.01
+--apply              path of a file to write the palette images kept by --suggest-prune to, for use with --palette-filter",
+            default_extensions().join(",")
+        );
+        return Ok(());
+    }
+
+    let palette_dir_path: PathBuf = args.value_from_str(["-p", "--palette-dir"])?;
+    let tile_size = args
+        .opt_value_from_fn(["-s", "--tile-size"], parse_tile_size)?
+        .unwrap_or(TileSize::square(16));
+    let cluster_count: usize = args
+        .opt_value_from_str(["-k", "--clusters"])?
+        .unwrap_or(DEFAULT_CLUSTER_COUNT);
+    let alpha_weighted = args.contains(["-a", "--alpha-weighted"]);
+    let extensions = args
+        .opt_value_from_fn(["-x", "--extensions"], parse_extensions)?
+        .unwrap_or_else(default_extensions);
+    let cache_dir_path: Option<PathBuf> = args.opt_value_from_str("--cache-dir")?;
+    let thread_count: Option<usize> = args.opt_value_from_str(["-t", "--threads"])?;
+    let suggest_prune = args.contains("--suggest-prune");
+    let prune_distance: Option<f32> = args.opt_value_from_str("--prune-distance")?;
+    let apply_path: Option<PathBuf> = args.opt_value_from_str("--apply")?;
+
+    if !palette_dir_path.is_dir() {
+        bail!("`-p`/`--palette-dir`: path does not point to a directory");
+    }
+    if tile_size.width == 0 || tile_size.height == 0 {
+        bail!("`-s`/`--tile-size`: tile size cannot be zero");
+    }
+    if cluster_count == 0 {
+        bail!("`-k`/`--clusters`: number of clusters cannot be zero");
+    }
+    if thread_count == Some(0) {
+        bail!("`-t`/`--threads`: thread count cannot be zero");
+    }
+    if prune_distance.is_some_and(|distance| distance.is_nan() || distance < 0.) {
+        bail!("`--prune-distance`: distance must be a non-negative number");
+    }
+    if !suggest_prune && (prune_distance.is_some() || apply_path.is_some()) {
+        bail!("`--prune-distance` and `--apply` can only be used with `--suggest-prune`");
+    }
+
+    if let Some(thread_count) = thread_count {
+        ThreadPoolBuilder::new()
+            .num_threads(thread_count)
+            .build_global()?;
+    }
+
+    let cache = cache_dir_path.map(TileCache::new).transpose()?;
+    let load_options = LoadOptions {
+        tile_size,
+        spans: &[1],
+        min_tile_source: None,
+        allow_upscale: false,
+        quality_filters: QualityFilters::default(),
+        upscale_policy: UpscalePolicy::default(),
+        animated_frames: AnimatedFrames::First,
+        max_source_pixels: None,
+        cache: cache.as_ref(),
+    };
+    let LoadedPalette {
+        paths, tile_sets, ..
+    } = load_images(find_images(&palette_dir_path, &extensions)?, &load_options)?;
+    let palette = Palette::new(
+        paths,
+        tile_size,
+        vec![1],
+        tile_sets,
+        alpha_weighted,
+        None,
+        None,
+    )?;
+
+    if suggest_prune {
+        let redundancies = suggest_prune_images(
+            &palette.colors,
+            prune_distance.unwrap_or(DEFAULT_PRUNE_DISTANCE),
+        );
+        print_redundancies(&redundancies, &palette, &palette_dir_path);
+
+        if let Some(apply_path) = apply_path {
+            let mut is_pruned = vec![false; palette.paths.len()];
+            for redundancy in &redundancies {
+                is_pruned[redundancy.pruned] = true;
+            }
+            let mut contents = String::from(
+                "# palette images kept by `tessera inspect --suggest-prune`, for use with --palette-filter\n",
+            );
+            for (path, _) in palette
+                .paths
+                .iter()
+                .zip(is_pruned)
+                .filter(|(_, is_pruned)| !is_pruned)
+            {
+                contents.push_str(&encode_relative_path(path, &palette_dir_path)?);
+                contents.push('\n');
+            }
+            write(&apply_path, contents)?;
+            log!(
+                "wrote {} kept palette images to {}",
+                format_count((palette.paths.len() - redundancies.len()) as u64),
+                apply_path.display()
+            );
+        }
+    } else {
+        let clusters = cluster(&palette.colors, cluster_count);
+        print_clusters(&clusters, &palette, &palette_dir_path);
+    }
+
+    Ok(())
+}
+
+// A group of palette images with similar average colors
+pub(crate) struct Cluster {
+    // Mean of the average colors of the cluster's palette images, in Oklab space
+    pub(crate) centroid: [f32; 3],
+    // Indices of the cluster's palette images, nearest to the centroid first
+    pub(crate) members: Vec<usize>,
+}
+
+// Groups colors (in Oklab space) into at most `count` clusters with k-means clustering, largest cluster first.
+// Initial centroids are chosen with k-means++ from a fixed seed, so results are the same on every run.
+pub(crate) fn cluster(colors: &[[f32; 3]], count: usize) -> Vec<Cluster> {
+    let count = count.min(colors.len());
+    let mut rng = Rng::new(0);
+
+    // k-means++: each centroid after the first is a color chosen with probability proportional to its squared
+    // distance from the nearest centroid so far, which spreads the initial centroids out
+    let mut centroids = vec![colors[rng.below(colors.len())]];
+    while centroids.len() < count {
+        let distances: Vec<_> = colors
+            .iter()
+            .map(|color| nearest_centroid(&centroids, color).1)
+            .collect();
+        let total: f32 = distances.iter().sum();
+        if total <= 0. {
+            // Every remaining color is identical to a centroid
+            break;
+        }
+        let mut target = (rng.next_u64() >> 40) as f32 / (1 << 24) as f32 * total;
+        let idx = distances
+            .iter()
+            .position(|&distance| {
+                target -= distance;
+                target < 0.
+            })
+            .unwrap_or(colors.len() - 1);
+        centroids.push(colors[idx]);
+    }
+
+    let mut assignments = vec![usize::MAX; colors.len()];
+    for _ in 0..MAX_KMEANS_ROUNDS {
+        let mut is_changed = false;
+        for (assignment, color) in assignments.iter_mut().zip(colors) {
+            let (centroid_idx, _) = nearest_centroid(&centroids, color);
+            is_changed |= *assignment != centroid_idx;
+            *assignment = centroid_idx;
+        }
+        if !is_changed {
+            break;
+        }
+
+        let mut sums = vec![([0.; 3], 0); centroids.len()];
+        for (&assignment, color) in assignments.iter().zip(colors) {
+            let (sum, member_count) = &mut sums[assignment];
+            for (sum, component) in sum.iter_mut().zip(color) {
+                *sum += component;
+            }
+            *member_count += 1;
+        }
+        for (centroid, (sum, member_count)) in centroids.iter_mut().zip(sums) {
+            // Empty clusters keep their centroid
+            if member_count > 0 {
+                *centroid = sum.map(|sum: f32| sum / member_count as f32);
+            }
+        }
+    }
+
+    let mut clusters: Vec<_> = centroids
+        .into_iter()
+        .map(|centroid| Cluster {
+            centroid,
+            members: Vec::new(),
+        })
+        .collect();
+    for (idx, &assignment) in assignments.iter().enumerate() {
+        clusters[assignment].members.push(idx);
+    }
+    for cluster in &mut clusters {
+        cluster.members.sort_by(|&a, &b| {
+            squared_distance(&colors[a], &cluster.centroid)
+                .total_cmp(&squared_distance(&colors[b], &cluster.centroid))
+        });
+    }
+    clusters.retain(|cluster| !cluster.members.is_empty());
+    clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.members.len()));
+
+    clusters
+}
+
+// A palette image suggested for pruning, since a kept palette image has nearly the same color
+pub(crate) struct Redundancy {
+    pub(crate) pruned: usize,
+    pub(crate) kept: usize,
+    pub(crate) distance: f32,
+}
+
+// Finds palette images whose colors (in Oklab space) are within `max_distance` of a palette image that is kept,
+// so removing them changes how closely the palette covers any color by at most `max_distance`.
+// Colors farthest from the palette's mean color are considered first, so the extremes of the gamut are always kept
+// and near-duplicates inside it are pruned.
+pub(crate) fn suggest_prune_images(colors: &[[f32; 3]], max_distance: f32) -> Vec<Redundancy> {
+    let mut mean = [0.; 3];
+    for color in colors {
+        for (mean, component) in mean.iter_mut().zip(color) {
+            *mean += component / colors.len() as f32;
+        }
+    }
+    let mut order: Vec<_> = (0..colors.len()).collect();
+    order.sort_by(|&a, &b| {
+        squared_distance(&colors[b], &mean).total_cmp(&squared_distance(&colors[a], &mean))
+    });
+
+    let mut kept: KdTree<f32, 3> = KdTree::with_capacity(colors.len());
+    let mut redundancies = Vec::new();
+    for idx in order {
+        if kept.size() > 0 {
+            let nearest = kept.nearest_one::<SquaredEuclidean>(&colors[idx]);
+            let distance = nearest.distance.sqrt();
+            if distance <= max_distance {
+                redundancies.push(Redundancy {
+                    pruned: idx,
+                    kept: nearest.item as usize,
+                    distance,
+                });
+                continue;
+            }
+        }
+        kept.add(&colors[idx], idx as u64);
+    }
+
+    redundancies.sort_by_key(|redundancy| redundancy.pruned);
+    redundancies
+}
+
+// Returns the index of the centroid nearest to a color, and its squared distance.
+fn nearest_centroid(centroids: &[[f32; 3]], color: &[f32; 3]) -> (usize, f32) {
+    centroids
+        .iter()
+        .map(|centroid| squared_distance(centroid, color))
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap()
+}
+
+fn squared_distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum()
+}
+
+// Formats a color in Oklab space as a hex color, such as "#1e1e1e".
+pub(crate) fn hex_color([l, a, b]: [f32; 3]) -> String {
+    let [r, g, b] = oklab_to_linear_srgb(l, a, b)
+        .map(|c| (c.clamp(0., 1.) * INPUT_PIXEL_COMPONENT_MAX).round() as u8);
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+fn print_clusters(clusters: &[Cluster], palette: &Palette, palette_dir: &Path) {
+    let image_count = palette.paths.len();
+    println!(
+        "{image_count} palette images in {} clusters by color",
+        clusters.len()
+    );
+
+    for (cluster, n) in clusters.iter().zip(1..) {
+        let [l, ..] = cluster.centroid;
+        println!(
+            "cluster {n}: {} images ({:.1}%), mean color {} (lightness {l:.2})",
+            cluster.members.len(),
+            cluster.members.len() as f64 / image_count as f64 * 100.,
+            hex_color(cluster.centroid)
+        );
+        for &idx in cluster.members.iter().take(EXEMPLAR_COUNT) {
+            let path = &palette.paths[idx];
+            println!(
+                "  {}",
+                path.strip_prefix(palette_dir).unwrap_or(path).display()
+            );
+        }
+    }
+}
+
+fn print_redundancies(redundancies: &[Redundancy], palette: &Palette, palette_dir: &Path) {
+    let relative = |idx: usize| {
+        let path = &palette.paths[idx];
+        path.strip_prefix(palette_dir).unwrap_or(path).display()
+    };
+
+    println!(
+        "{} of {} palette images are redundant",
+        redundancies.len(),
+        palette.paths.len()
+    );
+    for redundancy in redundancies {
+        println!(
+            "{} (distance {:.4} from {})",
+            relative(redundancy.pruned),
+            redundancy.distance,
+            relative(redundancy.kept)
+        );
+    }
+}
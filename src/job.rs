@@ -0,0 +1,272 @@
+use crate::{
+    logging::{log, write_error},
+    schema::JOB_SCHEMA,
+};
+use anyhow::{Context, Result, bail};
+use pico_args::Arguments;
+use std::{
+    env::{current_dir, set_current_dir, var_os},
+    ffi::OsString,
+    fmt::Write as _,
+    fs::{create_dir_all, read_dir, read_to_string, write},
+    path::PathBuf,
+};
+use toml::{Table, Value};
+
+// Extension of saved job files in the jobs directory
+const JOB_EXTENSION: &str = "toml";
+
+// A saved job: the options of a `tessera` run, and the directory it was saved from, which relative paths among its
+// options are resolved against
+struct Job {
+    directory: PathBuf,
+    args: Vec<String>,
+}
+
+// Runs `tessera job`, which saves the options of recurring runs (e.g. a weekly team mosaic) under a name, to be
+// run again later with one short command.
+pub(crate) fn run(mut args: Arguments) -> Result<()> {
+    match args.subcommand()?.as_deref() {
+        Some("save") => save(args),
+        Some("run") => run_job(args),
+        Some("list") => list(),
+        Some(subcommand) => bail!("unknown subcommand `job {subcommand}`"),
+        None if args.contains(["-h", "--help"]) => {
+            println!(
+                "tessera job: save the options of a run under a name, and run them again later
+usage: tessera job save NAME OPTIONS...
+       tessera job run NAME [OPTIONS...]
+       tessera job list
+
+-h, --help           print this message
+
+`save` records OPTIONS (as given to `tessera`) and the current directory as the job NAME, replacing any job of that
+name. `run` runs a job from the directory it was saved in, with any extra OPTIONS added to its own (e.g.
+another `--burst` frame, or `--yes`). `list` lists the saved jobs and their options.
+Jobs are saved in {}.",
+                jobs_dir().map_or_else(
+                    |_| "the user configuration directory".to_owned(),
+                    |dir| dir.display().to_string()
+                )
+            );
+            Ok(())
+        }
+        None => bail!(
+            "expected a subcommand: `tessera job save`, `tessera job run`, or `tessera job list`"
+        ),
+    }
+}
+
+// Runs `tessera job save`
+fn save(mut args: Arguments) -> Result<()> {
+    let name: String = args.free_from_str()?;
+    let job_args = utf8_args(args.finish())?;
+    check_name(&name)?;
+    if job_args.is_empty() {
+        bail!("`tessera job save`: no options were given to save");
+    }
+    if job_args.first().is_some_and(|arg| !arg.starts_with('-')) {
+        bail!("`tessera job save`: only runs of `tessera` can be saved, not subcommands");
+    }
+
+    let job = Job {
+        directory: current_dir()?,
+        args: job_args,
+    };
+    let dir = jobs_dir()?;
+    create_dir_all(&dir)?;
+    let path = dir.join(format!("{name}.{JOB_EXTENSION}"));
+    let replaced = path.exists();
+    write(&path, job.to_toml()?)?;
+
+    log!(
+        "{} job `{name}` ({})",
+        if replaced { "replaced" } else { "saved" },
+        path.display()
+    );
+    Ok(())
+}
+
+// Runs `tessera job run`
+fn run_job(mut args: Arguments) -> Result<()> {
+    let name: String = args.free_from_str()?;
+    let extra_args = utf8_args(args.finish())?;
+    check_name(&name)?;
+
+    let path = jobs_dir()?.join(format!("{name}.{JOB_EXTENSION}"));
+    if !path.is_file() {
+        bail!("no job named `{name}` was saved; see `tessera job list`");
+    }
+    let job =
+        Job::read(&read_to_string(&path)?).with_context(|| format!("`{}`", path.display()))?;
+
+    set_current_dir(&job.directory).with_context(|| {
+        format!(
+            "job `{name}`: cannot enter the directory it was saved in, {}",
+            job.directory.display()
+        )
+    })?;
+    log!("running job `{name}`: tessera {}", command_line(&job.args));
+
+    let args = job
+        .args
+        .into_iter()
+        .chain(extra_args)
+        .map(OsString::from)
+        .collect();
+    crate::run(Arguments::from_vec(args)).inspect_err(write_error)
+}
+
+// Runs `tessera job list`
+fn list() -> Result<()> {
+    let dir = jobs_dir()?;
+    let mut jobs = Vec::new();
+    if dir.is_dir() {
+        for entry in read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == JOB_EXTENSION)
+                && let Some(name) = path.file_stem().and_then(|stem| stem.to_str())
+            {
+                let job = Job::read(&read_to_string(&path)?)
+                    .with_context(|| format!("`{}`", path.display()))?;
+                jobs.push((name.to_owned(), job));
+            }
+        }
+    }
+    jobs.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    if jobs.is_empty() {
+        println!("no jobs are saved in {}", dir.display());
+    }
+    for (name, job) in jobs {
+        println!(
+            "{name}: tessera {} (in {})",
+            command_line(&job.args),
+            job.directory.display()
+        );
+    }
+    Ok(())
+}
+
+impl Job {
+    // Writes the job as a TOML file, e.g. `directory = "/home/me/mosaics"` and `args = ["-p", "photos", ...]`,
+    // after a version line
+    fn to_toml(&self) -> Result<String> {
+        let Some(directory) = self.directory.to_str() else {
+            bail!("the current directory's path must be valid UTF-8 to save a job");
+        };
+
+        let mut toml = JOB_SCHEMA.line();
+        write!(toml, "\ndirectory = {}\nargs = [", toml_string(directory))?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                toml.push_str(", ");
+            }
+            toml.push_str(&toml_string(arg));
+        }
+        toml.push_str("]\n");
+        Ok(toml)
+    }
+
+    fn read(text: &str) -> Result<Self> {
+        if let Some(line) = text.lines().next() {
+            JOB_SCHEMA.check(line)?;
+        }
+
+        let table: Table = text.parse()?;
+        let Some(Value::String(directory)) = table.get("directory") else {
+            bail!("`directory` must be a string");
+        };
+        let Some(Value::Array(values)) = table.get("args") else {
+            bail!("`args` must be a list of strings");
+        };
+        let mut args = Vec::with_capacity(values.len());
+        for value in values {
+            let Value::String(arg) = value else {
+                bail!("`args` must be a list of strings");
+            };
+            args.push(arg.clone());
+        }
+
+        Ok(Self {
+            directory: PathBuf::from(directory),
+            args,
+        })
+    }
+}
+
+// Directory that saved jobs are kept in: "tessera/jobs" in the user configuration directory, which is
+// `%APPDATA%` on Windows, and `$XDG_CONFIG_HOME` or else `$HOME/.config` elsewhere
+fn jobs_dir() -> Result<PathBuf> {
+    let config_dir = if cfg!(windows) {
+        var_os("APPDATA").map(PathBuf::from)
+    } else {
+        var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    let Some(config_dir) = config_dir else {
+        bail!("cannot find the user configuration directory to keep saved jobs in");
+    };
+    Ok(config_dir.join("tessera").join("jobs"))
+}
+
+// Checks that a job name can be used as a file name on every platform
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.starts_with(['-', '.'])
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        bail!(
+            "invalid job name `{name}`; job names may only contain letters, digits, `-`, `_`, and `.`, \
+            and cannot start with `-` or `.`"
+        );
+    }
+    Ok(())
+}
+
+fn utf8_args(args: Vec<OsString>) -> Result<Vec<String>> {
+    args.into_iter()
+        .map(|arg| {
+            arg.into_string().map_err(|arg| {
+                anyhow::anyhow!("job options must be valid UTF-8: {}", arg.display())
+            })
+        })
+        .collect()
+}
+
+// Quotes a string as a TOML basic string
+fn toml_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => {
+                write!(quoted, "\\u{:04X}", u32::from(c)).unwrap();
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Formats a job's options as they would be typed in a shell, quoting the ones with spaces or quotes
+fn command_line(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+            {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            } else {
+                arg.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
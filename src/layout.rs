@@ -0,0 +1,199 @@
+use crate::InputImage;
+use anyhow::{Result, bail};
+use image::{
+    GrayImage, Rgb, RgbImage,
+    imageops::{FilterType, interpolate_bilinear, resize},
+};
+use rayon::prelude::*;
+use std::{f32::consts::SQRT_2, str::FromStr};
+
+// A square region of the output image that is covered by a single tile.
+// Coordinates and span are in units of input image pixels, so a cell with a span of 2
+// covers 2x2 pixels of the input image and 2x2 tiles' worth of the output image.
+pub(crate) struct Cell {
+    pub(crate) x: u32,
+    pub(crate) y: u32,
+    pub(crate) span: u32,
+    // Target color of the tile placed in this cell
+    pub(crate) color: Rgb<u8>,
+    // How this cell must be drawn regardless of color, if it is pinned
+    pub(crate) pinned: Option<Pinned>,
+}
+
+// How a pinned cell is drawn
+#[derive(Clone, Copy)]
+pub(crate) enum Pinned {
+    // A palette image, by index
+    Tile(usize),
+    // A tile of the cell's color
+    Solid,
+}
+
+// How cells are arranged in the output image, for `--layout`
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Layout {
+    // A grid of square tiles
+    #[default]
+    Square,
+    // A grid rotated by 45°, where each tile is a diamond
+    Diamond,
+    // Concentric rings of tiles around a center point (see `Radial`)
+    Rings,
+    // A spiral of tiles from a center point (see `Radial`)
+    Spiral,
+}
+
+impl FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "square" => Ok(Self::Square),
+            "diamond" => Ok(Self::Diamond),
+            "rings" => Ok(Self::Rings),
+            "spiral" => Ok(Self::Spiral),
+            _ => bail!("expected \"square\", \"diamond\", \"rings\", or \"spiral\""),
+        }
+    }
+}
+
+// Lays out one cell per pixel of the input image, in row-major order.
+pub(crate) fn grid_cells(image: &InputImage) -> Vec<Cell> {
+    image
+        .enumerate_pixels()
+        .map(|(x, y, px)| Cell {
+            x,
+            y,
+            span: 1,
+            color: *px,
+            pinned: None,
+        })
+        .collect()
+}
+
+// Lays out cells according to a depth map, where lighter pixels are nearer and darker pixels are farther away.
+// Each 2x2 block of input image pixels whose mean depth is farther than `threshold` (from 0.0 to 1.0)
+// is covered by a single cell with a span of 2; every other pixel gets a cell of its own.
+// The depth map is resized to the dimensions of the input image first.
+pub(crate) fn depth_cells(image: &InputImage, depth_map: &GrayImage, threshold: f32) -> Vec<Cell> {
+    let (width, height) = image.dimensions();
+    let depth_map = resize(depth_map, width, height, FilterType::Triangle);
+
+    let mut cells = Vec::new();
+
+    for block_y in (0..height).step_by(2) {
+        for block_x in (0..width).step_by(2) {
+            // Blocks along the right and bottom edges can be partial; these always get single-pixel cells
+            let is_full_block = block_x + 1 < width && block_y + 1 < height;
+            let block = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                .map(|(dx, dy)| (block_x + dx, block_y + dy))
+                .into_iter()
+                .filter(|&(x, y)| x < width && y < height);
+
+            if is_full_block {
+                let mean_depth = block
+                    .clone()
+                    .map(|(x, y)| f32::from(depth_map.get_pixel(x, y)[0]))
+                    .sum::<f32>()
+                    / (4. * f32::from(u8::MAX));
+
+                if mean_depth < threshold {
+                    let mut sums = [0u32; 3];
+                    for (x, y) in block {
+                        for (sum, &component) in sums.iter_mut().zip(&image.get_pixel(x, y).0) {
+                            *sum += u32::from(component);
+                        }
+                    }
+
+                    cells.push(Cell {
+                        x: block_x,
+                        y: block_y,
+                        span: 2,
+                        color: Rgb(sums.map(|sum| ((sum + 2) / 4) as u8)),
+                        pinned: None,
+                    });
+                    continue;
+                }
+            }
+
+            cells.extend(block.map(|(x, y)| Cell {
+                x,
+                y,
+                span: 1,
+                color: *image.get_pixel(x, y),
+                pinned: None,
+            }));
+        }
+    }
+
+    cells
+}
+
+// With `--layout diamond`, matching and rendering work as usual in a grid rotated by 45°, where cells are still squares,
+// and only the rendered image is rotated back, turning each tile into a diamond. A point (x, y) of the unrotated
+// grid, in cells, is at (u, v) = ((x + y) / √2, (y - x + width) / √2) in the rotated grid, which covers the bounding
+// box of the rotated input image. Both grids have cells of the same size, so the tile count barely changes.
+
+// Resamples the input image (with one pixel per cell of the unrotated grid) to one pixel per cell of the rotated grid,
+// taking the color at the center of each diamond.
+pub(crate) fn diamond_grid(image: &InputImage) -> InputImage {
+    let (width, height) = image.dimensions();
+    let size = ((width + height) as f32 / SQRT_2).ceil() as u32;
+    let max_x = width as f32 - 1.;
+    let max_y = height as f32 - 1.;
+
+    InputImage::from_fn(size, size, |u, v| {
+        let (x, y) = unrotate(u as f32 + 0.5, v as f32 + 0.5, width as f32);
+        // Diamonds along the edges are partly outside the input image, so their color is taken from the nearest edge
+        interpolate_bilinear(
+            image,
+            (x - 0.5).clamp(0., max_x),
+            (y - 0.5).clamp(0., max_y),
+        )
+        .unwrap()
+    })
+}
+
+// Lays out one cell per pixel of `diamond_grid`'s image, leaving out cells entirely outside the output image,
+// which is `width` by `height` cells before rotation.
+pub(crate) fn diamond_cells(image: &InputImage, width: u32, height: u32) -> Vec<Cell> {
+    let (width, height) = (width as f32, height as f32);
+    grid_cells(image)
+        .into_iter()
+        .filter(|cell| {
+            let (u, v) = (cell.x as f32, cell.y as f32);
+            // Bounds of the diamond, from its left, right, top, and bottom corners
+            let (left, _) = unrotate(u, v + 1., width);
+            let (right, _) = unrotate(u + 1., v, width);
+            let (_, top) = unrotate(u, v, width);
+            let (_, bottom) = unrotate(u + 1., v + 1., width);
+            right > 0. && left < width && bottom > 0. && top < height
+        })
+        .collect()
+}
+
+// Rotates the image rendered in `diamond_grid`'s rotated grid back, cropping it to the output image
+// (`width` by `height` pixels). Tiles are sampled bilinearly, which smooths the diagonal edges between them.
+pub(crate) fn diamond_image(rotated_image: &RgbImage, width: u32, height: u32) -> RgbImage {
+    let max = rotated_image.width() as f32 - 1.;
+    let mut image = RgbImage::new(width, height);
+
+    image.par_enumerate_pixels_mut().for_each(|(x, y, px)| {
+        // The same rotation as for cells, in units of pixels rather than cells
+        let u = (x + y + 1) as f32 / SQRT_2;
+        let v = (y as f32 - x as f32 + width as f32) / SQRT_2;
+        *px = interpolate_bilinear(
+            rotated_image,
+            (u - 0.5).clamp(0., max),
+            (v - 0.5).clamp(0., max),
+        )
+        .unwrap();
+    });
+
+    image
+}
+
+// Converts a point of the rotated grid to the unrotated grid, whose width is `width`.
+fn unrotate(u: f32, v: f32, width: f32) -> (f32, f32) {
+    ((u - v) / SQRT_2 + width / 2., (u + v) / SQRT_2 - width / 2.)
+}
@@ -0,0 +1,92 @@
+use anyhow::{Result, bail};
+use std::{
+    fmt::Arguments,
+    fs::File,
+    io::Write,
+    path::Path,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+
+// File that every message is also written to, for `--log-file`
+static LOG_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+// How numbers are formatted in messages and reports, for `--stats-format`
+static STATS_FORMAT: OnceLock<StatsFormat> = OnceLock::new();
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum StatsFormat {
+    // Counts with thousands separators (e.g. "1,234,567") and sizes in binary units (e.g. "1.5 GiB")
+    #[default]
+    Human,
+    // Plain numbers (e.g. "1234567" and "1610612736 B"), for scripts parsing the output
+    Raw,
+}
+
+impl FromStr for StatsFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "human" => Ok(Self::Human),
+            "raw" => Ok(Self::Raw),
+            _ => bail!("expected \"human\" or \"raw\""),
+        }
+    }
+}
+
+pub(crate) fn set_stats_format(format: StatsFormat) {
+    STATS_FORMAT.get_or_init(|| format);
+}
+
+pub(crate) fn stats_format() -> StatsFormat {
+    STATS_FORMAT.get().copied().unwrap_or_default()
+}
+
+// Formats a count for display, e.g. "1,234,567".
+pub(crate) fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    if stats_format() == StatsFormat::Raw {
+        return digits;
+    }
+
+    let mut formatted = String::with_capacity(digits.len() * 4 / 3);
+    for (idx, digit) in digits.chars().enumerate() {
+        if idx > 0 && (digits.len() - idx).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+// Writes a line of human-readable output (progress, warnings, and summaries) to stderr, and to the log file if any.
+// Nothing is logged to stdout, so output images and reports piped from stdout are never mixed with messages.
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::logging::write_message(format_args!($($arg)*))
+    };
+}
+pub(crate) use log;
+
+// Starts copying every message to a file, replacing its contents.
+pub(crate) fn open_log_file(path: &Path) -> Result<()> {
+    let file = File::create(path)?;
+    LOG_FILE.get_or_init(|| Mutex::new(file));
+    Ok(())
+}
+
+pub(crate) fn write_message(message: Arguments<'_>) {
+    eprintln!("{message}");
+    if let Some(file) = LOG_FILE.get() {
+        // Failing to write to the log file shouldn't stop the run it is logging
+        writeln!(file.lock().unwrap(), "{message}").ok();
+    }
+}
+
+// Writes the error that ended a run to the log file, if any; the error itself is printed to stderr on exit.
+pub(crate) fn write_error(error: &anyhow::Error) {
+    if let Some(file) = LOG_FILE.get() {
+        writeln!(file.lock().unwrap(), "Error: {error:#}").ok();
+    }
+}
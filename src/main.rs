@@ -1,198 +1,2366 @@
-#![feature(array_chunks)]
+mod animation;
+mod bench;
+mod burst;
+mod cache;
+mod cell_mask;
+mod color;
+mod colorspace;
+mod compose;
+mod compression;
+mod curves;
+mod cvd;
+mod debug;
+mod decoder;
+mod demo;
+mod diff;
+mod downscale;
+mod draw;
+mod edit;
+mod environment;
+mod focus;
+mod frame;
+mod geometry;
+mod glyphs;
+mod harmonize;
+mod hints;
+mod hook;
+mod hot;
+mod inspect;
+mod job;
+mod layout;
+mod logging;
+mod median_cut;
+mod memory;
+mod mosaic;
+mod order;
+mod output;
+mod overlay;
+mod pack;
+mod palette;
+mod paths;
+mod pins;
+mod pixel;
+mod placements;
+mod prescale;
+mod quality;
+mod radial;
+mod remainder;
+mod report;
+mod rng;
+mod schema;
+mod shard;
+mod sharpen;
+mod softproof;
+mod sparse;
+mod stage;
+mod stickers;
+mod story;
+mod template;
+mod theme;
+mod tone;
+mod tui;
+mod validate;
+mod warm;
+mod watch;
+mod weights;
+mod xmp;
 
+use animation::AnimatedFrames;
 use anyhow::{Result, bail};
-use fast_image_resize::{FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer, images::Image};
-use foldhash::{HashMap, HashMapExt};
-use image::{GenericImageView, ImageReader, Pixel, Rgb, RgbImage, RgbaImage};
-use kiddo::{ImmutableKdTree, SquaredEuclidean};
+use burst::merge_burst;
+use cache::{TileCache, format_size, parse_size};
+use cell_mask::CellMask;
+use color::parse_hex_color;
+use colorspace::OutputColorSpace;
+use curves::{CurveSpace, Curves};
+use cvd::{parse_deficiencies, simulate};
+use debug::DebugOutput;
+use decoder::{DEFAULT_RECENT_TILES_SIZE, TileDecoder};
+use downscale::GridFilter;
+use draw::{Shadow, parse_shadow_offset};
+use focus::{Focus, focal_point};
+use frame::{Frame, FrameStyle};
+use geometry::{Geometry, grid_dimensions, parse_tile_sizes};
+use glyphs::{Charset, Glyphs, TextOutput};
+use harmonize::Harmonizer;
+use hints::PaletteHints;
+use hook::CompletionHook;
+use hot::HotPalette;
+use image::{
+    GenericImageView, ImageReader, Pixel, Rgb, RgbImage, RgbaImage,
+    imageops::{FilterType, resize},
+};
+use layout::{Cell, Layout, depth_cells, diamond_cells, diamond_grid, diamond_image, grid_cells};
+use logging::{StatsFormat, format_count, log, open_log_file, set_stats_format, write_error};
+use memory::report_memory;
+use mosaic::{
+    MAX_QUANTIZED_COLOR_COUNT, MatchOptions, Quantizer, Records, VarietyNoise, band_memory, dither,
+    dither_to_distinct_count, perturb, posterize, render, render_image,
+};
+use order::{CellOrder, rank_cells};
+use output::{Outputs, TwoPass, check_output_path, check_profile_support, save_image};
+use overlay::{GRID_OVERLAY_SUFFIX, GridOverlay, burn_in, grid_layer};
+use pack::{PACK_EXTENSION, is_pack, load_pack, write_pack};
+use palette::{
+    CROP_ZOOM, LoadOptions, LoadedPalette, Palette, Preference, TileSet, UpscalePolicy,
+    filter_images, find_images, load_descriptors, load_images_for_sizes,
+};
 use pico_args::Arguments;
-use quantette::{ColorSpace, ImagePipeline};
-use std::path::PathBuf;
-use walkdir::WalkDir;
+use pins::{apply_pins, load_pins};
+use placements::Placements;
+use quality::QualityFilters;
+use radial::Radial;
+use rayon::ThreadPoolBuilder;
+use remainder::{FitRemainder, RemainderFill};
+use report::{ReportContents, RunReport};
+use shard::Shard;
+use sharpen::{Sharpener, TileEdges};
+use softproof::{SOFTPROOF_SUFFIX, Softproof};
+use sparse::{CANVAS_LAYER_SUFFIX, Sparse, TILES_LAYER_SUFFIX, sparse_layers};
+use stage::{Resume, Stage, encode, read_image_list, write_image_list};
+use std::{
+    fs::{read_to_string, rename},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use stickers::{PageSize, save_sheet, sticker_sheets};
+use story::{apply_story, load_story};
+use template::{expand_output_paths, expand_tile_size};
+use theme::{apply_theme, load_theme};
+use tone::tone_curve;
+use watch::Watch;
+use weights::load_weights;
+use xmp::{sidecar_path, write_sidecar};
 
 type TileImage = RgbaImage;
 type InputImage = RgbImage;
+type TilePixel = <TileImage as GenericImageView>::Pixel;
+type InputPixel = <InputImage as GenericImageView>::Pixel;
 
-const TILE_PIXEL_SIZE: usize = size_of::<<TileImage as GenericImageView>::Pixel>(); // 4
-const TILE_PIXEL_COMPONENT_MAX_INT: u8 =
-    <<TileImage as GenericImageView>::Pixel as Pixel>::Subpixel::MAX; // 255
+const TILE_PIXEL_SIZE: usize = size_of::<TilePixel>(); // 4
+const TILE_PIXEL_COMPONENT_MAX_INT: u8 = <TilePixel as Pixel>::Subpixel::MAX; // 255
 const TILE_PIXEL_COMPONENT_MAX: f32 = TILE_PIXEL_COMPONENT_MAX_INT as f32; // 255.0
-const INPUT_PIXEL_COMPONENT_MAX: f32 =
-    <<InputImage as GenericImageView>::Pixel as Pixel>::Subpixel::MAX as f32; // 255.0
-
-// Resizes the input image to the specified dimensions via triangle/bilinear sampling, producing a new image as output.
-fn resize_image(image: RgbaImage, new_width: u32, new_height: u32) -> Result<Image<'static>> {
-    let (width, height) = image.dimensions();
-    let image = Image::from_vec_u8(width, height, image.into_vec(), PixelType::U8x4)?;
-    let mut resized_image = Image::new(new_width, new_height, PixelType::U8x4);
-
-    Resizer::new().resize(
-        &image,
-        &mut resized_image,
-        &ResizeOptions::default().resize_alg(ResizeAlg::Interpolation(FilterType::Bilinear)),
-    )?;
-
-    Ok(resized_image)
+const INPUT_PIXEL_COMPONENT_MAX: f32 = <InputPixel as Pixel>::Subpixel::MAX as f32; // 255.0
+
+// Number of pixels in an output image above which `--yes` is required, since rendering and writing such an image
+// takes a long time and a lot of disk space (about 2.1 gigapixels)
+const CONFIRMED_OUTPUT_PIXELS: u64 = 1 << 31;
+
+// Number of cells in the grid below which a warning is given, since so few tiles show little of the input image
+// (e.g. a tiny icon rendered with one cell per pixel)
+const MIN_CELL_COUNT: u64 = 64;
+
+// File extensions of palette images that are processed by default: those of every format this build can decode,
+// so palette images in formats left out by Cargo features are skipped rather than failing the run
+fn default_extensions() -> Vec<String> {
+    [
+        ("avif", cfg!(feature = "avif")),
+        ("jpeg", cfg!(feature = "jpeg")),
+        ("jpg", cfg!(feature = "jpeg")),
+        ("png", true),
+        ("webp", cfg!(feature = "webp")),
+    ]
+    .into_iter()
+    .filter(|&(_, enabled)| enabled)
+    .map(|(extension, _)| extension.to_owned())
+    .collect()
+}
+
+// Parses a comma-separated list of file extensions (e.g. "jpg,png").
+// If the list starts with "+", the extensions are added to the default list instead of replacing it.
+fn parse_extensions(s: &str) -> Result<Vec<String>> {
+    let (mut extensions, list) = match s.strip_prefix('+') {
+        Some(list) => (default_extensions(), list),
+        None => (Vec::new(), s),
+    };
+
+    for ext in list.split(',') {
+        let ext = ext.trim().trim_start_matches('.');
+        if ext.is_empty() {
+            bail!("extension list contains an empty entry");
+        }
+        extensions.push(ext.to_owned());
+    }
+
+    Ok(extensions)
+}
+
+// Parses a comma-separated list of tags (e.g. "beach,summer"), for `--tags`.
+fn parse_tags(s: &str) -> Result<Vec<String>> {
+    let mut tags = Vec::new();
+    for tag in s.split(',') {
+        let tag = tag.trim();
+        if tag.is_empty() {
+            bail!("tag list contains an empty entry");
+        }
+        tags.push(tag.to_owned());
+    }
+    Ok(tags)
 }
 
-// Converts a (R, G, B) triple in linear sRGB space (i.e. every component's value is from 0.0 to 1.0)
-// to its corresponding (L, a, b) triple in Oklab space.
-// From https://bottosson.github.io/posts/oklab/
-fn linear_srgb_to_oklab(r: f32, g: f32, b: f32) -> [f32; 3] {
-    let lp = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
-    let mp = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
-    let sp = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+// Inserts a suffix between a path's file stem and extension, e.g. "out.png" becomes "out.suffix.png".
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_owned();
+    file_name.push(".");
+    file_name.push(suffix);
 
-    let l = 0.2104542553 * lp + 0.7936177850 * mp - 0.0040720468 * sp;
-    let a = 1.9779984951 * lp - 2.4285922050 * mp + 0.4505937099 * sp;
-    let b = 0.0259040371 * lp + 0.7827717662 * mp - 0.8086757660 * sp;
+    if let Some(ext) = path.extension() {
+        file_name.push(".");
+        file_name.push(ext);
+    }
 
-    [l, a, b]
+    path.with_file_name(file_name)
 }
 
 fn main() -> Result<()> {
-    // Parse and validate input arguments
     let mut args = Arguments::from_env();
 
+    match args.subcommand()?.as_deref() {
+        Some("bench") => bench::run(args),
+        Some("cache") => cache::run(args),
+        Some("demo") => demo::run(args),
+        Some("compose") => compose::run(args),
+        Some("diff") => diff::run(args),
+        Some("edit") => edit::run(args),
+        Some("index") => pack::run(args),
+        Some("inspect") => inspect::run(args),
+        Some("job") => job::run(args),
+        Some("merge") => shard::run(args),
+        Some("tui") => tui::run(args),
+        Some("warm") => warm::run(args),
+        Some(subcommand) => bail!("unknown subcommand `{subcommand}`"),
+        None => run(args).inspect_err(write_error),
+    }
+}
+
+// Generates a mosaic from a palette directory and an input image, then runs the `--on-complete` command, if any.
+fn run(mut args: Arguments) -> Result<()> {
+    // Parse and validate input arguments
     if args.contains(["-h", "--help"]) {
         println!(
             "tessera: image mosaic generator
+usage: tessera [OPTIONS]
+       tessera bench [OPTIONS]   measure the throughput of each pipeline stage on synthetic data
+       tessera demo [OPTIONS] DIR
+                                 generate a mosaic and its placements file from a synthetic palette and input image
+       tessera warm [OPTIONS]    resize palette images into a tile cache ahead of time, for --cache-dir
+       tessera cache [OPTIONS]   inspect, shrink, or clear a tile cache
+       tessera inspect [OPTIONS] summarize a palette by clustering its images by color
+       tessera index export [OPTIONS] PACK
+                                 bundle resized palette images into a palette pack (.tsrpack), for use with -p
+       tessera compose [OPTIONS] PLACEMENTS
+                                 draw an output image from a (possibly edited) --placements file
+       tessera edit [OPTIONS] PLACEMENTS
+                                 interactively swap palette images in a --placements file for their alternates
+       tessera tui [OPTIONS]     choose the options of a run interactively, with a preview of the mosaic in the terminal
+       tessera diff [OPTIONS] PLACEMENTS_A PLACEMENTS_B
+                                 draw an image highlighting the cells whose palette image changed between two runs
+       tessera merge [OPTIONS] SHARD...
+                                 stack the output images of shards rendered with --shard into one output image
+       tessera job save NAME OPTIONS...
+                                 save the options of a run as a named job, to run later with `tessera job run NAME`
+
 -h, --help           print this message
--p, --palette-dir    path to directory containing images to tile the output image with
--s, --tile-size      width and height of each tile in the output image, in pixels
+--validate           check the color conversions, tile averaging, and color distances against built-in reference values,
+                     reporting each deviation, then exit; fails if any is out of tolerance
+-p, --palette-dir    path to directory containing images to tile the output image with;
+                     paths matching patterns in the directory's .tesseraignore file (gitignore syntax) are excluded;
+                     may instead be a palette pack (.tsrpack) from `tessera index export`
+--palette-filter     path of a file listing the palette images to use, one path (relative to the palette directory) per line,
+                     e.g. from `tessera inspect --suggest-prune --apply`; other palette images are excluded
+--tags               comma-separated list of tags; only palette images given any of them by a palette.toml are used
+--glyphs             path of a font (TrueType or OpenType) whose characters, drawn light on black, are the palette
+                     instead of a palette directory, for text-art mosaics matched on each glyph's coverage
+--charset            characters of the --glyphs font to use: \"ascii\", \"braille\", \"cjk\", or \"chars:\" followed by
+                     the characters themselves (e.g. \"chars: .:-=+*#%@\"); default is \"ascii\"
+--text               path of a plain-text file to write with the character placed in each cell, one line per row, for --glyphs
+-s, --tile-size      width and height of each tile in the output image, in pixels, e.g. \"64\" or \"64x96\";
+                     a comma-separated list of tile sizes with the same aspect ratio (e.g. \"32,64,128\") writes an output
+                     image for each from a single pass over the palette, suffixed with its tile size (e.g. \"out.32x32.png\")
 -d, --dither         \"true\" to enable or \"false\" to disable dithering when processing input image; default is \"true\"
--i, --input          input image path; input will be read from this location
--o, --output         output image path; output will be written to this location"
+--quantizer          how the colors the input image is quantized to before dithering are chosen: \"quantette\" (Wu's method),
+                     \"median-cut\", or \"none\" (no quantization or dithering, like -d false); default is \"quantette\"
+--max-cell-colors    target number of distinct cell colors (e.g. 4096); the input image is quantized to the most colors
+                     (up to 256) that keep it within the target, counted after --noise, bounding the cost of matching
+--curves             tone curves applied to the input image before matching, as \"channel=input:output,...\" separated by \";\",
+                     e.g. \"rgb=0:0,128:150,255:255;b=0:20,255:255\"; points are interpolated linearly
+--curve-space        color space --curves is applied in: \"rgb\" (channels r, g, b, or rgb for all three; 0 to 255)
+                     or \"oklab\" (channels l from 0 to 1, a, and b); default is \"rgb\"
+--theme              path of a file listing colors (hex or GIMP palette) that the input image is mapped to before matching,
+                     e.g. a brand color set or a retro console palette
+--noise              amplitude of a blue-noise-like perturbation of each cell's lightness before matching (e.g. 0.02),
+                     which breaks up contours in smooth gradients; most useful with dithering disabled
+--posterize-l        quantize each cell's lightness into this many bands before matching, for stylized, poster-like mosaics
+                     with strong tonal separation; hue and chroma are kept
+--tone-match         after composing the output image, correct its lightness with a global tone curve so that, seen at thumbnail
+                     size, it matches the tones of the input image; the output image is written only once it is corrected
+--save-quantized     path to write the processed input image to, with one pixel per cell in palette-ready colors
+                     (after --curves, --theme, --posterize-l, dithering, and --noise), e.g. for pixel-art workflows
+--harmonize          soften seams between tiles by shifting each tile's pixels near its edges toward its neighbors' facing
+                     edge pixels, from 0 (no change) to 1 (facing edge pixels meet at their average), e.g. 0.5
+--harmonize-width    width of the band of pixels along each edge shifted by --harmonize, in pixels;
+                     default is a quarter of the smaller tile dimension
+--sharpen            amount of unsharp masking applied to the output image, to restore detail lost in shrinking palette
+                     images to tiles, e.g. 0.5 (0 leaves it unchanged)
+--sharpen-radius     radius (standard deviation) of the blur that --sharpen subtracts, in pixels; default is 1
+--sharpen-edges      only sharpen near the edges between tiles, fading out over twice --sharpen-radius
+-e, --max-error      maximum color distance between a pixel and its tile; pixels without a close enough tile are drawn as solid color
+-b, --blend          color distance above which a pixel is drawn as a blend of its two nearest tiles
+--match-precision    size of the Oklab buckets (e.g. 0.005) that cell colors are grouped into when matching, so nearly identical
+                     colors share one search for their nearest tile; speeds up matching noisy input images at a small cost in accuracy
+--variety            choose each cell's tile among its this many nearest tiles, so large regions of one color
+                     don't repeat a single tile; default is 1
+--variety-noise      how the choice among nearest tiles is made for --variety: \"white\" (independently for each cell)
+                     or \"blue\" (spacing repeated tiles evenly over the grid); default is \"white\"
+--tie-epsilon        color distance (e.g. 0.005) within which other tiles tie with a cell's tile; each cell places one of
+                     the tied tiles chosen from its position and --seed, so edits elsewhere never reshuffle it
+--max-usage-share    largest share of the cells that any one palette image is placed in, e.g. 0.05; cells whose tile has
+                     reached it get the nearest tile that hasn't
+--rotate-jitter      largest angle, in degrees, that each tile is rotated by (either way), chosen at random for each cell,
+                     to mimic hand-laid tesserae; e.g. 4
+--cell-mask          path to a mask image that every tile is cut to, e.g. a star, by its alpha channel or else its lightness;
+                     the --grout color shows where it is transparent (or black)
+--grout              hex color showing around tiles rotated by --rotate-jitter and through --cell-mask, e.g. \"#d8d2c4\";
+                     default is \"#000000\"
+--shadow             opacity of a soft shadow that each tile casts on the grout around it, from 0 to 1, e.g. 0.5;
+                     needs --rotate-jitter or --cell-mask
+--shadow-radius      blur radius of the edges of --shadow, in pixels; default is a sixteenth of the tile width
+--shadow-offset      offset of --shadow from its tile, as \"X,Y\" in pixels (positive toward the bottom right);
+                     default is the shadow radius both ways
+--pins               path of a file pinning palette images to grid cells regardless of color, one \"column,row,path\" line each
+--story              path of a file asking for palette images to appear in broad regions of the output image, one
+                     \"region,path\" line each, with regions such as \"top\", \"center\", or \"bottom-left\"; each image is
+                     placed once in its region where it costs the least color accuracy, if within --story-margin
+--story-margin       largest color distance beyond a cell's nearest tile that a --story image may cost; default is 0.05
+--weights            path of a file assigning desirability weights to palette images, one \"path,weight\" line each;
+                     near-ties in matching are broken in favor of higher-weighted images
+--weight-margin      color distance from the nearest tile within which tiles count as a near-tie, for --weights
+                     and for preferring palette images in the same orientation as non-square tiles; default is 0.02
+--equalize           color distance (e.g. 0.01) by which tiles are penalized when ranking candidates each time the number of
+                     near-identical palette images sharing their color doubles, so crowded colors don't shadow rarer ones
+-x, --extensions     comma-separated list of file extensions of palette images to process, replacing the default list;
+                     prefix the list with \"+\" to add to the default list instead; default is \"{}\"
+-a, --alpha-weighted compute the average color of each tile from its visible pixels only, weighted by opacity
+--normalize-exposure normalize the mean lightness of each tile to the mean across the palette
+--normalize-contrast also normalize the lightness contrast of each tile; implies --normalize-exposure
+--source-crops       draw each cell with the crop of its palette image (zoomed in 2x) whose color is nearest to the cell's,
+                     instead of the whole image, for more variety from small palettes
+-t, --threads        number of threads to use; default is the number of logical CPUs
+--cache-dir          directory of a cache of resized palette images, which is reused and added to;
+                     the cache is not used with --reject-blurry, --reject-low-contrast, or --reject-uniform
+--cache-max-size     maximum size of the --cache-dir cache, e.g. \"2G\"; the least recently used entries are evicted
+--descriptors-only   keep only each palette image's average color in memory, decoding tiles again whenever they are drawn,
+                     for palettes too large for their tiles to fit in memory; fastest with --cache-dir
+--compress-tiles     keep tiles compressed in memory, decompressing them whenever they are drawn, to save memory
+--recent-tiles-size  maximum size of the recently drawn tiles kept with --descriptors-only or --compress-tiles, e.g. \"256M\";
+                     default is \"64M\"
+--debug-dir          directory to write intermediate outputs to, for understanding how the output image was produced
+--memory-report      report the process's resident memory (current and peak) and the sizes of major allocations
+                     after each stage, for finding what to tune when running out of memory
+--report             path of a self-contained HTML report to write, with the input and output images side by side,
+                     a heatmap of color error, palette image usage, gamut coverage, and timings
+--layout             arrangement of tiles: \"square\" for a grid, \"diamond\" for a grid rotated by 45°, where each tile
+                     is drawn as a diamond, or \"rings\" or \"spiral\" for tiles bent along concentric rings or a spiral
+                     around --layout-center; default is \"square\"
+--layout-center      center of --layout rings or spiral, as fractions of the output image's width and height,
+                     e.g. \"0.5,0.35\" for a face above the middle, or \"auto\" for the input image's most salient
+                     region; default is \"0.5,0.5\"
+--depth-map          path to a depth map of the input image, where lighter is nearer; farther regions get tiles twice as large
+--depth-threshold    depth (0.0 is farthest, 1.0 is nearest) below which regions get larger tiles; default is 0.5
+--sparse             lightness (0.0 to 1.0, in Oklab) below which cells of the input image receive tiles; every other cell is
+                     drawn as --canvas-color, for text- or logo-shaped mosaics from a dark silhouette on a light background
+--sparse-mask        path to a mask image stretched over the grid; only cells where it is lighter than 50% gray receive tiles,
+                     and every other cell is drawn as --canvas-color
+--canvas-color       hex color of the cells left without tiles by --sparse or --sparse-mask; default is \"#ffffff\"
+--split-layers       with --sparse or --sparse-mask, also write the tiled cells and the rest of the output image as separate
+                     transparent PNG layers, e.g. \"out.tiles.png\" and \"out.canvas.png\" for \"out.png\"
+--cvd-preview        comma-separated list of color vision deficiencies (protan, deutan, tritan) to simulate;
+                     a copy of the output image is written for each, e.g. \"out.protan.png\" for \"out.png\"
+--softproof          path of a printer's ICC profile; a copy of the output image simulating how it looks when printed
+                     with it is written, e.g. \"out.softproof.png\" for \"out.png\", with colors it can't print marked in magenta
+--grid-overlay       also write lines along the edges of every cell, with rows and columns numbered as in --placements files,
+                     e.g. to \"out.grid.png\" for \"out.png\": \"burn\" (over a copy of the output image) or \"layer\"
+                     (alone on a transparent PNG image, to layer over the output image)
+--sticker-sheet      path of a PNG image to write a printable sheet to, with a sticker of every tiled cell labeled with its row
+                     and column, to cut out and assemble by hand; numbered, e.g. \"stickers.1.png\", if it takes several pages
+--sticker-dpi        resolution that the sticker sheet is printed at, in pixels per inch; default is 300
+--sticker-page       page size of the sticker sheet: \"a4\" or \"letter\"; default is \"a4\"
+--reject-blurry      reject palette images whose Laplacian variance (a measure of sharpness) is below this value
+--reject-low-contrast
+                     reject palette images whose standard deviation of lightness is below this value
+--reject-uniform     reject palette images whose mean color distance from their average color is below this value
+--min-tile-source    reject palette images whose width or height is smaller than this value, in pixels
+--allow-upscale      keep palette images smaller than --min-tile-source, only listing them instead of rejecting them
+--upscale-policy     how to handle palette images smaller than a tile: \"bilinear\", \"nearest\", \"lanczos\", or \"skip\";
+                     default is \"bilinear\"
+--max-source-megapixels
+                     largest palette image to decode at full size, in megapixels, e.g. 50; larger PNG images are
+                     downsampled while they are decoded, and larger images in other formats are rejected
+--animated           how animated (APNG or WebP) palette images become tiles: their \"first\" frame, the \"representative\" frame
+                     nearest their average color, an \"average\" of their frames, or \"each\" frame as a palette image of its own;
+                     default is \"first\"
+--placements         path of a CSV file to write listing the palette image placed in each cell, with its color distance
+--placement-alternates
+                     number of alternate palette images to list for each cell in the --placements file; default is 0
+--order              order of cells to record in an `order` column of the --placements file, for building up the mosaic
+                     cell by cell (see `tessera compose --cell-count`): \"row\", \"column\", \"random\", \"center-out\", or \"mask\"
+--order-center       point that --order center-out places cells outward from, as fractions of the output image's width and
+                     height (e.g. \"0.5,0.35\"), or \"auto\" for the input image's most salient region; default is \"0.5,0.5\"
+--order-mask         path to a grayscale priority image for --order mask, where lighter cells are placed first
+--seed               seed for randomized options (--order random, --variety, --tie-epsilon, and --rotate-jitter), recorded in the --placements file; default is 0
+--frame              draw a frame around the output image: a hex color (e.g. \"#1e1e1e\"), \"tiles\" for palette tiles, or an image path
+--frame-width        width of the frame, in pixels; default is the tile width
+--xmp                write an XMP sidecar next to the output image summarizing the palette and the most-used palette images
+--grid               number of columns and rows of tiles, as an ImageMagick-style geometry (e.g. \"120x\", \"120x80\", \"50%\");
+                     by default, there is one tile per pixel of the input image
+--grid-filter        how the input image is shrunk to the --grid or --fit grid: \"average\" (blurring away features
+                     thinner than a tile) or \"detail\" (keeping thin features such as text strokes); default is \"average\"
+--fit                size of the output image, as an ImageMagick-style geometry relative to the input image (e.g. \"50%\", \"1920x1080\");
+                     the number of tiles is chosen to fit; cannot be used with --grid
+--fit-remainder      how the part of the --fit size that is not a whole number of tiles is filled: \"crop\" (a partial
+                     last row and column of tiles), \"pad\" (black bars around the tiles), or \"scale-last\" (the last row
+                     and column of tiles stretched to fit); by default, the output image is cut down to whole tiles
+--shard              render only one horizontal slice of the output image, as \"I/N\" for the Ith of N (e.g. \"2/4\"),
+                     to spread a large render across machines; stack the slices with `tessera merge`
+--stop-after         stop after a pipeline stage (\"scan\", \"index\", \"quantize\", \"match\", \"compose\", or \"encode\"),
+                     writing its artifact to the -o path instead of an output image
+--resume-from        resume from a pipeline stage's artifact, as \"STAGE:PATH\" (e.g. \"match:placements.csv\"),
+                     skipping that stage and every stage before it
+--yes                proceed even if the output image would be larger than 2 gigapixels
+-i, --input          input image path; input will be read from this location; not needed with --resume-from quantize or later
+--burst              path to another photo of the same scene as the input image, taken in a burst (e.g. on a phone);
+                     each is shifted to line up with the input image and averaged with it, for a cleaner input image
+                     with less noise; must be the same size as the input image; may be given more than once
+--two-pass           \"preview\" to write a quick preview of the output image, with each tile drawn as its target color, to every
+                     output path before rendering, replacing it with the output image once the output image is complete
+--on-complete        shell command to run when the run finishes, e.g. \"upload.sh {{output}}\"; \"{{output}}\" is replaced with the
+                     paths written, \"{{report}}\" with the --report path, and \"{{status}}\" with 0 on success or 1 on failure
+--watch              after rendering, keep checking the palette directory every this many seconds, and render again
+                     whenever palette images are added, removed, or changed, e.g. as photos arrive at an event; runs until stopped
+--watch-snapshots    with --watch, also copy the files written by each render to numbered snapshots (e.g. \"out.0001.png\")
+--log-file           path of a file to copy every message to, as well as stderr, for long unattended runs
+--stats-format       how numbers in messages and the --report are formatted: \"human\" (e.g. \"1,234,567\" and \"1.5 GiB\")
+                     or \"raw\" (plain numbers, e.g. \"1234567\" and \"1610612736 B\", for scripts); default is \"human\"
+-o, --output         output image path; output will be written to this location.
+                     may be given more than once to write the same output image in several formats, e.g. \"-o out.png -o out.jpg\";
+                     PPM outputs (\"out.ppm\") are flushed after each band of tiles, so progress can be inspected mid-run
+                     may contain {{stem}} (of the input image), {{date}}, {{tile_size}}, {{palette}} (its name), and {{seed}},
+                     e.g. \"{{stem}}-{{palette}}-s{{seed}}.png\", for self-describing names in parameter sweeps and saved jobs
+--output-colorspace  color space to convert output images to, embedding its ICC profile: \"srgb\", \"display-p3\", or \"adobe-rgb\";
+                     only PNG, JPEG, and WebP outputs can embed a profile; by default, outputs are sRGB without a profile",
+            default_extensions().join(",")
         );
         return Ok(());
     }
+    if args.contains("--validate") {
+        return validate::run();
+    }
+
+    // The log file is opened first, so any error in the other arguments is logged too
+    let log_file_path: Option<PathBuf> = args.opt_value_from_str("--log-file")?;
+    if let Some(path) = &log_file_path {
+        open_log_file(path)?;
+    }
+    let stats_format: Option<StatsFormat> = args.opt_value_from_str("--stats-format")?;
+    set_stats_format(stats_format.unwrap_or_default());
+    let on_complete: Option<CompletionHook> = args.opt_value_from_str("--on-complete")?;
+    let output_image_paths = expand_output_paths(args.values_from_str(["-o", "--output"])?, &args)?;
+    let report_path: Option<PathBuf> = args.opt_value_from_str("--report")?;
+    let watch_interval: Option<f64> = args.opt_value_from_str("--watch")?;
+    let watch_snapshots = args.contains("--watch-snapshots");
+    let thread_count: Option<usize> = args.opt_value_from_str(["-t", "--threads"])?;
+
+    if watch_interval.is_some_and(|interval| !interval.is_finite() || interval <= 0.) {
+        bail!("`--watch`: interval must be a positive number of seconds");
+    }
+    if watch_snapshots && watch_interval.is_none() {
+        bail!("`--watch-snapshots`: the palette directory is not being watched with `--watch`");
+    }
+    if thread_count == Some(0) {
+        bail!("`-t`/`--threads`: thread count cannot be zero");
+    }
+
+    // The global thread pool can only be set up once, so it is shared by every render with `--watch`
+    if let Some(thread_count) = thread_count {
+        ThreadPoolBuilder::new()
+            .num_threads(thread_count)
+            .build_global()?;
+    }
+
+    // With `--watch`, palette images are loaded through the watch's palette, so they stay loaded between renders
+    let render = |args: Arguments, hot_palette: Option<&mut HotPalette>| {
+        let mut written_paths = Vec::new();
+        let result = generate(
+            args,
+            output_image_paths.clone(),
+            report_path.as_deref(),
+            hot_palette,
+            &mut written_paths,
+        );
+        if let Some(hook) = &on_complete {
+            // The command also runs when the run fails, so it can report the failure
+            let hook_result = hook.run(&written_paths, report_path.as_deref(), result.is_ok());
+            match (&result, hook_result) {
+                (Ok(()), Err(err)) => return Err(err),
+                (Err(_), Err(err)) => log!("{err:#}"),
+                (_, Ok(())) => {}
+            }
+        }
+        result.map(|()| written_paths)
+    };
+    match watch_interval {
+        Some(interval) => Watch {
+            interval: Duration::from_secs_f64(interval),
+            snapshots: watch_snapshots,
+            ..Watch::default()
+        }
+        .run(&args, |args, hot_palette| render(args, Some(hot_palette))),
+        None => render(args, None).map(drop),
+    }
+}
 
-    let palette_dir_path: PathBuf = args.value_from_str(["-p", "--palette-dir"])?;
-    let tile_size: u32 = args.value_from_str(["-s", "--tile-size"])?;
+// Generates a mosaic from a palette directory and an input image, adding the path of each output (or stage artifact)
+// to `written_paths` once it is written. With several tile sizes, these are the paths suffixed with each tile size.
+// Palette images are loaded through `hot_palette`, if given, which keeps them loaded for later renders.
+fn generate(
+    mut args: Arguments,
+    output_image_paths: Vec<PathBuf>,
+    report_path: Option<&Path>,
+    hot_palette: Option<&mut HotPalette>,
+    written_paths: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let palette_dir_path: Option<PathBuf> = args.opt_value_from_str(["-p", "--palette-dir"])?;
+    let palette_filter_path: Option<PathBuf> = args.opt_value_from_str("--palette-filter")?;
+    let tags = args.opt_value_from_fn("--tags", parse_tags)?;
+    let glyphs_font_path: Option<PathBuf> = args.opt_value_from_str("--glyphs")?;
+    let charset: Option<Charset> = args.opt_value_from_str("--charset")?;
+    let text_path: Option<PathBuf> = args.opt_value_from_str("--text")?;
+    let tile_sizes = args.opt_value_from_fn(["-s", "--tile-size"], parse_tile_sizes)?;
+    let extensions = args
+        .opt_value_from_fn(["-x", "--extensions"], parse_extensions)?
+        .unwrap_or_else(default_extensions);
+    let alpha_weighted = args.contains(["-a", "--alpha-weighted"]);
+    let normalize_contrast = args.contains("--normalize-contrast");
+    let exposure_normalized = normalize_contrast || args.contains("--normalize-exposure");
+    let source_crops = args.contains("--source-crops");
     let dither_enabled: bool = args.opt_value_from_str(["-d", "--dither"])?.unwrap_or(true);
-    let input_image_path: PathBuf = args.value_from_str(["-i", "--input"])?;
-    let output_image_path: PathBuf = args.value_from_str(["-o", "--output"])?;
+    let quantizer: Option<Quantizer> = args.opt_value_from_str("--quantizer")?;
+    let max_cell_colors: Option<u32> = args.opt_value_from_str("--max-cell-colors")?;
+    let curves_spec: Option<String> = args.opt_value_from_str("--curves")?;
+    let curve_space: CurveSpace = args
+        .opt_value_from_str("--curve-space")?
+        .unwrap_or_default();
+    let theme_path: Option<PathBuf> = args.opt_value_from_str("--theme")?;
+    let noise_amplitude: Option<f32> = args.opt_value_from_str("--noise")?;
+    let posterize_bands: Option<u32> = args.opt_value_from_str("--posterize-l")?;
+    let tone_match = args.contains("--tone-match");
+    let quantized_path: Option<PathBuf> = args.opt_value_from_str("--save-quantized")?;
+    let harmonize_strength: Option<f32> = args.opt_value_from_str("--harmonize")?;
+    let harmonize_width: Option<u32> = args.opt_value_from_str("--harmonize-width")?;
+    let sharpen_amount: Option<f32> = args.opt_value_from_str("--sharpen")?;
+    let sharpen_radius: Option<f32> = args.opt_value_from_str("--sharpen-radius")?;
+    let sharpen_edges = args.contains("--sharpen-edges");
+    let max_error: Option<f32> = args.opt_value_from_str(["-e", "--max-error"])?;
+    let blend_threshold: Option<f32> = args.opt_value_from_str(["-b", "--blend"])?;
+    let match_precision: Option<f32> = args.opt_value_from_str("--match-precision")?;
+    let variety: Option<usize> = args.opt_value_from_str("--variety")?;
+    let variety_noise: Option<VarietyNoise> = args.opt_value_from_str("--variety-noise")?;
+    let tie_epsilon: Option<f32> = args.opt_value_from_str("--tie-epsilon")?;
+    let max_usage_share: Option<f32> = args.opt_value_from_str("--max-usage-share")?;
+    let rotate_jitter: Option<f32> = args.opt_value_from_str("--rotate-jitter")?;
+    let cell_mask_path: Option<PathBuf> = args.opt_value_from_str("--cell-mask")?;
+    let grout = args.opt_value_from_fn("--grout", parse_hex_color)?;
+    let shadow_opacity: Option<f32> = args.opt_value_from_str("--shadow")?;
+    let shadow_radius: Option<f32> = args.opt_value_from_str("--shadow-radius")?;
+    let shadow_offset = args.opt_value_from_fn("--shadow-offset", parse_shadow_offset)?;
+    let sparse_threshold: Option<f32> = args.opt_value_from_str("--sparse")?;
+    let sparse_mask_path: Option<PathBuf> = args.opt_value_from_str("--sparse-mask")?;
+    let canvas_color = args.opt_value_from_fn("--canvas-color", parse_hex_color)?;
+    let split_layers = args.contains("--split-layers");
+    let pins_path: Option<PathBuf> = args.opt_value_from_str("--pins")?;
+    let story_path: Option<PathBuf> = args.opt_value_from_str("--story")?;
+    let story_margin: Option<f32> = args.opt_value_from_str("--story-margin")?;
+    let weights_path: Option<PathBuf> = args.opt_value_from_str("--weights")?;
+    let weight_margin: f32 = args.opt_value_from_str("--weight-margin")?.unwrap_or(0.02);
+    let equalize_strength: Option<f32> = args.opt_value_from_str("--equalize")?;
+    let cache_dir_path: Option<PathBuf> = args.opt_value_from_str("--cache-dir")?;
+    let cache_max_size: Option<u64> = args.opt_value_from_fn("--cache-max-size", parse_size)?;
+    let descriptors_only = args.contains("--descriptors-only");
+    let compress_tiles = args.contains("--compress-tiles");
+    let recent_tiles_size: Option<u64> =
+        args.opt_value_from_fn("--recent-tiles-size", parse_size)?;
+    let debug_dir_path: Option<PathBuf> = args.opt_value_from_str("--debug-dir")?;
+    let memory_report = args.contains("--memory-report");
+    let depth_map_path: Option<PathBuf> = args.opt_value_from_str("--depth-map")?;
+    let depth_threshold: f32 = args.opt_value_from_str("--depth-threshold")?.unwrap_or(0.5);
+    let cvd_previews = args
+        .opt_value_from_fn("--cvd-preview", parse_deficiencies)?
+        .unwrap_or_default();
+    let softproof = args
+        .opt_value_from_str::<_, PathBuf>("--softproof")?
+        .map(|path| Softproof::load(&path))
+        .transpose()?;
+    let grid_overlay: Option<GridOverlay> = args.opt_value_from_str("--grid-overlay")?;
+    let sticker_sheet_path: Option<PathBuf> = args.opt_value_from_str("--sticker-sheet")?;
+    let sticker_dpi: u32 = args.opt_value_from_str("--sticker-dpi")?.unwrap_or(300);
+    let sticker_page: PageSize = args
+        .opt_value_from_str("--sticker-page")?
+        .unwrap_or(PageSize::A4);
+    let quality_filters = QualityFilters {
+        blurry: args.opt_value_from_str("--reject-blurry")?,
+        low_contrast: args.opt_value_from_str("--reject-low-contrast")?,
+        uniform: args.opt_value_from_str("--reject-uniform")?,
+    };
+    let min_tile_source: Option<u32> = args.opt_value_from_str("--min-tile-source")?;
+    let allow_upscale = args.contains("--allow-upscale");
+    let upscale_policy: UpscalePolicy = args
+        .opt_value_from_str("--upscale-policy")?
+        .unwrap_or_default();
+    let animated_frames: AnimatedFrames =
+        args.opt_value_from_str("--animated")?.unwrap_or_default();
+    let max_source_megapixels: Option<f64> = args.opt_value_from_str("--max-source-megapixels")?;
+    let placements_path: Option<PathBuf> = args.opt_value_from_str("--placements")?;
+    let placement_alternates: Option<usize> = args.opt_value_from_str("--placement-alternates")?;
+    let cell_order: Option<CellOrder> = args.opt_value_from_str("--order")?;
+    let order_center: Option<Focus> = args.opt_value_from_str("--order-center")?;
+    let layout: Layout = args.opt_value_from_str("--layout")?.unwrap_or_default();
+    let layout_center: Option<Focus> = args.opt_value_from_str("--layout-center")?;
+    let order_mask_path: Option<PathBuf> = args.opt_value_from_str("--order-mask")?;
+    let seed: Option<u64> = args.opt_value_from_str("--seed")?;
+    let frame_style: Option<FrameStyle> = args.opt_value_from_str("--frame")?;
+    let frame_width: Option<u32> = args.opt_value_from_str("--frame-width")?;
+    let xmp_enabled = args.contains("--xmp");
+    let grid: Option<Geometry> = args.opt_value_from_str("--grid")?;
+    let fit: Option<Geometry> = args.opt_value_from_str("--fit")?;
+    let grid_filter: Option<GridFilter> = args.opt_value_from_str("--grid-filter")?;
+    let fit_remainder: Option<FitRemainder> = args.opt_value_from_str("--fit-remainder")?;
+    let confirmed = args.contains("--yes");
+    let two_pass: Option<TwoPass> = args.opt_value_from_str("--two-pass")?;
+    let shard: Option<Shard> = args.opt_value_from_str("--shard")?;
+    let stop_after: Option<Stage> = args.opt_value_from_str("--stop-after")?;
+    let resume: Option<Resume> = args.opt_value_from_str("--resume-from")?;
+    let input_image_path: Option<PathBuf> = args.opt_value_from_str(["-i", "--input"])?;
+    let burst_paths: Vec<PathBuf> = args.values_from_str("--burst")?;
+    let output_color_space: Option<OutputColorSpace> =
+        args.opt_value_from_str("--output-colorspace")?;
 
-    if !palette_dir_path.is_dir() {
-        bail!("`-p`/`--palette-dir`: path does not point to a directory");
+    let resume_stage = resume.as_ref().map(|resume| resume.stage);
+    if let Some(resume) = &resume
+        && !resume.path.is_file()
+    {
+        bail!("`--resume-from`: path does not point to a file");
     }
-    if tile_size == 0 {
-        bail!("`-s`/`--tile-size`: tile size cannot be zero");
+    if let (Some(resume_stage), Some(stop_after)) = (resume_stage, stop_after)
+        && stop_after <= resume_stage
+    {
+        bail!("`--stop-after`: stage must come after the stage resumed from with `--resume-from`");
     }
-    if !input_image_path.is_file() {
-        bail!("`-i`/`--input`: path does not point to a file");
+    if output_image_paths.is_empty() {
+        bail!("`-o`/`--output`: at least one output path is required");
+    }
+    // Stopping before the `encode` stage writes the stage's artifact to the only output path instead
+    match stop_after {
+        Some(stage) if stage < Stage::Encode => {
+            if output_image_paths.len() > 1 {
+                bail!(
+                    "`--stop-after`: only one `-o`/`--output` path can be given, for the stage's artifact"
+                );
+            }
+            let path = &output_image_paths[0];
+            match stage {
+                Stage::Index if !is_pack(path) => {
+                    bail!(
+                        "`--stop-after index`: output path must have the extension `.{PACK_EXTENSION}`"
+                    );
+                }
+                Stage::Quantize | Stage::Compose => check_output_path(path)?,
+                _ => {}
+            }
+            if output_color_space.is_some()
+                || !cvd_previews.is_empty()
+                || softproof.is_some()
+                || grid_overlay.is_some()
+                || split_layers
+                || sticker_sheet_path.is_some()
+                || report_path.is_some()
+                || xmp_enabled
+            {
+                bail!(
+                    "`--output-colorspace`, `--cvd-preview`, `--softproof`, `--grid-overlay`, `--split-layers`, `--sticker-sheet`, `--report`, and `--xmp` need the `encode` stage, so they cannot be used with `--stop-after {}`",
+                    stage.name()
+                );
+            }
+            if stage == Stage::Match && placements_path.is_some() {
+                bail!(
+                    "`--placements` cannot be used with `--stop-after match`, whose artifact is a placements file"
+                );
+            }
+        }
+        _ => {
+            for path in &output_image_paths {
+                check_output_path(path)?;
+                if output_color_space.is_some() {
+                    check_profile_support(path)?;
+                }
+            }
+        }
     }
+    // The artifact of the `match` stage is a placements file
+    let placements_path = if stop_after == Some(Stage::Match) {
+        Some(output_image_paths[0].clone())
+    } else {
+        placements_path
+    };
 
-    // Calculate scaling factor used in computing the average color of a tile
-    let palette_scale = TILE_PIXEL_COMPONENT_MAX * tile_size as f32 * tile_size as f32;
+    // Resuming from a placements file or a composed output image needs neither the palette nor the input image
+    if let Some(Resume {
+        stage: stage @ (Stage::Match | Stage::Compose),
+        ..
+    }) = &resume
+        && (report_path.is_some() || xmp_enabled)
+    {
+        bail!(
+            "`--report` and `--xmp` need the `match` stage, so they cannot be used with `--resume-from {}`",
+            stage.name()
+        );
+    }
+    if let Some(Resume {
+        stage: Stage::Compose,
+        path,
+    }) = &resume
+    {
+        encode(
+            path,
+            &output_image_paths,
+            output_color_space,
+            &cvd_previews,
+            softproof.as_ref(),
+        )?;
+        written_paths.extend(output_image_paths);
+        return Ok(());
+    }
 
-    // Calculate average color of each tile in the palette
-    let mut palette_colors = Vec::new();
-    let mut palette_images = Vec::new();
+    let Some(tile_sizes) = tile_sizes else {
+        bail!("`-s`/`--tile-size`: a tile size is required");
+    };
+    if tile_sizes
+        .iter()
+        .any(|tile_size| tile_size.width == 0 || tile_size.height == 0)
+    {
+        bail!("`-s`/`--tile-size`: tile size cannot be zero");
+    }
 
-    for entry in WalkDir::new(palette_dir_path) {
-        // Only process images in supported formats
-        let path = entry?.into_path();
-        if path.is_dir()
-            || path.extension().is_none_or(|ext| {
-                !matches!(ext.to_str(), Some("avif" | "jpeg" | "jpg" | "png" | "webp"))
-            })
+    if let Some(Resume {
+        stage: Stage::Match,
+        path,
+    }) = &resume
+    {
+        if tile_sizes.len() > 1 {
+            bail!(
+                "`-s`/`--tile-size`: a list of tile sizes cannot be used with `--resume-from match`"
+            );
+        }
+        if output_color_space.is_some()
+            || !cvd_previews.is_empty()
+            || softproof.is_some()
+            || grid_overlay.is_some()
+            || split_layers
+            || sticker_sheet_path.is_some()
+            || tone_match
         {
-            continue;
+            bail!(
+                "`--output-colorspace`, `--cvd-preview`, `--softproof`, `--grid-overlay`, `--split-layers`, `--sticker-sheet`, and `--tone-match` cannot be used with `--resume-from match`"
+            );
         }
+        let cache = cache_dir_path.map(TileCache::new).transpose()?;
+        compose::compose(
+            &read_to_string(path)?,
+            tile_sizes[0],
+            upscale_policy,
+            cache.as_ref(),
+            grout.map_or([0; 3], |grout| grout.0),
+            None,
+            output_image_paths.clone(),
+        )?;
+        written_paths.extend(output_image_paths);
+        return Ok(());
+    }
 
-        let image: TileImage = ImageReader::open(path)?.decode()?.into_rgba8();
-        let mut resized_image = resize_image(image, tile_size, tile_size)?;
+    if let Some(path) = &glyphs_font_path {
+        if !path.is_file() {
+            bail!("`--glyphs`: path does not point to a file");
+        }
+        if palette_dir_path.is_some() {
+            bail!(
+                "`--glyphs` cannot be used with `-p`/`--palette-dir`, since the font's glyphs are the palette"
+            );
+        }
+        if matches!(stop_after, Some(Stage::Scan | Stage::Index))
+            || matches!(resume_stage, Some(Stage::Scan | Stage::Index))
+        {
+            bail!(
+                "`--glyphs`: glyphs are rendered rather than scanned and indexed, so `--stop-after` and `--resume-from` cannot name `scan` or `index`"
+            );
+        }
+        // Glyphs aren't image files, so they can't be drawn again from a placements file
+        if placements_path.is_some()
+            || palette_filter_path.is_some()
+            || pins_path.is_some()
+            || story_path.is_some()
+            || weights_path.is_some()
+            || cache_dir_path.is_some()
+            || min_tile_source.is_some()
+            || quality_filters.is_enabled()
+            || source_crops
+        {
+            bail!(
+                "`--glyphs` cannot be used with `--placements`, `--stop-after match`, `--palette-filter`, `--pins`, `--story`, `--weights`, `--cache-dir`, `--min-tile-source`, the `--reject-*` filters, or `--source-crops`"
+            );
+        }
+    } else if charset.is_some() || text_path.is_some() {
+        bail!("`--charset` and `--text` can only be used with `--glyphs`");
+    }
+    if text_path.is_some() && (layout != Layout::Square || shard.is_some()) {
+        bail!(
+            "`--text` needs a square grid covering the whole output image, so it cannot be used with a `--layout` other than `square` or with `--shard`"
+        );
+    }
 
-        let (mut r_sum, mut g_sum, mut b_sum) = (0., 0., 0.);
+    // Resuming from the `index` stage loads its palette pack in place of the palette directory,
+    // and resuming from the `quantize` stage reads its processed input image in place of the input image
+    let palette_dir_path = match (&resume, palette_dir_path) {
+        (
+            Some(Resume {
+                stage: Stage::Index,
+                ..
+            }),
+            Some(_),
+        ) => {
+            bail!(
+                "`-p`/`--palette-dir` cannot be used with `--resume-from index`, whose palette pack replaces it"
+            );
+        }
+        (
+            Some(Resume {
+                stage: Stage::Index,
+                path,
+            }),
+            None,
+        ) => path.clone(),
+        (_, Some(path)) => path,
+        // With `--glyphs`, the font takes the palette directory's place in placements files and reports
+        (_, None) => match &glyphs_font_path {
+            Some(path) => path.clone(),
+            None => bail!("`-p`/`--palette-dir`: a palette directory is required"),
+        },
+    };
+    let input_image_path = match (&resume, input_image_path) {
+        (
+            Some(Resume {
+                stage: Stage::Quantize,
+                ..
+            }),
+            Some(_),
+        ) => {
+            bail!(
+                "`-i`/`--input` cannot be used with `--resume-from quantize`, whose processed input image replaces it"
+            );
+        }
+        (
+            Some(Resume {
+                stage: Stage::Quantize,
+                path,
+            }),
+            None,
+        ) => path.clone(),
+        (_, Some(path)) => path,
+        (_, None) => bail!("`-i`/`--input`: an input image is required"),
+    };
+    if resume_stage == Some(Stage::Quantize)
+        && (grid.is_some()
+            || fit.is_some()
+            || grid_filter.is_some()
+            || curves_spec.is_some()
+            || theme_path.is_some()
+            || posterize_bands.is_some()
+            || noise_amplitude.is_some())
+    {
+        bail!(
+            "`--resume-from quantize`: the input image was already processed, so `--grid`, `--fit`, `--grid-filter`, `--curves`, `--theme`, `--posterize-l`, and `--noise` cannot be used"
+        );
+    }
+    if resume_stage == Some(Stage::Quantize) && !burst_paths.is_empty() {
+        bail!(
+            "`--burst` cannot be used with `--resume-from quantize`, whose processed input image replaces it"
+        );
+    }
+    if burst_paths.iter().any(|path| !path.is_file()) {
+        bail!("`--burst`: path does not point to a file");
+    }
+    if quantizer.is_some() && !dither_enabled {
+        bail!("`--quantizer`: dithering was disabled with `-d false`");
+    }
+    let quantizer = quantizer.unwrap_or_default();
+    // A processed input image was already dithered
+    let dither_enabled =
+        dither_enabled && quantizer != Quantizer::None && resume_stage != Some(Stage::Quantize);
+    if max_cell_colors == Some(0) {
+        bail!("`--max-cell-colors`: number of colors must be at least 1");
+    }
+    if max_cell_colors.is_some() && !dither_enabled {
+        bail!(
+            "`--max-cell-colors`: the input image is not quantized, since dithering was disabled with `-d false` or `--quantizer none`, or the input image was already processed by `--resume-from quantize`"
+        );
+    }
 
-        for px in resized_image
-            .buffer_mut()
-            .array_chunks_mut::<TILE_PIXEL_SIZE>()
+    let palette_is_pack =
+        glyphs_font_path.is_none() && is_pack(&palette_dir_path) && palette_dir_path.is_file();
+    if glyphs_font_path.is_none() && !palette_dir_path.is_dir() && !palette_is_pack {
+        bail!("`-p`/`--palette-dir`: path does not point to a directory or a palette pack");
+    }
+    if palette_is_pack && quality_filters.is_enabled() {
+        bail!(
+            "`--reject-blurry`, `--reject-low-contrast`, and `--reject-uniform` cannot be used with a palette pack"
+        );
+    }
+    if palette_filter_path
+        .as_ref()
+        .is_some_and(|path| !path.is_file())
+    {
+        bail!("`--palette-filter`: path does not point to a file");
+    }
+    if palette_is_pack && palette_filter_path.is_some() {
+        bail!("`--palette-filter` cannot be used with a palette pack");
+    }
+    if (palette_is_pack || glyphs_font_path.is_some()) && tags.is_some() {
+        bail!(
+            "`--tags` needs the palette.toml files of a palette directory, so it cannot be used with a palette pack or `--glyphs`"
+        );
+    }
+    if stop_after == Some(Stage::Index) && tags.is_some() {
+        bail!("`--stop-after index`: a palette pack cannot be written with `--tags`");
+    }
+    if palette_is_pack && matches!(stop_after, Some(Stage::Scan | Stage::Index)) {
+        bail!("`--stop-after`: a palette pack was already scanned and indexed");
+    }
+    if palette_is_pack && resume_stage == Some(Stage::Scan) {
+        bail!("`--resume-from scan` cannot be used with a palette pack");
+    }
+    if stop_after == Some(Stage::Index) && (tile_sizes.len() > 1 || palette_filter_path.is_some()) {
+        bail!(
+            "`--stop-after index`: a palette pack cannot be written with a list of tile sizes or `--palette-filter`"
+        );
+    }
+    // The first tile size is the one palette images' colors are calculated at, and which decides the grid's aspect ratio
+    let tile_size = tile_sizes[0];
+    for (idx, other) in tile_sizes.iter().enumerate().skip(1) {
+        if u64::from(other.width) * u64::from(tile_size.height)
+            != u64::from(tile_size.width) * u64::from(other.height)
         {
-            // The output image is opaque. The average color calculation
-            // assumes each pixel of the tile is over a black (r=0, g=0, b=0) background.
-            // This also simplifies calculations for new RGB values when the source pixels of tiles are not opaque.
-            if px[3] == TILE_PIXEL_COMPONENT_MAX_INT {
-                r_sum += f32::from(px[0]);
-                g_sum += f32::from(px[1]);
-                b_sum += f32::from(px[2]);
-            } else {
-                let a = f32::from(px[3]);
+            bail!("`-s`/`--tile-size`: every tile size in a list must have the same aspect ratio");
+        }
+        if tile_sizes[..idx]
+            .iter()
+            .any(|earlier| earlier.width == other.width && earlier.height == other.height)
+        {
+            bail!("`-s`/`--tile-size`: tile size is listed more than once");
+        }
+    }
+    if palette_is_pack && tile_sizes.len() > 1 {
+        bail!("`-s`/`--tile-size`: a list of tile sizes cannot be used with a palette pack");
+    }
+    if source_crops && (palette_is_pack || stop_after == Some(Stage::Index)) {
+        bail!(
+            "`--source-crops` needs palette images resized larger than a tile, so it cannot be used with a palette pack or `--stop-after index`"
+        );
+    }
+    let curves = curves_spec
+        .map(|spec| Curves::parse(&spec, curve_space))
+        .transpose()?;
+    if theme_path.as_ref().is_some_and(|path| !path.is_file()) {
+        bail!("`--theme`: path does not point to a file");
+    }
+    if noise_amplitude.is_some_and(|amplitude| amplitude.is_nan() || amplitude < 0.) {
+        bail!("`--noise`: amplitude must be a non-negative number");
+    }
+    if posterize_bands.is_some_and(|band_count| band_count < 2) {
+        bail!("`--posterize-l`: number of bands must be at least 2");
+    }
+    if harmonize_strength.is_some_and(|strength| !(0. ..=1.).contains(&strength)) {
+        bail!("`--harmonize`: strength must be from 0 to 1");
+    }
+    if harmonize_width == Some(0) {
+        bail!("`--harmonize-width`: width cannot be zero");
+    }
+    if harmonize_strength.is_none() && harmonize_width.is_some() {
+        bail!("`--harmonize-width` can only be used with `--harmonize`");
+    }
+    if sharpen_amount.is_some_and(|amount| !amount.is_finite() || amount < 0.) {
+        bail!("`--sharpen`: amount must be a non-negative number");
+    }
+    if sharpen_radius.is_some_and(|radius| !radius.is_finite() || radius <= 0.) {
+        bail!("`--sharpen-radius`: radius must be greater than 0");
+    }
+    if sharpen_amount.is_none() && (sharpen_radius.is_some() || sharpen_edges) {
+        bail!("`--sharpen-radius` and `--sharpen-edges` can only be used with `--sharpen`");
+    }
+    if sharpen_edges && layout != Layout::Square {
+        bail!(
+            "`--sharpen-edges`: tile edges are only found in a square grid, so it cannot be used with a `--layout` other than `square`"
+        );
+    }
+    if max_error.is_some_and(|max_error| max_error.is_nan() || max_error < 0.) {
+        bail!("`-e`/`--max-error`: maximum error must be a non-negative number");
+    }
+    if blend_threshold
+        .is_some_and(|blend_threshold| blend_threshold.is_nan() || blend_threshold < 0.)
+    {
+        bail!("`-b`/`--blend`: blend threshold must be a non-negative number");
+    }
+    if match_precision.is_some_and(|precision| precision.is_nan() || precision <= 0.) {
+        bail!("`--match-precision`: bucket size must be a positive number");
+    }
+    if variety == Some(0) {
+        bail!("`--variety`: number of tiles cannot be zero");
+    }
+    if variety_noise.is_some() && variety.is_none() {
+        bail!("`--variety-noise`: no variety was requested with `--variety`");
+    }
+    if tie_epsilon.is_some_and(|epsilon| epsilon.is_nan() || epsilon <= 0.) {
+        bail!("`--tie-epsilon`: color distance must be a positive number");
+    }
+    if tie_epsilon.is_some() && variety.is_some_and(|variety| variety > 1) {
+        bail!(
+            "`--tie-epsilon` cannot be used with `--variety`, which already chooses among the nearest tiles"
+        );
+    }
+    if max_usage_share.is_some_and(|share| !(share > 0. && share <= 1.)) {
+        bail!("`--max-usage-share`: share must be greater than 0 and at most 1");
+    }
+    if rotate_jitter.is_some_and(|angle| !(0. ..=45.).contains(&angle)) {
+        bail!("`--rotate-jitter`: angle must be from 0 to 45 degrees");
+    }
+    if sparse_threshold.is_some() && sparse_mask_path.is_some() {
+        bail!("`--sparse` and `--sparse-mask` cannot be used together");
+    }
+    if sparse_threshold.is_some_and(|threshold| !(0. ..=1.).contains(&threshold)) {
+        bail!("`--sparse`: lightness must be between 0 and 1");
+    }
+    if sparse_mask_path
+        .as_ref()
+        .is_some_and(|path| !path.is_file())
+    {
+        bail!("`--sparse-mask`: path does not point to a file");
+    }
+    if split_layers && sparse_threshold.is_none() && sparse_mask_path.is_none() {
+        bail!(
+            "`--split-layers`: no sparse mosaic was requested with `--sparse` or `--sparse-mask`"
+        );
+    }
+    if split_layers && (layout != Layout::Square || shard.is_some() || fit_remainder.is_some()) {
+        bail!(
+            "`--split-layers` needs a square grid covering the whole output image, so it cannot be used with a `--layout` other than `square`, `--shard`, or `--fit-remainder`"
+        );
+    }
+    if canvas_color.is_some() && sparse_threshold.is_none() && sparse_mask_path.is_none() {
+        bail!(
+            "`--canvas-color`: no sparse mosaic was requested with `--sparse` or `--sparse-mask`"
+        );
+    }
+    if cell_mask_path.as_ref().is_some_and(|path| !path.is_file()) {
+        bail!("`--cell-mask`: path does not point to a file");
+    }
+    if grout.is_some() && rotate_jitter.is_none() && cell_mask_path.is_none() {
+        bail!(
+            "`--grout`: no rotation or mask was requested with `--rotate-jitter` or `--cell-mask`"
+        );
+    }
+    if shadow_opacity.is_some() && rotate_jitter.is_none() && cell_mask_path.is_none() {
+        bail!(
+            "`--shadow`: tiles only cast shadows on grout, so `--rotate-jitter` or `--cell-mask` is needed"
+        );
+    }
+    if shadow_opacity.is_some_and(|opacity| !(0. ..=1.).contains(&opacity)) {
+        bail!("`--shadow`: opacity must be between 0 and 1");
+    }
+    if (shadow_radius.is_some() || shadow_offset.is_some()) && shadow_opacity.is_none() {
+        bail!("`--shadow-radius` and `--shadow-offset`: no shadow was requested with `--shadow`");
+    }
+    if shadow_radius.is_some_and(|radius| !radius.is_finite() || radius <= 0.) {
+        bail!("`--shadow-radius`: radius must be greater than 0");
+    }
+    if pins_path.as_ref().is_some_and(|path| !path.is_file()) {
+        bail!("`--pins`: path does not point to a file");
+    }
+    if story_path.as_ref().is_some_and(|path| !path.is_file()) {
+        bail!("`--story`: path does not point to a file");
+    }
+    if story_margin.is_some() && story_path.is_none() {
+        bail!("`--story-margin` can only be used with `--story`");
+    }
+    if story_margin.is_some_and(|margin| margin.is_nan() || margin < 0.) {
+        bail!("`--story-margin`: margin must be a non-negative number");
+    }
+    if weights_path.as_ref().is_some_and(|path| !path.is_file()) {
+        bail!("`--weights`: path does not point to a file");
+    }
+    if weight_margin.is_nan() || weight_margin < 0. {
+        bail!("`--weight-margin`: weight margin must be a non-negative number");
+    }
+    if equalize_strength.is_some_and(|strength| strength.is_nan() || strength < 0.) {
+        bail!("`--equalize`: strength must be a non-negative number");
+    }
+    if cache_max_size.is_some() && cache_dir_path.is_none() {
+        bail!("`--cache-max-size`: no cache was requested with `--cache-dir`");
+    }
+    if recent_tiles_size.is_some() && !descriptors_only && !compress_tiles {
+        bail!(
+            "`--recent-tiles-size`: tiles are only decoded when drawn with `--descriptors-only` or `--compress-tiles`"
+        );
+    }
+    if compress_tiles && descriptors_only {
+        bail!(
+            "`--compress-tiles` cannot be used with `--descriptors-only`, which keeps no tiles to compress"
+        );
+    }
+    if compress_tiles
+        && (blend_threshold.is_some() || matches!(frame_style, Some(FrameStyle::Tiles)))
+    {
+        bail!(
+            "`--compress-tiles` cannot be used with `-b`/`--blend` or `--frame tiles`, which read tiles directly"
+        );
+    }
+    if descriptors_only
+        && (glyphs_font_path.is_some()
+            || palette_is_pack
+            || stop_after == Some(Stage::Index)
+            || tile_sizes.len() > 1
+            || source_crops
+            || exposure_normalized
+            || blend_threshold.is_some()
+            || matches!(frame_style, Some(FrameStyle::Tiles)))
+    {
+        bail!(
+            "`--descriptors-only` cannot be used with `--glyphs`, a palette pack, `--stop-after index`, several tile sizes, `--source-crops`, `--normalize-exposure`, `--normalize-contrast`, `-b`/`--blend`, or `--frame tiles`, which need every tile at once"
+        );
+    }
+    if max_source_megapixels.is_some_and(|megapixels| megapixels.is_nan() || megapixels <= 0.) {
+        bail!("`--max-source-megapixels`: limit must be a positive number");
+    }
+    let max_source_pixels =
+        max_source_megapixels.map(|megapixels| ((megapixels * 1e6) as u64).max(1));
+    if animated_frames == AnimatedFrames::Each && descriptors_only {
+        bail!(
+            "`--animated each` cannot be used with `--descriptors-only`, which decodes tiles again by palette image path"
+        );
+    }
+    if depth_map_path.as_ref().is_some_and(|path| !path.is_file()) {
+        bail!("`--depth-map`: path does not point to a file");
+    }
+    if !(0. ..=1.).contains(&depth_threshold) {
+        bail!("`--depth-threshold`: threshold must be between 0.0 and 1.0");
+    }
+    if let Some(FrameStyle::Image(path)) = &frame_style
+        && !path.is_file()
+    {
+        bail!("`--frame`: path does not point to a file");
+    }
+    if frame_width.is_some() && frame_style.is_none() {
+        bail!("`--frame-width`: no frame was requested with `--frame`");
+    }
+    if placement_alternates.is_some() && placements_path.is_none() {
+        bail!("`--placement-alternates`: no placements file was requested with `--placements`");
+    }
+    if cell_order.is_some() && placements_path.is_none() {
+        bail!("`--order`: no placements file was requested with `--placements`");
+    }
+    if order_center.is_some() && !matches!(cell_order, Some(CellOrder::CenterOut)) {
+        bail!("`--order-center`: only `--order center-out` has a center");
+    }
+    if matches!(cell_order, Some(CellOrder::Mask)) && order_mask_path.is_none() {
+        bail!("`--order`: a priority image is required with `--order-mask`");
+    }
+    if order_mask_path.is_some() && !matches!(cell_order, Some(CellOrder::Mask)) {
+        bail!("`--order-mask`: priority images are only used with `--order mask`");
+    }
+    if order_mask_path.as_ref().is_some_and(|path| !path.is_file()) {
+        bail!("`--order-mask`: path does not point to a file");
+    }
+    // Whether any randomized option was requested
+    let randomized = matches!(cell_order, Some(CellOrder::Random))
+        || variety.is_some_and(|variety| variety > 1)
+        || tie_epsilon.is_some()
+        || rotate_jitter.is_some_and(|angle| angle > 0.);
+    if seed.is_some() && !randomized {
+        bail!(
+            "`--seed`: no randomized option (such as `--order random`, `--variety`, `--tie-epsilon`, or `--rotate-jitter`) was requested"
+        );
+    }
+    if shard.is_some()
+        && (harmonize_strength.is_some()
+            || sharpen_amount.is_some()
+            || tone_match
+            || report_path.is_some()
+            || debug_dir_path.is_some())
+    {
+        bail!(
+            "`--harmonize`, `--sharpen`, `--tone-match`, `--report`, and `--debug-dir` need the whole output image, so they cannot be used with `--shard`"
+        );
+    }
+    if shard.is_some() && output_color_space.is_some() {
+        bail!(
+            "`--output-colorspace` cannot be used with `--shard`; pass it to `tessera merge` instead"
+        );
+    }
+    if layout_center.is_some() && !matches!(layout, Layout::Rings | Layout::Spiral) {
+        bail!("`--layout-center`: only `--layout rings` and `--layout spiral` have a center");
+    }
+    if layout != Layout::Square {
+        if tile_sizes
+            .iter()
+            .any(|tile_size| tile_size.width != tile_size.height)
+        {
+            bail!("`--layout`: tiles must be square unless the layout is `square`");
+        }
+        if depth_map_path.is_some()
+            || pins_path.is_some()
+            || story_path.is_some()
+            || placements_path.is_some()
+            || harmonize_strength.is_some()
+            || tone_match
+            || shard.is_some()
+            || report_path.is_some()
+            || debug_dir_path.is_some()
+            || sparse_mask_path.is_some()
+            || resume_stage == Some(Stage::Quantize)
+        {
+            bail!(
+                "a `--layout` other than `square` cannot be used with `--depth-map`, `--pins`, `--story`, `--placements`, `--harmonize`, `--tone-match`, `--shard`, `--report`, `--debug-dir`, `--sparse-mask`, or `--resume-from quantize`"
+            );
+        }
+    }
+    if grid.is_some() && fit.is_some() {
+        bail!("`--grid` and `--fit` cannot be used together");
+    }
+    if grid_filter.is_some() && grid.is_none() && fit.is_none() {
+        bail!("`--grid-filter`: no grid was requested with `--grid` or `--fit`");
+    }
+    if fit_remainder.is_some() && fit.is_none() {
+        bail!("`--fit-remainder`: no output size was requested with `--fit`");
+    }
+    if fit_remainder.is_some() && shard.is_some() {
+        bail!("`--fit-remainder` cannot be used with `--shard`");
+    }
+    if grid_overlay.is_some()
+        && (layout != Layout::Square || shard.is_some() || fit_remainder.is_some())
+    {
+        bail!(
+            "`--grid-overlay` needs a square grid covering the whole output image, so it cannot be used with a `--layout` other than `square`, `--shard`, or `--fit-remainder`"
+        );
+    }
+    if let Some(path) = &sticker_sheet_path {
+        if layout != Layout::Square || shard.is_some() || fit_remainder.is_some() {
+            bail!(
+                "`--sticker-sheet` needs a square grid covering the whole output image, so it cannot be used with a `--layout` other than `square`, `--shard`, or `--fit-remainder`"
+            );
+        }
+        if !path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+        {
+            bail!(
+                "`--sticker-sheet`: path must have the extension `.png` ({})",
+                path.display()
+            );
+        }
+        if sticker_dpi == 0 {
+            bail!("`--sticker-dpi`: must be greater than 0");
+        }
+    }
+    if let Some(path) = &quantized_path {
+        if check_output_path(path).is_err() {
+            bail!(
+                "`--save-quantized`: cannot write an image with this extension ({})",
+                path.display()
+            );
+        }
+        if layout != Layout::Square {
+            bail!("`--save-quantized` cannot be used with a `--layout` other than `square`");
+        }
+    }
+    if two_pass.is_some()
+        && (layout != Layout::Square
+            || shard.is_some()
+            || stop_after.is_some_and(|stage| stage < Stage::Encode))
+    {
+        bail!(
+            "`--two-pass` cannot be used with a `--layout` other than `square`, `--shard`, or `--stop-after` before `encode`"
+        );
+    }
+    if !input_image_path.is_file() {
+        bail!("`-i`/`--input`: path does not point to a file");
+    }
 
-                let r = f32::from(px[0]) * a / TILE_PIXEL_COMPONENT_MAX;
-                let g = f32::from(px[1]) * a / TILE_PIXEL_COMPONENT_MAX;
-                let b = f32::from(px[2]) * a / TILE_PIXEL_COMPONENT_MAX;
+    // Catch parameters that are likely mistakes before any work starts
+    let input_dimensions = match ImageReader::open(&input_image_path)?.into_dimensions() {
+        Ok((0, _) | (_, 0)) => bail!("`-i`/`--input`: input image is empty"),
+        Ok(dimensions) => dimensions,
+        Err(err) => bail!("`-i`/`--input`: cannot read input image: {err}"),
+    };
+    for path in &burst_paths {
+        match ImageReader::open(path)?.into_dimensions() {
+            Ok(dimensions) if dimensions == input_dimensions => {}
+            Ok((width, height)) => bail!(
+                "`--burst`: {} is {width}x{height}, but the input image is {}x{}",
+                path.display(),
+                input_dimensions.0,
+                input_dimensions.1
+            ),
+            Err(err) => bail!("`--burst`: cannot read {}: {err}", path.display()),
+        }
+    }
+    for &tile_size in &tile_sizes {
+        let (grid_width, grid_height) =
+            grid_dimensions(grid, fit, fit_remainder, input_dimensions, tile_size)
+                .unwrap_or(input_dimensions);
+        if let (Some(FitRemainder::Pad), Some(fit)) = (fit_remainder, fit) {
+            let (fit_width, fit_height) =
+                fit.apply(f64::from(input_dimensions.0), f64::from(input_dimensions.1));
+            if fit_width < tile_size.width || fit_height < tile_size.height {
+                bail!("`--fit-remainder pad`: the `--fit` size is smaller than a tile");
+            }
+        }
+        let output_width = u64::from(grid_width) * u64::from(tile_size.width);
+        let output_height = u64::from(grid_height) * u64::from(tile_size.height);
+        if output_width * output_height > CONFIRMED_OUTPUT_PIXELS && !confirmed {
+            bail!(
+                "output image would be {output_width}x{output_height} ({:.1} gigapixels); use a smaller tile size or `--grid`, or pass `--yes` to proceed",
+                (output_width * output_height) as f64 / 1e9
+            );
+        }
+        if u64::from(grid_width) * u64::from(grid_height) < MIN_CELL_COUNT {
+            log!(
+                "warning: the grid is only {grid_width}x{grid_height} cells, so the output image shows little of the input image; use `--grid` or `--fit` for more cells"
+            );
+        }
+        if tile_size.width == 1 && tile_size.height == 1 {
+            log!(
+                "warning: with a tile size of 1, each tile is a single pixel, so the output image is the input image in palette colors; use a larger tile size to see palette images"
+            );
+        }
+    }
 
-                px[0] = r as u8;
-                px[1] = g as u8;
-                px[2] = b as u8;
+    let theme = theme_path.map(|path| load_theme(&path)).transpose()?;
+    let glyphs = glyphs_font_path
+        .as_deref()
+        .map(|path| Glyphs::new(path, &charset.unwrap_or_default()))
+        .transpose()?;
 
-                r_sum += r;
-                g_sum += g;
-                b_sum += b;
+    // Every span of cell that can appear in the output image; tiles are resized once for each of these.
+    // With a depth map, farther regions are covered by cells with a span of 2.
+    let spans = if depth_map_path.is_some() {
+        vec![1, 2]
+    } else {
+        vec![1]
+    };
+
+    // With `--source-crops`, tiles are also resized to CROP_ZOOM times each span, to crop from
+    let mut load_spans = spans.clone();
+    if source_crops {
+        load_spans.extend(spans.iter().map(|span| span * CROP_ZOOM));
+        load_spans.sort_unstable();
+        load_spans.dedup();
+    }
+
+    // Name and duration of each stage, for --report
+    let mut timings = Vec::new();
+    let load_start = Instant::now();
+
+    // Resize each tile in the palette, once per cell span, skipping palette images rejected by filters
+    // With several tile sizes, each palette image is decoded once and resized for each of them
+    let cache = cache_dir_path.map(TileCache::new).transpose()?;
+    let load_options: Vec<_> = tile_sizes
+        .iter()
+        .map(|&tile_size| LoadOptions {
+            tile_size,
+            spans: &load_spans,
+            min_tile_source,
+            allow_upscale,
+            quality_filters,
+            upscale_policy,
+            animated_frames,
+            max_source_pixels,
+            cache: cache.as_ref(),
+        })
+        .collect();
+    // Hints from the palette.toml files in the palette directory, if it is one
+    let mut hints = None;
+    // With `--descriptors-only`, the average color of each palette image, which is all that is kept of it
+    let mut descriptor_colors = None;
+    let mut loaded_palettes = if let Some(glyphs) = &glyphs {
+        tile_sizes
+            .iter()
+            .map(|&tile_size| glyphs.load(tile_size, &spans))
+            .collect()
+    } else if palette_is_pack {
+        vec![load_pack(&palette_dir_path, &load_options[0])?]
+    } else {
+        let mut palette_paths = match &resume {
+            Some(Resume {
+                stage: Stage::Scan,
+                path,
+            }) => read_image_list(path, &palette_dir_path)?,
+            _ => find_images(&palette_dir_path, &extensions)?,
+        };
+        if let Some(path) = &palette_filter_path {
+            palette_paths = filter_images(palette_paths, path, &palette_dir_path)?;
+        }
+        let palette_hints = PaletteHints::load(&palette_dir_path, &palette_paths)?;
+        if let Some(tags) = &tags {
+            palette_paths.retain(|path| palette_hints.has_any_tag(path, tags));
+            if palette_paths.is_empty() {
+                bail!("`--tags`: no palette images are tagged with any of the given tags");
             }
         }
-
-        let oklab = linear_srgb_to_oklab(
-            r_sum / palette_scale,
-            g_sum / palette_scale,
-            b_sum / palette_scale,
+        hints = Some(palette_hints);
+        if stop_after == Some(Stage::Scan) {
+            write_image_list(&output_image_paths[0], &palette_paths, &palette_dir_path)?;
+            log!(
+                "stopped after `scan`: listed {} palette images in {}",
+                format_count(palette_paths.len() as u64),
+                output_image_paths[0].display()
+            );
+            written_paths.extend(output_image_paths);
+            return Ok(());
+        }
+        if descriptors_only {
+            let colors;
+            let loaded_palette;
+            (loaded_palette, colors) =
+                load_descriptors(palette_paths, &load_options[0], alpha_weighted)?;
+            descriptor_colors = Some(colors);
+            vec![loaded_palette]
+        } else if let Some(hot_palette) = hot_palette {
+            hot_palette.load(palette_paths, &load_options)?
+        } else {
+            load_images_for_sizes(palette_paths, &load_options)?
+        }
+    };
+    if stop_after == Some(Stage::Index) {
+        let path = &output_image_paths[0];
+        write_pack(
+            path,
+            &palette_dir_path,
+            tile_size,
+            &spans,
+            &loaded_palettes[0],
+        )?;
+        log!(
+            "stopped after `index`: exported {} palette images to {}",
+            format_count(loaded_palettes[0].paths.len() as u64),
+            path.display()
         );
+        written_paths.push(path.clone());
+        return Ok(());
+    }
+    // Tiles for each span, and with `--source-crops`, zoomed tiles to crop from for each span.
+    // A zoomed span that is also a span (2, with a depth map) has its tiles shared.
+    let split_tile_sets = |tile_sets: Vec<TileSet>| -> (Vec<TileSet>, Vec<TileSet>) {
+        let mut tile_sets: Vec<_> = tile_sets.into_iter().map(Some).collect();
+        let idx = |span: u32| load_spans.binary_search(&span).unwrap();
+        let crop_tile_sets = if source_crops {
+            spans
+                .iter()
+                .map(|&span| {
+                    let tile_set = &mut tile_sets[idx(span * CROP_ZOOM)];
+                    if spans.contains(&(span * CROP_ZOOM)) {
+                        tile_set.clone().unwrap()
+                    } else {
+                        tile_set.take().unwrap()
+                    }
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let tile_sets = spans
+            .iter()
+            .map(|&span| tile_sets[idx(span)].take().unwrap())
+            .collect();
+        (tile_sets, crop_tile_sets)
+    };
+    let LoadedPalette {
+        paths: palette_paths,
+        tile_sets,
+        source_dimensions,
+    } = loaded_palettes.remove(0);
+    let (tile_sets, crop_tile_sets) = split_tile_sets(tile_sets);
 
-        palette_colors.push(oklab);
-        palette_images.push(resized_image.into_vec());
+    if let (Some(cache), Some(cache_max_size)) = (&cache, cache_max_size) {
+        let (removed_count, removed_size) = cache.evict(cache_max_size)?;
+        if removed_count > 0 {
+            log!(
+                "evicted {} tile cache entries ({})",
+                format_count(removed_count as u64),
+                format_size(removed_size)
+            );
+        }
     }
 
-    // Construct k-d tree for nearest-neighbor queries for colors
-    let tree = ImmutableKdTree::new_from_slice(&palette_colors);
+    let pins = pins_path
+        .map(|path| load_pins(&path, &palette_dir_path, &palette_paths))
+        .transpose()?;
+    let story = story_path
+        .map(|path| load_story(&path, &palette_dir_path, &palette_paths))
+        .transpose()?;
+    // Weights from palette.toml files apply first, and the `--weights` file overrides them image by image
+    let mut weights = hints
+        .as_ref()
+        .and_then(|hints: &PaletteHints| hints.weights(&palette_paths));
+    if let Some(path) = &weights_path {
+        let default_weights = weights.unwrap_or_else(|| vec![1.; palette_paths.len()]);
+        weights = Some(load_weights(
+            path,
+            &palette_dir_path,
+            &palette_paths,
+            default_weights,
+        )?);
+    }
 
-    let mut input_image: InputImage = ImageReader::open(input_image_path)?.decode()?.into_rgb8();
+    // Near-ties in matching are broken in favor of palette images in the same orientation as non-square tiles,
+    // then in favor of higher-weighted palette images
+    let preferences = (weights.is_some() || tile_size.width != tile_size.height).then(|| {
+        source_dimensions
+            .iter()
+            .enumerate()
+            .map(|(idx, &dimensions)| Preference {
+                orientation_matches: Preference::orientation_matches(dimensions, tile_size),
+                weight: weights.as_ref().map_or(1., |weights| weights[idx]),
+            })
+            .collect()
+    });
 
-    if dither_enabled {
-        // Apply Floyd-Steinberg dithering to the input image
-        input_image = ImagePipeline::try_from(&input_image)?
-            .colorspace(ColorSpace::Oklab)
-            .quantized_rgbimage_par();
+    let palette_size = palette_paths.len();
+    let load_elapsed = load_start.elapsed().as_secs_f64();
+    timings.push(("loading palette images".to_owned(), load_elapsed));
+    let start = Instant::now();
+    let mut palette = match descriptor_colors {
+        Some(colors) => Palette::with_decoder(
+            palette_paths,
+            tile_size,
+            spans.clone(),
+            colors,
+            preferences,
+            TileDecoder::new(
+                tile_size,
+                upscale_policy,
+                animated_frames,
+                max_source_pixels,
+                cache.clone(),
+                recent_tiles_size.unwrap_or(DEFAULT_RECENT_TILES_SIZE),
+            ),
+        )?,
+        None => Palette::new(
+            palette_paths,
+            tile_size,
+            spans.clone(),
+            tile_sets,
+            alpha_weighted,
+            exposure_normalized.then_some(normalize_contrast),
+            preferences,
+        )?,
+    };
+    // Counts the tiles the palette was built with, leaving out palette images that couldn't be used
+    let usable_count = palette.paths.len();
+    if dither_enabled && usable_count <= 2 {
+        log!(
+            "warning: dithering with only {usable_count} usable palette images produces a noisy output image; add palette images or pass `-d false`"
+        );
+    }
+    if let Some(strength) = equalize_strength {
+        palette.equalize(strength);
     }
+    if source_crops {
+        palette.set_crops(
+            crop_tile_sets,
+            exposure_normalized.then_some(normalize_contrast),
+        );
+    }
+    // Palettes for the other tile sizes share the first palette's colors, so each places the same palette images
+    let mut palettes = vec![palette];
+    for (loaded_palette, &tile_size) in loaded_palettes.into_iter().zip(&tile_sizes[1..]) {
+        let (tile_sets, crop_tile_sets) = split_tile_sets(loaded_palette.tile_sets);
+        let mut resized_palette = palettes[0].resized(
+            tile_size,
+            tile_sets,
+            exposure_normalized.then_some(normalize_contrast),
+        );
+        if source_crops {
+            resized_palette.set_crops(
+                crop_tile_sets,
+                exposure_normalized.then_some(normalize_contrast),
+            );
+        }
+        palettes.push(resized_palette);
+    }
+    if compress_tiles {
+        for palette in &mut palettes {
+            palette.compress_tiles(recent_tiles_size.unwrap_or(DEFAULT_RECENT_TILES_SIZE));
+        }
+    }
+    let index_elapsed = start.elapsed().as_secs_f64();
+    timings.push(("indexing the palette".to_owned(), index_elapsed));
+    // Throughput covers decoding and resizing palette images as well as indexing them, which is most of the work
+    let elapsed = load_start.elapsed().as_secs_f64();
+    log!(
+        "loaded and indexed {} palette images in {elapsed:.2} s ({} images/s; loading took {load_elapsed:.2} s, indexing {index_elapsed:.2} s)",
+        format_count(palette_size as u64),
+        format_count((palette_size as f64 / elapsed.max(f64::EPSILON)).round() as u64)
+    );
+    if memory_report {
+        report_memory(
+            "indexing the palette",
+            &[(
+                "palette tiles",
+                palettes.iter().map(Palette::tile_memory).sum(),
+            )],
+        );
+    }
+
+    let mut source_image: InputImage = ImageReader::open(input_image_path)?.decode()?.into_rgb8();
+    if !burst_paths.is_empty() {
+        let start = Instant::now();
+        source_image = merge_burst(&source_image, &burst_paths)?;
+        let elapsed = start.elapsed().as_secs_f64();
+        timings.push(("merging burst frames".to_owned(), elapsed));
+        log!(
+            "aligned and averaged {} burst frames with the input image in {elapsed:.2} s",
+            format_count(burst_paths.len() as u64)
+        );
+    }
+    let order_mask = match &order_mask_path {
+        Some(path) => Some(ImageReader::open(path)?.decode()?.into_luma8()),
+        None => None,
+    };
+    let cell_mask_image = match &cell_mask_path {
+        Some(path) => Some(ImageReader::open(path)?.decode()?),
+        None => None,
+    };
+    let sparse = match (sparse_threshold, &sparse_mask_path) {
+        (Some(threshold), _) => Some(Sparse::Lightness(threshold)),
+        (None, Some(path)) => Some(Sparse::Mask(
+            ImageReader::open(path)?.decode()?.into_luma8(),
+        )),
+        (None, None) => None,
+    };
+    let canvas_color = canvas_color.unwrap_or(Rgb([255; 3]));
+    let seed = seed.unwrap_or(0);
+
+    for palette in &palettes {
+        let tile_size = palette.tile_size;
+        // With several tile sizes, every output is suffixed with its tile size, e.g. "out.32x32.png" for "out.png",
+        // unless its path already contains the tile size as "{tile_size}"
+        let suffix =
+            (palettes.len() > 1).then(|| format!("{}x{}", tile_size.width, tile_size.height));
+        let sized = |path: &Path| {
+            expand_tile_size(path, tile_size).unwrap_or_else(|| {
+                suffix
+                    .as_deref()
+                    .map_or_else(|| path.to_owned(), |suffix| with_suffix(path, suffix))
+            })
+        };
+
+        let process_start = Instant::now();
+
+        // Resize the input image so each of its pixels becomes one cell of the requested grid
+        let mut input_image = source_image.clone();
+        if let Some((grid_width, grid_height)) = grid_dimensions(
+            grid,
+            fit,
+            fit_remainder,
+            input_image.dimensions(),
+            tile_size,
+        ) {
+            input_image =
+                grid_filter
+                    .unwrap_or_default()
+                    .resize(&input_image, grid_width, grid_height);
+        }
+        // Dimensions of the grid, in cells, before any rotation
+        let (grid_width, grid_height) = input_image.dimensions();
+        // Centers given as "auto" are the focal point of the input image, found before it is processed any further
+        let focal_point = [layout_center, order_center]
+            .contains(&Some(Focus::Auto))
+            .then(|| {
+                let (x, y) = focal_point(&input_image);
+                log!("found the focal point of the input image at {x:.2},{y:.2}");
+                (x, y)
+            });
+        let focus_point = |focus: Option<Focus>| match focus {
+            Some(Focus::Point(x, y)) => (x, y),
+            Some(Focus::Auto) => focal_point.unwrap(),
+            None => (0.5, 0.5),
+        };
+        // With `--fit-remainder`, the mosaic is fitted to the exact `--fit` size rather than cut down to whole tiles
+        let remainder_fill = fit_remainder.zip(fit).map(|(policy, fit)| {
+            let (source_width, source_height) = source_image.dimensions();
+            RemainderFill::new(
+                policy,
+                fit.apply(f64::from(source_width), f64::from(source_height)),
+                tile_size,
+                (grid_width, grid_height),
+            )
+        });
+        // Size of the mosaic in the output image, in pixels, not counting any frame
+        let (mosaic_width, mosaic_height) = remainder_fill.as_ref().map_or(
+            (grid_width * tile_size.width, grid_height * tile_size.height),
+            RemainderFill::dimensions,
+        );
+        // With a diamond, ring, or spiral layout, the rest of the pipeline works in the rotated or strip grid until
+        // the output image is drawn
+        let radial = matches!(layout, Layout::Rings | Layout::Spiral).then(|| {
+            Radial::new(
+                layout == Layout::Spiral,
+                focus_point(layout_center),
+                grid_width,
+                grid_height,
+            )
+        });
+        match (layout, &radial) {
+            (Layout::Diamond, _) => input_image = diamond_grid(&input_image),
+            (_, Some(radial)) => input_image = radial.grid(&input_image),
+            _ => {}
+        }
+
+        if let Some(curves) = &curves {
+            curves.apply(&mut input_image);
+        }
+        if let Some(theme) = &theme {
+            apply_theme(&mut input_image, theme);
+        }
+        if let Some(band_count) = posterize_bands {
+            input_image = posterize(&input_image, band_count);
+        }
+        if dither_enabled {
+            match max_cell_colors {
+                Some(max_cell_colors) => {
+                    let (color_count, distinct_count);
+                    (input_image, color_count, distinct_count) = dither_to_distinct_count(
+                        &input_image,
+                        quantizer,
+                        max_cell_colors,
+                        noise_amplitude,
+                    )?;
+                    if distinct_count <= max_cell_colors {
+                        log!(
+                            "quantized the input image to {color_count} colors, giving {} distinct cell colors (`--max-cell-colors` {})",
+                            format_count(u64::from(distinct_count)),
+                            format_count(u64::from(max_cell_colors))
+                        );
+                    } else {
+                        log!(
+                            "`--max-cell-colors`: even a single quantized color gives {} distinct cell colors after `--noise`, above the target of {}",
+                            format_count(u64::from(distinct_count)),
+                            format_count(u64::from(max_cell_colors))
+                        );
+                    }
+                }
+                None => input_image = dither(&input_image, quantizer, MAX_QUANTIZED_COLOR_COUNT)?,
+            }
+        }
+        if let Some(noise_amplitude) = noise_amplitude {
+            input_image = perturb(&input_image, noise_amplitude);
+        }
+        if let Some(path) = &quantized_path {
+            let (width, height) = input_image.dimensions();
+            let mut outputs = Outputs::new(vec![sized(path)], width, height, false, None)?;
+            outputs.write_band(&input_image)?;
+            outputs.finish()?;
+        }
+        if stop_after == Some(Stage::Quantize) {
+            let path = sized(&output_image_paths[0]);
+            save_image(&input_image, &path, None)?;
+            log!(
+                "stopped after `quantize`: wrote the processed input image to {}",
+                path.display()
+            );
+            written_paths.push(path);
+            continue;
+        }
+
+        let (width, height) = input_image.dimensions();
+
+        // Divide the output image into cells, each of which is covered by one tile
+        let mut cells = match &depth_map_path {
+            Some(depth_map_path) => {
+                let depth_map = ImageReader::open(depth_map_path)?.decode()?.into_luma8();
+                depth_cells(&input_image, &depth_map, depth_threshold)
+            }
+            None if layout == Layout::Diamond => {
+                diamond_cells(&input_image, grid_width, grid_height)
+            }
+            None => match &radial {
+                Some(radial) => radial.cells(&input_image),
+                None => grid_cells(&input_image),
+            },
+        };
 
-    let (width, height) = input_image.dimensions();
+        // Pins are applied afterward, so pinned palette images are placed even on the canvas
+        if let Some(sparse) = &sparse {
+            let tiled_count = sparse.apply(&mut cells, canvas_color, width, height);
+            log!(
+                "{} of {} cells receive tiles; the rest are drawn as the canvas color",
+                format_count(tiled_count as u64),
+                format_count(cells.len() as u64)
+            );
+        }
+        if let Some(pins) = &pins {
+            apply_pins(&mut cells, pins, width, height)?;
+        }
+        // Story requests are applied after pins, so they don't take pinned cells
+        if let Some(story) = &story {
+            let granted_count = apply_story(
+                &mut cells,
+                story,
+                palette,
+                (width, height),
+                story_margin.unwrap_or(0.05),
+            );
+            log!(
+                "placed {} of {} palette images requested by `--story` in their regions",
+                format_count(u64::from(granted_count)),
+                format_count(story.len() as u64)
+            );
+        }
 
-    let mut output_image = RgbImage::new(width * tile_size, height * tile_size);
+        // With `--shard`, only the cells in the shard's rows are matched. Bands outside the shard are still
+        // passed along by `render`, but they are empty, and they are skipped rather than written.
+        let shard_rows = shard.map(|shard| shard.rows(height, *palette.spans.last().unwrap()));
+        if let Some(rows) = &shard_rows {
+            if rows.is_empty() {
+                bail!(
+                    "`--shard`: the output image has too few bands of tiles to split into this many shards"
+                );
+            }
+            cells.retain(|cell| rows.contains(&cell.y));
+        }
+        // Whether the shard (if any) includes the top or bottom of the output image, where the frame's edges are
+        let (has_top, has_bottom) = shard_rows
+            .as_ref()
+            .map_or((true, true), |rows| (rows.start == 0, rows.end == height));
 
-    // Cache nearest-neighbor queries to avoid repeating work
-    // Heuristic for initial capacity: probably fewer than half of the pixels in the input image have unique colors.
-    // Even if this ends up being incorrect, the capacity will simply double and will never double again.
-    // (Except when `width` and `height` are odd numbers and every pixel in the input image is unique...)
-    let mut palette_cache = HashMap::with_capacity((width * height / 2) as usize);
+        // With several tile sizes, stages are reported for each
+        let stage = |name: &str| match &suffix {
+            Some(suffix) => format!("{name} ({suffix} tiles)"),
+            None => name.to_owned(),
+        };
+        let mut run_timings = timings.clone();
+        run_timings.push((
+            stage("processing the input image"),
+            process_start.elapsed().as_secs_f64(),
+        ));
+        if memory_report {
+            report_memory(
+                &stage("processing the input image"),
+                &[
+                    ("input image", input_image.as_raw().len() as u64),
+                    ("cells", (cells.len() * size_of::<Cell>()) as u64),
+                ],
+            );
+        }
 
-    for (input_px, tile_idx) in input_image.pixels().zip(0..) {
-        // Get the tile with average color "nearest" to the color of the current pixel
-        let palette_image = palette_cache.entry(input_px).or_insert_with(|| {
-            let r = f32::from(input_px[0]) / INPUT_PIXEL_COMPONENT_MAX;
-            let g = f32::from(input_px[1]) / INPUT_PIXEL_COMPONENT_MAX;
-            let b = f32::from(input_px[2]) / INPUT_PIXEL_COMPONENT_MAX;
-            let oklab = linear_srgb_to_oklab(r, g, b);
-            let palette_idx = tree.nearest_one::<SquaredEuclidean>(&oklab).item as usize;
-            palette_images.get(palette_idx).unwrap()
+        let mut debug_output = debug_dir_path
+            .as_deref()
+            .map(|dir| DebugOutput::new(sized(dir), width, height));
+        let ranks = cell_order.map(|cell_order| {
+            rank_cells(
+                &cells,
+                cell_order,
+                seed,
+                focus_point(order_center),
+                order_mask.as_ref(),
+                width,
+                height,
+            )
         });
+        let mut placements = placements_path
+            .as_deref()
+            .map(|path| {
+                Placements::new(
+                    &sized(path),
+                    placement_alternates.unwrap_or(0),
+                    ranks,
+                    randomized.then_some(seed),
+                )
+            })
+            .transpose()?;
 
-        // Place each pixel of the tile in the output image
-        for (tile_px, px_idx) in palette_image.array_chunks::<TILE_PIXEL_SIZE>().zip(0..) {
-            let tile_x = tile_idx % width;
-            let tile_y = tile_idx / width;
+        let cell_mask = cell_mask_image
+            .as_ref()
+            .map(|image| CellMask::new(image, tile_size, &palette.spans));
+        let shadow = shadow_opacity.map(|opacity| {
+            let radius = shadow_radius.unwrap_or((tile_size.width as f32 / 16.).max(1.));
+            Shadow {
+                opacity,
+                radius,
+                offset: shadow_offset.unwrap_or((radius.round() as i32, radius.round() as i32)),
+            }
+        });
+        let match_options = MatchOptions {
+            max_error,
+            blend_threshold,
+            weight_margin,
+            variety: variety.unwrap_or(1),
+            variety_noise: variety_noise.unwrap_or_default(),
+            tie_epsilon,
+            match_precision,
+            max_usage_share,
+            seed,
+            rotate_jitter: rotate_jitter.unwrap_or(0.),
+            rotations: None,
+            grout: grout.map_or([0; 3], |grout| grout.0),
+            cell_mask: cell_mask.as_ref(),
+            shadow,
+        };
 
-            let px_x = px_idx % tile_size;
-            let px_y = px_idx / tile_size;
+        let frame = frame_style
+            .clone()
+            .map(|style| {
+                Frame::new(
+                    style,
+                    frame_width.unwrap_or(tile_size.width),
+                    palette,
+                    mosaic_width,
+                    mosaic_height,
+                )
+            })
+            .transpose()?;
+        let (output_width, mut output_height) = frame
+            .as_ref()
+            .map_or((mosaic_width, mosaic_height), Frame::outer_dimensions);
+        if let Some(rows) = &shard_rows {
+            let frame_width = frame.as_ref().map_or(0, Frame::width);
+            output_height = rows.len() as u32 * tile_size.height
+                + frame_width * (u32::from(has_top) + u32::from(has_bottom));
+        }
 
-            let x = tile_x * tile_size + px_x;
-            let y = tile_y * tile_size + px_y;
+        // Render the output image once, writing it to every output as it is rendered.
+        // CVD previews and the report are made from the full output image, so it is kept around if they are requested.
+        // With `--tone-match`, the full output image is corrected before it is written to any output.
+        let render_start = Instant::now();
+        // Stopping after the `match` stage writes only the placements file
+        let output_image_paths: Vec<_> = if stop_after == Some(Stage::Match) {
+            Vec::new()
+        } else {
+            output_image_paths.iter().map(|path| sized(path)).collect()
+        };
+        // With `--two-pass`, the output image is rendered next to each output path, e.g. to "out.partial.png" for
+        // "out.png", and moved into place once complete, so the preview is replaced all at once
+        let render_paths: Vec<_> = if two_pass.is_some() {
+            output_image_paths
+                .iter()
+                .map(|path| with_suffix(path, "partial"))
+                .collect()
+        } else {
+            output_image_paths.clone()
+        };
+        if two_pass == Some(TwoPass::Preview) {
+            let preview = resize(
+                &input_image,
+                grid_width * tile_size.width,
+                grid_height * tile_size.height,
+                FilterType::Nearest,
+            );
+            let mut preview_outputs = Outputs::new(
+                render_paths.clone(),
+                output_width,
+                output_height,
+                false,
+                output_color_space,
+            )?;
+            let preview_bands = match remainder_fill.clone() {
+                Some(mut fill) => fill.fit(&preview),
+                None => vec![preview],
+            };
+            let mut preview_y = frame.as_ref().map_or(0, Frame::width);
+            if let Some(frame) = &frame {
+                preview_outputs.write_band(&frame.top())?;
+            }
+            for band in preview_bands {
+                let band = match &frame {
+                    Some(frame) => frame.surround(&band, preview_y),
+                    None => band,
+                };
+                preview_y += band.height();
+                preview_outputs.write_band(&band)?;
+            }
+            if let Some(frame) = &frame {
+                preview_outputs.write_band(&frame.bottom())?;
+            }
+            preview_outputs.finish()?;
+            for (render_path, path) in render_paths.iter().zip(&output_image_paths) {
+                rename(render_path, path)?;
+            }
+            log!("wrote a preview of the output image");
+        }
+        let mut outputs = Outputs::new(
+            if tone_match {
+                Vec::new()
+            } else {
+                render_paths.clone()
+            },
+            output_width,
+            output_height,
+            !cvd_previews.is_empty()
+                || softproof.is_some()
+                || grid_overlay == Some(GridOverlay::Burn)
+                || split_layers
+                || sticker_sheet_path.is_some()
+                || report_path.is_some()
+                || tone_match,
+            output_color_space,
+        )?;
+        let mut report = report_path.as_ref().map(|_| RunReport::new(width, height));
+        let mut text = text_path.as_ref().map(|_| TextOutput::new(width, height));
+        if let Some(frame) = &frame
+            && has_top
+        {
+            outputs.write_band(&frame.top())?;
+        }
+        // Row of the output image where the next band goes, and row of the grid where it starts
+        let mut band_y = frame.as_ref().map_or(0, Frame::width);
+        let mut band_row = 0;
+        let mut remainder_fill = remainder_fill;
+        let mut write_output_band = |band: RgbImage| {
+            let row = band_row;
+            band_row += band.height() / tile_size.height;
+            if shard_rows.as_ref().is_some_and(|rows| !rows.contains(&row)) {
+                band_y += band.height();
+                return Ok(());
+            }
+            let bands = match &mut remainder_fill {
+                Some(fill) => fill.fit(&band),
+                None => vec![band],
+            };
+            for band in bands {
+                let band = match &frame {
+                    Some(frame) => frame.surround(&band, band_y),
+                    None => band,
+                };
+                band_y += band.height();
+                outputs.write_band(&band)?;
+            }
+            Ok(())
+        };
+        // Harmonizing the edges between bands holds each band back until the next one is rendered
+        let mut harmonizer = harmonize_strength.map(|strength| {
+            Harmonizer::new(
+                &cells,
+                width,
+                height,
+                tile_size,
+                strength,
+                harmonize_width.unwrap_or(tile_size.width.min(tile_size.height) / 4),
+            )
+        });
+        // Sharpening holds each band back until the rows below it are rendered, after harmonizing
+        let mut sharpener = sharpen_amount.map(|amount| {
+            let radius = sharpen_radius.unwrap_or(1.);
+            let edges = sharpen_edges.then(|| {
+                TileEdges::new(
+                    &cells,
+                    width,
+                    height,
+                    tile_size,
+                    (radius * 2.).ceil() as u32,
+                )
+            });
+            Sharpener::new(amount, radius, edges)
+        });
+        let mut write_band = |band: RgbImage| match &mut sharpener {
+            Some(sharpener) => sharpener.push(band).map_or(Ok(()), &mut write_output_band),
+            None => write_output_band(band),
+        };
+        let records = Records {
+            debug_output: debug_output.as_mut(),
+            placements: placements.as_mut(),
+            report: report.as_mut(),
+            text: text.as_mut(),
+        };
+        let stats = match layout {
+            Layout::Square => render(
+                palette,
+                &cells,
+                width,
+                height,
+                match_options,
+                records,
+                |band| match &mut harmonizer {
+                    Some(harmonizer) => harmonizer.push(band).map_or(Ok(()), &mut write_band),
+                    None => write_band(band),
+                },
+            )?,
+            // The rotated or strip image has to be rendered in full before it can be rotated or warped back
+            Layout::Rings | Layout::Spiral => {
+                let (strip_image, stats) =
+                    render_image(palette, &cells, width, height, match_options, records)?;
+                write_band(radial.as_ref().unwrap().image(
+                    &strip_image,
+                    tile_size.width,
+                    grid_width * tile_size.width,
+                    grid_height * tile_size.height,
+                ))?;
+                stats
+            }
+            Layout::Diamond => {
+                let (rotated_image, stats) =
+                    render_image(palette, &cells, width, height, match_options, records)?;
+                write_band(diamond_image(
+                    &rotated_image,
+                    grid_width * tile_size.width,
+                    grid_height * tile_size.height,
+                ))?;
+                stats
+            }
+        };
+        if let Some(band) = harmonizer.and_then(Harmonizer::finish) {
+            write_band(band)?;
+        }
+        if let Some(band) = sharpener.and_then(Sharpener::finish) {
+            write_output_band(band)?;
+        }
+        if let Some(frame) = &frame
+            && has_bottom
+        {
+            outputs.write_band(&frame.bottom())?;
+        }
+        let mut output_image = outputs.finish()?;
+        run_timings.push((stage("rendering"), render_start.elapsed().as_secs_f64()));
+        if let Some(output_image) = output_image.as_mut().filter(|_| tone_match) {
+            let tone_start = Instant::now();
+            // The curve is fitted to the mosaic alone, but applied to the frame too
+            let frame_width = frame.as_ref().map_or(0, Frame::width);
+            let curve = tone_curve(
+                &*output_image.view(frame_width, frame_width, mosaic_width, mosaic_height),
+                &input_image,
+            );
+            curve.apply(output_image);
+            let mut outputs = Outputs::new(
+                render_paths.clone(),
+                output_width,
+                output_height,
+                false,
+                output_color_space,
+            )?;
+            outputs.write_band(output_image)?;
+            outputs.finish()?;
+            run_timings.push((stage("tone matching"), tone_start.elapsed().as_secs_f64()));
+        }
+        if two_pass.is_some() {
+            for (render_path, path) in render_paths.iter().zip(&output_image_paths) {
+                rename(render_path, path)?;
+            }
+        }
+        written_paths.extend(output_image_paths.iter().cloned());
+        if memory_report {
+            report_memory(
+                &stage("rendering"),
+                &[
+                    ("palette tiles", palette.tile_memory()),
+                    ("output image bands (at most)", band_memory(palette, width)),
+                    ("placements cached by cell color", stats.match_cache_memory),
+                    (
+                        "full output image",
+                        output_image
+                            .as_ref()
+                            .map_or(0, |image| image.as_raw().len() as u64),
+                    ),
+                ],
+            );
+        }
+
+        // Cells with identical colors are matched once; few distinct colors means little variety in the output image
+        if stats.matched_count > 0 {
+            let color = if match_precision.is_some() {
+                "color bucket"
+            } else {
+                "color"
+            };
+            log!(
+                "matched {} cells with {} distinct {color}s ({:.1}% coalesced with an earlier cell of the same {color})",
+                format_count(stats.matched_count.into()),
+                format_count(stats.distinct_color_count.into()),
+                f64::from(stats.matched_count - stats.distinct_color_count)
+                    / f64::from(stats.matched_count)
+                    * 100.
+            );
+        }
+        if max_error.is_some() {
+            log!(
+                "{} of {} cells had no tile within the maximum error and were drawn as solid color",
+                format_count(stats.solid_count.into()),
+                format_count(cells.len() as u64)
+            );
+        }
 
-            output_image.put_pixel(x, y, Rgb(*tile_px.first_chunk().unwrap()));
+        if let Some(placements) = placements {
+            placements.finish()?;
+        }
+        if let (Some(text), Some(path), Some(glyphs)) = (&text, &text_path, &glyphs) {
+            text.write(&sized(path), &glyphs.chars)?;
+        }
+        if stop_after == Some(Stage::Match) {
+            let path = sized(placements_path.as_deref().unwrap());
+            log!(
+                "stopped after `match`: wrote the placements file to {}",
+                path.display()
+            );
+            written_paths.push(path);
+            continue;
         }
-    }
 
-    output_image.save(output_image_path)?;
+        if let Some(debug_output) = debug_output {
+            debug_output.save(&input_image, &palette.paths)?;
+        }
+
+        if let (Some(report), Some(path), Some(output_image)) =
+            (report, &report_path, &output_image)
+        {
+            report.write(
+                &sized(path),
+                &ReportContents {
+                    source_image: &source_image,
+                    target_image: &input_image,
+                    output_image,
+                    palette,
+                    palette_dir: &palette_dir_path,
+                    stats: &stats,
+                    timings: &run_timings,
+                },
+            )?;
+        }
+
+        if xmp_enabled {
+            let mut sidecar_paths: Vec<_> = output_image_paths
+                .iter()
+                .map(|path| sidecar_path(path))
+                .collect();
+            // Outputs in several formats (e.g. "out.png" and "out.jpg") share a sidecar
+            sidecar_paths.sort_unstable();
+            sidecar_paths.dedup();
+            for path in sidecar_paths {
+                write_sidecar(&path, &palette_dir_path, &palette.paths, &stats)?;
+            }
+        }
+
+        if let Some(grid_overlay) = grid_overlay {
+            let layer = grid_layer(
+                &cells,
+                width,
+                height,
+                tile_size,
+                frame.as_ref().map_or(0, Frame::width),
+                (output_width, output_height),
+            );
+            match grid_overlay {
+                GridOverlay::Burn => {
+                    // The output image is kept in memory for this
+                    let burned_image = burn_in(output_image.as_ref().unwrap(), &layer);
+                    for path in &output_image_paths {
+                        save_image(
+                            &burned_image,
+                            &with_suffix(path, GRID_OVERLAY_SUFFIX),
+                            output_color_space,
+                        )?;
+                    }
+                }
+                GridOverlay::Layer => {
+                    // The layer needs an alpha channel, so it is always a PNG image
+                    let mut layer_paths: Vec<_> = output_image_paths
+                        .iter()
+                        .map(|path| with_suffix(path, GRID_OVERLAY_SUFFIX).with_extension("png"))
+                        .collect();
+                    layer_paths.sort_unstable();
+                    layer_paths.dedup();
+                    for path in layer_paths {
+                        layer.save(path)?;
+                    }
+                }
+            }
+        }
+
+        if let Some(path) = &sticker_sheet_path {
+            let pages = sticker_sheets(
+                output_image.as_ref().unwrap(),
+                &cells,
+                tile_size,
+                frame.as_ref().map_or(0, Frame::width),
+                sticker_page,
+                sticker_dpi,
+            )?;
+            let path = sized(path);
+            // Sheets of several pages are numbered from 1, e.g. "stickers.1.png" and "stickers.2.png"
+            if let [page] = &pages[..] {
+                save_sheet(page, &path, sticker_dpi)?;
+            } else {
+                for (page, page_number) in pages.iter().zip(1..) {
+                    save_sheet(
+                        page,
+                        &with_suffix(&path, &page_number.to_string()),
+                        sticker_dpi,
+                    )?;
+                }
+            }
+            log!(
+                "wrote {} page(s) of stickers for {}",
+                format_count(pages.len() as u64),
+                path.display()
+            );
+        }
+
+        if split_layers {
+            let (tiles_layer, canvas_layer) = sparse_layers(
+                output_image.as_ref().unwrap(),
+                &cells,
+                tile_size,
+                frame.as_ref().map_or(0, Frame::width),
+            );
+            // Layers need an alpha channel, so they are always PNG images
+            let mut layer_paths: Vec<_> = output_image_paths
+                .iter()
+                .map(|path| path.with_extension("png"))
+                .collect();
+            layer_paths.sort_unstable();
+            layer_paths.dedup();
+            for path in layer_paths {
+                tiles_layer.save(with_suffix(&path, TILES_LAYER_SUFFIX))?;
+                canvas_layer.save(with_suffix(&path, CANVAS_LAYER_SUFFIX))?;
+            }
+        }
+
+        if let Some(output_image) = output_image {
+            for &deficiency in &cvd_previews {
+                let preview_image = simulate(&output_image, deficiency);
+                for path in &output_image_paths {
+                    save_image(
+                        &preview_image,
+                        &with_suffix(path, deficiency.name()),
+                        output_color_space,
+                    )?;
+                }
+            }
+            if let Some(softproof) = &softproof {
+                let proof_image = softproof.simulate(&output_image)?;
+                for path in &output_image_paths {
+                    save_image(
+                        &proof_image,
+                        &with_suffix(path, SOFTPROOF_SUFFIX),
+                        output_color_space,
+                    )?;
+                }
+            }
+        }
+    }
 
     Ok(())
 }
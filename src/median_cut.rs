@@ -0,0 +1,122 @@
+use crate::InputImage;
+use foldhash::{HashMap, HashMapExt};
+use quantette::palette::{IntoColor, LinSrgb, Oklab, Srgb};
+use std::ops::Range;
+
+// A distinct color of the image, with its number of pixels
+struct ColorCount {
+    rgb: [u8; 3],
+    oklab: [f32; 3],
+    count: u32,
+}
+
+// Quantizes an image to a palette of at most `color_count` colors (up to 256) by median cut in Oklab space,
+// for `--quantizer median-cut`. The set of the image's distinct colors is repeatedly split in two at the median of
+// whichever box of colors spans the widest range along any axis, and each box's colors are replaced by their mean.
+// Returns the palette and the index of each pixel's color in it.
+pub(crate) fn median_cut(image: &InputImage, color_count: usize) -> (Vec<Srgb<u8>>, Vec<u8>) {
+    let mut counts = HashMap::new();
+    for pixel in image.pixels() {
+        *counts.entry(pixel.0).or_insert(0) += 1;
+    }
+    let mut colors: Vec<_> = counts
+        .into_iter()
+        .map(|(rgb, count)| {
+            let oklab: Oklab = Srgb::from(rgb).into_linear().into_color();
+            ColorCount {
+                rgb,
+                oklab: [oklab.l, oklab.a, oklab.b],
+                count,
+            }
+        })
+        .collect();
+    // Sorting first makes the result independent of the hash map's iteration order
+    colors.sort_unstable_by_key(|color| color.rgb);
+
+    // Each box of colors, with the axis along which its colors span the widest range and that range
+    let mut boxes = vec![widest_axis(&colors, 0..colors.len())];
+    while boxes.len() < color_count {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, (range, _, _))| range.len() > 1)
+            .max_by(|(_, a), (_, b)| a.2.total_cmp(&b.2));
+        let Some((box_idx, &(ref range, axis, _))) = widest else {
+            // Every box holds a single color
+            break;
+        };
+
+        let range = range.clone();
+        let box_colors = &mut colors[range.clone()];
+        box_colors.sort_unstable_by(|a, b| {
+            a.oklab[axis]
+                .total_cmp(&b.oklab[axis])
+                .then(a.rgb.cmp(&b.rgb))
+        });
+        // Split where half of the box's pixels are on each side, leaving at least one color in each half
+        let half = box_colors
+            .iter()
+            .map(|color| u64::from(color.count))
+            .sum::<u64>()
+            / 2;
+        let mut cumulative = 0;
+        let split = box_colors
+            .iter()
+            .position(|color| {
+                cumulative += u64::from(color.count);
+                cumulative >= half
+            })
+            .map_or(1, |idx| idx + 1)
+            .clamp(1, box_colors.len() - 1);
+        boxes[box_idx] = widest_axis(&colors, range.start..range.start + split);
+        boxes.push(widest_axis(&colors, range.start + split..range.end));
+    }
+
+    let mut palette = Vec::with_capacity(boxes.len());
+    let mut indices_by_color = HashMap::with_capacity(colors.len());
+    for (palette_idx, (range, _, _)) in boxes.into_iter().enumerate() {
+        let box_colors = &colors[range];
+        let total = box_colors
+            .iter()
+            .map(|color| color.count as f32)
+            .sum::<f32>();
+        let mean = [0, 1, 2].map(|axis| {
+            box_colors
+                .iter()
+                .map(|color| color.oklab[axis] * color.count as f32)
+                .sum::<f32>()
+                / total
+        });
+        let linear: LinSrgb = Oklab::new(mean[0], mean[1], mean[2]).into_color();
+        palette.push(Srgb::from_linear(LinSrgb::new(
+            linear.red.clamp(0., 1.),
+            linear.green.clamp(0., 1.),
+            linear.blue.clamp(0., 1.),
+        )));
+        for color in box_colors {
+            indices_by_color.insert(color.rgb, palette_idx as u8);
+        }
+    }
+
+    let indices = image
+        .pixels()
+        .map(|pixel| indices_by_color[&pixel.0])
+        .collect();
+    (palette, indices)
+}
+
+// Finds the axis (L, a, or b) along which a box of colors spans the widest range, and that range.
+fn widest_axis(colors: &[ColorCount], range: Range<usize>) -> (Range<usize>, usize, f32) {
+    let (axis, extent) = (0..3)
+        .map(|axis| {
+            let (min, max) = colors[range.clone()]
+                .iter()
+                .fold((f32::MAX, f32::MIN), |(min, max), color| {
+                    (min.min(color.oklab[axis]), max.max(color.oklab[axis]))
+                });
+            (axis, max - min)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap();
+    (range, axis, extent)
+}
@@ -0,0 +1,38 @@
+use crate::{cache::format_size, logging::log};
+use std::fs::read_to_string;
+
+// Prints the resident set size of the process (current and peak so far) after a stage of a run, for `--memory-report`,
+// along with the sizes of the major allocations alive at that point, in bytes.
+pub(crate) fn report_memory(stage: &str, allocations: &[(&str, u64)]) {
+    let resident = match resident_set_size() {
+        Some((current, peak)) => format!(
+            "{} resident (peak {})",
+            format_size(current),
+            format_size(peak)
+        ),
+        None => "resident set size unavailable on this platform".to_owned(),
+    };
+    log!("memory after {stage}: {resident}");
+
+    for (name, size) in allocations {
+        log!("  {name}: {}", format_size(*size));
+    }
+}
+
+// Reads the current and peak resident set size of the process, in bytes. Only Linux (via /proc) is supported.
+fn resident_set_size() -> Option<(u64, u64)> {
+    let status = read_to_string("/proc/self/status").ok()?;
+    let field = |name: &str| -> Option<u64> {
+        let kilobytes = status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))?
+            .trim()
+            .strip_suffix("kB")?
+            .trim()
+            .parse::<u64>()
+            .ok()?;
+        Some(kilobytes * 1024)
+    };
+
+    Some((field("VmRSS:")?, field("VmHWM:")?))
+}
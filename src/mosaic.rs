@@ -0,0 +1,943 @@
+use crate::{
+    INPUT_PIXEL_COMPONENT_MAX, InputImage, TILE_PIXEL_COMPONENT_MAX_INT, TILE_PIXEL_SIZE,
+    TilePixel,
+    cell_mask::CellMask,
+    color::{linear_srgb_to_oklab, oklab_to_linear_srgb},
+    debug::DebugOutput,
+    draw::{Shadow, TileDrawer},
+    glyphs::TextOutput,
+    layout::{Cell, Pinned},
+    median_cut::median_cut,
+    palette::Palette,
+    pixel::PixelLayout,
+    placements::Placements,
+    report::RunReport,
+    rng::hash,
+};
+use anyhow::{Result, bail};
+use foldhash::{HashMap, HashMapExt, HashSet};
+use image::{GenericImage, ImageBuffer, Rgb, RgbImage};
+use kiddo::{NearestNeighbour, SquaredEuclidean};
+use quantette::{
+    ColorSpace, FloydSteinberg, ImagePipeline, PaletteSize,
+    palette::{IntoColor, Oklab, Srgb},
+};
+use rayon::prelude::*;
+use std::{
+    num::NonZero,
+    str::FromStr,
+    sync::{
+        Arc,
+        mpsc::{Receiver, SyncSender, sync_channel},
+    },
+    thread::scope,
+};
+
+// Options controlling how cells are matched with tiles, and how tiles are drawn
+#[derive(Clone, Copy, Default)]
+pub(crate) struct MatchOptions<'a> {
+    // Maximum color distance between a cell and its tile; cells without a close enough tile are drawn as solid color
+    pub(crate) max_error: Option<f32>,
+    // Color distance above which a cell is drawn as a blend of its two nearest tiles
+    pub(crate) blend_threshold: Option<f32>,
+    // If the palette has preferences, color distance from the nearest tile within which tiles count as a near-tie,
+    // with the most preferred of them being placed
+    pub(crate) weight_margin: f32,
+    // Number of nearest tiles that each cell's tile is chosen among, for variety; 0 or 1 always places the nearest
+    pub(crate) variety: usize,
+    // How the choice among the nearest tiles is made for each cell, with `variety`
+    pub(crate) variety_noise: VarietyNoise,
+    // Color distance from a cell's tile within which other tiles count as equally good matches, one of them being
+    // chosen for each cell by its position and `seed`; `None` always places the tile found
+    pub(crate) tie_epsilon: Option<f32>,
+    // Size of the Oklab buckets that cell colors are grouped into, so cells whose colors fall in the same bucket
+    // share one nearest-neighbor query; `None` only shares queries between cells of exactly the same color
+    pub(crate) match_precision: Option<f32>,
+    // Largest share of the cells that any one palette image is placed in; cells whose tile has reached it
+    // get the nearest tile that hasn't, or are drawn as solid color if every tile has
+    pub(crate) max_usage_share: Option<f32>,
+    // Seed for the choice among the nearest tiles, with `variety`, among equally good tiles, with `tie_epsilon`, and
+    // for the angle of each tile, with `rotate_jitter`
+    pub(crate) seed: u64,
+    // Largest angle (in degrees, either way) that each tile is rotated by, chosen at random for each cell;
+    // 0 draws tiles upright
+    pub(crate) rotate_jitter: f32,
+    // Angle (in degrees) that each cell's tile is rotated by, keyed by the position of the cell's top-left corner,
+    // instead of one chosen with `rotate_jitter`; cells without one are drawn upright. Read from a placements file.
+    pub(crate) rotations: Option<&'a HashMap<(u32, u32), f32>>,
+    // Color showing around rotated tiles, with `rotate_jitter`, and through `cell_mask`
+    pub(crate) grout: [u8; 3],
+    // Shape that every tile is cut to before it is drawn, if any
+    pub(crate) cell_mask: Option<&'a CellMask>,
+    // Shadow that every tile casts on the grout around it, if any
+    pub(crate) shadow: Option<Shadow>,
+}
+
+// Noise that chooses among each cell's nearest tiles, for `--variety`
+#[derive(Clone, Copy, Default)]
+pub(crate) enum VarietyNoise {
+    // Independent for each cell, so the same tile can land in neighboring cells and repeated tiles clump together
+    #[default]
+    White,
+    // Following the R2 sequence over the grid (as in `perturb`), so neighboring cells get very different choices
+    // and repeated tiles are spaced evenly
+    Blue,
+}
+
+impl FromStr for VarietyNoise {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "white" => Ok(Self::White),
+            "blue" => Ok(Self::Blue),
+            _ => bail!("expected \"white\" or \"blue\""),
+        }
+    }
+}
+
+impl VarietyNoise {
+    // Returns a number in [0, 1) for the cell at (x, y).
+    fn sample(self, seed: u64, x: u32, y: u32) -> f64 {
+        // Scales the top 53 bits of a pseudorandom number to [0, 1)
+        let unit = |bits: u64| (bits >> 11) as f64 / (1u64 << 53) as f64;
+
+        match self {
+            Self::White => unit(hash(seed ^ hash(u64::from(y) << 32 | u64::from(x)))),
+            Self::Blue => (unit(hash(seed)) + f64::from(x) * R2_X + f64::from(y) * R2_Y).fract(),
+        }
+    }
+}
+
+// The R2 low-discrepancy sequence, indexed by pixel position, has a blue-noise-like spectrum:
+// neighboring pixels get very different offsets, so the pattern has no visible low-frequency structure.
+// From https://extremelearning.com.au/unreasonable-effectiveness-of-quasirandom-sequences/
+const R2_X: f64 = 0.754_877_666_246_692_7;
+const R2_Y: f64 = 0.569_840_290_998_053_3;
+
+// How a cell is drawn in the output image
+enum Placement {
+    // The tile in the palette nearest to the cell's color, by index
+    Tile(usize),
+    // A blend of the two tiles in the palette nearest to the cell's color.
+    // The index of the nearest tile is kept alongside the blended image.
+    Blend(usize, Vec<u8>),
+    // A tile of the cell's color, used when no tile in the palette is close enough
+    Solid,
+}
+
+impl Placement {
+    // Returns the index of the (nearest) palette image used by this placement, if any.
+    fn tile_index(&self) -> Option<usize> {
+        match self {
+            Self::Tile(tile_index) | Self::Blend(tile_index, _) => Some(*tile_index),
+            Self::Solid => None,
+        }
+    }
+}
+
+// Statistics about how the output image was produced
+pub(crate) struct RenderStats {
+    // Number of cells drawn as solid color because no tile was close enough
+    pub(crate) solid_count: u32,
+    // Number of cells each palette image was placed in, indexed the same way as the palette's paths
+    pub(crate) tile_uses: Vec<u32>,
+    // Size of the cache of placements by cell color, including blended tiles, in bytes
+    pub(crate) match_cache_memory: u64,
+    // Number of cells matched by color (i.e. not pinned)
+    pub(crate) matched_count: u32,
+    // Number of distinct colors (and spans) among cells matched by color, i.e. of entries in the cache of placements.
+    // Every other matched cell was a cache hit, coalesced with an earlier cell of the same color
+    // (or of a color in the same Oklab bucket, with a match precision).
+    pub(crate) distinct_color_count: u32,
+}
+
+// Optional records of how the output image was produced, written while rendering
+#[derive(Default)]
+pub(crate) struct Records<'a> {
+    pub(crate) debug_output: Option<&'a mut DebugOutput>,
+    pub(crate) placements: Option<&'a mut Placements>,
+    pub(crate) report: Option<&'a mut RunReport>,
+    pub(crate) text: Option<&'a mut TextOutput>,
+}
+
+// Number of nearest tiles considered when breaking near-ties by preference
+const PREFERENCE_CANDIDATE_COUNT: usize = 16;
+
+// Maximum number of bands that can be queued between two stages of rendering.
+// This bounds memory usage when a later stage (e.g. encoding) is slower than an earlier one.
+const BAND_QUEUE_CAPACITY: usize = 4;
+
+// Number of rows in each band of the input image that is dithered in parallel with the others
+const DITHER_BAND_HEIGHT: usize = 256;
+
+// Largest number of colors in the palette that the input image is quantized to before dithering, which is used unless
+// `--max-cell-colors` asks for fewer; the same as quantette's default and maximum
+pub(crate) const MAX_QUANTIZED_COLOR_COUNT: u16 = 256;
+
+// How the palette that the input image is quantized to before dithering is chosen, for `--quantizer`
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Quantizer {
+    // quantette's quantizer (Wu's method), in Oklab space
+    #[default]
+    Quantette,
+    // Median cut, in Oklab space
+    MedianCut,
+    // No quantization (and so no dithering); the input image is matched as is
+    None,
+}
+
+impl FromStr for Quantizer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "quantette" => Ok(Self::Quantette),
+            "median-cut" => Ok(Self::MedianCut),
+            "none" => Ok(Self::None),
+            _ => bail!("expected \"quantette\", \"median-cut\", or \"none\""),
+        }
+    }
+}
+
+// Quantizes the input image to at most `color_count` colors with the given quantizer and applies Floyd-Steinberg
+// dithering to it.
+pub(crate) fn dither(
+    image: &InputImage,
+    quantizer: Quantizer,
+    color_count: u16,
+) -> Result<InputImage> {
+    let (palette, mut indices) = match quantizer {
+        Quantizer::Quantette => {
+            let mut pipeline = ImagePipeline::try_from(image)?;
+            pipeline
+                .colorspace(ColorSpace::Oklab)
+                .palette_size(PaletteSize::from_clamped(color_count));
+            if image.height() as usize <= DITHER_BAND_HEIGHT {
+                return Ok(pipeline.quantized_rgbimage());
+            }
+            pipeline.dither(false).indexed_palette()
+        }
+        Quantizer::MedianCut => median_cut(image, usize::from(color_count)),
+        Quantizer::None => return Ok(image.clone()),
+    };
+
+    // quantette's parallel dithering splits the image into one chunk per thread, so its output depends on
+    // the number of threads. Instead, the image is split into bands of a fixed height, which are dithered in parallel.
+    // Each band after the first is dithered along with the last row of the band above it, which is then discarded,
+    // so error is diffused into the band's first row much like it would be if the whole image were dithered serially.
+    let width = image.width() as usize;
+    let oklab = |color: Srgb<u8>| -> Oklab { color.into_linear().into_color() };
+    let oklab_palette: Vec<_> = palette.iter().copied().map(oklab).collect();
+    let colors: Vec<_> = image
+        .pixels()
+        .map(|pixel| oklab(Srgb::from(pixel.0)))
+        .collect();
+
+    let band_len = width * DITHER_BAND_HEIGHT;
+    indices
+        .par_chunks_mut(band_len)
+        .enumerate()
+        .for_each(|(band_idx, band_indices)| {
+            let band_start = band_idx * band_len;
+            let start = band_start.saturating_sub(width);
+            let end = band_start + band_indices.len();
+
+            let mut seeded_indices = band_indices[..band_start - start].to_vec();
+            seeded_indices.extend_from_slice(band_indices);
+            FloydSteinberg::new().dither(
+                &oklab_palette,
+                &mut seeded_indices,
+                &colors[start..end],
+                width as u32,
+                ((end - start) / width) as u32,
+            );
+            band_indices.copy_from_slice(&seeded_indices[band_start - start..]);
+        });
+
+    Ok(RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let idx = y as usize * width + x as usize;
+        Rgb(palette[usize::from(indices[idx])].into())
+    }))
+}
+
+// Dithers the input image like `dither`, with the largest color count for which the processed input image has at most
+// `max_distinct_count` distinct colors, for `--max-cell-colors`. Every distinct cell color costs a search for its
+// nearest tile, so this bounds the cost of matching on very large grids while keeping as much variety as fits. The
+// colors are counted after `--noise` (with `noise_amplitude`) perturbs them, which can split each quantized color into
+// many, but the returned image isn't perturbed. If even a single color is too many, the image is quantized to one
+// color. Returns the dithered image, its color count, and its number of distinct colors once processed.
+pub(crate) fn dither_to_distinct_count(
+    image: &InputImage,
+    quantizer: Quantizer,
+    max_distinct_count: u32,
+    noise_amplitude: Option<f32>,
+) -> Result<(InputImage, u16, u32)> {
+    let dither_counted = |color_count| -> Result<_> {
+        let dithered = dither(image, quantizer, color_count)?;
+        let distinct_count = match noise_amplitude {
+            Some(amplitude) => perturb(&dithered, amplitude)
+                .pixels()
+                .collect::<HashSet<_>>()
+                .len(),
+            None => dithered.pixels().collect::<HashSet<_>>().len(),
+        };
+        Ok((dithered, color_count, distinct_count as u32))
+    };
+
+    // The number of distinct colors grows with the color count, so the largest color count within the target is found
+    // by binary search, after trying the full color count, which most images are within the target with
+    let full = dither_counted(MAX_QUANTIZED_COLOR_COUNT)?;
+    if full.2 <= max_distinct_count {
+        return Ok(full);
+    }
+    let mut best = None;
+    let (mut low, mut high) = (1, MAX_QUANTIZED_COLOR_COUNT - 1);
+    while low <= high {
+        let color_count = low.midpoint(high);
+        let counted = dither_counted(color_count)?;
+        if counted.2 <= max_distinct_count {
+            best = Some(counted);
+            low = color_count + 1;
+        } else {
+            high = color_count - 1;
+        }
+    }
+    match best {
+        Some(best) => Ok(best),
+        None => dither_counted(1),
+    }
+}
+
+// Perturbs the lightness of each pixel of the input image by up to `amplitude` in either direction,
+// following a blue-noise-like pattern. This breaks up visible contours where smooth gradients in the input image
+// would otherwise be covered by large runs of the same tile, which is most noticeable when dithering is disabled.
+pub(crate) fn perturb(image: &InputImage, amplitude: f32) -> InputImage {
+    // Follows the R2 sequence, in single precision
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let noise = (x as f32 * R2_X as f32 + y as f32 * R2_Y as f32).fract() * 2. - 1.;
+        let [l, a, b] = cell_oklab(*image.get_pixel(x, y));
+        let rgb = oklab_to_linear_srgb(l + noise * amplitude, a, b);
+        Rgb(rgb.map(|c| (c.clamp(0., 1.) * INPUT_PIXEL_COMPONENT_MAX).round() as u8))
+    })
+}
+
+// Quantizes the lightness of each pixel of the input image into `band_count` equal bands, replacing it with the middle
+// of its band, for stylized, poster-like output images. Hue and chroma are kept.
+pub(crate) fn posterize(image: &InputImage, band_count: u32) -> InputImage {
+    let band_count = band_count as f32;
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let [l, a, b] = cell_oklab(*image.get_pixel(x, y));
+        let band = (l.clamp(0., 1.) * band_count).floor().min(band_count - 1.);
+        let rgb = oklab_to_linear_srgb((band + 0.5) / band_count, a, b);
+        Rgb(rgb.map(|c| (c.clamp(0., 1.) * INPUT_PIXEL_COMPONENT_MAX).round() as u8))
+    })
+}
+
+// Draws the output image by covering each cell with the tile nearest to the cell's color.
+// `width` and `height` are the dimensions of the input image the cells were laid out on.
+//
+// Rendering is a streaming pipeline: the output image is produced in horizontal bands, each as tall as
+// the largest cell span, and each band moves through matching, composition, and `write_band` on separate threads.
+// `write_band` receives every band of the output image in order from top to bottom.
+// Since stages are connected by bounded queues, only a few bands are held in memory at a time.
+pub(crate) fn render(
+    palette: &Palette,
+    cells: &[Cell],
+    width: u32,
+    height: u32,
+    options: MatchOptions<'_>,
+    records: Records<'_>,
+    write_band: impl FnMut(RgbImage) -> Result<()> + Send,
+) -> Result<RenderStats> {
+    let band_span = *palette.spans.last().unwrap();
+
+    // Group cells by band, from top to bottom
+    let mut bands = vec![Vec::new(); height.div_ceil(band_span) as usize];
+    for cell in cells {
+        bands[(cell.y / band_span) as usize].push(cell);
+    }
+
+    let (placement_sender, placement_receiver) = sync_channel(BAND_QUEUE_CAPACITY);
+    let (band_sender, band_receiver) = sync_channel(BAND_QUEUE_CAPACITY);
+
+    scope(|scope| {
+        let composer = scope.spawn(move || {
+            compose_bands(
+                palette,
+                width,
+                height,
+                &placement_receiver,
+                &band_sender,
+                options,
+                records.debug_output,
+            )
+        });
+        let writer = scope.spawn(move || -> Result<()> {
+            let mut write_band = write_band;
+            for band in band_receiver {
+                write_band(band)?;
+            }
+            Ok(())
+        });
+
+        let stats = match_bands(
+            palette,
+            bands,
+            options,
+            &placement_sender,
+            records.placements,
+            records.report,
+            records.text,
+        );
+        drop(placement_sender);
+
+        composer.join().unwrap()?;
+        writer.join().unwrap()?;
+
+        stats
+    })
+}
+
+// Upper bound on the size of the bands of the output image held in memory at once while rendering, in bytes:
+// a full queue of bands, plus the bands being composed and written.
+pub(crate) fn band_memory(palette: &Palette, width: u32) -> u64 {
+    let band_span = *palette.spans.last().unwrap();
+    let band_size = u64::from(width * palette.tile_size.width)
+        * u64::from(band_span * palette.tile_size.height)
+        * 3;
+    (BAND_QUEUE_CAPACITY as u64 + 2) * band_size
+}
+
+// Draws the output image in full by collecting the bands produced by `render`.
+pub(crate) fn render_image(
+    palette: &Palette,
+    cells: &[Cell],
+    width: u32,
+    height: u32,
+    options: MatchOptions<'_>,
+    records: Records<'_>,
+) -> Result<(RgbImage, RenderStats)> {
+    let tile_size = palette.tile_size;
+    let mut output_image = RgbImage::new(width * tile_size.width, height * tile_size.height);
+    let mut y = 0;
+
+    let stats = render(palette, cells, width, height, options, records, |band| {
+        output_image.copy_from(&band, 0, y)?;
+        y += band.height();
+        Ok(())
+    })?;
+
+    Ok((output_image, stats))
+}
+
+// Cells of a band along with how each of them is drawn
+type PlacedBand<'a> = Vec<(&'a Cell, Arc<Placement>)>;
+
+// Decides how to draw every cell, sending the results band by band. Stops early if the receiver hangs up.
+fn match_bands<'a>(
+    palette: &Palette,
+    bands: Vec<Vec<&'a Cell>>,
+    options: MatchOptions<'_>,
+    sender: &SyncSender<PlacedBand<'a>>,
+    mut placements: Option<&mut Placements>,
+    mut report: Option<&mut RunReport>,
+    mut text: Option<&mut TextOutput>,
+) -> Result<RenderStats> {
+    // Cache nearest-neighbor queries to avoid repeating work, by cell color (see `cache_key`)
+    // Heuristic for initial capacity: probably fewer than half of the cells have unique colors.
+    // Even if this ends up being incorrect, the capacity will simply double and will never double again.
+    // (Except when the number of cells is odd and every cell has a unique color...)
+    let cell_count: usize = bands.iter().map(Vec::len).sum();
+    let mut palette_cache = HashMap::with_capacity(cell_count / 2);
+    // Nearest tiles to each cell color, for variety
+    let mut variety_cache = HashMap::new();
+    // Tiles about as near to each cell color as its tile, with `tie_epsilon`, by cell color and span
+    let mut tie_cache = HashMap::new();
+    let mut solid_count: u32 = 0;
+    let mut matched_count: u32 = 0;
+    let mut tile_uses = vec![0; palette.paths.len()];
+    // Cells are matched from top to bottom, so with a usage cap, the top of the grid gets the first pick of tiles
+    let max_uses = options
+        .max_usage_share
+        .map(|share| (share * cell_count as f32).ceil() as u32);
+
+    for band in bands {
+        let mut placed_band = Vec::with_capacity(band.len());
+
+        for cell in band {
+            // Get the tile with average color "nearest" to the color of the current cell
+            let (placement, error) = match cell.pinned {
+                // Pinned cells are drawn as pinned regardless of color
+                Some(Pinned::Tile(tile_idx)) => {
+                    let [l, a, b] = cell_oklab(cell.color);
+                    let [tile_l, tile_a, tile_b] = palette.colors[tile_idx];
+                    let error =
+                        ((l - tile_l).powi(2) + (a - tile_a).powi(2) + (b - tile_b).powi(2)).sqrt();
+                    (Arc::new(Placement::Tile(tile_idx)), error)
+                }
+                Some(Pinned::Solid) => (Arc::new(Placement::Solid), 0.),
+                None => {
+                    matched_count += 1;
+                    palette_cache
+                        .entry((cache_key(cell.color, options.match_precision), cell.span))
+                        .or_insert_with(|| {
+                            let (placement, error) =
+                                find_placement(palette, cell.span, cell.color, options);
+                            (Arc::new(placement), error)
+                        })
+                        .clone()
+                }
+            };
+
+            // For variety, a cell that would get its nearest tile gets one of its nearest few tiles instead
+            let (placement, error) = match &*placement {
+                Placement::Tile(_) if cell.pinned.is_none() && options.variety > 1 => {
+                    let candidates = variety_cache
+                        .entry(cell.color)
+                        .or_insert_with(|| nearest_tiles(palette, cell.color, options.variety));
+                    let sample = options.variety_noise.sample(options.seed, cell.x, cell.y);
+                    let (tile_idx, error) = candidates
+                        [((sample * candidates.len() as f64) as usize).min(candidates.len() - 1)];
+                    (Arc::new(Placement::Tile(tile_idx)), error)
+                }
+                _ => (placement, error),
+            };
+
+            // A cell whose tile ties with other tiles gets one of them, chosen by the cell's position alone, so
+            // changing other cells never changes which of them it gets. With a match precision, the tile found for
+            // the cell's bucket depends on which of its cells was matched first, so ties are found from the cell's
+            // own nearest tile instead.
+            let (placement, error) = match (&*placement, options.tie_epsilon) {
+                (&Placement::Tile(tile_idx), Some(tie_epsilon)) if cell.pinned.is_none() => {
+                    let candidates =
+                        tie_cache.entry((cell.color, cell.span)).or_insert_with(|| {
+                            let own_placement = options
+                                .match_precision
+                                .map(|_| find_placement(palette, cell.span, cell.color, options));
+                            match own_placement {
+                                Some((Placement::Tile(own_idx), own_error)) => {
+                                    tied_tiles(palette, cell.color, own_idx, own_error, tie_epsilon)
+                                }
+                                _ => tied_tiles(palette, cell.color, tile_idx, error, tie_epsilon),
+                            }
+                        });
+                    let &(tile_idx, error) =
+                        &candidates[tie_choice(options.seed, cell, candidates.len())];
+                    (Arc::new(Placement::Tile(tile_idx)), error)
+                }
+                _ => (placement, error),
+            };
+
+            // A cell whose tile has been placed as often as the usage cap allows gets the nearest tile that hasn't.
+            // Pinned cells and cells drawn as blends count towards the cap but are not held to it.
+            let (placement, error) = match (&*placement, max_uses) {
+                (Placement::Tile(tile_idx), Some(max_uses))
+                    if cell.pinned.is_none() && tile_uses[*tile_idx] >= max_uses =>
+                {
+                    match nearest_uncapped_tile(palette, cell.color, &tile_uses, max_uses) {
+                        Some((tile_idx, error))
+                            if options.max_error.is_none_or(|max_error| error <= max_error) =>
+                        {
+                            (Arc::new(Placement::Tile(tile_idx)), error)
+                        }
+                        _ => (Arc::new(Placement::Solid), error),
+                    }
+                }
+                _ => (placement, error),
+            };
+
+            match placement.tile_index() {
+                Some(tile_idx) => tile_uses[tile_idx] += 1,
+                None => solid_count += 1,
+            }
+
+            if let Some(placements) = &mut placements {
+                let source = placement
+                    .tile_index()
+                    .map(|tile_idx| (palette.paths[tile_idx].as_path(), error));
+                let alternates: Vec<_> = if placements.alternate_count() > 0 {
+                    nearest_tiles(palette, cell.color, placements.alternate_count() + 1)
+                        .into_iter()
+                        .filter(|&(tile_idx, _)| Some(tile_idx) != placement.tile_index())
+                        .take(placements.alternate_count())
+                        .map(|(tile_idx, error)| (palette.paths[tile_idx].as_path(), error))
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                placements.record(cell, source, rotation_angle(options, cell), &alternates)?;
+            }
+            if let Some(report) = &mut report {
+                report.record(cell, error);
+            }
+            if let Some(text) = &mut text {
+                text.record(cell, placement.tile_index());
+            }
+
+            placed_band.push((cell, placement));
+        }
+
+        if sender.send(placed_band).is_err() {
+            break;
+        }
+    }
+
+    let match_cache_memory = palette_cache
+        .values()
+        .map(|(placement, _): &(Arc<Placement>, f32)| {
+            let blended_size = match &**placement {
+                Placement::Blend(_, blended_image) => blended_image.len(),
+                _ => 0,
+            };
+            (size_of::<(([i32; 3], u32), (Arc<Placement>, f32))>()
+                + size_of::<Placement>()
+                + blended_size) as u64
+        })
+        .sum();
+
+    Ok(RenderStats {
+        solid_count,
+        tile_uses,
+        match_cache_memory,
+        matched_count,
+        distinct_color_count: palette_cache.len() as u32,
+    })
+}
+
+// Draws each band of cells into an image, sending the results band by band. Stops early if the receiver hangs up.
+// Fails if a palette that doesn't keep its tiles can't decode one.
+fn compose_bands(
+    palette: &Palette,
+    width: u32,
+    height: u32,
+    receiver: &Receiver<PlacedBand<'_>>,
+    sender: &SyncSender<RgbImage>,
+    options: MatchOptions<'_>,
+    mut debug_output: Option<&mut DebugOutput>,
+) -> Result<()> {
+    let tile_size = palette.tile_size;
+    let band_span = *palette.spans.last().unwrap();
+
+    // Buffer for cells whose nearest tile is too far away; these are drawn as a tile of solid color.
+    // It is large enough to hold a tile for a cell of any span.
+    let max_tile_pixel_count = (band_span * tile_size.width) * (band_span * tile_size.height);
+    let mut solid_tile =
+        vec![TILE_PIXEL_COMPONENT_MAX_INT; max_tile_pixel_count as usize * TILE_PIXEL_SIZE];
+    // Draws tiles rotated by `rotate_jitter`, cut to `cell_mask`, or casting a `shadow`, if any of them are used
+    let mut tile_drawer = (options.rotate_jitter > 0.
+        || options.rotations.is_some()
+        || options.cell_mask.is_some()
+        || options.shadow.is_some())
+    .then(|| TileDrawer::new(max_tile_pixel_count as usize));
+    let mut drawn_tile = if tile_drawer.is_some() {
+        vec![0; max_tile_pixel_count as usize * TILE_PIXEL_SIZE]
+    } else {
+        Vec::new()
+    };
+    // Buffer for crops within palette images, with `--source-crops`, also large enough for a cell of any span
+    let mut crop_tile = if palette.has_crops() {
+        vec![0; max_tile_pixel_count as usize * TILE_PIXEL_SIZE]
+    } else {
+        Vec::new()
+    };
+
+    for (placed_band, band_idx) in receiver.iter().zip(0..) {
+        // The last band is shorter if the height of the input image isn't a multiple of the band span
+        let band_y = band_idx * band_span;
+        let band_height = band_span.min(height - band_y);
+        let mut band_image = RgbImage::new(width * tile_size.width, band_height * tile_size.height);
+
+        for (cell, placement) in placed_band {
+            let cell_tile_width = cell.span * tile_size.width;
+            let cell_tile_height = cell.span * tile_size.height;
+
+            let tile;
+            let palette_image: &[u8] = match &*placement {
+                Placement::Tile(tile_idx) if palette.has_crops() => {
+                    let crop_tile = &mut crop_tile
+                        [..(cell_tile_width * cell_tile_height) as usize * TILE_PIXEL_SIZE];
+                    palette.crop(*tile_idx, cell.span, &cell_oklab(cell.color), crop_tile);
+                    crop_tile
+                }
+                Placement::Tile(tile_idx) => {
+                    tile = palette.tile(*tile_idx, cell.span)?;
+                    &tile
+                }
+                Placement::Blend(_, blended_image) => blended_image,
+                Placement::Solid => {
+                    let solid_tile = &mut solid_tile
+                        [..(cell_tile_width * cell_tile_height) as usize * TILE_PIXEL_SIZE];
+                    for px in solid_tile.as_chunks_mut::<TILE_PIXEL_SIZE>().0 {
+                        px[..3].copy_from_slice(&cell.color.0);
+                    }
+                    solid_tile
+                }
+            };
+
+            if let Some(debug_output) = &mut debug_output {
+                for y in cell.y..cell.y + cell.span {
+                    for x in cell.x..cell.x + cell.span {
+                        debug_output.record(x, y, placement.tile_index(), palette_image);
+                    }
+                }
+            }
+
+            let palette_image = if let Some(tile_drawer) = &mut tile_drawer {
+                let drawn_tile = &mut drawn_tile[..palette_image.len()];
+                tile_drawer.draw(
+                    palette_image,
+                    (cell_tile_width, cell_tile_height, cell.span),
+                    rotation_angle(options, cell).to_radians(),
+                    options,
+                    drawn_tile,
+                );
+                drawn_tile
+            } else {
+                palette_image
+            };
+
+            place_tile::<TilePixel, _>(
+                &mut band_image,
+                palette_image,
+                cell_tile_width,
+                (
+                    cell.x * tile_size.width,
+                    (cell.y - band_y) * tile_size.height,
+                ),
+            );
+        }
+
+        if sender.send(band_image).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Places each pixel of a tile `tile_width` pixels wide, with channels in the layout of `T`, in a band of the output
+// image with its top-left corner at (`x`, `y`). Pixels are converted to the band's layout by keeping their leading
+// channels, so tiles composited over black lose their alpha channel in an RGB band and keep it in an RGBA one.
+fn place_tile<T: PixelLayout, O: PixelLayout<Subpixel = T::Subpixel>>(
+    band_image: &mut ImageBuffer<O, Vec<O::Subpixel>>,
+    tile: &[T::Subpixel],
+    tile_width: u32,
+    (x, y): (u32, u32),
+) {
+    for (tile_px, px_idx) in tile.chunks_exact(T::SIZE).zip(0..) {
+        band_image.put_pixel(
+            x + px_idx % tile_width,
+            y + px_idx / tile_width,
+            *O::from_slice(&tile_px[..O::SIZE]),
+        );
+    }
+}
+
+// Key that a cell's color is cached under when matching: the color itself, or with a precision,
+// the Oklab bucket of that size containing it, so that nearly identical colors (e.g. in noisy input images)
+// share a single placement, found for the first of them to be matched.
+fn cache_key(color: Rgb<u8>, precision: Option<f32>) -> [i32; 3] {
+    match precision {
+        Some(precision) => cell_oklab(color).map(|c| (c / precision).floor() as i32),
+        None => color.0.map(i32::from),
+    }
+}
+
+// Finds the angle (in degrees) that a cell's tile is rotated by: its angle in `rotations`, if given, or else one
+// chosen for `rotate_jitter`, independently for each cell.
+fn rotation_angle(options: MatchOptions<'_>, cell: &Cell) -> f32 {
+    if let Some(rotations) = options.rotations {
+        return rotations.get(&(cell.x, cell.y)).copied().unwrap_or(0.);
+    }
+    if options.rotate_jitter <= 0. {
+        return 0.;
+    }
+    // Drawn from a different stream than the choice of tile for `variety`, so the two aren't correlated
+    let bits = hash(hash(options.seed) ^ hash(u64::from(cell.y) << 32 | u64::from(cell.x)));
+    // Scales the top 24 bits to [-1, 1)
+    let unit = (bits >> 40) as f32 / (1u32 << 23) as f32 - 1.;
+    unit * options.rotate_jitter
+}
+
+// Converts the color of a cell to Oklab space.
+pub(crate) fn cell_oklab(color: Rgb<u8>) -> [f32; 3] {
+    let r = f32::from(color[0]) / INPUT_PIXEL_COMPONENT_MAX;
+    let g = f32::from(color[1]) / INPUT_PIXEL_COMPONENT_MAX;
+    let b = f32::from(color[2]) / INPUT_PIXEL_COMPONENT_MAX;
+    linear_srgb_to_oklab(r, g, b)
+}
+
+// Finds up to `count` tiles nearest to the given color, nearest first, with their color distances.
+fn nearest_tiles(palette: &Palette, color: Rgb<u8>, count: usize) -> Vec<(usize, f32)> {
+    nearest_candidates(palette, &cell_oklab(color), count)
+        .into_iter()
+        .map(|neighbor| (neighbor.item as usize, neighbor.distance.sqrt()))
+        .collect()
+}
+
+// Finds the tiles whose color distance to the given color is within `epsilon` of that of the tile at `tile_idx` (which
+// is `distance`), including that tile, in order of palette index. If the palette has preferences, only tiles as
+// preferred as that tile count, so ties never undo a preference.
+fn tied_tiles(
+    palette: &Palette,
+    color: Rgb<u8>,
+    tile_idx: usize,
+    distance: f32,
+    epsilon: f32,
+) -> Vec<(usize, f32)> {
+    let limit = distance + epsilon;
+    let mut tied: Vec<_> = palette
+        .tree
+        .within_unsorted::<SquaredEuclidean>(&cell_oklab(color), limit * limit)
+        .into_iter()
+        .map(|neighbor| (neighbor.item as usize, neighbor.distance.sqrt()))
+        .filter(|&(idx, _)| {
+            palette
+                .preferences
+                .as_ref()
+                .is_none_or(|preferences| preferences[idx] == preferences[tile_idx])
+        })
+        .collect();
+    if !tied.iter().any(|&(idx, _)| idx == tile_idx) {
+        tied.push((tile_idx, distance));
+    }
+    tied.sort_unstable_by_key(|&(idx, _)| idx);
+    tied
+}
+
+// Chooses one of `count` tied tiles for a cell, from a hash of its position and the seed
+fn tie_choice(seed: u64, cell: &Cell, count: usize) -> usize {
+    // Drawn from a different stream than the choice of tile for `variety` and the angle for `rotate_jitter`
+    let bits = hash(hash(hash(seed)) ^ hash(u64::from(cell.y) << 32 | u64::from(cell.x)));
+    ((u128::from(bits) * count as u128) >> 64) as usize
+}
+
+// Finds the tile nearest to the given color that has been placed fewer than `max_uses` times, with its color
+// distance, or `None` if every tile has been placed that often.
+fn nearest_uncapped_tile(
+    palette: &Palette,
+    color: Rgb<u8>,
+    tile_uses: &[u32],
+    max_uses: u32,
+) -> Option<(usize, f32)> {
+    let mut count = PREFERENCE_CANDIDATE_COUNT;
+    loop {
+        let nearest = nearest_tiles(palette, color, count);
+        if let Some(&uncapped) = nearest
+            .iter()
+            .find(|&&(tile_idx, _)| tile_uses[tile_idx] < max_uses)
+        {
+            return Some(uncapped);
+        }
+        if count >= palette.paths.len() {
+            return None;
+        }
+        count *= 2;
+    }
+}
+
+// Finds up to `count` candidate tiles for a color (in Oklab space), best first, with their squared color distances.
+// Tiles are ranked by distance, plus their palette image's penalty if the palette is equalized.
+fn nearest_candidates(
+    palette: &Palette,
+    oklab: &[f32; 3],
+    count: usize,
+) -> Vec<NearestNeighbour<f32, u64>> {
+    let nearest = palette
+        .tree
+        .nearest_n::<SquaredEuclidean>(oklab, NonZero::new(count).unwrap());
+    let Some(equalization) = &palette.equalization else {
+        return nearest;
+    };
+
+    // Penalties are never negative, so a tile ranking among the first `count` is at most
+    // the largest penalty farther away than the `count`th nearest tile
+    let radius = nearest.last().unwrap().distance.sqrt() + equalization.max_penalty;
+    let mut candidates = palette
+        .tree
+        .within_unsorted::<SquaredEuclidean>(oklab, radius * radius);
+    let rank = |candidate: &NearestNeighbour<f32, u64>| {
+        candidate.distance.sqrt() + equalization.penalties[candidate.item as usize]
+    };
+    candidates.sort_unstable_by(|a, b| rank(a).total_cmp(&rank(b)).then(a.item.cmp(&b.item)));
+    candidates.truncate(count);
+    candidates
+}
+
+// Decides how to draw a cell of the given color and span.
+// Also returns the color distance between the cell and its nearest tile.
+fn find_placement(
+    palette: &Palette,
+    span: u32,
+    color: Rgb<u8>,
+    options: MatchOptions<'_>,
+) -> (Placement, f32) {
+    let oklab = cell_oklab(color);
+    let candidate_count = if palette.preferences.is_some() {
+        PREFERENCE_CANDIDATE_COUNT
+    } else {
+        2
+    };
+    let mut nearest = nearest_candidates(palette, &oklab, candidate_count);
+
+    // Among the tiles nearly as near as the nearest tile, move the most preferred one to the front.
+    // Ties in preference go to the nearer tile, and the other tiles stay in order of distance.
+    if let Some(preferences) = &palette.preferences {
+        let margin_limit = (nearest[0].distance.sqrt() + options.weight_margin).powi(2);
+        let preference = |idx: usize| preferences[nearest[idx].item as usize];
+        let chosen_idx = (0..nearest.len())
+            .take_while(|&idx| nearest[idx].distance <= margin_limit)
+            .fold(0, |best_idx, idx| {
+                if preference(idx) > preference(best_idx) {
+                    idx
+                } else {
+                    best_idx
+                }
+            });
+        let chosen = nearest.remove(chosen_idx);
+        nearest.insert(0, chosen);
+    }
+
+    // Distances from the k-d tree are squared, so compare against squared thresholds
+    let nearest_distance = nearest[0].distance;
+    let nearest_idx = nearest[0].item as usize;
+    let error = nearest_distance.sqrt();
+
+    if options
+        .max_error
+        .is_some_and(|max_error| nearest_distance > max_error * max_error)
+    {
+        return (Placement::Solid, error);
+    }
+
+    let placement = match nearest.get(1) {
+        Some(second)
+            if options.blend_threshold.is_some_and(|blend_threshold| {
+                nearest_distance > blend_threshold * blend_threshold
+            }) =>
+        {
+            // Weight each tile by the inverse of its distance to the cell's color
+            let nearest_distance = nearest_distance.sqrt();
+            let second_distance = second.distance.sqrt();
+            let second_weight = nearest_distance / (nearest_distance + second_distance);
+            let tiles = palette.tiles(span);
+            let nearest_image = tiles.get(nearest_idx).unwrap();
+            let second_image = tiles.get(second.item as usize).unwrap();
+
+            let blended_image = nearest_image
+                .iter()
+                .zip(second_image)
+                .map(|(&a, &b)| {
+                    (f32::from(a) * (1. - second_weight) + f32::from(b) * second_weight).round()
+                        as u8
+                })
+                .collect();
+
+            Placement::Blend(nearest_idx, blended_image)
+        }
+        _ => Placement::Tile(nearest_idx),
+    };
+
+    (placement, error)
+}
@@ -0,0 +1,89 @@
+use crate::{layout::Cell, rng::Rng};
+use anyhow::{Result, bail};
+use foldhash::{HashMap, HashMapExt};
+use image::{
+    GrayImage,
+    imageops::{FilterType, resize},
+};
+use std::str::FromStr;
+
+// Order in which cells are placed, as recorded in the `order` column of the placements file
+#[derive(Clone, Copy)]
+pub(crate) enum CellOrder {
+    // Left to right, then top to bottom
+    Row,
+    // Top to bottom, then left to right
+    Column,
+    // Shuffled, by `--seed`
+    Random,
+    // Nearest to a center point (by default, the center of the image) first
+    CenterOut,
+    // Lightest in a grayscale priority image first
+    Mask,
+}
+
+impl FromStr for CellOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "row" => Ok(Self::Row),
+            "column" => Ok(Self::Column),
+            "random" => Ok(Self::Random),
+            "center-out" => Ok(Self::CenterOut),
+            "mask" => Ok(Self::Mask),
+            _ => bail!("expected \"row\", \"column\", \"random\", \"center-out\", or \"mask\""),
+        }
+    }
+}
+
+// Ranks every cell by when it is placed, from 0, keyed by the position of the cell's top-left corner.
+// `width` and `height` are the dimensions of the grid of cells. Ties are broken in row order.
+// For `CellOrder::CenterOut`, `center` is the point cells are placed outward from, as fractions of the grid's width
+// and height. For `CellOrder::Mask`, `priority_mask` is resized to the dimensions of the grid first,
+// and each cell's priority is the mean of the pixels it covers.
+pub(crate) fn rank_cells(
+    cells: &[Cell],
+    order: CellOrder,
+    seed: u64,
+    center: (f32, f32),
+    priority_mask: Option<&GrayImage>,
+    width: u32,
+    height: u32,
+) -> HashMap<(u32, u32), u32> {
+    let mut sorted: Vec<&Cell> = cells.iter().collect();
+    sorted.sort_by_key(|cell| (cell.y, cell.x));
+
+    match order {
+        CellOrder::Row => {}
+        CellOrder::Column => sorted.sort_by_key(|cell| (cell.x, cell.y)),
+        CellOrder::Random => Rng::new(seed).shuffle(&mut sorted),
+        CellOrder::CenterOut => {
+            // Compare doubled coordinates of cell centers, so distances from the center of the grid are exact
+            let center_x = 2. * f64::from(center.0) * f64::from(width);
+            let center_y = 2. * f64::from(center.1) * f64::from(height);
+            let distance = |cell: &Cell| {
+                let dx = f64::from(2 * cell.x + cell.span) - center_x;
+                let dy = f64::from(2 * cell.y + cell.span) - center_y;
+                dx * dx + dy * dy
+            };
+            sorted.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+        }
+        CellOrder::Mask => {
+            let mask = resize(priority_mask.unwrap(), width, height, FilterType::Triangle);
+            sorted.sort_by_cached_key(|cell| {
+                let sum: u32 = (cell.y..cell.y + cell.span)
+                    .flat_map(|y| (cell.x..cell.x + cell.span).map(move |x| (x, y)))
+                    .map(|(x, y)| u32::from(mask.get_pixel(x, y)[0]))
+                    .sum();
+                std::cmp::Reverse(sum / (cell.span * cell.span))
+            });
+        }
+    }
+
+    let mut ranks = HashMap::with_capacity(sorted.len());
+    for (cell, rank) in sorted.into_iter().zip(0..) {
+        ranks.insert((cell.x, cell.y), rank);
+    }
+    ranks
+}
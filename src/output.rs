@@ -0,0 +1,241 @@
+use crate::colorspace::OutputColorSpace;
+use anyhow::{Result, bail};
+#[cfg(feature = "jpeg")]
+use image::codecs::jpeg::JpegEncoder;
+#[cfg(feature = "webp")]
+use image::codecs::webp::WebPEncoder;
+use image::{
+    ExtendedColorType, GenericImage, ImageEncoder, ImageFormat, RgbImage, codecs::png::PngEncoder,
+};
+use png::{BitDepth, ColorType, Encoder, Info, StreamWriter};
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+// Whether an output image is written as a binary PPM, which is appended to as each band is rendered.
+// This is handled here rather than by the `image` crate, so each band reaches the file as soon as it is rendered.
+fn is_ppm(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("ppm"))
+}
+
+// What is written to the output paths before the output image itself, for `--two-pass`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TwoPass {
+    // A quick preview with each cell filled with its target color
+    Preview,
+}
+
+impl FromStr for TwoPass {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "preview" => Ok(Self::Preview),
+            _ => bail!("expected \"preview\""),
+        }
+    }
+}
+
+// Checks that an output image can be written to a path, based on its extension.
+pub(crate) fn check_output_path(path: &Path) -> Result<()> {
+    if is_ppm(path) {
+        return Ok(());
+    }
+
+    match ImageFormat::from_path(path) {
+        Ok(format) if format.writing_enabled() => Ok(()),
+        Ok(format) => bail!(
+            "`-o`/`--output`: writing {format:?} images is not supported ({})",
+            path.display()
+        ),
+        Err(_) => bail!(
+            "`-o`/`--output`: could not determine image format from extension ({})",
+            path.display()
+        ),
+    }
+}
+
+// Checks that an ICC profile can be embedded in an output image, for `--output-colorspace`, based on its extension.
+pub(crate) fn check_profile_support(path: &Path) -> Result<()> {
+    if is_ppm(path)
+        || !matches!(
+            ImageFormat::from_path(path)?,
+            ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP
+        )
+    {
+        bail!(
+            "`--output-colorspace`: an ICC profile can only be embedded in PNG, JPEG, and WebP outputs ({})",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+// Saves an image, converting it from sRGB to a color space and embedding the color space's ICC profile if one is given.
+// The image's format must support ICC profiles (see `check_profile_support`) if a color space is given.
+pub(crate) fn save_image(
+    image: &RgbImage,
+    path: &Path,
+    color_space: Option<OutputColorSpace>,
+) -> Result<()> {
+    let Some(color_space) = color_space else {
+        image.save(path)?;
+        return Ok(());
+    };
+
+    let mut converted;
+    let image = if color_space.needs_conversion() {
+        converted = image.clone();
+        color_space.convert(&mut converted);
+        &converted
+    } else {
+        image
+    };
+
+    // Encoders are configured the same way as by `RgbImage::save`
+    let file = BufWriter::new(File::create(path)?);
+    let profile = color_space.icc_profile();
+    match ImageFormat::from_path(path)? {
+        ImageFormat::Png => encode_with_profile(PngEncoder::new(file), image, profile),
+        #[cfg(feature = "jpeg")]
+        ImageFormat::Jpeg => encode_with_profile(JpegEncoder::new(file), image, profile),
+        #[cfg(feature = "webp")]
+        ImageFormat::WebP => encode_with_profile(WebPEncoder::new_lossless(file), image, profile),
+        format => bail!("{format:?} images cannot embed an ICC profile"),
+    }
+}
+
+fn encode_with_profile(
+    mut encoder: impl ImageEncoder,
+    image: &RgbImage,
+    profile: Vec<u8>,
+) -> Result<()> {
+    encoder.set_icc_profile(profile)?;
+    encoder.write_image(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+        ExtendedColorType::Rgb8,
+    )?;
+    Ok(())
+}
+
+// Every destination the output image is written to, receiving the output image band by band as it is rendered.
+// PNG outputs are encoded incrementally, so they never need the full output image in memory.
+// PPM outputs are also written incrementally, and flushed after every band, so a partially rendered output image
+// can be inspected while rendering is in progress.
+// Other formats are encoded from the full output image once rendering finishes.
+pub(crate) struct Outputs {
+    png_writers: Vec<StreamWriter<'static, BufWriter<File>>>,
+    ppm_writers: Vec<BufWriter<File>>,
+    // Paths of outputs that are encoded from the full output image
+    buffered_paths: Vec<PathBuf>,
+    // The full output image, assembled from bands if any output (or the caller) needs it
+    full_image: Option<RgbImage>,
+    // Row of the full output image where the next band goes
+    next_y: u32,
+    // Color space outputs are converted to, if any; the full output image is kept in sRGB
+    color_space: Option<OutputColorSpace>,
+}
+
+impl Outputs {
+    // Creates every PNG and PPM output file up front so it can be written to while rendering.
+    // If `keep_full_image` is true, the full output image is assembled even if every output is a PNG.
+    // If `color_space` is given, every output is converted to it and has its ICC profile embedded.
+    pub(crate) fn new(
+        paths: Vec<PathBuf>,
+        width: u32,
+        height: u32,
+        keep_full_image: bool,
+        color_space: Option<OutputColorSpace>,
+    ) -> Result<Self> {
+        let mut png_writers = Vec::new();
+        let mut ppm_writers = Vec::new();
+        let mut buffered_paths = Vec::new();
+
+        for path in paths {
+            if is_ppm(&path) {
+                let mut file = BufWriter::new(File::create(&path)?);
+                write!(file, "P6\n{width} {height}\n255\n")?;
+                file.flush()?;
+                ppm_writers.push(file);
+            } else if ImageFormat::from_path(&path)? == ImageFormat::Png {
+                let file = BufWriter::new(File::create(&path)?);
+                let mut info = Info::with_size(width, height);
+                info.color_type = ColorType::Rgb;
+                info.bit_depth = BitDepth::Eight;
+                info.icc_profile =
+                    color_space.map(|color_space| Cow::Owned(color_space.icc_profile()));
+                let encoder = Encoder::with_info(file, info)?;
+                png_writers.push(encoder.write_header()?.into_stream_writer()?);
+            } else {
+                buffered_paths.push(path);
+            }
+        }
+
+        let full_image =
+            (keep_full_image || !buffered_paths.is_empty()).then(|| RgbImage::new(width, height));
+
+        Ok(Self {
+            png_writers,
+            ppm_writers,
+            buffered_paths,
+            full_image,
+            next_y: 0,
+            color_space,
+        })
+    }
+
+    // Writes the next band of the output image, from top to bottom, to every output.
+    pub(crate) fn write_band(&mut self, band: &RgbImage) -> Result<()> {
+        if let Some(full_image) = &mut self.full_image {
+            full_image.copy_from(band, 0, self.next_y)?;
+        }
+        self.next_y += band.height();
+
+        let mut converted;
+        let band = match self.color_space {
+            Some(color_space)
+                if color_space.needs_conversion()
+                    && !(self.png_writers.is_empty() && self.ppm_writers.is_empty()) =>
+            {
+                converted = band.clone();
+                color_space.convert(&mut converted);
+                &converted
+            }
+            _ => band,
+        };
+        for writer in &mut self.png_writers {
+            writer.write_all(band)?;
+        }
+        for writer in &mut self.ppm_writers {
+            writer.write_all(band)?;
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // Finishes writing every output, returning the full output image if it was assembled.
+    pub(crate) fn finish(self) -> Result<Option<RgbImage>> {
+        for writer in self.png_writers {
+            writer.finish()?;
+        }
+        for mut writer in self.ppm_writers {
+            writer.flush()?;
+        }
+
+        if let Some(full_image) = &self.full_image {
+            for path in &self.buffered_paths {
+                save_image(full_image, path, self.color_space)?;
+            }
+        }
+
+        Ok(self.full_image)
+    }
+}
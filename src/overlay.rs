@@ -0,0 +1,197 @@
+use crate::{geometry::TileSize, layout::Cell};
+use anyhow::{Result, bail};
+use image::{Rgb, RgbImage, Rgba, RgbaImage};
+use std::str::FromStr;
+
+// Suffix of the paths that grid overlays are written to, e.g. "out.grid.png" for "out.png"
+pub(crate) const GRID_OVERLAY_SUFFIX: &str = "grid";
+
+// Color of the lines between cells, with the lines of labeled rows and columns drawn opaque and the others translucent
+const LINE_COLOR: [u8; 3] = [255, 0, 255];
+const MINOR_LINE_ALPHA: u8 = 144;
+
+// Colors of labels and of the badges behind them
+const LABEL_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const BADGE_COLOR: Rgba<u8> = Rgba([0, 0, 0, 208]);
+
+// Digits 0 to 9 of a 3 by 5 pixel font, one row per byte, with the leftmost pixel of each row in the third-lowest bit
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b011, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+const COMMA: [u8; 5] = [0b000, 0b000, 0b000, 0b010, 0b100];
+
+// Intervals between labeled rows and columns, the smallest one that the labels fit in being used
+const LABEL_INTERVALS: [u32; 9] = [1, 2, 5, 10, 20, 50, 100, 200, 500];
+
+// How the grid overlay is written, for `--grid-overlay`: lines along the edges of every cell, with the rows and columns
+// numbered along the top and left edges of the mosaic as in `--placements` files, for proofing and for assembling
+// physical mosaics
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum GridOverlay {
+    // Drawn over a copy of the output image
+    Burn,
+    // Drawn alone on a transparent PNG image, to be layered over the output image in an image editor
+    Layer,
+}
+
+impl FromStr for GridOverlay {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "burn" => Ok(Self::Burn),
+            "layer" => Ok(Self::Layer),
+            _ => bail!("expected \"burn\" or \"layer\""),
+        }
+    }
+}
+
+// Draws the grid overlay for the cells of a mosaic `width` by `height` cells, on a transparent image of the output
+// image's dimensions, with the mosaic `frame_width` pixels in from its top-left corner.
+pub(crate) fn grid_layer(
+    cells: &[Cell],
+    width: u32,
+    height: u32,
+    tile_size: TileSize,
+    frame_width: u32,
+    (output_width, output_height): (u32, u32),
+) -> RgbaImage {
+    let mut layer = RgbaImage::new(output_width, output_height);
+    let tile_min = tile_size.width.min(tile_size.height);
+    let thickness = (tile_min / 32).max(1);
+    // Digits are scaled up to stay legible on large tiles
+    let scale = (tile_min / 24).max(1);
+
+    // Labels are drawn for every `interval`th row and column, so the widest label fits between two of them
+    let label_size = |label: u32| {
+        let digit_count = label.checked_ilog10().unwrap_or(0) + 1;
+        ((digit_count * 4 + 1) * scale, 7 * scale)
+    };
+    let (widest_label, label_height) = label_size(width.max(height).saturating_sub(1));
+    let interval = LABEL_INTERVALS
+        .into_iter()
+        .find(|interval| {
+            widest_label + thickness <= interval * tile_size.width
+                && label_height + thickness <= interval * tile_size.height
+        })
+        .unwrap_or(*LABEL_INTERVALS.last().unwrap());
+
+    let mut fill = |x: u32, y: u32, w: u32, h: u32, color: Rgba<u8>| {
+        for y in y..(y + h).min(output_height) {
+            for x in x..(x + w).min(output_width) {
+                layer.put_pixel(x, y, color);
+            }
+        }
+    };
+    let line_color = |labeled: bool| {
+        let [r, g, b] = LINE_COLOR;
+        Rgba([r, g, b, if labeled { 255 } else { MINOR_LINE_ALPHA }])
+    };
+
+    // Each cell draws its top and left edges, and the mosaic's bottom and right edges are drawn at the end
+    let (left, top) = (frame_width, frame_width);
+    for cell in cells {
+        let (x, y) = (
+            left + cell.x * tile_size.width,
+            top + cell.y * tile_size.height,
+        );
+        let (cell_width, cell_height) = (cell.span * tile_size.width, cell.span * tile_size.height);
+        fill(
+            x,
+            y,
+            cell_width,
+            thickness,
+            line_color(cell.y.is_multiple_of(interval)),
+        );
+        fill(
+            x,
+            y,
+            thickness,
+            cell_height,
+            line_color(cell.x.is_multiple_of(interval)),
+        );
+    }
+    let (right, bottom) = (
+        left + width * tile_size.width,
+        top + height * tile_size.height,
+    );
+    fill(
+        left,
+        bottom - thickness,
+        right - left,
+        thickness,
+        line_color(true),
+    );
+    fill(
+        right - thickness,
+        top,
+        thickness,
+        bottom - top,
+        line_color(true),
+    );
+
+    // Row and column numbers, each on a badge in the top-left corner of the first cell of its row or column
+    let labels = (0..width)
+        .step_by(interval as usize)
+        .map(|column| (column, left + column * tile_size.width, top))
+        .chain(
+            (interval..height)
+                .step_by(interval as usize)
+                .map(|row| (row, left, top + row * tile_size.height)),
+        );
+    for (label, x, y) in labels {
+        let (x, y) = (x + thickness, y + thickness);
+        let (label_width, label_height) = label_size(label);
+        fill(x, y, label_width, label_height, BADGE_COLOR);
+        for (column, row) in text_pixels(&label.to_string()) {
+            fill(
+                x + (1 + column) * scale,
+                y + (1 + row) * scale,
+                scale,
+                scale,
+                LABEL_COLOR,
+            );
+        }
+    }
+
+    layer
+}
+
+// Finds the pixels to draw for a line of text of digits and commas in the 3 by 5 pixel font, as (column, row) from the
+// top-left corner of the text. Characters are 3 pixels wide with 1 pixel between them, so text of n characters is
+// 4n - 1 pixels wide. Any other characters are left blank.
+pub(crate) fn text_pixels(text: &str) -> impl Iterator<Item = (u32, u32)> {
+    (0..).zip(text.chars()).flat_map(|(char_idx, c)| {
+        let glyph = match c {
+            '0'..='9' => DIGITS[c as usize - '0' as usize],
+            ',' => COMMA,
+            _ => [0; 5],
+        };
+        (0..).zip(glyph).flat_map(move |(row_idx, row)| {
+            (0..3)
+                .filter(move |column_idx| row & (0b100 >> column_idx) != 0)
+                .map(move |column_idx| (char_idx * 4 + column_idx, row_idx))
+        })
+    })
+}
+
+// Draws a grid overlay from `grid_layer` over a copy of the output image
+pub(crate) fn burn_in(image: &RgbImage, layer: &RgbaImage) -> RgbImage {
+    let mut burned = image.clone();
+    for (px, layer_px) in burned.pixels_mut().zip(layer.pixels()) {
+        let alpha = f32::from(layer_px[3]) / 255.;
+        *px = Rgb([0, 1, 2].map(|c| {
+            (f32::from(px[c]) + (f32::from(layer_px[c]) - f32::from(px[c])) * alpha).round() as u8
+        }));
+    }
+    burned
+}
@@ -0,0 +1,289 @@
+use crate::{
+    TILE_PIXEL_SIZE,
+    animation::AnimatedFrames,
+    default_extensions,
+    geometry::{TileSize, parse_tile_size},
+    logging::{format_count, log},
+    palette::{
+        LoadOptions, LoadedPalette, UpscalePolicy, check_dimensions, find_images, load_images,
+    },
+    parse_extensions,
+    paths::{decode_relative_path, encode_relative_path},
+    quality::QualityFilters,
+};
+use anyhow::{Context, Result, bail};
+use pico_args::Arguments;
+use rayon::ThreadPoolBuilder;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+// Identifies the format of palette packs
+const PACK_MAGIC: &[u8; 4] = b"TSRP";
+// Version 2 escapes paths with `encode_path`; version 1 stored them lossily, and is still read
+const PACK_VERSION: u8 = 2;
+
+// File extension of palette packs
+pub(crate) const PACK_EXTENSION: &str = "tsrpack";
+
+// Whether a path is a palette pack, based on its extension.
+pub(crate) fn is_pack(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == PACK_EXTENSION)
+}
+
+// Runs `tessera index`, whose only subcommand is `export`.
+pub(crate) fn run(mut args: Arguments) -> Result<()> {
+    match args.subcommand()?.as_deref() {
+        Some("export") => export(args),
+        Some(subcommand) => bail!("unknown subcommand `index {subcommand}`"),
+        None => bail!("expected a subcommand: `tessera index export`"),
+    }
+}
+
+// Runs `tessera index export`, which bundles resized palette images into a palette pack:
+// a single file that can be used with `-p` in place of the palette directory, on machines without the original images.
+fn export(mut args: Arguments) -> Result<()> {
+    if args.contains(["-h", "--help"]) {
+        println!(
+            "tessera index export: bundle resized palette images into a palette pack, for use with -p
+usage: tessera index export [OPTIONS] PACK
+
+-h, --help           print this message
+-p, --palette-dir    path to directory containing palette images
+-s, --tile-size      width and height of each tile in the output image, in pixels, e.g. \"64\" or \"64x96\"
+--depth              also include the larger tiles used with --depth-map
+-x, --extensions     comma-separated list of file extensions of palette images to process, replacing the default list;
+                     prefix the list with \"+\" to add to the default list instead; default is \"{}\"
+--reject-blurry      reject palette images whose Laplacian variance (a measure of sharpness) is below this value
+--reject-low-contrast
+                     reject palette images whose standard deviation of lightness is below this value
+--reject-uniform     reject palette images whose mean color distance from their average color is below this value
+--upscale-policy     how to handle palette images smaller than a tile: \"bilinear\", \"nearest\", \"lanczos\", or \"skip\";
+                     default is \"bilinear\"
+-t, --threads        number of threads to use; default is the number of logical CPUs",
+            default_extensions().join(",")
+        );
+        return Ok(());
+    }
+
+    let palette_dir_path: PathBuf = args.value_from_str(["-p", "--palette-dir"])?;
+    let tile_size = args.value_from_fn(["-s", "--tile-size"], parse_tile_size)?;
+    let depth = args.contains("--depth");
+    let extensions = args
+        .opt_value_from_fn(["-x", "--extensions"], parse_extensions)?
+        .unwrap_or_else(default_extensions);
+    let quality_filters = QualityFilters {
+        blurry: args.opt_value_from_str("--reject-blurry")?,
+        low_contrast: args.opt_value_from_str("--reject-low-contrast")?,
+        uniform: args.opt_value_from_str("--reject-uniform")?,
+    };
+    let upscale_policy: UpscalePolicy = args
+        .opt_value_from_str("--upscale-policy")?
+        .unwrap_or_default();
+    let thread_count: Option<usize> = args.opt_value_from_str(["-t", "--threads"])?;
+    let pack_path: PathBuf = args.free_from_str()?;
+
+    if !palette_dir_path.is_dir() {
+        bail!("`-p`/`--palette-dir`: path does not point to a directory");
+    }
+    if tile_size.width == 0 || tile_size.height == 0 {
+        bail!("`-s`/`--tile-size`: tile size cannot be zero");
+    }
+    if thread_count == Some(0) {
+        bail!("`-t`/`--threads`: thread count cannot be zero");
+    }
+    if !is_pack(&pack_path) {
+        bail!("pack path must have the extension `.{PACK_EXTENSION}`");
+    }
+
+    if let Some(thread_count) = thread_count {
+        ThreadPoolBuilder::new()
+            .num_threads(thread_count)
+            .build_global()?;
+    }
+
+    let spans = if depth { vec![1, 2] } else { vec![1] };
+    let palette_paths = find_images(&palette_dir_path, &extensions)?;
+    let load_options = LoadOptions {
+        tile_size,
+        spans: &spans,
+        min_tile_source: None,
+        allow_upscale: false,
+        quality_filters,
+        upscale_policy,
+        animated_frames: AnimatedFrames::First,
+        max_source_pixels: None,
+        cache: None,
+    };
+    let palette = load_images(palette_paths, &load_options)?;
+
+    write_pack(&pack_path, &palette_dir_path, tile_size, &spans, &palette)?;
+    log!(
+        "exported {} palette images to {}",
+        format_count(palette.paths.len() as u64),
+        pack_path.display()
+    );
+
+    Ok(())
+}
+
+// Writes a palette pack. It consists of:
+// - a header: the magic bytes, the format version, the tile width and height, and the cell spans tiles are stored for
+// - for each palette image: its path relative to the palette directory, its original width and height,
+//   and its tile for each span
+// Every number is a little-endian u32, and each path is preceded by its length in bytes.
+pub(crate) fn write_pack(
+    path: &Path,
+    palette_dir: &Path,
+    tile_size: TileSize,
+    spans: &[u32],
+    palette: &LoadedPalette,
+) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let write_u32 =
+        |writer: &mut BufWriter<File>, value: u32| writer.write_all(&value.to_le_bytes());
+
+    writer.write_all(PACK_MAGIC)?;
+    writer.write_all(&[PACK_VERSION])?;
+    write_u32(&mut writer, tile_size.width)?;
+    write_u32(&mut writer, tile_size.height)?;
+    write_u32(&mut writer, spans.len() as u32)?;
+    for &span in spans {
+        write_u32(&mut writer, span)?;
+    }
+    write_u32(&mut writer, palette.paths.len() as u32)?;
+
+    for (idx, image_path) in palette.paths.iter().enumerate() {
+        // Paths use "/" as a separator regardless of platform, so packs are portable
+        let relative_path = encode_relative_path(image_path, palette_dir)?;
+        write_u32(&mut writer, relative_path.len() as u32)?;
+        writer.write_all(relative_path.as_bytes())?;
+
+        let (width, height) = palette.source_dimensions[idx];
+        write_u32(&mut writer, width)?;
+        write_u32(&mut writer, height)?;
+        for tile_set in &palette.tile_sets {
+            writer.write_all(&tile_set[idx])?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+// Loads the tiles for every span in `options` from a palette pack, skipping palette images rejected by
+// `--min-tile-source` or `--upscale-policy skip`. The pack must have been exported with the same tile size.
+// Each palette image's path is its path within the pack, joined to the pack's path (e.g. "palette.tsrpack/sub/a.jpg").
+pub(crate) fn load_pack(path: &Path, options: &LoadOptions<'_>) -> Result<LoadedPalette> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let read_u32 = |reader: &mut BufReader<File>| -> Result<u32> {
+        let mut bytes = [0; 4];
+        reader
+            .read_exact(&mut bytes)
+            .context("palette pack is truncated")?;
+        Ok(u32::from_le_bytes(bytes))
+    };
+
+    let mut magic = [0; 5];
+    reader
+        .read_exact(&mut magic)
+        .context("palette pack is truncated")?;
+    let version = magic[4];
+    if magic[..4] != *PACK_MAGIC || version == 0 {
+        bail!(
+            "`-p`/`--palette-dir`: {} is not a palette pack in a supported format",
+            path.display()
+        );
+    }
+    if version > PACK_VERSION {
+        bail!(
+            "`-p`/`--palette-dir`: {} is in version {version} of the palette pack format, written by a newer version of tessera; this version of tessera reads up to version {PACK_VERSION}",
+            path.display()
+        );
+    }
+
+    let pack_tile_size = TileSize {
+        width: read_u32(&mut reader)?,
+        height: read_u32(&mut reader)?,
+    };
+    if pack_tile_size.width != options.tile_size.width
+        || pack_tile_size.height != options.tile_size.height
+    {
+        bail!(
+            "`-s`/`--tile-size`: palette pack was exported for a tile size of {}x{}",
+            pack_tile_size.width,
+            pack_tile_size.height
+        );
+    }
+
+    let pack_span_count = read_u32(&mut reader)?;
+    let pack_spans = (0..pack_span_count)
+        .map(|_| read_u32(&mut reader))
+        .collect::<Result<Vec<_>>>()?;
+    let span_indices = options
+        .spans
+        .iter()
+        .map(|span| {
+            pack_spans.iter().position(|pack_span| pack_span == span).with_context(|| {
+                format!("palette pack has no tiles for cells with a span of {span}; export it with `--depth`")
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let image_count = read_u32(&mut reader)? as usize;
+    let mut palette = LoadedPalette {
+        paths: Vec::with_capacity(image_count),
+        tile_sets: vec![Vec::with_capacity(image_count); options.spans.len()],
+        source_dimensions: Vec::with_capacity(image_count),
+    };
+
+    for _ in 0..image_count {
+        let mut relative_path = vec![0; read_u32(&mut reader)? as usize];
+        reader
+            .read_exact(&mut relative_path)
+            .context("palette pack is truncated")?;
+        let relative_path = String::from_utf8(relative_path)?;
+        let image_path = if version >= 2 {
+            decode_relative_path(path, &relative_path)?
+        } else {
+            path.join(relative_path)
+        };
+        let dimensions = (read_u32(&mut reader)?, read_u32(&mut reader)?);
+
+        let mut tiles = pack_spans
+            .iter()
+            .map(|span| {
+                let mut tile = vec![
+                    0;
+                    (span * pack_tile_size.width * span * pack_tile_size.height)
+                        as usize
+                        * TILE_PIXEL_SIZE
+                ];
+                reader
+                    .read_exact(&mut tile)
+                    .context("palette pack is truncated")?;
+                Ok(tile)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        match check_dimensions(dimensions, options) {
+            Ok(note) => {
+                if let Some(note) = note {
+                    log!("palette image {}: {note}", image_path.display());
+                }
+                for (tile_set, &span_idx) in palette.tile_sets.iter_mut().zip(&span_indices) {
+                    tile_set.push(std::mem::take(&mut tiles[span_idx]));
+                }
+                palette.paths.push(image_path);
+                palette.source_dimensions.push(dimensions);
+            }
+            Err(reason) => {
+                log!("rejected palette image {}: {reason}", image_path.display());
+            }
+        }
+    }
+
+    Ok(palette)
+}
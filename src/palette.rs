@@ -0,0 +1,1107 @@
+use crate::{
+    TILE_PIXEL_COMPONENT_MAX, TILE_PIXEL_COMPONENT_MAX_INT, TILE_PIXEL_SIZE, TileImage, TilePixel,
+    animation::{AnimatedFrames, decode_palette_image},
+    cache::{CacheKey, TileCache},
+    color::{linear_srgb_to_oklab, oklab_to_linear_srgb},
+    compression::CompressedTiles,
+    decoder::TileDecoder,
+    geometry::TileSize,
+    hints::is_excluded,
+    logging::log,
+    paths::decode_relative_path,
+    pixel::PixelLayout,
+    prescale::{Prescaled, prescale},
+    quality::QualityFilters,
+};
+use anyhow::{Context, Result, bail};
+use fast_image_resize::{FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer, images::Image};
+use foldhash::HashSet;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use image::{RgbaImage, imageops::crop_imm};
+use kiddo::{ImmutableKdTree, SquaredEuclidean};
+use rayon::prelude::*;
+use std::{
+    fs::read_to_string,
+    ops::Deref,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+use walkdir::WalkDir;
+
+// Resized tiles for every image in the palette, indexed the same way as the palette's paths
+pub(crate) type TileSet = Vec<Vec<u8>>;
+
+// Tiles and their average colors for every image in the palette, ready for matching
+pub(crate) struct Palette {
+    // Path of each palette image, by index
+    pub(crate) paths: Vec<PathBuf>,
+    pub(crate) tile_size: TileSize,
+    // Every span of cell that tiles are available for, in ascending order
+    pub(crate) spans: Vec<u32>,
+    // Where tiles are kept
+    tiles: Tiles,
+    // Average color of each tile (in Oklab space), by index
+    pub(crate) colors: Vec<[f32; 3]>,
+    // k-d tree for nearest-neighbor queries for colors
+    pub(crate) tree: ImmutableKdTree<f32, 3>,
+    // Preference for each palette image, by index, used to break near-ties when matching
+    pub(crate) preferences: Option<Vec<Preference>>,
+    // Penalty for each palette image in dense regions of the palette's colors, from `equalize`
+    pub(crate) equalization: Option<Equalization>,
+    // Crops within each palette image that cells are drawn with instead of the whole tile, from `set_crops`
+    crops: Option<Crops>,
+}
+
+// Where a palette's tiles are kept
+enum Tiles {
+    // One set of tiles (composited over black) per span, each indexed the same way as `paths`
+    Kept(Vec<TileSet>),
+    // Nowhere; tiles are decoded from their palette images whenever they are drawn, for `--descriptors-only`
+    Decoded(TileDecoder),
+    // Compressed, and decompressed whenever they are drawn, for `--compress-tiles`
+    Compressed(CompressedTiles),
+}
+
+// A tile (composited over black), either kept by the palette or decoded for drawing
+pub(crate) enum Tile<'a> {
+    Kept(&'a [u8]),
+    Decoded(Arc<Vec<u8>>),
+}
+
+impl Deref for Tile<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Kept(tile) => tile,
+            Self::Decoded(tile) => tile,
+        }
+    }
+}
+
+// How many times larger than a tile each palette image is resized to for `--source-crops`, so each crop shows
+// 1/CROP_ZOOM of the image's width and height
+pub(crate) const CROP_ZOOM: u32 = 2;
+
+// Number of crop positions along each axis of a palette image, evenly spaced from one edge to the other
+const CROP_POSITIONS: u32 = 5;
+
+// Tile-sized crops within larger versions of each palette image, for `--source-crops`. Once a palette image is chosen
+// for a cell, the cell is drawn with whichever crop's average color is nearest to the cell's color.
+struct Crops {
+    // One set of zoomed tiles per span, each CROP_ZOOM times the size of the span's tiles
+    tile_sets: Vec<TileSet>,
+    // Average color (in Oklab space) of the crop at each position, in row-major order, by palette image index.
+    // Colors are averaged over a grid of blocks of the smallest span's zoomed tile, so they are cheap to search.
+    colors: Vec<Vec<[f32; 3]>>,
+}
+
+// Color distance within which palette images count toward each other's density, for `--equalize`
+const DENSITY_RADIUS: f32 = 0.01;
+
+// Penalties that keep colors shared by many near-identical palette images from crowding out rarer colors, for `--equalize`.
+// Candidate tiles are ranked by their color distance plus their palette image's penalty.
+#[derive(Clone)]
+pub(crate) struct Equalization {
+    // Penalty for each palette image, by index, as a color distance
+    pub(crate) penalties: Vec<f32>,
+    // Largest of the penalties, bounding how far beyond the nearest tiles a better-ranked tile can be
+    pub(crate) max_penalty: f32,
+}
+
+// How strongly a palette image is preferred when several tiles are nearly as near to a cell's color.
+// Preferences are compared by orientation first, then by weight.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub(crate) struct Preference {
+    // Whether the palette image's native orientation (portrait or landscape) matches the tiles'
+    pub(crate) orientation_matches: bool,
+    // Desirability of the palette image, from `--weights`
+    pub(crate) weight: f32,
+}
+
+impl Preference {
+    // Determines whether a palette image of the given dimensions has the same orientation as tiles of the given size.
+    // Square images and tiles match every orientation.
+    pub(crate) fn orientation_matches((width, height): (u32, u32), tile_size: TileSize) -> bool {
+        width == height
+            || tile_size.width == tile_size.height
+            || (width > height) == (tile_size.width > tile_size.height)
+    }
+}
+
+impl Palette {
+    // Builds a palette from tiles that were resized once per span.
+    // If `normalize_exposure` is `Some`, tiles' exposure is normalized first, including contrast if it is `Some(true)`.
+    pub(crate) fn new(
+        paths: Vec<PathBuf>,
+        tile_size: TileSize,
+        spans: Vec<u32>,
+        mut tile_sets: Vec<TileSet>,
+        alpha_weighted: bool,
+        normalize_exposure: Option<bool>,
+        preferences: Option<Vec<Preference>>,
+    ) -> Result<Self> {
+        if paths.is_empty() {
+            bail!("`-p`/`--palette-dir`: palette contains no usable images");
+        }
+
+        if let Some(contrast) = normalize_exposure {
+            for tile_set in &mut tile_sets {
+                self::normalize_exposure(tile_set, contrast);
+            }
+        }
+
+        // Composite tiles of every span, calculating the average color of each tile in the palette along the way.
+        // Both happen in a single pass over the smallest span's tiles, which are the ones matched against.
+        let (base_tile_set, other_tile_sets) = tile_sets.split_first_mut().unwrap();
+        let colors: Vec<_> = base_tile_set
+            .par_iter_mut()
+            .map(|tile| {
+                composite_over_black(tile);
+                average_color::<TilePixel>(tile, alpha_weighted)
+            })
+            .collect();
+        for tile_set in other_tile_sets {
+            tile_set
+                .par_iter_mut()
+                .for_each(|tile| composite_over_black(tile));
+        }
+
+        let tree = ImmutableKdTree::new_from_slice(&colors);
+
+        Ok(Self {
+            paths,
+            tile_size,
+            spans,
+            tiles: Tiles::Kept(tile_sets),
+            colors,
+            tree,
+            preferences,
+            equalization: None,
+            crops: None,
+        })
+    }
+
+    // Builds a palette from the average colors of palette images (from `load_descriptors`), without keeping any of
+    // their tiles, which `decoder` decodes again whenever they are drawn, for `--descriptors-only`.
+    pub(crate) fn with_decoder(
+        paths: Vec<PathBuf>,
+        tile_size: TileSize,
+        spans: Vec<u32>,
+        colors: Vec<[f32; 3]>,
+        preferences: Option<Vec<Preference>>,
+        decoder: TileDecoder,
+    ) -> Result<Self> {
+        if paths.is_empty() {
+            bail!("`-p`/`--palette-dir`: palette contains no usable images");
+        }
+
+        let tree = ImmutableKdTree::new_from_slice(&colors);
+
+        Ok(Self {
+            paths,
+            tile_size,
+            spans,
+            tiles: Tiles::Decoded(decoder),
+            colors,
+            tree,
+            preferences,
+            equalization: None,
+            crops: None,
+        })
+    }
+
+    // Penalizes palette images by the density of the palette's colors around them: each palette image's penalty
+    // grows by `strength` (a color distance) every time the number of palette images within `DENSITY_RADIUS`
+    // of its color (including itself) doubles, so a palette image with no near-duplicates has no penalty.
+    pub(crate) fn equalize(&mut self, strength: f32) {
+        let penalties: Vec<_> = self
+            .colors
+            .par_iter()
+            .map(|color| {
+                let density = self
+                    .tree
+                    .within_unsorted::<SquaredEuclidean>(color, DENSITY_RADIUS * DENSITY_RADIUS)
+                    .len()
+                    .max(1);
+                strength * (density as f32).log2()
+            })
+            .collect();
+        let max_penalty = penalties.iter().copied().fold(0., f32::max);
+        self.equalization = Some(Equalization {
+            penalties,
+            max_penalty,
+        });
+    }
+
+    // Builds a palette of the same palette images for another tile size, from tiles resized once per span.
+    // The palette's colors are shared rather than recalculated, so every tile size places the same palette images.
+    pub(crate) fn resized(
+        &self,
+        tile_size: TileSize,
+        mut tile_sets: Vec<TileSet>,
+        normalize_exposure: Option<bool>,
+    ) -> Self {
+        if let Some(contrast) = normalize_exposure {
+            for tile_set in &mut tile_sets {
+                self::normalize_exposure(tile_set, contrast);
+            }
+        }
+        for tile_set in &mut tile_sets {
+            tile_set
+                .par_iter_mut()
+                .for_each(|tile| composite_over_black(tile));
+        }
+
+        Self {
+            paths: self.paths.clone(),
+            tile_size,
+            spans: self.spans.clone(),
+            tiles: Tiles::Kept(tile_sets),
+            colors: self.colors.clone(),
+            tree: self.tree.clone(),
+            preferences: self.preferences.clone(),
+            equalization: self.equalization.clone(),
+            crops: None,
+        }
+    }
+
+    // Draws cells with crops within palette images instead of whole tiles, for `--source-crops`, from zoomed tiles
+    // resized once per span to CROP_ZOOM times the span. Zoomed tiles are treated like the palette's own tiles,
+    // including normalizing their exposure if `normalize_exposure` is `Some`.
+    pub(crate) fn set_crops(
+        &mut self,
+        mut tile_sets: Vec<TileSet>,
+        normalize_exposure: Option<bool>,
+    ) {
+        if let Some(contrast) = normalize_exposure {
+            for tile_set in &mut tile_sets {
+                self::normalize_exposure(tile_set, contrast);
+            }
+        }
+        for tile_set in &mut tile_sets {
+            tile_set
+                .par_iter_mut()
+                .for_each(|tile| composite_over_black(tile));
+        }
+
+        let zoomed_width = CROP_ZOOM * self.spans[0] * self.tile_size.width;
+        let zoomed_height = CROP_ZOOM * self.spans[0] * self.tile_size.height;
+        let colors = tile_sets[0]
+            .par_iter()
+            .map(|tile| crop_colors(tile, zoomed_width, zoomed_height))
+            .collect();
+        self.crops = Some(Crops { tile_sets, colors });
+    }
+
+    // Compresses the palette's tiles, which are then decompressed whenever they are drawn, keeping recently drawn
+    // tiles decompressed up to a total size of `recent_capacity` bytes. Requires tiles kept in the palette.
+    pub(crate) fn compress_tiles(&mut self, recent_capacity: u64) {
+        let Tiles::Kept(tile_sets) = &mut self.tiles else {
+            unreachable!();
+        };
+        let tile_sets = std::mem::take(tile_sets);
+        self.tiles = Tiles::Compressed(CompressedTiles::new(tile_sets, recent_capacity));
+    }
+
+    // Whether cells are drawn with crops within palette images, from `set_crops`
+    pub(crate) fn has_crops(&self) -> bool {
+        self.crops.is_some()
+    }
+
+    // Copies the crop of a palette image whose average color is nearest to `color` (in Oklab space) into `crop`,
+    // which must be the size of a tile for cells with the given span. Requires `set_crops`.
+    pub(crate) fn crop(&self, tile_idx: usize, span: u32, color: &[f32; 3], crop: &mut [u8]) {
+        let crops = self.crops.as_ref().unwrap();
+        let distance = |crop_color: &[f32; 3]| {
+            crop_color
+                .iter()
+                .zip(color)
+                .map(|(a, b)| (a - b).powi(2))
+                .sum::<f32>()
+        };
+        let position_idx = crops.colors[tile_idx]
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+            .unwrap()
+            .0 as u32;
+
+        let span_idx = self.spans.iter().position(|&s| s == span).unwrap();
+        let zoomed_tile = &crops.tile_sets[span_idx][tile_idx];
+        let (width, height) = (span * self.tile_size.width, span * self.tile_size.height);
+        let zoomed_width = CROP_ZOOM * width;
+        let zoomed_height = CROP_ZOOM * height;
+        let x = (zoomed_width - width) * (position_idx % CROP_POSITIONS) / (CROP_POSITIONS - 1);
+        let y = (zoomed_height - height) * (position_idx / CROP_POSITIONS) / (CROP_POSITIONS - 1);
+
+        let row_len = width as usize * TILE_PIXEL_SIZE;
+        for (row_idx, row) in crop.chunks_exact_mut(row_len).enumerate() {
+            let start =
+                ((y as usize + row_idx) * zoomed_width as usize + x as usize) * TILE_PIXEL_SIZE;
+            row.copy_from_slice(&zoomed_tile[start..start + row_len]);
+        }
+    }
+
+    // Total size of every tile kept in the palette, in bytes, including zoomed tiles for crops.
+    // Compressed tiles count at their compressed size.
+    pub(crate) fn tile_memory(&self) -> u64 {
+        let (tile_sets, compressed_size) = match &self.tiles {
+            Tiles::Kept(tile_sets) => (tile_sets.as_slice(), 0),
+            Tiles::Decoded(_) => (&[][..], 0),
+            Tiles::Compressed(compressed) => (&[][..], compressed.memory()),
+        };
+        tile_sets
+            .iter()
+            .chain(self.crops.iter().flat_map(|crops| &crops.tile_sets))
+            .flatten()
+            .map(|tile| tile.len() as u64)
+            .sum::<u64>()
+            + compressed_size
+    }
+
+    // Returns the tiles for cells with the given span. Requires tiles kept in the palette, i.e. not `with_decoder`
+    // or `compress_tiles`.
+    pub(crate) fn tiles(&self, span: u32) -> &[Vec<u8>] {
+        let Tiles::Kept(tile_sets) = &self.tiles else {
+            unreachable!();
+        };
+        &tile_sets[self.spans.iter().position(|&s| s == span).unwrap()]
+    }
+
+    // Returns the tile of a palette image for cells with the given span, decoding or decompressing it if the palette
+    // doesn't keep it as is.
+    pub(crate) fn tile(&self, tile_idx: usize, span: u32) -> Result<Tile<'_>> {
+        match &self.tiles {
+            Tiles::Kept(_) => Ok(Tile::Kept(&self.tiles(span)[tile_idx])),
+            Tiles::Decoded(decoder) => Ok(Tile::Decoded(decoder.tile(
+                &self.paths[tile_idx],
+                tile_idx,
+                span,
+            )?)),
+            Tiles::Compressed(compressed) => {
+                let span_idx = self.spans.iter().position(|&s| s == span).unwrap();
+                Ok(Tile::Decoded(compressed.tile(tile_idx, span_idx, span)?))
+            }
+        }
+    }
+}
+
+// Name of the file in a palette directory listing paths to exclude from the palette, in gitignore syntax
+const IGNORE_FILE_NAME: &str = ".tesseraignore";
+
+// Finds every file in a directory (recursively) with one of the given extensions.
+// Entries are sorted so the palette's order, and therefore the output image, doesn't depend on the file system.
+// Files and directories matched by the directory's `.tesseraignore`, if it has one, are skipped,
+// as are directories excluded by their palette.toml.
+pub(crate) fn find_images(dir: &Path, extensions: &[String]) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+
+    let ignore_file_path = dir.join(IGNORE_FILE_NAME);
+    let ignore = if ignore_file_path.is_file() {
+        let mut builder = GitignoreBuilder::new(dir);
+        if let Some(err) = builder.add(&ignore_file_path) {
+            bail!("`{}`: {err}", ignore_file_path.display());
+        }
+        builder.build()?
+    } else {
+        Gitignore::empty()
+    };
+
+    let mut entries = WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0
+                || !ignore
+                    .matched(entry.path(), entry.file_type().is_dir())
+                    .is_ignore()
+        });
+
+    while let Some(entry) = entries.next() {
+        let path = entry?.into_path();
+        // Directories whose palette.toml excludes them are skipped along with everything below them
+        if path.is_dir() {
+            if is_excluded(&path)? {
+                entries.skip_current_dir();
+            }
+            continue;
+        }
+        // Only process images with the allowed extensions
+        if path.extension().is_none_or(|ext| {
+            !extensions
+                .iter()
+                .any(|allowed| ext.eq_ignore_ascii_case(allowed))
+        }) {
+            continue;
+        }
+
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+// Keeps only the palette images listed in a filter file (e.g. written by `tessera inspect --suggest-prune --apply`).
+// Each non-empty line is a path relative to the palette directory (see `decode_relative_path`); lines starting with "#" are comments.
+pub(crate) fn filter_images(
+    paths: Vec<PathBuf>,
+    filter_path: &Path,
+    palette_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    let contents = read_to_string(filter_path)?;
+    let found: HashSet<&PathBuf> = paths.iter().collect();
+    let mut listed = HashSet::default();
+
+    for (line, line_number) in contents.lines().zip(1..) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let image_path = decode_relative_path(palette_dir, line)
+            .with_context(|| format!("`--palette-filter`: line {line_number}: invalid path"))?;
+        if !found.contains(&image_path) {
+            log!(
+                "`--palette-filter`: line {line_number}: {} is not in the palette",
+                image_path.display()
+            );
+        }
+        listed.insert(image_path);
+    }
+
+    Ok(paths
+        .into_iter()
+        .filter(|path| listed.contains(path))
+        .collect())
+}
+
+// Palette images that were kept after loading
+pub(crate) struct LoadedPalette {
+    pub(crate) paths: Vec<PathBuf>,
+    // Tiles resized once per span
+    pub(crate) tile_sets: Vec<TileSet>,
+    // Original width and height of each palette image, by index
+    pub(crate) source_dimensions: Vec<(u32, u32)>,
+}
+
+// Loads every palette image in parallel, reporting rejected images and notes in palette order.
+pub(crate) fn load_images(paths: Vec<PathBuf>, options: &LoadOptions<'_>) -> Result<LoadedPalette> {
+    Ok(load_images_for_sizes(paths, std::slice::from_ref(options))?
+        .pop()
+        .unwrap())
+}
+
+// Loads every palette image in parallel once for each element of `options` (e.g. for several tile sizes),
+// decoding each image at most once. An image rejected for any of them is rejected for all of them,
+// so every returned palette has the same palette images.
+pub(crate) fn load_images_for_sizes(
+    paths: Vec<PathBuf>,
+    options: &[LoadOptions<'_>],
+) -> Result<Vec<LoadedPalette>> {
+    let loaded_images = paths
+        .par_iter()
+        .map(|path| load_image_for_sizes(path, options))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(collect_loaded_images(paths, loaded_images, options))
+}
+
+// Collects palette images loaded once for each element of `options`, given in the same order as `paths`, into one
+// palette per element, reporting rejected images and notes in palette order.
+pub(crate) fn collect_loaded_images(
+    paths: Vec<PathBuf>,
+    loaded_images: Vec<Vec<LoadedImage>>,
+    options: &[LoadOptions<'_>],
+) -> Vec<LoadedPalette> {
+    let mut palettes: Vec<_> = options
+        .iter()
+        .map(|options| LoadedPalette {
+            paths: Vec::with_capacity(paths.len()),
+            tile_sets: vec![Vec::with_capacity(paths.len()); options.spans.len()],
+            source_dimensions: Vec::with_capacity(paths.len()),
+        })
+        .collect();
+
+    for (path, loaded_images) in paths.into_iter().zip(loaded_images) {
+        let rejection = loaded_images
+            .iter()
+            .find_map(|loaded_image| match loaded_image {
+                LoadedImage::Kept(..) => None,
+                LoadedImage::Rejected(reason) => Some(reason),
+            });
+        if let Some(reason) = rejection {
+            log!("rejected palette image {}: {reason}", path.display());
+            continue;
+        }
+
+        let mut is_noted = false;
+        for (palette, loaded_image) in palettes.iter_mut().zip(loaded_images) {
+            let LoadedImage::Kept(resized_images, dimensions, note) = loaded_image else {
+                unreachable!();
+            };
+            if let Some(note) = note
+                && !is_noted
+            {
+                log!("palette image {}: {note}", path.display());
+                is_noted = true;
+            }
+            // Each frame of an animated image loaded with `AnimatedFrames::Each` is a palette image of its own
+            let mut resized_images = resized_images.into_iter();
+            while resized_images.len() > 0 {
+                for tile_set in &mut palette.tile_sets {
+                    tile_set.push(resized_images.next().unwrap());
+                }
+                palette.paths.push(path.clone());
+                palette.source_dimensions.push(dimensions);
+            }
+        }
+    }
+
+    palettes
+}
+
+// Loads every palette image in parallel like `load_images`, but keeps only the average color (in Oklab space) of each
+// one's tile for the first span instead of its tiles, for `--descriptors-only`, so tiles never all have to be held in
+// memory at once. Returns the palette images with empty tile sets, and their colors by index.
+pub(crate) fn load_descriptors(
+    paths: Vec<PathBuf>,
+    options: &LoadOptions<'_>,
+    alpha_weighted: bool,
+) -> Result<(LoadedPalette, Vec<[f32; 3]>)> {
+    let tile_set_count = options.spans.len();
+    let options = LoadOptions {
+        spans: &options.spans[..1],
+        ..*options
+    };
+    let loaded_descriptors = paths
+        .par_iter()
+        .map(|path| {
+            Ok(match load_image(path, &options)? {
+                LoadedImage::Kept(mut resized_images, dimensions, note) => {
+                    let tile = &mut resized_images[0];
+                    composite_over_black(tile);
+                    Ok((
+                        average_color::<TilePixel>(tile, alpha_weighted),
+                        dimensions,
+                        note,
+                    ))
+                }
+                LoadedImage::Rejected(reason) => Err(reason),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut palette = LoadedPalette {
+        paths: Vec::with_capacity(paths.len()),
+        tile_sets: vec![Vec::new(); tile_set_count],
+        source_dimensions: Vec::with_capacity(paths.len()),
+    };
+    let mut colors = Vec::with_capacity(paths.len());
+    for (path, loaded_descriptor) in paths.into_iter().zip(loaded_descriptors) {
+        match loaded_descriptor {
+            Ok((color, dimensions, note)) => {
+                if let Some(note) = note {
+                    log!("palette image {}: {note}", path.display());
+                }
+                palette.paths.push(path);
+                palette.source_dimensions.push(dimensions);
+                colors.push(color);
+            }
+            Err(reason) => log!("rejected palette image {}: {reason}", path.display()),
+        }
+    }
+
+    Ok((palette, colors))
+}
+
+// Options controlling how palette images are loaded
+pub(crate) struct LoadOptions<'a> {
+    pub(crate) tile_size: TileSize,
+    // Every span of cell that can appear in the output image; each image is resized once for each of these
+    pub(crate) spans: &'a [u32],
+    // Minimum width and height of a palette image; smaller images are rejected unless `allow_upscale` is true
+    pub(crate) min_tile_source: Option<u32>,
+    pub(crate) allow_upscale: bool,
+    pub(crate) quality_filters: QualityFilters,
+    pub(crate) upscale_policy: UpscalePolicy,
+    pub(crate) animated_frames: AnimatedFrames,
+    // Number of pixels above which palette images are decoded at a reduced size, or rejected if they can't be
+    pub(crate) max_source_pixels: Option<u64>,
+    // Cache of resized tiles to use and add to, if any
+    pub(crate) cache: Option<&'a TileCache>,
+}
+
+// How palette images smaller than the tiles they are resized to are handled
+#[derive(Clone, Copy, Default)]
+pub(crate) enum UpscalePolicy {
+    // Upscale via triangle/bilinear sampling, like any other resize
+    #[default]
+    Bilinear,
+    // Upscale via nearest-neighbor sampling, keeping hard pixel edges (e.g. for pixel art palettes)
+    Nearest,
+    // Upscale via Lanczos resampling, which is sharper than bilinear sampling but slower
+    Lanczos,
+    // Reject the image
+    Skip,
+}
+
+impl UpscalePolicy {
+    // Name of the algorithm used when upscaling, which tiles in the tile cache are keyed by
+    fn algorithm_name(self) -> &'static str {
+        match self {
+            Self::Bilinear | Self::Skip => "bilinear",
+            Self::Nearest => "nearest",
+            Self::Lanczos => "lanczos",
+        }
+    }
+}
+
+impl FromStr for UpscalePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bilinear" => Ok(Self::Bilinear),
+            "nearest" => Ok(Self::Nearest),
+            "lanczos" => Ok(Self::Lanczos),
+            "skip" => Ok(Self::Skip),
+            _ => bail!("expected \"bilinear\", \"nearest\", \"lanczos\", or \"skip\""),
+        }
+    }
+}
+
+// Outcome of loading a palette image
+#[derive(Clone)]
+pub(crate) enum LoadedImage {
+    // The image resized once per cell span, along with its original dimensions and a note to report about it, if any.
+    // With `AnimatedFrames::Each`, an animated image has its frames resized once per cell span, one frame after another.
+    Kept(Vec<Vec<u8>>, (u32, u32), Option<String>),
+    // The image was rejected for the given reason
+    Rejected(String),
+}
+
+// Decodes a palette image and processes it with `process_image`.
+// If there is a tile cache, cached tiles are used instead when available, and newly resized tiles are cached.
+// The cache is bypassed when quality filters are enabled, since they need the decoded image.
+pub(crate) fn load_image(path: &Path, options: &LoadOptions<'_>) -> Result<LoadedImage> {
+    Ok(load_image_for_sizes(path, std::slice::from_ref(options))?
+        .pop()
+        .unwrap())
+}
+
+// Like `load_image`, once for each element of `options`, decoding the palette image at most once.
+// The cache is also bypassed when animated palette images aren't decoded to their first frame, since it only holds
+// tiles of first frames.
+pub(crate) fn load_image_for_sizes(
+    path: &Path,
+    options: &[LoadOptions<'_>],
+) -> Result<Vec<LoadedImage>> {
+    let mut images: Option<Vec<TileImage>> = None;
+    let mut loaded_images = Vec::with_capacity(options.len());
+
+    for options in options {
+        let cache = options.cache.filter(|_| {
+            !options.quality_filters.is_enabled()
+                && options.animated_frames == AnimatedFrames::First
+        });
+
+        if let Some(cache) = cache
+            && let Some(loaded_image) = load_cached_image(cache, path, options)?
+        {
+            loaded_images.push(loaded_image);
+            continue;
+        }
+
+        let images = match &mut images {
+            Some(images) => images,
+            None => match options
+                .max_source_pixels
+                .map(|max_pixels| prescale(path, max_pixels))
+                .transpose()
+                .with_context(|| format!("palette image {}", path.display()))?
+            {
+                Some(Prescaled::Decoded(image)) => images.insert(vec![image]),
+                Some(Prescaled::Unsupported(reason)) => {
+                    loaded_images.push(LoadedImage::Rejected(reason));
+                    continue;
+                }
+                Some(Prescaled::Within) | None => images.insert(
+                    decode_palette_image(path, options.animated_frames)
+                        .with_context(|| format!("palette image {}", path.display()))?,
+                ),
+            },
+        };
+        // An image is rejected if any of its frames is
+        let mut loaded_image = process_image(&images[0], options)?;
+        for image in &images[1..] {
+            match (&mut loaded_image, process_image(image, options)?) {
+                (LoadedImage::Kept(resized_images, ..), LoadedImage::Kept(frame_images, ..)) => {
+                    resized_images.extend(frame_images);
+                }
+                (LoadedImage::Kept(..), rejected) => {
+                    loaded_image = rejected;
+                    break;
+                }
+                (LoadedImage::Rejected(_), _) => break,
+            }
+        }
+
+        if let Some(cache) = cache
+            && let LoadedImage::Kept(resized_images, dimensions, _) = &loaded_image
+        {
+            for (&span, resized_image) in options.spans.iter().zip(resized_images) {
+                cache.store(path, &cache_key(options, span), *dimensions, resized_image)?;
+            }
+        }
+
+        loaded_images.push(loaded_image);
+    }
+
+    Ok(loaded_images)
+}
+
+// Ensures a palette image's tiles are in the tile cache of `options`, decoding and resizing it if they aren't.
+// Returns whether the image had to be resized, or `None` if it was rejected.
+pub(crate) fn warm_image(path: &Path, options: &LoadOptions<'_>) -> Result<Option<bool>> {
+    let (loaded_image, is_resized) = match options
+        .cache
+        .map(|cache| load_cached_image(cache, path, options))
+        .transpose()?
+        .flatten()
+    {
+        Some(loaded_image) => (loaded_image, false),
+        None => (load_image(path, options)?, true),
+    };
+
+    Ok(matches!(loaded_image, LoadedImage::Kept(..)).then_some(is_resized))
+}
+
+// Describes the tile for a cell span in the tile cache.
+fn cache_key<'a>(options: &LoadOptions<'a>, span: u32) -> CacheKey<'a> {
+    CacheKey {
+        tile_size: options.tile_size,
+        span,
+        upscale_algorithm: options.upscale_policy.algorithm_name(),
+        max_source_pixels: options.max_source_pixels,
+    }
+}
+
+// Loads a palette image's tiles for every cell span from the tile cache, checking its original dimensions
+// against the filters in `options`. Returns `None` unless every tile is cached.
+fn load_cached_image(
+    cache: &TileCache,
+    path: &Path,
+    options: &LoadOptions<'_>,
+) -> Result<Option<LoadedImage>> {
+    let mut dimensions = None;
+    let mut resized_images = Vec::with_capacity(options.spans.len());
+
+    for &span in options.spans {
+        let Some(entry) = cache.load(path, &cache_key(options, span))? else {
+            return Ok(None);
+        };
+
+        let expected_len = (span * options.tile_size.width * span * options.tile_size.height)
+            as usize
+            * TILE_PIXEL_SIZE;
+        if entry.tile.len() != expected_len
+            || dimensions.is_some_and(|dimensions| dimensions != entry.dimensions)
+        {
+            return Ok(None);
+        }
+
+        dimensions = Some(entry.dimensions);
+        resized_images.push(entry.tile);
+    }
+
+    Ok(
+        dimensions.map(|dimensions| match check_dimensions(dimensions, options) {
+            Ok(note) => LoadedImage::Kept(resized_images, dimensions, note),
+            Err(reason) => LoadedImage::Rejected(reason),
+        }),
+    )
+}
+
+// Checks a palette image against the filters in `options`, then resizes it to the size of a tile for each cell span.
+// Images are stretched to square tiles, but cropped (around their center) to the aspect ratio of non-square tiles.
+pub(crate) fn process_image(image: &TileImage, options: &LoadOptions<'_>) -> Result<LoadedImage> {
+    let dimensions = image.dimensions();
+
+    let note = match check_dimensions(dimensions, options) {
+        Ok(note) => note,
+        Err(reason) => return Ok(LoadedImage::Rejected(reason)),
+    };
+
+    if let Some(reason) = options.quality_filters.check(image)? {
+        return Ok(LoadedImage::Rejected(reason));
+    }
+
+    let tile_size = options.tile_size;
+    let (x, y, cropped_width, cropped_height) = crop_rect(dimensions, tile_size);
+    let cropped_image = (tile_size.width != tile_size.height)
+        .then(|| crop_imm(image, x, y, cropped_width, cropped_height).to_image());
+    let image = cropped_image.as_ref().unwrap_or(image);
+
+    let resized_images = options
+        .spans
+        .iter()
+        .map(|span| {
+            let (new_width, new_height) = (span * tile_size.width, span * tile_size.height);
+            let is_upscaled = cropped_width < new_width || cropped_height < new_height;
+            let algorithm = match options.upscale_policy {
+                UpscalePolicy::Nearest if is_upscaled => ResizeAlg::Nearest,
+                UpscalePolicy::Lanczos if is_upscaled => {
+                    ResizeAlg::Convolution(FilterType::Lanczos3)
+                }
+                _ => ResizeAlg::Interpolation(FilterType::Bilinear),
+            };
+            Ok(resize_image_with(image.clone(), new_width, new_height, algorithm)?.into_vec())
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(LoadedImage::Kept(resized_images, dimensions, note))
+}
+
+// Checks the dimensions of a palette image against `--min-tile-source` and `--upscale-policy skip`.
+// Returns a note to report about the image, if any, or the reason the image is rejected.
+pub(crate) fn check_dimensions(
+    (width, height): (u32, u32),
+    options: &LoadOptions<'_>,
+) -> std::result::Result<Option<String>, String> {
+    let mut note = None;
+
+    if let Some(min_tile_source) = options.min_tile_source
+        && width.min(height) < min_tile_source
+    {
+        let reason = format!(
+            "too small ({width}x{height} is below the minimum of {min_tile_source}x{min_tile_source})"
+        );
+        if !options.allow_upscale {
+            return Err(reason);
+        }
+        note = Some(format!("{reason}; upscaling anyway"));
+    }
+
+    // Every span's tile is at least as large as the smallest span's, so checking the largest span is enough
+    if matches!(options.upscale_policy, UpscalePolicy::Skip) {
+        let tile_size = options.tile_size;
+        let (_, _, cropped_width, cropped_height) = crop_rect((width, height), tile_size);
+        let largest_span = *options.spans.last().unwrap();
+        let (largest_width, largest_height) = (
+            largest_span * tile_size.width,
+            largest_span * tile_size.height,
+        );
+
+        if cropped_width < largest_width || cropped_height < largest_height {
+            return Err(format!(
+                "smaller than a tile ({cropped_width}x{cropped_height} is below {largest_width}x{largest_height})"
+            ));
+        }
+    }
+
+    Ok(note)
+}
+
+// Calculates the region (x, y, width, height) of a palette image that is kept when it is resized to a tile.
+// This is the whole image for square tiles, or the largest centered region with the aspect ratio of non-square tiles.
+fn crop_rect((width, height): (u32, u32), tile_size: TileSize) -> (u32, u32, u32, u32) {
+    if tile_size.width == tile_size.height {
+        return (0, 0, width, height);
+    }
+
+    let crop_width = width
+        .min((u64::from(height) * u64::from(tile_size.width) / u64::from(tile_size.height)) as u32)
+        .max(1);
+    let crop_height = height
+        .min((u64::from(width) * u64::from(tile_size.height) / u64::from(tile_size.width)) as u32)
+        .max(1);
+
+    (
+        (width - crop_width) / 2,
+        (height - crop_height) / 2,
+        crop_width,
+        crop_height,
+    )
+}
+
+// Resizes the input image to the specified dimensions via triangle/bilinear sampling, producing a new image as output.
+pub(crate) fn resize_image(
+    image: RgbaImage,
+    new_width: u32,
+    new_height: u32,
+) -> Result<Image<'static>> {
+    resize_image_with(
+        image,
+        new_width,
+        new_height,
+        ResizeAlg::Interpolation(FilterType::Bilinear),
+    )
+}
+
+// Resizes the input image to the specified dimensions with the given algorithm, producing a new image as output.
+fn resize_image_with(
+    image: RgbaImage,
+    new_width: u32,
+    new_height: u32,
+    algorithm: ResizeAlg,
+) -> Result<Image<'static>> {
+    let (width, height) = image.dimensions();
+    let image = Image::from_vec_u8(width, height, image.into_vec(), PixelType::U8x4)?;
+    let mut resized_image = Image::new(new_width, new_height, PixelType::U8x4);
+
+    Resizer::new().resize(
+        &image,
+        &mut resized_image,
+        &ResizeOptions::default().resize_alg(algorithm),
+    )?;
+
+    Ok(resized_image)
+}
+
+// Composites each pixel of a tile over a black (r=0, g=0, b=0) background.
+// The output image is opaque, so this is how tiles that are not opaque are drawn.
+// The alpha channel is left untouched so the tile's transparency can still be taken into account later.
+pub(crate) fn composite_over_black(tile: &mut [u8]) {
+    for px in tile.as_chunks_mut::<TILE_PIXEL_SIZE>().0 {
+        if px[3] != TILE_PIXEL_COMPONENT_MAX_INT {
+            let a = f32::from(px[3]);
+
+            px[0] = (f32::from(px[0]) * a / TILE_PIXEL_COMPONENT_MAX) as u8;
+            px[1] = (f32::from(px[1]) * a / TILE_PIXEL_COMPONENT_MAX) as u8;
+            px[2] = (f32::from(px[2]) * a / TILE_PIXEL_COMPONENT_MAX) as u8;
+        }
+    }
+}
+
+// Calculates the average color (in Oklab space) of the crop at each position within a zoomed tile that has been
+// composited over black, for `--source-crops`. The tile is divided into a grid of blocks, with CROP_ZOOM blocks for
+// each step between crop positions along each axis, and each crop's color is averaged from the blocks it covers.
+fn crop_colors(tile: &[u8], width: u32, height: u32) -> Vec<[f32; 3]> {
+    let steps = CROP_POSITIONS - 1;
+    let grid_size = CROP_ZOOM * steps;
+    // Sum of each color component and number of pixels in each block
+    let mut blocks = vec![[0.; 4]; (grid_size * grid_size) as usize];
+    for (px, px_idx) in tile.as_chunks::<TILE_PIXEL_SIZE>().0.iter().zip(0..) {
+        let block_x = px_idx % width * grid_size / width;
+        let block_y = px_idx / width * grid_size / height;
+        let block = &mut blocks[(block_y * grid_size + block_x) as usize];
+        for c in 0..3 {
+            block[c] += f32::from(px[c]);
+        }
+        block[3] += 1.;
+    }
+
+    // Each crop covers as many blocks as there are steps between positions, along each axis
+    (0..CROP_POSITIONS * CROP_POSITIONS)
+        .map(|position_idx| {
+            let (x, y) = (position_idx % CROP_POSITIONS, position_idx / CROP_POSITIONS);
+            let mut sum = [0.; 4];
+            for block_y in y..y + steps {
+                for block_x in x..x + steps {
+                    let block = blocks[(block_y * grid_size + block_x) as usize];
+                    for c in 0..4 {
+                        sum[c] += block[c];
+                    }
+                }
+            }
+            let scale = TILE_PIXEL_COMPONENT_MAX * sum[3].max(1.);
+            linear_srgb_to_oklab(sum[0] / scale, sum[1] / scale, sum[2] / scale)
+        })
+        .collect()
+}
+
+// Calculates the average color of a tile that has been composited over black, in Oklab space, from its channels in
+// the layout of `P`. By default, transparent pixels count as black. If `alpha_weighted` is true, the average is instead
+// weighted by the opacity of each pixel, so transparent pixels are excluded.
+pub(crate) fn average_color<P: PixelLayout>(
+    tile: &[P::Subpixel],
+    alpha_weighted: bool,
+) -> [f32; 3] {
+    let channel_max = P::channel_max();
+    let (mut sums, mut a_sum) = ([0.; 3], 0.);
+
+    for px in tile.chunks_exact(P::SIZE) {
+        for (sum, &c) in sums.iter_mut().zip(px) {
+            *sum += c.into();
+        }
+        a_sum += P::ALPHA_CHANNEL.map_or(1., |alpha| px[alpha].into() / channel_max);
+    }
+
+    // Fully transparent tiles are treated as black either way
+    let pixel_count = if alpha_weighted && a_sum > 0. {
+        a_sum
+    } else {
+        (tile.len() / P::SIZE) as f32
+    };
+    let scale = channel_max * pixel_count;
+
+    let [r_sum, g_sum, b_sum] = sums;
+    linear_srgb_to_oklab(r_sum / scale, g_sum / scale, b_sum / scale)
+}
+
+// Normalizes the mean lightness (and, if `contrast` is true, the spread of lightness) of each tile
+// to the mean across all tiles, so tiles from over- or underexposed sources don't cluster at the extremes.
+// Tiles are expected to not yet be composited over black; pixels are weighted by opacity.
+fn normalize_exposure(tiles: &mut [Vec<u8>], contrast: bool) {
+    // Mean and standard deviation of lightness for each tile
+    let stats: Vec<_> = tiles.par_iter().map(|tile| lightness_stats(tile)).collect();
+
+    if stats.is_empty() {
+        return;
+    }
+
+    let target_mean = stats.iter().map(|(mean, _)| mean).sum::<f32>() / stats.len() as f32;
+    let target_std_dev = stats.iter().map(|(_, std_dev)| std_dev).sum::<f32>() / stats.len() as f32;
+
+    tiles
+        .par_iter_mut()
+        .zip(stats)
+        .for_each(|(tile, (mean, std_dev))| {
+            let scale = if contrast && std_dev > 0. {
+                target_std_dev / std_dev
+            } else {
+                1.
+            };
+
+            for px in tile.as_chunks_mut::<TILE_PIXEL_SIZE>().0 {
+                let [l, a, b] = linear_srgb_to_oklab(
+                    f32::from(px[0]) / TILE_PIXEL_COMPONENT_MAX,
+                    f32::from(px[1]) / TILE_PIXEL_COMPONENT_MAX,
+                    f32::from(px[2]) / TILE_PIXEL_COMPONENT_MAX,
+                );
+                let l = (l - mean) * scale + target_mean;
+
+                for (component, value) in px.iter_mut().zip(oklab_to_linear_srgb(l, a, b)) {
+                    *component = (value.clamp(0., 1.) * TILE_PIXEL_COMPONENT_MAX).round() as u8;
+                }
+            }
+        });
+}
+
+// Calculates the opacity-weighted mean and standard deviation of the Oklab lightness of a tile's pixels.
+fn lightness_stats(tile: &[u8]) -> (f32, f32) {
+    let (mut l_sum, mut l_sq_sum, mut weight_sum) = (0., 0., 0.);
+
+    for px in tile.as_chunks::<TILE_PIXEL_SIZE>().0 {
+        let weight = f32::from(px[3]) / TILE_PIXEL_COMPONENT_MAX;
+        let [l, _, _] = linear_srgb_to_oklab(
+            f32::from(px[0]) / TILE_PIXEL_COMPONENT_MAX,
+            f32::from(px[1]) / TILE_PIXEL_COMPONENT_MAX,
+            f32::from(px[2]) / TILE_PIXEL_COMPONENT_MAX,
+        );
+
+        l_sum += l * weight;
+        l_sq_sum += l * l * weight;
+        weight_sum += weight;
+    }
+
+    if weight_sum == 0. {
+        return (0., 0.);
+    }
+
+    let mean = l_sum / weight_sum;
+    let variance = (l_sq_sum / weight_sum - mean * mean).max(0.);
+
+    (mean, variance.sqrt())
+}
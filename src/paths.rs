@@ -0,0 +1,118 @@
+use anyhow::{Result, bail};
+use std::{
+    ffi::OsString,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+// Formats a path as text losslessly, for manifests such as placements files and palette packs.
+// Paths are stored as UTF-8, with "%" and any bytes that are not valid UTF-8 (which file names on Unix may contain)
+// escaped as "%XX". On Windows, unpaired surrogates (which file names may also contain) are escaped the same way,
+// as bytes of their WTF-8 encoding. Paths that are valid UTF-8 and don't contain "%" are unchanged.
+pub(crate) fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for chunk in path.as_os_str().as_encoded_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '%' => encoded.push_str("%25"),
+                c => encoded.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            // Writing to a string can't fail
+            write!(encoded, "%{byte:02X}").unwrap();
+        }
+    }
+    encoded
+}
+
+// Parses a path formatted by `encode_path`. A "%" that isn't followed by two hex digits is kept as is,
+// so manifests written before paths were escaped still mostly work.
+pub(crate) fn decode_path(encoded: &str) -> Result<PathBuf> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%'
+            && let Some(hex) = tail.get(..2)
+            && let Ok(hex) = std::str::from_utf8(hex)
+            && let Ok(escaped) = u8::from_str_radix(hex, 16)
+        {
+            bytes.push(escaped);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    // No platform allows NUL in paths, but it could be escaped as "%00"
+    if bytes.contains(&0) {
+        bail!("path contains a NUL byte");
+    }
+
+    #[cfg(unix)]
+    let path: OsString = std::os::unix::ffi::OsStringExt::from_vec(bytes);
+    #[cfg(not(unix))]
+    let path = os_string_from_bytes(bytes)?;
+    Ok(PathBuf::from(path))
+}
+
+// Formats a path relative to a directory for a manifest (e.g. a palette pack), with `encode_path`'s escaping
+// and "/" as a separator on any platform, so manifests are portable.
+pub(crate) fn encode_relative_path(path: &Path, dir: &Path) -> Result<String> {
+    Ok(path
+        .strip_prefix(dir)?
+        .components()
+        .map(|component| encode_path(Path::new(component.as_os_str())))
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+// Joins a relative path from a manifest (formatted by `encode_relative_path`, or written by hand) onto a directory,
+// one component at a time. Extended-length paths on Windows ("\\?\C:\..."), which are needed for paths longer
+// than MAX_PATH, don't treat "/" as a separator, so it can't be left for the platform to interpret.
+pub(crate) fn decode_relative_path(dir: &Path, relative: &str) -> Result<PathBuf> {
+    let mut path = dir.to_path_buf();
+    for component in relative.split(['/', std::path::MAIN_SEPARATOR]) {
+        if !component.is_empty() && component != "." {
+            path.push(decode_path(component)?);
+        }
+    }
+    Ok(path)
+}
+
+#[cfg(windows)]
+fn os_string_from_bytes(bytes: Vec<u8>) -> Result<OsString> {
+    use std::os::windows::ffi::OsStringExt;
+
+    // Decode WTF-8, which is UTF-8 extended with unpaired surrogates (encoded like any other code point)
+    let mut wide = Vec::with_capacity(bytes.len());
+    let mut rest = &bytes[..];
+    while !rest.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(rest) {
+            Ok(valid) => (valid, &[][..]),
+            Err(err) => {
+                let (valid, invalid) = rest.split_at(err.valid_up_to());
+                (std::str::from_utf8(valid).unwrap(), invalid)
+            }
+        };
+        wide.extend(valid.encode_utf16());
+        rest = match invalid {
+            [] => invalid,
+            [0xed, high @ 0xa0..=0xbf, low @ 0x80..=0xbf, tail @ ..] => {
+                wide.push(0xd000 | (u16::from(high & 0x3f) << 6) | u16::from(low & 0x3f));
+                tail
+            }
+            _ => bail!("path is not valid on this platform"),
+        };
+    }
+
+    Ok(OsString::from_wide(&wide))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn os_string_from_bytes(bytes: Vec<u8>) -> Result<OsString> {
+    match String::from_utf8(bytes) {
+        Ok(path) => Ok(OsString::from(path)),
+        Err(_) => bail!("path is not valid on this platform"),
+    }
+}
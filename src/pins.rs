@@ -0,0 +1,102 @@
+use crate::{
+    layout::{Cell, Pinned},
+    paths::decode_relative_path,
+};
+use anyhow::{Context, Result, bail};
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+// A palette image that must be placed in the cell covering a specific position of the grid
+pub(crate) struct Pin {
+    // Position in the grid, in units of cells (i.e. pixels of the input image after any resizing)
+    column: u32,
+    row: u32,
+    // Palette image, by index
+    tile_index: usize,
+    line_number: u32,
+}
+
+// Reads a pin file. Each non-empty line has a column, a row, and a path (relative to the palette directory),
+// separated by commas, e.g. "12,40,family/portrait.jpg". Lines starting with "#" are comments.
+pub(crate) fn load_pins(
+    path: &Path,
+    palette_dir: &Path,
+    palette_paths: &[PathBuf],
+) -> Result<Vec<Pin>> {
+    let contents = read_to_string(path)?;
+    let mut pins = Vec::new();
+
+    for (line, line_number) in contents.lines().zip(1..) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.splitn(3, ',');
+        let (Some(column), Some(row), Some(image_path)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            bail!(
+                "`--pins`: line {line_number}: expected a column, a row, and a path separated by commas"
+            );
+        };
+        let column = column
+            .trim()
+            .parse()
+            .with_context(|| format!("`--pins`: line {line_number}: invalid column"))?;
+        let row = row
+            .trim()
+            .parse()
+            .with_context(|| format!("`--pins`: line {line_number}: invalid row"))?;
+
+        let image_path = decode_relative_path(palette_dir, image_path.trim())
+            .with_context(|| format!("`--pins`: line {line_number}: invalid path"))?;
+        let Some(tile_index) = palette_paths.iter().position(|path| *path == image_path) else {
+            bail!(
+                "`--pins`: line {line_number}: {} is not in the palette",
+                image_path.display()
+            );
+        };
+
+        pins.push(Pin {
+            column,
+            row,
+            tile_index,
+            line_number,
+        });
+    }
+
+    Ok(pins)
+}
+
+// Pins palette images to the cells covering the pinned positions.
+// `width` and `height` are the dimensions of the grid the cells were laid out on.
+pub(crate) fn apply_pins(cells: &mut [Cell], pins: &[Pin], width: u32, height: u32) -> Result<()> {
+    // Index of the cell covering each position of the grid
+    let mut cell_indices = vec![0; (width * height) as usize];
+    for (cell, cell_idx) in cells.iter().zip(0..) {
+        for y in cell.y..cell.y + cell.span {
+            for x in cell.x..cell.x + cell.span {
+                cell_indices[(y * width + x) as usize] = cell_idx;
+            }
+        }
+    }
+
+    for pin in pins {
+        if pin.column >= width || pin.row >= height {
+            bail!(
+                "`--pins`: line {}: position ({}, {}) is outside the {width}x{height} grid",
+                pin.line_number,
+                pin.column,
+                pin.row
+            );
+        }
+
+        let cell_idx = cell_indices[(pin.row * width + pin.column) as usize];
+        cells[cell_idx].pinned = Some(Pinned::Tile(pin.tile_index));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use image::{Pixel, Primitive, Rgb, Rgba};
+
+// Layout of the pixels of tiles and images, so loops over pixels (e.g. composing tiles and averaging their colors)
+// are written once for every layout instead of hard-coding RGBA8 tiles and RGB8 images. Beyond the channels described
+// by `Pixel`, a layout says which channel (if any) is alpha. Color channels come first, in RGB order.
+pub(crate) trait PixelLayout: Pixel<Subpixel: Into<f32>> {
+    // Index of the alpha channel, if pixels have one
+    const ALPHA_CHANNEL: Option<usize>;
+    // Number of channels of each pixel, for indexing flat buffers of channels
+    const SIZE: usize = Self::CHANNEL_COUNT as usize;
+
+    // Largest value of a channel, i.e. the value of an opaque alpha channel
+    fn channel_max() -> f32 {
+        Self::Subpixel::DEFAULT_MAX_VALUE.into()
+    }
+}
+
+impl<T: Primitive + Into<f32>> PixelLayout for Rgba<T>
+where
+    Self: Pixel<Subpixel = T>,
+{
+    const ALPHA_CHANNEL: Option<usize> = Some(3);
+}
+
+impl<T: Primitive + Into<f32>> PixelLayout for Rgb<T>
+where
+    Self: Pixel<Subpixel = T>,
+{
+    const ALPHA_CHANNEL: Option<usize> = None;
+}
@@ -0,0 +1,138 @@
+use crate::{debug::csv_field, layout::Cell, rng::RNG_VERSION, schema::PLACEMENTS_SCHEMA};
+use anyhow::{Context, Result, bail};
+use foldhash::HashMap;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+// Start of the line recording the version of the pseudorandom number generator a placements file was written with
+const RNG_LINE_PREFIX: &str = "# tessera rng v";
+
+// A CSV file listing how each cell of the output image was drawn, written to the `--placements` path.
+// Each row has these columns:
+// - `row`, `column`: position of the cell's top-left corner, in units of input image pixels
+// - `span`: width and height of the cell, in units of input image pixels
+// - `source`: path of the palette image placed in the cell, or empty if the cell was drawn as solid color
+// - `rotation`: angle (in degrees, clockwise) that the palette image was rotated by, with `--rotate-jitter`, or else 0
+// - `flip`: how the palette image was flipped; tiles are currently never flipped
+// - `error`: color distance (in Oklab space) between the cell and its palette image, or empty if there is none
+// - `color`: target color of the cell, as a hex color such as "#1e1e1e"
+// - `order`: if an order was requested with `--order`, when the cell is placed in that order, from 0
+// - `alternate_1`, `alternate_1_error`, ...: if alternates were requested, the next nearest palette images to the cell
+//   and their color distances, nearest first, so a cell's palette image can be swapped without matching again
+// Rows are written as cells are matched, so the file is never held in memory at once.
+// The header is preceded by a line recording the version of the file's format (see `PLACEMENTS_SCHEMA`), and if any
+// recorded result is randomized (e.g. with `--order random`), by a line recording the version of the pseudorandom
+// number generator and the seed, such as "# tessera rng v1 seed 42".
+pub(crate) struct Placements {
+    writer: BufWriter<File>,
+    // Number of alternate palette images recorded for each cell
+    alternate_count: usize,
+    // Rank of each cell in the requested order, keyed by the position of its top-left corner
+    ranks: Option<HashMap<(u32, u32), u32>>,
+}
+
+impl Placements {
+    pub(crate) fn new(
+        path: &Path,
+        alternate_count: usize,
+        ranks: Option<HashMap<(u32, u32), u32>>,
+        seed: Option<u64>,
+    ) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", PLACEMENTS_SCHEMA.line())?;
+        if let Some(seed) = seed {
+            writeln!(writer, "{RNG_LINE_PREFIX}{RNG_VERSION} seed {seed}")?;
+        }
+        write!(writer, "row,column,span,source,rotation,flip,error,color")?;
+        if ranks.is_some() {
+            write!(writer, ",order")?;
+        }
+        for n in 1..=alternate_count {
+            write!(writer, ",alternate_{n},alternate_{n}_error")?;
+        }
+        writeln!(writer)?;
+
+        Ok(Self {
+            writer,
+            alternate_count,
+            ranks,
+        })
+    }
+
+    pub(crate) fn alternate_count(&self) -> usize {
+        self.alternate_count
+    }
+
+    // Records the palette image placed in a cell, along with the color distance between them and the angle (in
+    // degrees) that it was rotated by, and up to `alternate_count` alternate palette images with their color distances.
+    pub(crate) fn record(
+        &mut self,
+        cell: &Cell,
+        source: Option<(&Path, f32)>,
+        rotation: f32,
+        alternates: &[(&Path, f32)],
+    ) -> Result<()> {
+        write!(self.writer, "{},{},{},", cell.y, cell.x, cell.span)?;
+
+        match source {
+            Some((path, error)) => {
+                write!(self.writer, "{},{rotation},none,{error}", csv_field(path))?;
+            }
+            None => write!(self.writer, ",{rotation},none,")?,
+        }
+        let [r, g, b] = cell.color.0;
+        write!(self.writer, ",#{r:02x}{g:02x}{b:02x}")?;
+        if let Some(ranks) = &self.ranks {
+            write!(self.writer, ",{}", ranks[&(cell.x, cell.y)])?;
+        }
+
+        // Cells with fewer alternates (e.g. with a small palette) get empty fields
+        for n in 0..self.alternate_count {
+            match alternates.get(n) {
+                Some((path, error)) => write!(self.writer, ",{},{error}", csv_field(path))?,
+                None => write!(self.writer, ",,")?,
+            }
+        }
+        writeln!(self.writer)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Splits the contents of a placements file into its leading comment lines and the CSV data after them.
+// Fails if the file is in a newer version of its format, or records randomized results from a different version of
+// the pseudorandom number generator, since they could not be reproduced.
+pub(crate) fn split_preamble(contents: &str) -> Result<(&str, &str)> {
+    let mut preamble_len = 0;
+
+    for line in contents.split_inclusive('\n') {
+        if !line.starts_with('#') {
+            break;
+        }
+        PLACEMENTS_SCHEMA.check(line)?;
+        if let Some(rest) = line.trim().strip_prefix(RNG_LINE_PREFIX) {
+            let version: u32 = rest
+                .split_whitespace()
+                .next()
+                .and_then(|version| version.parse().ok())
+                .context("placements file: invalid random number generator version")?;
+            if version != RNG_VERSION {
+                bail!(
+                    "placements file was written with version {version} of the random number generator, \
+                    but this version of tessera uses version {RNG_VERSION} and cannot reproduce its randomized results"
+                );
+            }
+        }
+        preamble_len += line.len();
+    }
+
+    Ok(contents.split_at(preamble_len))
+}
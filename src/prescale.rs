@@ -0,0 +1,86 @@
+use crate::TileImage;
+use anyhow::Result;
+use image::{ImageFormat, ImageReader, Rgba};
+use png::{ColorType, Decoder, Transformations};
+use std::{fs::File, io::BufReader, path::Path};
+
+// How a palette image is decoded under a limit on its number of pixels, for `--max-source-megapixels`
+pub(crate) enum Prescaled {
+    // The image is within the limit, and is decoded as usual
+    Within,
+    // The image is over the limit, and was downsampled while it was decoded
+    Decoded(TileImage),
+    // The image is over the limit, but its format can't be downsampled while it is decoded, for the given reason
+    Unsupported(String),
+}
+
+// Decodes a palette image with more than `max_pixels` pixels at a reduced size, so a single gigantic palette image
+// (say, a 300-megapixel photo) never has to fit in memory at full size. The image is downsampled by the smallest
+// whole factor that brings it within the limit, averaging each block of pixels as its rows are decoded, before the
+// usual resize to tiles. Only non-interlaced PNG images (of their first frame, if animated) are decoded this way.
+pub(crate) fn prescale(path: &Path, max_pixels: u64) -> Result<Prescaled> {
+    let reader = ImageReader::open(path)?;
+    let format = reader.format();
+    let (width, height) = reader.into_dimensions()?;
+    let pixel_count = u64::from(width) * u64::from(height);
+    if pixel_count <= max_pixels {
+        return Ok(Prescaled::Within);
+    }
+
+    let unsupported = |reason: &str| {
+        Ok(Prescaled::Unsupported(format!(
+            "{width}x{height} is over the limit of `--max-source-megapixels`, and {reason} can't be decoded at a reduced size"
+        )))
+    };
+    if format != Some(ImageFormat::Png) {
+        return unsupported("images in this format");
+    }
+
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut png_reader = decoder.read_info()?;
+    if png_reader.info().interlaced {
+        return unsupported("interlaced PNG images");
+    }
+    let (color_type, _) = png_reader.output_color_type();
+
+    let mut factor = (pixel_count as f64 / max_pixels as f64).sqrt().ceil() as u32;
+    while u64::from(width.div_ceil(factor)) * u64::from(height.div_ceil(factor)) > max_pixels {
+        factor += 1;
+    }
+    let (scaled_width, scaled_height) = (width.div_ceil(factor), height.div_ceil(factor));
+    let mut image = TileImage::new(scaled_width, scaled_height);
+
+    // Sums of each channel over the block of pixels that each pixel of the current scaled row averages
+    let mut sums = vec![[0u64; 4]; scaled_width as usize];
+    let mut y = 0;
+    while let Some(row) = png_reader.next_row()? {
+        for (x, px) in (0..).zip(row.data().chunks_exact(color_type.samples())) {
+            let rgba = match (color_type, px) {
+                (ColorType::Grayscale, &[l]) => [l, l, l, u8::MAX],
+                (ColorType::GrayscaleAlpha, &[l, a]) => [l, l, l, a],
+                (ColorType::Rgb, &[r, g, b]) => [r, g, b, u8::MAX],
+                (ColorType::Rgba, &[r, g, b, a]) => [r, g, b, a],
+                // Indexed colors are expanded by the transformations
+                _ => unreachable!(),
+            };
+            for (sum, c) in sums[(x / factor) as usize].iter_mut().zip(rgba) {
+                *sum += u64::from(c);
+            }
+        }
+
+        y += 1;
+        if y % factor == 0 || y == height {
+            let block_height = (y - 1) % factor + 1;
+            for (scaled_x, sum) in (0..).zip(&mut sums) {
+                let block_width = (width - scaled_x * factor).min(factor);
+                let count = u64::from(block_width * block_height);
+                let px = sum.map(|sum| ((sum + count / 2) / count) as u8);
+                image.put_pixel(scaled_x, (y - 1) / factor, Rgba(px));
+                *sum = [0; 4];
+            }
+        }
+    }
+
+    Ok(Prescaled::Decoded(image))
+}
@@ -0,0 +1,124 @@
+use crate::{
+    TILE_PIXEL_COMPONENT_MAX, TILE_PIXEL_SIZE, color::linear_srgb_to_oklab, palette::resize_image,
+};
+use anyhow::Result;
+use image::RgbaImage;
+
+// Width and height of the copy of a palette image that quality metrics are measured on.
+// Measuring at a fixed size keeps thresholds meaningful regardless of source resolution and tile size.
+const ANALYSIS_SIZE: u32 = 128;
+
+// Thresholds below which palette images are rejected for being low quality; `None` disables a filter
+#[derive(Clone, Copy, Default)]
+pub(crate) struct QualityFilters {
+    // Minimum variance of the Laplacian of the image's luma (from 0 to 255); lower values mean blurrier images
+    pub(crate) blurry: Option<f32>,
+    // Minimum standard deviation of the image's Oklab lightness
+    pub(crate) low_contrast: Option<f32>,
+    // Minimum mean Oklab distance between each pixel's color and the image's mean color
+    pub(crate) uniform: Option<f32>,
+}
+
+impl QualityFilters {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.blurry.is_some() || self.low_contrast.is_some() || self.uniform.is_some()
+    }
+
+    // Checks an image against every enabled filter, returning the reason it was rejected, if any.
+    pub(crate) fn check(&self, image: &RgbaImage) -> Result<Option<String>> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+
+        let image = resize_image(image.clone(), ANALYSIS_SIZE, ANALYSIS_SIZE)?;
+        let pixels = image.buffer().as_chunks::<TILE_PIXEL_SIZE>().0;
+
+        if let Some(threshold) = self.blurry {
+            let variance = laplacian_variance(pixels);
+            if variance < threshold {
+                return Ok(Some(format!(
+                    "blurry (Laplacian variance {variance:.1} is below {threshold})"
+                )));
+            }
+        }
+
+        let colors: Vec<_> = pixels
+            .iter()
+            .map(|px| {
+                linear_srgb_to_oklab(
+                    f32::from(px[0]) / TILE_PIXEL_COMPONENT_MAX,
+                    f32::from(px[1]) / TILE_PIXEL_COMPONENT_MAX,
+                    f32::from(px[2]) / TILE_PIXEL_COMPONENT_MAX,
+                )
+            })
+            .collect();
+        let count = colors.len() as f32;
+        let mean = colors.iter().fold([0.; 3], |sum, color| {
+            [0, 1, 2].map(|i| sum[i] + color[i] / count)
+        });
+
+        if let Some(threshold) = self.low_contrast {
+            let std_dev = (colors
+                .iter()
+                .map(|color| (color[0] - mean[0]).powi(2))
+                .sum::<f32>()
+                / count)
+                .sqrt();
+            if std_dev < threshold {
+                return Ok(Some(format!(
+                    "low contrast (lightness standard deviation {std_dev:.3} is below {threshold})"
+                )));
+            }
+        }
+
+        if let Some(threshold) = self.uniform {
+            let spread = colors
+                .iter()
+                .map(|color| {
+                    [0, 1, 2]
+                        .map(|i| (color[i] - mean[i]).powi(2))
+                        .iter()
+                        .sum::<f32>()
+                        .sqrt()
+                })
+                .sum::<f32>()
+                / count;
+            if spread < threshold {
+                return Ok(Some(format!(
+                    "nearly uniform (mean color distance {spread:.3} is below {threshold})"
+                )));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+// Calculates the variance of the Laplacian of an image's luma, a common measure of sharpness.
+// Pixels are expected in row-major order with a width and height of `ANALYSIS_SIZE`.
+fn laplacian_variance(pixels: &[[u8; TILE_PIXEL_SIZE]]) -> f32 {
+    let size = ANALYSIS_SIZE as usize;
+    let luma = |x: usize, y: usize| {
+        let px = pixels[y * size + x];
+        0.299 * f32::from(px[0]) + 0.587 * f32::from(px[1]) + 0.114 * f32::from(px[2])
+    };
+
+    let mut values = Vec::with_capacity((size - 2) * (size - 2));
+
+    for y in 1..size - 1 {
+        for x in 1..size - 1 {
+            values.push(
+                luma(x - 1, y) + luma(x + 1, y) + luma(x, y - 1) + luma(x, y + 1) - 4. * luma(x, y),
+            );
+        }
+    }
+
+    let count = values.len() as f32;
+    let mean = values.iter().sum::<f32>() / count;
+
+    values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f32>()
+        / count
+}
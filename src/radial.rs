@@ -0,0 +1,214 @@
+use crate::{
+    InputImage,
+    layout::{Cell, grid_cells},
+};
+use image::{Rgb, RgbImage, imageops::interpolate_bilinear};
+use rayon::prelude::*;
+use std::f32::consts::{PI, TAU};
+
+// Number of points along each axis of a tile that its color is sampled at, so each tile is matched against the
+// average color of the region it covers
+const SAMPLES_PER_AXIS: u32 = 4;
+
+// With `--layout rings` or `--layout spiral`, tiles are laid end to end along concentric rings or an Archimedean
+// spiral around a center point, each ring (or turn) one tile thick, with each tile's top edge facing the center.
+// Matching and rendering work as usual in a "strip" grid, where the tiles along the rings or spiral are numbered from
+// the center outward and wrapped into rows, and only the rendered image is warped back around the center, bending
+// each tile to follow its ring. Rings have as many tiles as fit along their middle, so tiles keep their size all the
+// way out; only the few innermost tiles are noticeably bent. Distances are in cells (tile widths) throughout.
+pub(crate) struct Radial {
+    spiral: bool,
+    // Center of the rings or spiral, in cells from the top-left corner of the grid
+    center: (f32, f32),
+    // Index of the first tile of each ring, with `--layout rings`, followed by the total number of tiles
+    ring_starts: Vec<u32>,
+    // Number of tiles along the rings or spiral
+    tile_count: u32,
+    // Number of columns of the strip grid
+    columns: u32,
+    // Dimensions of the grid, in cells
+    width: u32,
+    height: u32,
+}
+
+impl Radial {
+    // Lays out the rings or spiral over a grid of `width` by `height` cells, around a center given as fractions of
+    // the grid's width and height, far enough out to cover every corner of the grid
+    pub(crate) fn new(spiral: bool, (x, y): (f32, f32), width: u32, height: u32) -> Self {
+        let center = (x * width as f32, y * height as f32);
+        let max_radius = [(0., 0.), (1., 0.), (0., 1.), (1., 1.)]
+            .map(|(corner_x, corner_y)| {
+                (corner_x * width as f32 - center.0).hypot(corner_y * height as f32 - center.1)
+            })
+            .into_iter()
+            .fold(0f32, f32::max);
+        let ring_count = max_radius.ceil() as u32 + 1;
+
+        let mut ring_starts = vec![0];
+        let tile_count = if spiral {
+            // The spiral continues until a whole turn lies beyond the farthest corner
+            spiral_length((ring_count + 1) as f32 * TAU).ceil() as u32
+        } else {
+            for ring in 0..ring_count {
+                ring_starts.push(ring_starts.last().unwrap() + ring_tile_count(ring));
+            }
+            *ring_starts.last().unwrap()
+        };
+
+        Self {
+            spiral,
+            center,
+            ring_starts,
+            tile_count,
+            columns: tile_count.isqrt().max(1) + 1,
+            width,
+            height,
+        }
+    }
+
+    // Resamples the input image (with one pixel per cell of the grid) to one pixel per tile of the strip grid,
+    // taking the average color over each tile
+    pub(crate) fn grid(&self, image: &InputImage) -> InputImage {
+        let (width, height) = (image.width() as f32, image.height() as f32);
+        let mut strip = InputImage::new(self.columns, self.tile_count.div_ceil(self.columns));
+        strip
+            .par_chunks_exact_mut(3)
+            .take(self.tile_count as usize)
+            .enumerate()
+            .for_each(|(tile_idx, px)| {
+                let mut sum = [0.; 3];
+                for (x, y) in self.samples(tile_idx as u32) {
+                    let color = interpolate_bilinear(
+                        image,
+                        (x - 0.5).clamp(0., width - 1.),
+                        (y - 0.5).clamp(0., height - 1.),
+                    )
+                    .unwrap();
+                    for (c, color_c) in sum.iter_mut().zip(color.0) {
+                        *c += f32::from(color_c);
+                    }
+                }
+                let sample_count = (SAMPLES_PER_AXIS * SAMPLES_PER_AXIS) as f32;
+                px.copy_from_slice(&sum.map(|c| (c / sample_count).round() as u8));
+            });
+        strip
+    }
+
+    // Lays out one cell per pixel of `grid`'s image, leaving out tiles entirely outside the output image
+    pub(crate) fn cells(&self, image: &InputImage) -> Vec<Cell> {
+        let (width, height) = (self.width as f32, self.height as f32);
+        grid_cells(image)
+            .into_iter()
+            .filter(|cell| {
+                let tile_idx = cell.y * self.columns + cell.x;
+                // Tiles with a sample within a cell of the grid may cover part of it
+                tile_idx < self.tile_count
+                    && self.samples(tile_idx).any(|(x, y)| {
+                        (-1. ..width + 1.).contains(&x) && (-1. ..height + 1.).contains(&y)
+                    })
+            })
+            .collect()
+    }
+
+    // Points of the grid that a tile's color is sampled at, evenly spread over the tile
+    fn samples(&self, tile_idx: u32) -> impl Iterator<Item = (f32, f32)> {
+        (0..SAMPLES_PER_AXIS * SAMPLES_PER_AXIS).map(move |sample| {
+            let fx = ((sample % SAMPLES_PER_AXIS) as f32 + 0.5) / SAMPLES_PER_AXIS as f32;
+            let fy = ((sample / SAMPLES_PER_AXIS) as f32 + 0.5) / SAMPLES_PER_AXIS as f32;
+            self.position(tile_idx, fx, fy)
+        })
+    }
+
+    // Warps the image rendered in the strip grid around the center, cropping it to the output image (`width` by
+    // `height` pixels) with square tiles `tile_size` pixels wide. Each tile is sampled bilinearly within its own
+    // bounds, so tiles that are neighbors in the strip grid but not around the center don't bleed into each other.
+    pub(crate) fn image(
+        &self,
+        strip_image: &RgbImage,
+        tile_size: u32,
+        width: u32,
+        height: u32,
+    ) -> RgbImage {
+        let mut image = RgbImage::new(width, height);
+        let tile_size_f = tile_size as f32;
+
+        image.par_enumerate_pixels_mut().for_each(|(x, y, px)| {
+            let (tile_idx, fx, fy) = self.locate(
+                (x as f32 + 0.5) / tile_size_f,
+                (y as f32 + 0.5) / tile_size_f,
+            );
+            let column = (tile_idx % self.columns) * tile_size;
+            let row = (tile_idx / self.columns) * tile_size;
+            let last = tile_size_f - 1.;
+            *px = interpolate_bilinear(
+                strip_image,
+                column as f32 + (fx * tile_size_f - 0.5).clamp(0., last),
+                row as f32 + (fy * tile_size_f - 0.5).clamp(0., last),
+            )
+            .unwrap_or(Rgb([0; 3]));
+        });
+
+        image
+    }
+
+    // Finds the tile covering a point of the grid, and where in the tile the point is, from (0, 0) at its top-left
+    // corner to (1, 1) at its bottom-right corner
+    fn locate(&self, x: f32, y: f32) -> (u32, f32, f32) {
+        let (dx, dy) = (x - self.center.0, y - self.center.1);
+        let radius = dx.hypot(dy);
+        let angle = dy.atan2(dx).rem_euclid(TAU);
+
+        let (tile_idx, fx, fy) = if self.spiral {
+            // The turn of the spiral the point is on, and the total angle along the spiral to it
+            let turn = (radius - angle / TAU).floor().max(0.);
+            let spiral_angle = turn * TAU + angle;
+            let fy = (radius - spiral_angle / TAU).clamp(0., 1.);
+            let length = spiral_length(spiral_angle);
+            (length.floor() as u32, length.fract(), fy)
+        } else {
+            let ring = (radius.floor() as u32).min(self.ring_starts.len() as u32 - 2);
+            let tile_count = ring_tile_count(ring);
+            let position = angle / TAU * tile_count as f32;
+            let tile_in_ring = (position.floor() as u32).min(tile_count - 1);
+            (
+                self.ring_starts[ring as usize] + tile_in_ring,
+                position - tile_in_ring as f32,
+                (radius - ring as f32).min(1.),
+            )
+        };
+        (tile_idx.min(self.tile_count - 1), fx, fy)
+    }
+
+    // Finds the point of the grid at a position within a tile, from (0, 0) at its top-left corner to (1, 1) at its
+    // bottom-right corner; the inverse of `locate`
+    fn position(&self, tile_idx: u32, fx: f32, fy: f32) -> (f32, f32) {
+        let (radius, angle) = if self.spiral {
+            // Inverts `spiral_length`
+            let length = tile_idx as f32 + fx;
+            let spiral_angle = (PI * PI + 4. * PI * length).sqrt() - PI;
+            (spiral_angle / TAU + fy, spiral_angle)
+        } else {
+            let ring = self.ring_starts.partition_point(|&start| start <= tile_idx) - 1;
+            let position = (tile_idx - self.ring_starts[ring]) as f32 + fx;
+            (
+                ring as f32 + fy,
+                position / ring_tile_count(ring as u32) as f32 * TAU,
+            )
+        };
+        (
+            self.center.0 + radius * angle.cos(),
+            self.center.1 + radius * angle.sin(),
+        )
+    }
+}
+
+// Number of tiles around a ring, as many as fit along its middle
+fn ring_tile_count(ring: u32) -> u32 {
+    ((ring as f32 + 0.5) * TAU).round().max(1.) as u32
+}
+
+// Length of the spiral's middle line from the center to a total angle (in radians) around it, in cells. The spiral's
+// inner edge is at a radius of angle / 2π, so its middle line is at angle / 2π + 0.5.
+fn spiral_length(angle: f32) -> f32 {
+    angle * angle / (2. * TAU) + angle / 2.
+}
@@ -0,0 +1,137 @@
+use crate::geometry::TileSize;
+use anyhow::{Result, bail};
+use image::{
+    GenericImage, GenericImageView, RgbImage,
+    imageops::{FilterType, resize},
+};
+use std::str::FromStr;
+
+// How the part of the `--fit` size that is not a whole number of tiles is filled, for `--fit-remainder`
+#[derive(Clone, Copy)]
+pub(crate) enum FitRemainder {
+    // Add a partial row and column of tiles, cut off at the edges of the output image
+    Crop,
+    // Center the whole tiles in the output image, with black bars around them
+    Pad,
+    // Stretch (or squeeze) the tiles of the last row and column to reach the edges of the output image
+    ScaleLast,
+}
+
+impl FromStr for FitRemainder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "crop" => Ok(Self::Crop),
+            "pad" => Ok(Self::Pad),
+            "scale-last" => Ok(Self::ScaleLast),
+            _ => bail!("expected \"crop\", \"pad\", or \"scale-last\""),
+        }
+    }
+}
+
+// Fits the bands of the mosaic, rendered in whole tiles, to the exact size of the output image for `--fit-remainder`
+#[derive(Clone)]
+pub(crate) struct RemainderFill {
+    policy: FitRemainder,
+    tile_size: TileSize,
+    // Size of the mosaic in whole tiles, in pixels
+    mosaic_width: u32,
+    mosaic_height: u32,
+    // Size of the output image (before any frame), in pixels
+    width: u32,
+    height: u32,
+    // Row of the mosaic where the next band starts, in pixels
+    y: u32,
+}
+
+impl RemainderFill {
+    pub(crate) fn new(
+        policy: FitRemainder,
+        (width, height): (u32, u32),
+        tile_size: TileSize,
+        (grid_width, grid_height): (u32, u32),
+    ) -> Self {
+        Self {
+            policy,
+            tile_size,
+            mosaic_width: grid_width * tile_size.width,
+            mosaic_height: grid_height * tile_size.height,
+            width,
+            height,
+            y: 0,
+        }
+    }
+
+    // Size of the output image (before any frame), in pixels
+    pub(crate) fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    // Fits the next band of the mosaic to the width of the output image, returning the bands to write in its place.
+    // The first and last bands are also fitted to the height of the output image.
+    pub(crate) fn fit(&mut self, band: &RgbImage) -> Vec<RgbImage> {
+        let band_y = self.y;
+        self.y += band.height();
+        let is_last = self.y >= self.mosaic_height;
+
+        match self.policy {
+            FitRemainder::Crop => {
+                let height = band.height().min(self.height - band_y);
+                vec![band.view(0, 0, self.width, height).to_image()]
+            }
+            FitRemainder::Pad => {
+                let left = (self.width - self.mosaic_width) / 2;
+                let top = (self.height - self.mosaic_height) / 2;
+                let bottom = self.height - self.mosaic_height - top;
+                let mut padded = RgbImage::new(self.width, band.height());
+                padded.copy_from(band, left, 0).unwrap();
+
+                let mut bands = Vec::new();
+                if band_y == 0 && top > 0 {
+                    bands.push(RgbImage::new(self.width, top));
+                }
+                bands.push(padded);
+                if is_last && bottom > 0 {
+                    bands.push(RgbImage::new(self.width, bottom));
+                }
+                bands
+            }
+            FitRemainder::ScaleLast => {
+                // Every tile keeps its size except those of the last column and, in the last band, the last row.
+                // Each part of the band is given as (start, size in the band, size in the output image).
+                let kept_width = self.mosaic_width - self.tile_size.width;
+                let columns = [
+                    (0, kept_width, kept_width),
+                    (kept_width, self.tile_size.width, self.width - kept_width),
+                ];
+                let rows = if is_last {
+                    let kept_height = band.height() - self.tile_size.height;
+                    vec![
+                        (0, kept_height, kept_height),
+                        (
+                            kept_height,
+                            self.tile_size.height,
+                            self.height - (self.mosaic_height - self.tile_size.height),
+                        ),
+                    ]
+                } else {
+                    vec![(0, band.height(), band.height())]
+                };
+
+                let mut fitted = RgbImage::new(self.width, rows.iter().map(|row| row.2).sum());
+                for &(y, height, fitted_height) in &rows {
+                    for &(x, width, fitted_width) in &columns {
+                        if width > 0 && height > 0 {
+                            let part = band.view(x, y, width, height).to_image();
+                            let part =
+                                resize(&part, fitted_width, fitted_height, FilterType::Triangle);
+                            fitted.copy_from(&part, x, y).unwrap();
+                        }
+                    }
+                }
+                vec![fitted]
+            }
+        }
+    }
+}
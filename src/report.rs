@@ -0,0 +1,401 @@
+use crate::{
+    INPUT_PIXEL_COMPONENT_MAX, InputImage,
+    environment::fingerprint,
+    inspect::hex_color,
+    layout::Cell,
+    logging::format_count,
+    mosaic::{RenderStats, cell_oklab},
+    palette::Palette,
+    paths::encode_path,
+};
+use anyhow::Result;
+use image::{
+    ImageFormat, Rgb, RgbImage,
+    imageops::{FilterType, resize},
+};
+use std::{
+    fmt::Write as _,
+    fs::write,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+// Width at which images are shown in the report, in pixels
+const REPORT_IMAGE_WIDTH: u32 = 720;
+
+// Maximum number of cell colors plotted in the gamut coverage chart; cells are sampled evenly beyond this
+const MAX_PLOTTED_CELL_COUNT: usize = 4000;
+
+// Color distance within which a cell counts as well covered by the palette, for the gamut coverage summary
+const COVERED_DISTANCE: f32 = 0.05;
+
+// Number of most-used palette images listed under the usage histogram
+const TOP_SOURCE_COUNT: usize = 10;
+
+// Range of Oklab a and b components shown in the gamut coverage chart; sRGB colors fall within it
+const CHART_AB_RANGE: f32 = 0.35;
+
+// Size of the charts in the report, in pixels
+const CHART_SIZE: u32 = 360;
+
+// Collects the color distance between each cell and what it was drawn with, while rendering,
+// for the self-contained HTML report written with `--report`.
+pub(crate) struct RunReport {
+    width: u32,
+    height: u32,
+    // Color distance by pixel of the input image; every pixel of a cell gets the cell's error
+    errors: Vec<f32>,
+}
+
+// Everything else the report shows, available once rendering finishes
+pub(crate) struct ReportContents<'a> {
+    // The input image as given, before resizing or any processing
+    pub(crate) source_image: &'a InputImage,
+    // The input image after processing, with one pixel per cell
+    pub(crate) target_image: &'a InputImage,
+    pub(crate) output_image: &'a RgbImage,
+    pub(crate) palette: &'a Palette,
+    pub(crate) palette_dir: &'a Path,
+    pub(crate) stats: &'a RenderStats,
+    // Name and duration (in seconds) of each stage of the run
+    pub(crate) timings: &'a [(String, f64)],
+}
+
+impl RunReport {
+    // `width` and `height` are the dimensions of the grid of cells.
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            errors: vec![0.; (width * height) as usize],
+        }
+    }
+
+    // Records the color distance between a cell and the tile (or solid color) it was drawn with.
+    pub(crate) fn record(&mut self, cell: &Cell, error: f32) {
+        for y in cell.y..cell.y + cell.span {
+            for x in cell.x..cell.x + cell.span {
+                self.errors[(y * self.width + x) as usize] = error;
+            }
+        }
+    }
+
+    // Writes the report as a single HTML file, with every image and chart embedded.
+    pub(crate) fn write(self, path: &Path, contents: &ReportContents<'_>) -> Result<()> {
+        let palette = contents.palette;
+        let stats = contents.stats;
+        let cell_count = self.errors.len();
+        let mean_error = self.errors.iter().sum::<f32>() / cell_count as f32;
+        let max_error = self.errors.iter().copied().fold(0., f32::max);
+        let covered_count = self
+            .errors
+            .iter()
+            .filter(|&&error| error <= COVERED_DISTANCE)
+            .count();
+
+        let mut html = String::new();
+        writeln!(html, "<!DOCTYPE html>")?;
+        writeln!(html, "<html lang=\"en\">")?;
+        writeln!(html, "<head>")?;
+        writeln!(html, "<meta charset=\"utf-8\">")?;
+        writeln!(html, "<title>tessera report</title>")?;
+        writeln!(
+            html,
+            "<style>body{{font-family:sans-serif;max-width:{}px;margin:2em auto;padding:0 1em;color:#222}}\
+             table{{border-collapse:collapse}}td,th{{padding:0.2em 0.8em;text-align:left}}\
+             tr:nth-child(even){{background:#f2f2f2}}figure{{display:inline-block;margin:0 1em 1em 0}}\
+             img.cells{{image-rendering:pixelated}}</style>",
+            REPORT_IMAGE_WIDTH * 2 + 64
+        )?;
+        writeln!(html, "</head>")?;
+        writeln!(html, "<body>")?;
+        writeln!(html, "<h1>tessera report</h1>")?;
+
+        writeln!(html, "<h2>Summary</h2>")?;
+        writeln!(html, "<table>")?;
+        let rows = [
+            ("Palette", escape(&encode_path(contents.palette_dir))),
+            ("Palette images", format_count(palette.paths.len() as u64)),
+            (
+                "Grid",
+                format!("{} &times; {} cells", self.width, self.height),
+            ),
+            (
+                "Tile size",
+                format!(
+                    "{} &times; {} px",
+                    palette.tile_size.width, palette.tile_size.height
+                ),
+            ),
+            (
+                "Output image",
+                format!(
+                    "{} &times; {} px",
+                    contents.output_image.width(),
+                    contents.output_image.height()
+                ),
+            ),
+            (
+                "Distinct cell colors",
+                format!(
+                    "{} of {} cells matched by color",
+                    format_count(stats.distinct_color_count.into()),
+                    format_count(stats.matched_count.into())
+                ),
+            ),
+            (
+                "Cells drawn as solid color",
+                format_count(stats.solid_count.into()),
+            ),
+            ("Mean color distance", format!("{mean_error:.4}")),
+            ("Maximum color distance", format!("{max_error:.4}")),
+        ];
+        for (name, value) in rows {
+            writeln!(html, "<tr><th>{name}</th><td>{value}</td></tr>")?;
+        }
+        writeln!(html, "</table>")?;
+
+        writeln!(html, "<h2>Comparison</h2>")?;
+        let output_preview = preview(contents.output_image, REPORT_IMAGE_WIDTH);
+        let source_preview = resize(
+            contents.source_image,
+            output_preview.width(),
+            output_preview.height(),
+            FilterType::Triangle,
+        );
+        for (caption, image) in [("Input", &source_preview), ("Output", &output_preview)] {
+            writeln!(
+                html,
+                "<figure><img src=\"{}\" alt=\"{caption}\"><figcaption>{caption}</figcaption></figure>",
+                png_data_uri(image)?
+            )?;
+        }
+
+        writeln!(html, "<h2>Error heatmap</h2>")?;
+        writeln!(
+            html,
+            "<p>Color distance (in Oklab space) between each cell's target color and the tile it was drawn with, \
+             from 0 (dark) to {max_error:.4} (bright).</p>"
+        )?;
+        let heatmap = RgbImage::from_fn(self.width, self.height, |x, y| {
+            let error = self.errors[(y * self.width + x) as usize];
+            heat_color(if max_error > 0. {
+                error / max_error
+            } else {
+                0.
+            })
+        });
+        writeln!(
+            html,
+            "<img class=\"cells\" src=\"{}\" width=\"{}\" alt=\"Error heatmap\">",
+            png_data_uri(&heatmap)?,
+            output_preview.width()
+        )?;
+
+        writeln!(html, "<h2>Palette image usage</h2>")?;
+        self.write_usage(&mut html, contents)?;
+
+        writeln!(html, "<h2>Gamut coverage</h2>")?;
+        writeln!(
+            html,
+            "<p>{} of {} cells ({:.1}%) were drawn with a color within {COVERED_DISTANCE} \
+             of their target. The chart plots the hue and chroma (Oklab a and b) of cells' target colors (small dots) \
+             and palette images' average colors (outlined); gaps between them are colors the palette doesn't cover.</p>",
+            format_count(covered_count as u64),
+            format_count(cell_count as u64),
+            covered_count as f64 / cell_count as f64 * 100.
+        )?;
+        write_gamut_chart(&mut html, contents)?;
+
+        writeln!(html, "<h2>Timings</h2>")?;
+        writeln!(html, "<table>")?;
+        for (stage, seconds) in contents.timings {
+            writeln!(
+                html,
+                "<tr><th>{}</th><td>{seconds:.2} s</td></tr>",
+                escape(stage)
+            )?;
+        }
+        writeln!(html, "</table>")?;
+
+        writeln!(html, "<h2>Environment</h2>")?;
+        writeln!(html, "<table>")?;
+        for (name, value) in fingerprint() {
+            writeln!(html, "<tr><th>{name}</th><td>{}</td></tr>", escape(&value))?;
+        }
+        writeln!(html, "</table>")?;
+
+        writeln!(html, "</body>")?;
+        writeln!(html, "</html>")?;
+
+        write(path, html)?;
+
+        Ok(())
+    }
+
+    // Writes a histogram of how many cells each palette image was placed in, most used first,
+    // followed by a table of the most-used palette images.
+    fn write_usage(&self, html: &mut String, contents: &ReportContents<'_>) -> Result<()> {
+        let palette = contents.palette;
+        let mut uses: Vec<(u32, &PathBuf)> = contents
+            .stats
+            .tile_uses
+            .iter()
+            .copied()
+            .zip(&palette.paths)
+            .collect();
+        uses.sort_by(|(a, _), (b, _)| b.cmp(a));
+        let unused_count = uses.iter().filter(|&&(count, _)| count == 0).count();
+        let max_uses = uses.first().map_or(0, |&(count, _)| count).max(1);
+
+        writeln!(
+            html,
+            "<p>{unused_count} of {} palette images were never placed. The most-used palette image was placed in \
+             {max_uses} of {} cells.</p>",
+            palette.paths.len(),
+            self.errors.len()
+        )?;
+
+        // Bars are drawn as one outline, so huge palettes don't produce huge charts
+        let width = (CHART_SIZE * 2) as f32;
+        let height = CHART_SIZE as f32 / 2.;
+        let bar_width = width / uses.len().max(1) as f32;
+        let mut outline = format!("M0,{height}");
+        for (idx, &(count, _)) in uses.iter().enumerate() {
+            let bar_height = count as f32 / max_uses as f32 * height;
+            write!(
+                outline,
+                " L{:.2},{:.2} L{:.2},{:.2}",
+                idx as f32 * bar_width,
+                height - bar_height,
+                (idx + 1) as f32 * bar_width,
+                height - bar_height
+            )?;
+        }
+        write!(outline, " L{width},{height} Z")?;
+        writeln!(
+            html,
+            "<svg width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\" role=\"img\">\
+             <title>Cells per palette image, most used first</title>\
+             <path d=\"{outline}\" fill=\"#4a78b5\"/></svg>"
+        )?;
+
+        writeln!(html, "<table>")?;
+        writeln!(html, "<tr><th>Palette image</th><th>Cells</th></tr>")?;
+        for &(count, path) in uses.iter().take(TOP_SOURCE_COUNT) {
+            let path = path.strip_prefix(contents.palette_dir).unwrap_or(path);
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td></tr>",
+                escape(&encode_path(path)),
+                format_count(count.into())
+            )?;
+        }
+        writeln!(html, "</table>")?;
+
+        Ok(())
+    }
+}
+
+// Writes a scatter plot of cells' target colors and palette images' colors in the Oklab a-b plane.
+fn write_gamut_chart(html: &mut String, contents: &ReportContents<'_>) -> Result<()> {
+    let size = CHART_SIZE as f32;
+    let position = |[_, a, b]: [f32; 3]| {
+        (
+            (0.5 + a / CHART_AB_RANGE * 0.5) * size,
+            (0.5 - b / CHART_AB_RANGE * 0.5) * size,
+        )
+    };
+
+    writeln!(
+        html,
+        "<svg width=\"{size}\" height=\"{size}\" viewBox=\"0 0 {size} {size}\" role=\"img\">\
+         <title>Target colors of cells and average colors of palette images, by Oklab a and b</title>\
+         <rect width=\"{size}\" height=\"{size}\" fill=\"#f2f2f2\"/>\
+         <line x1=\"{half}\" y1=\"0\" x2=\"{half}\" y2=\"{size}\" stroke=\"#ccc\"/>\
+         <line x1=\"0\" y1=\"{half}\" x2=\"{size}\" y2=\"{half}\" stroke=\"#ccc\"/>",
+        half = size / 2.
+    )?;
+
+    let target_pixels: Vec<_> = contents.target_image.pixels().collect();
+    let step = target_pixels.len().div_ceil(MAX_PLOTTED_CELL_COUNT).max(1);
+    for &&color in target_pixels.iter().step_by(step) {
+        let (x, y) = position(cell_oklab(color));
+        writeln!(
+            html,
+            "<circle cx=\"{x:.1}\" cy=\"{y:.1}\" r=\"1.5\" fill=\"#{:02x}{:02x}{:02x}\"/>",
+            color[0], color[1], color[2]
+        )?;
+    }
+    for &color in &contents.palette.colors {
+        let (x, y) = position(color);
+        writeln!(
+            html,
+            "<circle cx=\"{x:.1}\" cy=\"{y:.1}\" r=\"4\" fill=\"{}\" stroke=\"#222\"/>",
+            hex_color(color)
+        )?;
+    }
+    writeln!(html, "</svg>")?;
+
+    Ok(())
+}
+
+// Downscales an image to at most `max_width` pixels wide, keeping its aspect ratio.
+fn preview(image: &RgbImage, max_width: u32) -> RgbImage {
+    if image.width() <= max_width {
+        return image.clone();
+    }
+    let height =
+        (u64::from(image.height()) * u64::from(max_width) / u64::from(image.width())).max(1) as u32;
+    resize(image, max_width, height, FilterType::Triangle)
+}
+
+// Maps a value from 0.0 to 1.0 to a color on a dark-purple-to-yellow ramp.
+fn heat_color(value: f32) -> Rgb<u8> {
+    const STOPS: [[f32; 3]; 3] = [[13., 8., 135.], [204., 71., 120.], [240., 249., 33.]];
+    let position = value.clamp(0., 1.) * (STOPS.len() - 1) as f32;
+    let idx = (position as usize).min(STOPS.len() - 2);
+    let t = position - idx as f32;
+    Rgb(std::array::from_fn(|c| {
+        (STOPS[idx][c] + (STOPS[idx + 1][c] - STOPS[idx][c]) * t)
+            .round()
+            .clamp(0., INPUT_PIXEL_COMPONENT_MAX) as u8
+    }))
+}
+
+// Encodes an image as a PNG data URI, for embedding in the report.
+fn png_data_uri(image: &RgbImage) -> Result<String> {
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, ImageFormat::Png)?;
+    Ok(format!("data:image/png;base64,{}", base64(png.get_ref())))
+}
+
+// Encodes bytes as standard base64, with padding.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (idx, &byte)| {
+            group | u32::from(byte) << (16 - 8 * idx)
+        });
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                encoded.push(char::from(
+                    ALPHABET[(group >> (18 - 6 * idx) & 63) as usize],
+                ));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// Escapes characters with special meaning in HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
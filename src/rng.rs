@@ -0,0 +1,42 @@
+// Version of the pseudorandom number generator below. It must be bumped whenever the sequence produced for a seed
+// changes (including how numbers are drawn from it, e.g. by `Rng::shuffle`), so that placements files recording
+// randomized results are never silently replayed with different ones.
+pub(crate) const RNG_VERSION: u32 = 1;
+
+// Produces a pseudorandom number from a seed, via the SplitMix64 finalizer.
+pub(crate) fn hash(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// SplitMix64 (https://prng.di.unimi.it/splitmix64.c), a small generator that produces the same sequence
+// for a seed on every platform. Every randomized feature draws from one of these, seeded by `--seed`.
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let value = hash(self.state);
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        value
+    }
+
+    // Returns a number in `0..bound`, by taking the high bits of a 128-bit product (bias is negligible for small bounds).
+    pub(crate) fn below(&mut self, bound: usize) -> usize {
+        ((u128::from(self.next_u64()) * bound as u128) >> 64) as usize
+    }
+
+    // Shuffles a slice with the Fisher-Yates algorithm, from the last element to the first.
+    pub(crate) fn shuffle<T>(&mut self, items: &mut [T]) {
+        for idx in (1..items.len()).rev() {
+            items.swap(idx, self.below(idx + 1));
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::{Context, Result, bail};
+
+// The format of a text file that tessera writes and reads back, recorded on a comment line at the top of the file,
+// such as "# tessera placements v2". Files written before a format had a version line are version 1.
+// A format's version is increased whenever files in the new format could be misread by an older tessera, which then
+// refuses them instead; files in every older version are still read.
+pub(crate) struct Schema {
+    // Name of the format on its version line
+    name: &'static str,
+    // Description of files in the format, for error messages
+    description: &'static str,
+    // Version of the format written by this version of tessera
+    version: u32,
+}
+
+// Placements files, as written by `--placements` and `--stop-after match` (see `Placements`).
+// Version 2 added the version line.
+pub(crate) const PLACEMENTS_SCHEMA: Schema = Schema {
+    name: "placements",
+    description: "placements file",
+    version: 2,
+};
+
+// Lists of palette images, as written by `--stop-after scan` (see `write_image_list`).
+// Version 2 added the version line.
+pub(crate) const IMAGE_LIST_SCHEMA: Schema = Schema {
+    name: "image-list",
+    description: "palette image list",
+    version: 2,
+};
+
+// Saved jobs, as written by `tessera job save` (see `job`)
+pub(crate) const JOB_SCHEMA: Schema = Schema {
+    name: "job",
+    description: "saved job",
+    version: 1,
+};
+
+impl Schema {
+    // The version line written at the top of files in this format
+    pub(crate) fn line(&self) -> String {
+        format!("# tessera {} v{}", self.name, self.version)
+    }
+
+    // Checks a comment line of a file in this format, failing if it is a version line recording a version that is
+    // newer than this version of tessera reads.
+    pub(crate) fn check(&self, line: &str) -> Result<()> {
+        let prefix = format!("# tessera {} v", self.name);
+        let Some(rest) = line.trim().strip_prefix(&prefix) else {
+            return Ok(());
+        };
+
+        let version: u32 = rest
+            .parse()
+            .ok()
+            .filter(|&version| version > 0)
+            .with_context(|| format!("{}: invalid format version", self.description))?;
+        if version > self.version {
+            bail!(
+                "{} is in version {version} of its format, written by a newer version of tessera; \
+                this version of tessera reads up to version {}",
+                self.description,
+                self.version
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{
+    colorspace::OutputColorSpace,
+    logging::log,
+    output::{Outputs, check_output_path, check_profile_support},
+};
+use anyhow::{Result, bail};
+use image::ImageReader;
+use pico_args::Arguments;
+use std::{ops::Range, path::PathBuf, str::FromStr};
+
+// One of several horizontal slices of the output image, for `--shard`, e.g. "2/4" for the second of four.
+// Each shard is rendered separately (possibly on another machine) and the results are stacked by `tessera merge`.
+#[derive(Clone, Copy)]
+pub(crate) struct Shard {
+    // From 1 to `count`
+    index: u32,
+    count: u32,
+}
+
+impl FromStr for Shard {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((index, count)) = s.split_once('/') else {
+            bail!("expected a shard number and a number of shards, e.g. \"2/4\"");
+        };
+        let (index, count): (u32, u32) = (index.trim().parse()?, count.trim().parse()?);
+        if index == 0 || index > count {
+            bail!("shard number must be from 1 to the number of shards");
+        }
+        Ok(Self { index, count })
+    }
+}
+
+impl Shard {
+    // Rows of a grid `height` cells tall covered by this shard. Shards are split between bands
+    // of `band_span` rows, so no cell is cut in two, and are as equal in size as the bands allow.
+    pub(crate) fn rows(self, height: u32, band_span: u32) -> Range<u32> {
+        let band_count = u64::from(height.div_ceil(band_span));
+        let band_at = |index: u32| (band_count * u64::from(index) / u64::from(self.count)) as u32;
+        let start = band_at(self.index - 1) * band_span;
+        let end = (band_at(self.index) * band_span).min(height);
+        start..end
+    }
+}
+
+// Runs `tessera merge`, which stacks the output images of shards rendered with `--shard` into one output image.
+pub(crate) fn run(mut args: Arguments) -> Result<()> {
+    if args.contains(["-h", "--help"]) {
+        println!(
+            "tessera merge: stack the output images of shards rendered with --shard into one output image
+usage: tessera merge [OPTIONS] SHARD...
+
+-h, --help           print this message
+--output-colorspace  color space to convert the output image to, embedding its ICC profile: \"srgb\", \"display-p3\", or \"adobe-rgb\"
+-o, --output         output image path; may be given more than once
+
+Shards are stacked from top to bottom in the order they are given, so list them from 1 to N."
+        );
+        return Ok(());
+    }
+
+    let output_color_space: Option<OutputColorSpace> =
+        args.opt_value_from_str("--output-colorspace")?;
+    let output_image_paths: Vec<PathBuf> = args.values_from_str(["-o", "--output"])?;
+    let shard_paths: Vec<PathBuf> = args.finish().into_iter().map(PathBuf::from).collect();
+
+    if output_image_paths.is_empty() {
+        bail!("`-o`/`--output`: at least one output path is required");
+    }
+    for path in &output_image_paths {
+        check_output_path(path)?;
+        if output_color_space.is_some() {
+            check_profile_support(path)?;
+        }
+    }
+    if shard_paths.is_empty() {
+        bail!("at least one shard is required");
+    }
+    if let Some(path) = shard_paths.iter().find(|path| !path.is_file()) {
+        bail!("shard {} does not point to a file", path.display());
+    }
+
+    // Every shard's dimensions are read up front, so only one shard is decoded at a time
+    let mut width = None;
+    let mut height = 0;
+    for path in &shard_paths {
+        let (shard_width, shard_height) = ImageReader::open(path)?.into_dimensions()?;
+        if width.is_some_and(|width| width != shard_width) {
+            bail!(
+                "shard {} is {shard_width} pixels wide, unlike the shards before it",
+                path.display()
+            );
+        }
+        width = Some(shard_width);
+        height += shard_height;
+    }
+
+    let mut outputs = Outputs::new(
+        output_image_paths,
+        width.unwrap(),
+        height,
+        false,
+        output_color_space,
+    )?;
+    for path in &shard_paths {
+        outputs.write_band(&ImageReader::open(path)?.decode()?.into_rgb8())?;
+    }
+    outputs.finish()?;
+
+    log!("merged {} shards", shard_paths.len());
+    Ok(())
+}
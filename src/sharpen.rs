@@ -0,0 +1,170 @@
+use crate::{INPUT_PIXEL_COMPONENT_MAX, geometry::TileSize, layout::Cell};
+use image::{GenericImage, GenericImageView, RgbImage, imageops::blur};
+
+// Sharpens the output image with an unsharp mask, for `--sharpen`: each pixel is pushed away from a Gaussian blur of
+// its surroundings by `amount` times the difference, restoring detail softened by shrinking palette images to tiles.
+// Works on bands of the output image as they are rendered, holding each band back until the rows below it that the
+// blur reaches are rendered.
+pub(crate) struct Sharpener {
+    amount: f32,
+    // Standard deviation of the blur, in pixels
+    radius: f32,
+    // Number of rows of pixels above and below a pixel that the blur reaches
+    reach: u32,
+    // With `--sharpen-edges`, where the sharpening is limited to
+    edges: Option<TileEdges>,
+    // The previous band, held back until the band below it is rendered
+    previous_band: Option<RgbImage>,
+    // The last rows of the band before the previous band, unsharpened, which the blur of its top rows reaches into
+    rows_above: Option<RgbImage>,
+    // Row of pixels of the output image where the previous band starts
+    previous_y: u32,
+}
+
+// The edges between neighboring tiles, for sharpening only near them
+pub(crate) struct TileEdges {
+    tile_size: TileSize,
+    // Number of cells across and down the grid
+    width: u32,
+    height: u32,
+    // Index of the cell covering each position of the grid, so edges inside cells with a span above 1 are left alone
+    cell_indices: Vec<u32>,
+    // Distance from an edge, in pixels, over which the sharpening fades out
+    edge_width: u32,
+}
+
+impl TileEdges {
+    // `edge_width` is limited to half of the smaller tile dimension, so the sharpening doesn't reach tile centers
+    pub(crate) fn new(
+        cells: &[Cell],
+        width: u32,
+        height: u32,
+        tile_size: TileSize,
+        edge_width: u32,
+    ) -> Self {
+        let mut cell_indices = vec![0; (width * height) as usize];
+        for (cell, idx) in cells.iter().zip(0..) {
+            for y in cell.y..cell.y + cell.span {
+                for x in cell.x..cell.x + cell.span {
+                    cell_indices[(y * width + x) as usize] = idx;
+                }
+            }
+        }
+
+        Self {
+            tile_size,
+            width,
+            height,
+            cell_indices,
+            edge_width: edge_width
+                .min(tile_size.width / 2)
+                .min(tile_size.height / 2)
+                .max(1),
+        }
+    }
+
+    fn cell_index(&self, column: u32, row: u32) -> u32 {
+        self.cell_indices[(row * self.width + column) as usize]
+    }
+
+    // How much a pixel of the mosaic is sharpened, from 1 beside an edge between two cells to 0 at `edge_width` or
+    // more away from any
+    fn weight(&self, x: u32, y: u32) -> f32 {
+        let (column, row) = (x / self.tile_size.width, y / self.tile_size.height);
+        let (x_in_tile, y_in_tile) = (x % self.tile_size.width, y % self.tile_size.height);
+        let cell_idx = self.cell_index(column, row);
+
+        // Distances to the edges of the tile that border another cell
+        let distances = [
+            (column > 0 && self.cell_index(column - 1, row) != cell_idx).then_some(x_in_tile),
+            (column + 1 < self.width && self.cell_index(column + 1, row) != cell_idx)
+                .then(|| self.tile_size.width - 1 - x_in_tile),
+            (row > 0 && self.cell_index(column, row - 1) != cell_idx).then_some(y_in_tile),
+            (row + 1 < self.height && self.cell_index(column, row + 1) != cell_idx)
+                .then(|| self.tile_size.height - 1 - y_in_tile),
+        ];
+        distances
+            .into_iter()
+            .flatten()
+            .min()
+            .map_or(0., |distance| {
+                (1. - distance as f32 / self.edge_width as f32).max(0.)
+            })
+    }
+}
+
+impl Sharpener {
+    pub(crate) fn new(amount: f32, radius: f32, edges: Option<TileEdges>) -> Self {
+        Self {
+            amount,
+            radius,
+            reach: (radius * 3.).ceil() as u32,
+            edges,
+            previous_band: None,
+            rows_above: None,
+            previous_y: 0,
+        }
+    }
+
+    // Takes the next band of the output image (without any frame), returning the previous band once it is sharpened.
+    pub(crate) fn push(&mut self, band: RgbImage) -> Option<RgbImage> {
+        let previous_band = self.previous_band.replace(band)?;
+        Some(self.sharpen(previous_band))
+    }
+
+    // Returns the last band, sharpened, which has no band below it.
+    pub(crate) fn finish(mut self) -> Option<RgbImage> {
+        let previous_band = self.previous_band.take()?;
+        Some(self.sharpen(previous_band))
+    }
+
+    // Sharpens the previous band, with the rows above it and the band below it (if any) as the blur's surroundings
+    fn sharpen(&mut self, mut band: RgbImage) -> RgbImage {
+        let (width, height) = band.dimensions();
+        let above_height = self.rows_above.as_ref().map_or(0, RgbImage::height);
+        let below_height = self
+            .previous_band
+            .as_ref()
+            .map_or(0, |below| below.height().min(self.reach));
+
+        let mut surroundings = RgbImage::new(width, above_height + height + below_height);
+        if let Some(rows_above) = &self.rows_above {
+            surroundings.copy_from(rows_above, 0, 0).unwrap();
+        }
+        surroundings.copy_from(&band, 0, above_height).unwrap();
+        if let Some(below) = &self.previous_band {
+            surroundings
+                .copy_from(
+                    &*below.view(0, 0, width, below_height),
+                    0,
+                    above_height + height,
+                )
+                .unwrap();
+        }
+        let blurred = blur(&surroundings, self.radius);
+        let kept_height = height.min(self.reach);
+        self.rows_above = Some(
+            band.view(0, height - kept_height, width, kept_height)
+                .to_image(),
+        );
+
+        for (x, y, px) in band.enumerate_pixels_mut() {
+            let weight = self
+                .edges
+                .as_ref()
+                .map_or(1., |edges| edges.weight(x, self.previous_y + y));
+            if weight == 0. {
+                continue;
+            }
+            let blurred_px = blurred.get_pixel(x, above_height + y);
+            for c in 0..3 {
+                let (original, blurred) = (f32::from(px[c]), f32::from(blurred_px[c]));
+                px[c] = (original + (original - blurred) * self.amount * weight)
+                    .round()
+                    .clamp(0., INPUT_PIXEL_COMPONENT_MAX) as u8;
+            }
+        }
+        self.previous_y += height;
+        band
+    }
+}
@@ -0,0 +1,106 @@
+use crate::mosaic::cell_oklab;
+use anyhow::{Result, bail};
+use image::{Rgb, RgbImage};
+use moxcms::{
+    ColorProfile, DataColorSpace, Layout, RenderingIntent, Transform8BitExecutor, TransformOptions,
+};
+use rayon::prelude::*;
+use std::{fs::read, path::Path, sync::Arc};
+
+// Suffix of the simulated print written next to each output image, e.g. "out.softproof.png" for "out.png"
+pub(crate) const SOFTPROOF_SUFFIX: &str = "softproof";
+
+// Color distance (in Oklab space) between a color and its printed simulation above which it is marked as
+// out of the printer's gamut
+const GAMUT_WARNING_DISTANCE: f32 = 0.03;
+
+// Color that colors out of the printer's gamut are blended halfway toward, for the gamut warning
+const GAMUT_WARNING_COLOR: [u8; 3] = [255, 0, 255];
+
+// Number of pixels converted at once by each thread
+const SOFTPROOF_CHUNK_PIXELS: usize = 4096;
+
+// A simulation of how output images look when printed with a printer's ICC profile, for `--softproof`.
+// Colors are converted from sRGB to the printer's color space and back with relative colorimetric intent,
+// so colors the printer can reproduce are kept and the others are clipped to the nearest color it can.
+pub(crate) struct Softproof {
+    to_printer: Arc<Transform8BitExecutor>,
+    from_printer: Arc<Transform8BitExecutor>,
+    // Number of channels of the printer's color space (e.g. 4 for CMYK)
+    channels: usize,
+}
+
+impl Softproof {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let printer = match ColorProfile::new_from_slice(&read(path)?) {
+            Ok(profile) => profile,
+            Err(err) => bail!("`--softproof`: cannot read ICC profile: {err}"),
+        };
+        let (layout, channels) = match printer.color_space {
+            DataColorSpace::Rgb => (Layout::Rgb, 3),
+            DataColorSpace::Cmyk => (Layout::Rgba, 4),
+            DataColorSpace::Gray => (Layout::Gray, 1),
+            _ => bail!("`--softproof`: only RGB, CMYK, and grayscale ICC profiles are supported"),
+        };
+
+        let srgb = ColorProfile::new_srgb();
+        let options = TransformOptions {
+            rendering_intent: RenderingIntent::RelativeColorimetric,
+            ..TransformOptions::default()
+        };
+        let transforms = srgb
+            .create_transform_8bit(Layout::Rgb, &printer, layout, options)
+            .and_then(|to_printer| {
+                printer
+                    .create_transform_8bit(layout, &srgb, Layout::Rgb, options)
+                    .map(|from_printer| (to_printer, from_printer))
+            });
+        let (to_printer, from_printer) = match transforms {
+            Ok(transforms) => transforms,
+            Err(err) => bail!("`--softproof`: cannot convert colors with ICC profile: {err}"),
+        };
+
+        Ok(Self {
+            to_printer,
+            from_printer,
+            channels,
+        })
+    }
+
+    // Produces a copy of an image simulating how it looks when printed. Colors the printer can't reproduce are
+    // blended toward magenta, as a gamut warning.
+    pub(crate) fn simulate(&self, image: &RgbImage) -> Result<RgbImage> {
+        let mut simulated_image = image.clone();
+
+        simulated_image
+            .par_chunks_mut(3 * SOFTPROOF_CHUNK_PIXELS)
+            .zip(image.par_chunks(3 * SOFTPROOF_CHUNK_PIXELS))
+            .try_for_each(|(simulated, original)| -> Result<()> {
+                let mut printed = vec![0; original.len() / 3 * self.channels];
+                self.to_printer.transform(original, &mut printed)?;
+                self.from_printer.transform(&printed, simulated)?;
+
+                for (simulated_px, original_px) in simulated
+                    .as_chunks_mut::<3>()
+                    .0
+                    .iter_mut()
+                    .zip(original.as_chunks::<3>().0)
+                {
+                    let [l, a, b] = cell_oklab(Rgb(*simulated_px));
+                    let [original_l, original_a, original_b] = cell_oklab(Rgb(*original_px));
+                    let distance = ((l - original_l).powi(2)
+                        + (a - original_a).powi(2)
+                        + (b - original_b).powi(2))
+                    .sqrt();
+                    if distance > GAMUT_WARNING_DISTANCE {
+                        for (c, warning_c) in simulated_px.iter_mut().zip(GAMUT_WARNING_COLOR) {
+                            *c = c.midpoint(warning_c);
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+
+        Ok(simulated_image)
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{
+    geometry::TileSize,
+    layout::{Cell, Pinned},
+};
+use image::{
+    GrayImage, Rgb, RgbImage, Rgba, RgbaImage,
+    imageops::{FilterType, resize},
+};
+use quantette::palette::{IntoColor, Oklab, Srgb};
+
+// Suffixes of the paths that the layers of a sparse mosaic are written to, for `--split-layers`, e.g. "out.tiles.png"
+// and "out.canvas.png" for "out.png"
+pub(crate) const TILES_LAYER_SUFFIX: &str = "tiles";
+pub(crate) const CANVAS_LAYER_SUFFIX: &str = "canvas";
+
+// Mask value (out of 255) above which a cell of a `--sparse-mask` receives a tile
+const MASK_THRESHOLD: u8 = 127;
+
+// Which cells receive tiles in a sparse mosaic, for `--sparse` and `--sparse-mask`. Every other cell is drawn as the
+// canvas color, so the tiles form the shape of a silhouette (e.g. text or a logo).
+pub(crate) enum Sparse {
+    // Cells of the input image darker than this lightness (in Oklab space)
+    Lightness(f32),
+    // Cells where this mask image, stretched over the grid, is lighter than 50% gray
+    Mask(GrayImage),
+}
+
+impl Sparse {
+    // Draws every cell that doesn't receive a tile as a solid tile of the canvas color, returning the number of cells
+    // that receive tiles. `width` and `height` are the dimensions of the grid the cells were laid out on.
+    pub(crate) fn apply(
+        &self,
+        cells: &mut [Cell],
+        canvas: Rgb<u8>,
+        width: u32,
+        height: u32,
+    ) -> usize {
+        match self {
+            Self::Lightness(threshold) => fill_canvas(cells, canvas, |cell| {
+                let oklab: Oklab = Srgb::from(cell.color.0).into_linear().into_color();
+                oklab.l < *threshold
+            }),
+            Self::Mask(mask) => {
+                let mask = resize(mask, width, height, FilterType::Triangle);
+                // The mask is sampled at the center of each cell
+                fill_canvas(cells, canvas, |cell| {
+                    let x = (cell.x + cell.span / 2).min(width - 1);
+                    let y = (cell.y + cell.span / 2).min(height - 1);
+                    mask.get_pixel(x, y)[0] > MASK_THRESHOLD
+                })
+            }
+        }
+    }
+}
+
+// Draws every cell for which `tiled` is false as a solid tile of the canvas color, returning the number of cells
+// for which it is true.
+fn fill_canvas(cells: &mut [Cell], canvas: Rgb<u8>, tiled: impl Fn(&Cell) -> bool) -> usize {
+    let mut tiled_count = 0;
+    for cell in cells {
+        if tiled(cell) {
+            tiled_count += 1;
+        } else {
+            cell.color = canvas;
+            cell.pinned = Some(Pinned::Solid);
+        }
+    }
+    tiled_count
+}
+
+// Splits the output image of a sparse mosaic into two layers for compositing, for `--split-layers`: the cells that
+// received tiles, and everything else (the canvas, and the frame, if any), each transparent where the other is opaque.
+// Stacking them gives back the output image. `frame_width` is the distance from the output image's top-left corner to
+// the mosaic's.
+pub(crate) fn sparse_layers(
+    image: &RgbImage,
+    cells: &[Cell],
+    tile_size: TileSize,
+    frame_width: u32,
+) -> (RgbaImage, RgbaImage) {
+    let (width, height) = image.dimensions();
+    let mut tiled = vec![false; (width * height) as usize];
+    for cell in cells
+        .iter()
+        .filter(|cell| !matches!(cell.pinned, Some(Pinned::Solid)))
+    {
+        let x = frame_width + cell.x * tile_size.width;
+        let y = frame_width + cell.y * tile_size.height;
+        for y in y..(y + cell.span * tile_size.height).min(height) {
+            for x in x..(x + cell.span * tile_size.width).min(width) {
+                tiled[(y * width + x) as usize] = true;
+            }
+        }
+    }
+
+    let layer = |tiles: bool| {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let [r, g, b] = image.get_pixel(x, y).0;
+            let opaque = tiled[(y * width + x) as usize] == tiles;
+            Rgba([r, g, b, if opaque { 255 } else { 0 }])
+        })
+    };
+    (layer(true), layer(false))
+}
@@ -0,0 +1,175 @@
+use crate::{
+    colorspace::OutputColorSpace,
+    cvd::{Deficiency, simulate},
+    output::save_image,
+    paths::{decode_relative_path, encode_relative_path},
+    schema::IMAGE_LIST_SCHEMA,
+    softproof::{SOFTPROOF_SUFFIX, Softproof},
+    with_suffix,
+};
+use anyhow::{Context, Result, bail};
+use image::ImageReader;
+use std::{
+    fs::{read_to_string, write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+// Named stages of the pipeline, in order, for `--stop-after` and `--resume-from`.
+// Stopping after a stage writes its artifact to the `-o` path instead of writing an output image,
+// and resuming from a stage reads its artifact in place of running it and every stage before it:
+// - `scan`: the palette images found, one path per line (relative to the palette directory), as for `--palette-filter`
+// - `index`: a palette pack of the resized palette images, as written by `tessera index export`
+// - `quantize`: the processed input image, with one pixel per cell
+// - `match`: a placements file, as written by `--placements`
+// - `compose`: the output image in sRGB, before any conversion to `--output-colorspace`
+// - `encode`: the output images themselves, so stopping after it is the same as not stopping
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Stage {
+    Scan,
+    Index,
+    Quantize,
+    Match,
+    Compose,
+    Encode,
+}
+
+impl FromStr for Stage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "scan" => Ok(Self::Scan),
+            "index" => Ok(Self::Index),
+            "quantize" => Ok(Self::Quantize),
+            "match" => Ok(Self::Match),
+            "compose" => Ok(Self::Compose),
+            "encode" => Ok(Self::Encode),
+            _ => bail!(
+                "expected \"scan\", \"index\", \"quantize\", \"match\", \"compose\", or \"encode\""
+            ),
+        }
+    }
+}
+
+impl Stage {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Scan => "scan",
+            Self::Index => "index",
+            Self::Quantize => "quantize",
+            Self::Match => "match",
+            Self::Compose => "compose",
+            Self::Encode => "encode",
+        }
+    }
+}
+
+// A stage to resume from and the path of its artifact, for `--resume-from`, e.g. "match:placements.csv"
+pub(crate) struct Resume {
+    pub(crate) stage: Stage,
+    pub(crate) path: PathBuf,
+}
+
+impl FromStr for Resume {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((stage, path)) = s.split_once(':') else {
+            bail!("expected a stage and the path of its artifact, e.g. \"match:placements.csv\"");
+        };
+        let stage = stage.parse()?;
+        if stage == Stage::Encode {
+            bail!("`encode` is the last stage, so there is nothing to resume");
+        }
+        Ok(Self {
+            stage,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+// Writes the artifact of the `scan` stage: the path of each palette image, relative to the palette directory, after
+// a line recording the version of the list's format (see `IMAGE_LIST_SCHEMA`).
+pub(crate) fn write_image_list(
+    path: &Path,
+    image_paths: &[PathBuf],
+    palette_dir: &Path,
+) -> Result<()> {
+    let mut contents = IMAGE_LIST_SCHEMA.line();
+    contents.push('\n');
+    for image_path in image_paths {
+        contents += &encode_relative_path(image_path, palette_dir)?;
+        contents.push('\n');
+    }
+    write(path, contents)?;
+    Ok(())
+}
+
+// Reads the artifact of the `scan` stage, in the format of `--palette-filter`.
+// Every listed palette image must still exist, since it is loaded without scanning the palette directory again.
+pub(crate) fn read_image_list(path: &Path, palette_dir: &Path) -> Result<Vec<PathBuf>> {
+    let contents = read_to_string(path)?;
+    let mut image_paths = Vec::new();
+
+    for (line, line_number) in contents.lines().zip(1..) {
+        let line = line.trim();
+        if line.starts_with('#') {
+            IMAGE_LIST_SCHEMA.check(line)?;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let image_path = decode_relative_path(palette_dir, line)
+            .with_context(|| format!("`--resume-from`: line {line_number}: invalid path"))?;
+        if !image_path.is_file() {
+            bail!(
+                "`--resume-from`: line {line_number}: {} does not point to a file",
+                image_path.display()
+            );
+        }
+        image_paths.push(image_path);
+    }
+
+    Ok(image_paths)
+}
+
+// Runs the `encode` stage alone, for `--resume-from compose`: writes a composed output image to every output,
+// converted to `color_space` if given, along with any color vision deficiency previews.
+pub(crate) fn encode(
+    image_path: &Path,
+    output_image_paths: &[PathBuf],
+    color_space: Option<OutputColorSpace>,
+    cvd_previews: &[Deficiency],
+    softproof: Option<&Softproof>,
+) -> Result<()> {
+    let image = ImageReader::open(image_path)?.decode()?.into_rgb8();
+    for path in output_image_paths {
+        save_image(&image, path, color_space)?;
+    }
+
+    for &deficiency in cvd_previews {
+        let preview_image = simulate(&image, deficiency);
+        for path in output_image_paths {
+            save_image(
+                &preview_image,
+                &with_suffix(path, deficiency.name()),
+                color_space,
+            )?;
+        }
+    }
+
+    if let Some(softproof) = softproof {
+        let proof_image = softproof.simulate(&image)?;
+        for path in output_image_paths {
+            save_image(
+                &proof_image,
+                &with_suffix(path, SOFTPROOF_SUFFIX),
+                color_space,
+            )?;
+        }
+    }
+
+    Ok(())
+}
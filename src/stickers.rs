@@ -0,0 +1,161 @@
+use crate::{
+    geometry::TileSize,
+    layout::{Cell, Pinned},
+    overlay::text_pixels,
+};
+use anyhow::{Result, bail};
+use image::{GenericImage, GenericImageView, Rgb, RgbImage};
+use png::{BitDepth, ColorType, Encoder, Info, PixelDimensions, Unit};
+use std::{fs::File, io::BufWriter, path::Path, str::FromStr};
+
+// Colors of the page, of the outline to cut each sticker along, and of the labels beneath stickers
+const PAGE_COLOR: Rgb<u8> = Rgb([255, 255, 255]);
+const CUT_LINE_COLOR: Rgb<u8> = Rgb([192, 192, 192]);
+const LABEL_COLOR: Rgb<u8> = Rgb([0, 0, 0]);
+
+// Size of the pages that sticker sheets are printed on, for `--sticker-page`
+#[derive(Clone, Copy)]
+pub(crate) enum PageSize {
+    // 210 by 297 millimeters
+    A4,
+    // 8.5 by 11 inches
+    Letter,
+}
+
+impl FromStr for PageSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "a4" => Ok(Self::A4),
+            "letter" => Ok(Self::Letter),
+            _ => bail!("expected \"a4\" or \"letter\""),
+        }
+    }
+}
+
+impl PageSize {
+    // Width and height of the page in pixels, printed at `dpi` pixels per inch
+    fn dimensions(self, dpi: u32) -> (u32, u32) {
+        let (width, height) = match self {
+            Self::A4 => (210. / 25.4, 297. / 25.4),
+            Self::Letter => (8.5, 11.),
+        };
+        let dpi = dpi as f32;
+        ((width * dpi) as u32, (height * dpi) as u32)
+    }
+}
+
+// Lays out one sticker for every tiled cell of the output image on printable pages, for `--sticker-sheet`: each the
+// cell's region of the output image at one pixel per dot, outlined to be cut along, with its row and column (as in
+// `--placements` files) printed beneath. Stickers are ordered row by row, as the cells are, and fill each page in rows.
+// Cells left to the canvas color by `--sparse` or `--sparse-mask` get no sticker. `frame_width` is the distance from
+// the output image's top-left corner to the mosaic's.
+pub(crate) fn sticker_sheets(
+    image: &RgbImage,
+    cells: &[Cell],
+    tile_size: TileSize,
+    frame_width: u32,
+    page_size: PageSize,
+    dpi: u32,
+) -> Result<Vec<RgbImage>> {
+    let (page_width, page_height) = page_size.dimensions(dpi);
+    // Half an inch of margin around each page, and an eighth of an inch between stickers
+    let margin = (dpi / 2).max(1);
+    let gap = (dpi / 8).max(4);
+    // Labels are scaled up to stay legible at high resolutions
+    let scale = (dpi / 100).max(1);
+    let label_height = 5 * scale;
+
+    let mut cells: Vec<_> = cells
+        .iter()
+        .filter(|cell| !matches!(cell.pinned, Some(Pinned::Solid)))
+        .collect();
+    cells.sort_by_key(|cell| (cell.y, cell.x));
+
+    let mut pages = Vec::new();
+    let mut page: Option<RgbImage> = None;
+    // Top-left corner of the next sticker, and the height of the tallest sticker (with its label) in its row so far
+    let (mut x, mut y, mut row_height) = (margin, margin, 0);
+    for cell in cells {
+        let label = format!("{},{}", cell.y, cell.x);
+        let label_width = (label.chars().count() as u32 * 4 - 1) * scale;
+        let (sticker_width, sticker_height) =
+            (cell.span * tile_size.width, cell.span * tile_size.height);
+        let width = sticker_width.max(label_width);
+        let height = sticker_height + 2 * scale + label_height;
+        if width + 2 * margin > page_width || height + 2 * margin > page_height {
+            bail!(
+                "`--sticker-sheet`: a sticker of {sticker_width} by {sticker_height} pixels doesn't fit on a page at {dpi} DPI; use a higher `--sticker-dpi`, a larger `--sticker-page`, or smaller tiles"
+            );
+        }
+
+        if x + width > page_width - margin {
+            (x, y) = (margin, y + row_height + gap);
+            row_height = 0;
+        }
+        if y + height > page_height - margin {
+            pages.extend(page.take());
+            (x, y) = (margin, margin);
+        }
+        let page =
+            page.get_or_insert_with(|| RgbImage::from_pixel(page_width, page_height, PAGE_COLOR));
+
+        // The outline is drawn just outside the sticker, so cutting along it keeps the whole tile
+        for outline_x in x - 1..=x + sticker_width {
+            page.put_pixel(outline_x, y - 1, CUT_LINE_COLOR);
+            page.put_pixel(outline_x, y + sticker_height, CUT_LINE_COLOR);
+        }
+        for outline_y in y - 1..=y + sticker_height {
+            page.put_pixel(x - 1, outline_y, CUT_LINE_COLOR);
+            page.put_pixel(x + sticker_width, outline_y, CUT_LINE_COLOR);
+        }
+        page.copy_from(
+            &*image.view(
+                frame_width + cell.x * tile_size.width,
+                frame_width + cell.y * tile_size.height,
+                sticker_width,
+                sticker_height,
+            ),
+            x,
+            y,
+        )?;
+
+        let label_y = y + sticker_height + 2 * scale;
+        for (column, row) in text_pixels(&label) {
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    page.put_pixel(
+                        x + column * scale + dx,
+                        label_y + row * scale + dy,
+                        LABEL_COLOR,
+                    );
+                }
+            }
+        }
+
+        x += width + gap;
+        row_height = row_height.max(height);
+    }
+    pages.extend(page);
+
+    Ok(pages)
+}
+
+// Writes a page of stickers as a PNG image, recording its resolution so it prints at its physical size
+pub(crate) fn save_sheet(page: &RgbImage, path: &Path, dpi: u32) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut info = Info::with_size(page.width(), page.height());
+    info.color_type = ColorType::Rgb;
+    info.bit_depth = BitDepth::Eight;
+    let pixels_per_meter = (f64::from(dpi) / 0.0254).round() as u32;
+    info.pixel_dims = Some(PixelDimensions {
+        xppu: pixels_per_meter,
+        yppu: pixels_per_meter,
+        unit: Unit::Meter,
+    });
+    let mut writer = Encoder::with_info(file, info)?.write_header()?;
+    writer.write_image_data(page.as_raw())?;
+    writer.finish()?;
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use crate::{
+    layout::{Cell, Pinned},
+    logging::log,
+    mosaic::cell_oklab,
+    palette::Palette,
+    paths::decode_relative_path,
+};
+use anyhow::{Context, Result, bail};
+use kiddo::SquaredEuclidean;
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+// A broad region of the grid, for `--story`: a third of the grid (e.g. "top" or "left"), or a ninth of it where a
+// third across meets a third down (e.g. "bottom-left" or "center")
+#[derive(Clone, Copy)]
+pub(crate) struct Region {
+    // Third of the grid's height (from 0 at the top) and of its width (from 0 at the left) that the region is in;
+    // `None` spans all three
+    row: Option<u32>,
+    column: Option<u32>,
+}
+
+impl FromStr for Region {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let row = |s| match s {
+            "top" => Some(0),
+            "center" => Some(1),
+            "bottom" => Some(2),
+            _ => None,
+        };
+        let column = |s| match s {
+            "left" => Some(0),
+            "center" => Some(1),
+            "right" => Some(2),
+            _ => None,
+        };
+        let region = match s.split_once('-') {
+            Some((vertical, horizontal)) => {
+                row(vertical)
+                    .zip(column(horizontal))
+                    .map(|(row, column)| Self {
+                        row: Some(row),
+                        column: Some(column),
+                    })
+            }
+            None if s == "center" => Some(Self {
+                row: Some(1),
+                column: Some(1),
+            }),
+            None => row(s)
+                .map(|row| Self {
+                    row: Some(row),
+                    column: None,
+                })
+                .or_else(|| {
+                    column(s).map(|column| Self {
+                        row: None,
+                        column: Some(column),
+                    })
+                }),
+        };
+        region.with_context(|| {
+            format!(
+                "unknown region \"{s}\"; expected \"top\", \"bottom\", \"left\", \"right\", \"center\", or a vertical and a horizontal part such as \"bottom-left\""
+            )
+        })
+    }
+}
+
+impl Region {
+    // Determines whether the center of a cell lies in the region of a grid of `width` by `height` cells
+    fn contains(self, cell: &Cell, width: u32, height: u32) -> bool {
+        let third = |position: u32, size: u32| (3 * (2 * position + cell.span) / (2 * size)).min(2);
+        self.row.is_none_or(|row| third(cell.y, height) == row)
+            && self
+                .column
+                .is_none_or(|column| third(cell.x, width) == column)
+    }
+}
+
+// A palette image asked to appear in a region of the output image
+pub(crate) struct StoryRequest {
+    region: Region,
+    // Palette image, by index
+    tile_index: usize,
+    line_number: u32,
+}
+
+// Reads a story file. Each non-empty line has a region and a path (relative to the palette directory), separated by a
+// comma, e.g. "top-left,1998/wedding.jpg". Lines starting with "#" are comments.
+pub(crate) fn load_story(
+    path: &Path,
+    palette_dir: &Path,
+    palette_paths: &[PathBuf],
+) -> Result<Vec<StoryRequest>> {
+    let contents = read_to_string(path)?;
+    let mut requests = Vec::new();
+
+    for (line, line_number) in contents.lines().zip(1..) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((region, image_path)) = line.split_once(',') else {
+            bail!(
+                "`--story`: line {line_number}: expected a region and a path separated by a comma"
+            );
+        };
+        let region = region
+            .trim()
+            .parse()
+            .with_context(|| format!("`--story`: line {line_number}: invalid region"))?;
+
+        let image_path = decode_relative_path(palette_dir, image_path.trim())
+            .with_context(|| format!("`--story`: line {line_number}: invalid path"))?;
+        let Some(tile_index) = palette_paths.iter().position(|path| *path == image_path) else {
+            bail!(
+                "`--story`: line {line_number}: {} is not in the palette",
+                image_path.display()
+            );
+        };
+
+        requests.push(StoryRequest {
+            region,
+            tile_index,
+            line_number,
+        });
+    }
+
+    Ok(requests)
+}
+
+// Places each requested palette image once in its region, in the cell where it costs the least color accuracy, i.e.
+// where its color distance to the cell exceeds that of the cell's nearest tile by the least. A request is only granted
+// if that cost is at most `margin`, so requests give way to color where no cell of their region suits them. Granted requests pin their cells, and requests are granted in the
+// order of the story file, so earlier requests get the first pick of cells. Returns the number of requests granted.
+// `width` and `height` are the dimensions of the grid the cells were laid out on.
+pub(crate) fn apply_story(
+    cells: &mut [Cell],
+    requests: &[StoryRequest],
+    palette: &Palette,
+    (width, height): (u32, u32),
+    margin: f32,
+) -> u32 {
+    // Color distance from each cell to its nearest tile
+    let nearest_distances: Vec<f32> = cells
+        .iter()
+        .map(|cell| {
+            palette
+                .tree
+                .nearest_one::<SquaredEuclidean>(&cell_oklab(cell.color))
+                .distance
+                .sqrt()
+        })
+        .collect();
+
+    let mut granted_count = 0;
+    for request in requests {
+        let [tile_l, tile_a, tile_b] = palette.colors[request.tile_index];
+        let best = cells
+            .iter()
+            .zip(&nearest_distances)
+            .enumerate()
+            .filter(|(_, (cell, _))| {
+                cell.pinned.is_none() && request.region.contains(cell, width, height)
+            })
+            .map(|(cell_idx, (cell, nearest_distance))| {
+                let [l, a, b] = cell_oklab(cell.color);
+                let distance =
+                    ((l - tile_l).powi(2) + (a - tile_a).powi(2) + (b - tile_b).powi(2)).sqrt();
+                (cell_idx, distance - nearest_distance)
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        match best {
+            Some((cell_idx, cost)) if cost <= margin => {
+                cells[cell_idx].pinned = Some(Pinned::Tile(request.tile_index));
+                granted_count += 1;
+            }
+            _ => log!(
+                "`--story`: line {}: no free cell in its region is within the story margin of {}; it is placed by color as usual",
+                request.line_number,
+                palette.paths[request.tile_index].display()
+            ),
+        }
+    }
+
+    granted_count
+}
@@ -0,0 +1,151 @@
+use crate::geometry::{TileSize, parse_tile_sizes};
+use anyhow::{Result, bail};
+use pico_args::Arguments;
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Variable for the tile size in output paths, which is only filled in once the tile size of each output is known
+const TILE_SIZE_VARIABLE: &str = "{tile_size}";
+
+// Values of the variables that output paths may contain, e.g. "renders/{stem}-{date}-s{seed}.png", so parameter
+// sweeps and scheduled runs produce self-describing file names
+struct PathVariables {
+    // File stem of the input image, if one was given
+    stem: Option<String>,
+    // Today's date in UTC, as "YYYY-MM-DD"
+    date: String,
+    // Name of the palette directory (or the file stem of a palette pack), if one was given
+    palette: Option<String>,
+    seed: u64,
+    // Tile size, unless several were given, in which case it is filled in for each output by `expand_tile_size`
+    tile_size: Option<TileSize>,
+}
+
+// Fills in the variables in output paths, reading their values from the run's options without consuming them.
+// `{tile_size}` is left in place when several tile sizes were given.
+pub(crate) fn expand_output_paths(paths: Vec<PathBuf>, args: &Arguments) -> Result<Vec<PathBuf>> {
+    if !paths
+        .iter()
+        .any(|path| path.to_str().is_some_and(|path| path.contains('{')))
+    {
+        return Ok(paths);
+    }
+
+    let mut args = args.clone();
+    let input_path: Option<PathBuf> = args.opt_value_from_str(["-i", "--input"])?;
+    let palette_path: Option<PathBuf> = args.opt_value_from_str(["-p", "--palette-dir"])?;
+    let seed: Option<u64> = args.opt_value_from_str("--seed")?;
+    let tile_sizes = args.opt_value_from_fn(["-s", "--tile-size"], parse_tile_sizes)?;
+
+    let variables = PathVariables {
+        stem: input_path
+            .as_deref()
+            .and_then(Path::file_stem)
+            .map(|stem| stem.to_string_lossy().into_owned()),
+        date: utc_date(SystemTime::now()),
+        palette: palette_path.and_then(|path| {
+            // A palette directory given as e.g. "." is named after the directory it resolves to
+            let path = path.canonicalize().unwrap_or(path);
+            let name = if path.is_dir() {
+                path.file_name()
+            } else {
+                path.file_stem()
+            };
+            name.map(|name| name.to_string_lossy().into_owned())
+        }),
+        seed: seed.unwrap_or(0),
+        tile_size: tile_sizes.and_then(|sizes| match sizes[..] {
+            [size] => Some(size),
+            _ => None,
+        }),
+    };
+    paths
+        .into_iter()
+        .map(|path| expand_path(path, &variables))
+        .collect()
+}
+
+// Fills in `{tile_size}` in an output path left in place by `expand_output_paths`.
+// Returns `None` if the path doesn't contain it.
+pub(crate) fn expand_tile_size(path: &Path, tile_size: TileSize) -> Option<PathBuf> {
+    let path = path.to_str()?;
+    path.contains(TILE_SIZE_VARIABLE).then(|| {
+        path.replace(TILE_SIZE_VARIABLE, &format_tile_size(tile_size))
+            .into()
+    })
+}
+
+fn expand_path(path: PathBuf, variables: &PathVariables) -> Result<PathBuf> {
+    // Paths that aren't valid UTF-8 are left as they are
+    let Some(template) = path.to_str() else {
+        return Ok(path);
+    };
+
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let Some(length) = rest[start..].find('}') else {
+            bail!(
+                "`-o`/`--output`: unclosed \"{{\" in {template}; variables are written as e.g. \"{{stem}}\""
+            );
+        };
+        let name = &rest[start + 1..start + length];
+        let missing = |option: &str| {
+            format!("`-o`/`--output`: {{{name}}} needs {option}, which was not given ({template})")
+        };
+        match name {
+            "stem" => match &variables.stem {
+                Some(stem) => expanded.push_str(stem),
+                None => bail!(missing("an input image (`-i`/`--input`)")),
+            },
+            "date" => expanded.push_str(&variables.date),
+            "palette" => match &variables.palette {
+                Some(palette) => expanded.push_str(palette),
+                None => bail!(missing("a palette (`-p`/`--palette-dir`)")),
+            },
+            "seed" => expanded.push_str(&variables.seed.to_string()),
+            "tile_size" => match variables.tile_size {
+                Some(tile_size) => expanded.push_str(&format_tile_size(tile_size)),
+                None => expanded.push_str(TILE_SIZE_VARIABLE),
+            },
+            _ => bail!(
+                "`-o`/`--output`: unknown variable {{{name}}} in {template}; expected {{stem}}, {{date}}, {{tile_size}}, {{palette}}, or {{seed}}"
+            ),
+        }
+        rest = &rest[start + length + 1..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded.into())
+}
+
+// Formats a tile size as in the suffixes of outputs for several tile sizes, e.g. "32x32"
+fn format_tile_size(tile_size: TileSize) -> String {
+    format!("{}x{}", tile_size.width, tile_size.height)
+}
+
+// Formats the date of a time in UTC as "YYYY-MM-DD", converting days since the Unix epoch to a civil date with
+// Howard Hinnant's algorithm (https://howardhinnant.github.io/date_algorithms.html#civil_from_days)
+fn utc_date(time: SystemTime) -> String {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / 86_400);
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
@@ -0,0 +1,72 @@
+use crate::{InputImage, mosaic::cell_oklab};
+use anyhow::{Context, Result, bail};
+use foldhash::{HashMap, HashMapExt};
+use image::Rgb;
+use std::{fs::read_to_string, path::Path};
+
+// Reads a theme: a list of colors that the input image is mapped to before matching.
+// Each non-empty line is a color, either as hex (e.g. "1e1e1e" or "#1e1e1e") or as decimal components separated
+// by whitespace (e.g. "30 30 30"), optionally followed by a name. Lines starting with "#" that aren't hex colors are
+// comments. This covers both plain hex lists and GIMP palettes (.gpl files), whose header lines are skipped.
+pub(crate) fn load_theme(path: &Path) -> Result<Vec<Rgb<u8>>> {
+    let contents = read_to_string(path)?;
+    let mut colors = Vec::new();
+
+    for (line, line_number) in contents.lines().zip(1..) {
+        let line = line.trim();
+        if line.is_empty()
+            || line == "GIMP Palette"
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let first = fields.next().unwrap();
+        let hex = first.strip_prefix('#').unwrap_or(first);
+
+        if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+            colors.push(Rgb([component(0), component(2), component(4)]));
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let (Some(g), Some(b)) = (fields.next(), fields.next()) else {
+            bail!("`--theme`: line {line_number}: expected a hex color or three color components");
+        };
+        let component = |s: &str| {
+            s.parse()
+                .with_context(|| format!("`--theme`: line {line_number}: invalid color component"))
+        };
+        colors.push(Rgb([component(first)?, component(g)?, component(b)?]));
+    }
+
+    if colors.is_empty() {
+        bail!("`--theme`: theme has no colors");
+    }
+
+    Ok(colors)
+}
+
+// Replaces each pixel of the input image with the theme color nearest to it.
+pub(crate) fn apply_theme(image: &mut InputImage, theme: &[Rgb<u8>]) {
+    let theme_oklab: Vec<_> = theme.iter().map(|&color| cell_oklab(color)).collect();
+    let mut nearest_cache = HashMap::new();
+
+    for pixel in image.pixels_mut() {
+        *pixel = *nearest_cache.entry(*pixel).or_insert_with(|| {
+            let [l, a, b] = cell_oklab(*pixel);
+            let distance = |&[theme_l, theme_a, theme_b]: &[f32; 3]| {
+                (l - theme_l).powi(2) + (a - theme_a).powi(2) + (b - theme_b).powi(2)
+            };
+            let nearest_idx = (0..theme.len())
+                .min_by(|&i, &j| distance(&theme_oklab[i]).total_cmp(&distance(&theme_oklab[j])))
+                .unwrap();
+            theme[nearest_idx]
+        });
+    }
+}
@@ -0,0 +1,43 @@
+use crate::{InputImage, curves::Curves, mosaic::cell_oklab};
+use image::{
+    GenericImageView, Rgb,
+    imageops::{FilterType, resize},
+};
+
+// Number of segments of the correction curve fitted by `--tone-match`, spaced evenly by rank
+const CURVE_SEGMENT_COUNT: usize = 32;
+
+// Fits a correction curve for `--tone-match`, which maps the Oklab lightness of the mosaic, seen at thumbnail size,
+// to that of the image it was matched against. The mosaic is downscaled to the target image's size, so each tile
+// is seen as its average color, and their lightness distributions are matched rank by rank (histogram matching).
+pub(crate) fn tone_curve(
+    mosaic: &impl GenericImageView<Pixel = Rgb<u8>>,
+    target: &InputImage,
+) -> Curves {
+    let thumbnail = resize(
+        mosaic,
+        target.width(),
+        target.height(),
+        FilterType::Triangle,
+    );
+    let sorted_lightness = |image: &InputImage| {
+        let mut lightness: Vec<_> = image.pixels().map(|&pixel| cell_oklab(pixel)[0]).collect();
+        lightness.sort_unstable_by(f32::total_cmp);
+        lightness
+    };
+    let mosaic_lightness = sorted_lightness(&thumbnail);
+    let target_lightness = sorted_lightness(target);
+
+    let mut points: Vec<(f32, f32)> = Vec::with_capacity(CURVE_SEGMENT_COUNT + 1);
+    for idx in 0..=CURVE_SEGMENT_COUNT {
+        let at_rank =
+            |lightness: &[f32]| lightness[(lightness.len() - 1) * idx / CURVE_SEGMENT_COUNT];
+        let point = (at_rank(&mosaic_lightness), at_rank(&target_lightness));
+        // Runs of equal lightness in the mosaic (e.g. a single tile repeated) keep their first point
+        if points.last().is_none_or(|&(x, _)| x < point.0) {
+            points.push(point);
+        }
+    }
+
+    Curves::lightness(points)
+}
@@ -0,0 +1,365 @@
+use crate::{
+    InputImage,
+    animation::AnimatedFrames,
+    default_extensions,
+    geometry::{TileSize, parse_tile_size},
+    layout::grid_cells,
+    mosaic::{MAX_QUANTIZED_COLOR_COUNT, MatchOptions, Quantizer, Records, dither, render_image},
+    palette::{LoadOptions, LoadedPalette, Palette, UpscalePolicy, find_images, load_images},
+    quality::QualityFilters,
+};
+use anyhow::{Result, anyhow, bail};
+use image::{
+    ImageReader,
+    imageops::{FilterType, resize},
+};
+use pico_args::Arguments;
+use std::{
+    ffi::OsString,
+    fmt::Write as _,
+    io::{Write as _, stdin, stdout},
+    path::PathBuf,
+};
+
+const COMMANDS: &str = "commands:
+  palette DIR          use the palette images in a directory
+  input PATH           use an input image
+  output PATH          write the full render to a path
+  tile-size SIZE       set the tile size of the full render, e.g. \"32\" or \"32x48\"
+  variety N            choose each cell's tile among its N nearest tiles; 1 always places the nearest tile
+  dither true|false    enable or disable dithering of the input image
+  set OPTION [VALUE]   pass any other option of `tessera` to the full render, e.g. \"set --seed 7\"
+  unset OPTION         stop passing an option given with `set`
+  show                 print the current options as a `tessera` command line
+  preview              print a low-resolution preview of the mosaic
+  render               render the full mosaic with the current options
+  help                 print this message
+  quit                 exit";
+
+// Options that have their own command, by each of their names
+const COMMAND_OPTIONS: [(&str, &str); 11] = [
+    ("-p", "palette"),
+    ("--palette-dir", "palette"),
+    ("-i", "input"),
+    ("--input", "input"),
+    ("-o", "output"),
+    ("--output", "output"),
+    ("-s", "tile-size"),
+    ("--tile-size", "tile-size"),
+    ("--variety", "variety"),
+    ("-d", "dither"),
+    ("--dither", "dither"),
+];
+
+// Options that can't be given with `set`, since renders share a process: the global thread pool is set up once,
+// and `--watch` never returns
+const PROCESS_OPTIONS: [&str; 3] = ["-t", "--threads", "--watch"];
+
+// Width and height of the tile drawn for each cell in previews, in terminal pixels. Each character shows two pixels
+// stacked with a half block, so a tile takes up two characters side by side, which is about square in a terminal.
+const PREVIEW_TILE_SIZE: u32 = 2;
+
+// Runs `tessera tui`, which sets up a run of `tessera` one option at a time from commands read from standard input,
+// printing a low-resolution preview of the mosaic to the terminal after each change to the palette, input image,
+// variety, or dithering, then renders it in full with the options chosen.
+pub(crate) fn run(mut args: Arguments) -> Result<()> {
+    if args.contains(["-h", "--help"]) {
+        println!(
+            "tessera tui: choose the options of a run interactively, with a preview of the mosaic in the terminal
+usage: tessera tui [OPTIONS]
+
+-h, --help           print this message
+-p, --palette-dir    path to directory containing images to tile the output image with
+-i, --input          input image path
+-o, --output         output image path of the full render
+-s, --tile-size      width and height of each tile in the full render, in pixels, e.g. \"64\" or \"64x96\"; default is 32
+--preview-width      width of previews, in terminal columns; default is 64
+
+{COMMANDS}"
+        );
+        return Ok(());
+    }
+
+    let palette_dir_path: Option<PathBuf> = args.opt_value_from_str(["-p", "--palette-dir"])?;
+    let input_image_path: Option<PathBuf> = args.opt_value_from_str(["-i", "--input"])?;
+    let output_image_path: Option<PathBuf> = args.opt_value_from_str(["-o", "--output"])?;
+    let tile_size = args
+        .opt_value_from_fn(["-s", "--tile-size"], parse_tile_size)?
+        .unwrap_or(TileSize::square(32));
+    let preview_width: u32 = args.opt_value_from_str("--preview-width")?.unwrap_or(64);
+
+    if tile_size.width == 0 || tile_size.height == 0 {
+        bail!("`-s`/`--tile-size`: tile size cannot be zero");
+    }
+    if preview_width < PREVIEW_TILE_SIZE {
+        bail!("`--preview-width`: previews must be at least {PREVIEW_TILE_SIZE} columns wide");
+    }
+
+    let mut session = Session {
+        palette_dir_path,
+        input_image_path,
+        output_image_path,
+        tile_size,
+        variety: 1,
+        dither: true,
+        options: Vec::new(),
+        preview_width,
+        preview_palette: None,
+    };
+    session.run()
+}
+
+// The options chosen so far, and the palette loaded for previews
+struct Session {
+    palette_dir_path: Option<PathBuf>,
+    input_image_path: Option<PathBuf>,
+    output_image_path: Option<PathBuf>,
+    tile_size: TileSize,
+    variety: usize,
+    dither: bool,
+    // Other options given with `set`, in order, each with its values
+    options: Vec<(String, Vec<String>)>,
+    preview_width: u32,
+    // Palette of previews, with tiles the size of `PREVIEW_TILE_SIZE`, and the directory it was loaded from
+    preview_palette: Option<(PathBuf, Palette)>,
+}
+
+impl Session {
+    // Reads and runs commands until `quit` or the end of standard input.
+    fn run(&mut self) -> Result<()> {
+        println!("{COMMANDS}");
+        if self.palette_dir_path.is_some() && self.input_image_path.is_some() {
+            self.print_preview()?;
+        }
+        let mut lines = stdin().lines();
+
+        loop {
+            print!("> ");
+            stdout().flush()?;
+            let Some(line) = lines.next().transpose()? else {
+                return Ok(());
+            };
+
+            let words: Vec<_> = line.split_whitespace().collect();
+            let result = match words.as_slice() {
+                [] => Ok(()),
+                ["palette", path] => {
+                    self.palette_dir_path = Some(PathBuf::from(path));
+                    self.print_preview()
+                }
+                ["input", path] => {
+                    self.input_image_path = Some(PathBuf::from(path));
+                    self.print_preview()
+                }
+                ["output", path] => {
+                    self.output_image_path = Some(PathBuf::from(path));
+                    Ok(())
+                }
+                ["tile-size", size] => parse_tile_size(size).and_then(|tile_size| {
+                    if tile_size.width == 0 || tile_size.height == 0 {
+                        bail!("tile size cannot be zero");
+                    }
+                    self.tile_size = tile_size;
+                    Ok(())
+                }),
+                ["variety", n] => match n.parse() {
+                    Ok(0) | Err(_) => Err(anyhow!("variety must be a positive number of tiles")),
+                    Ok(variety) => {
+                        self.variety = variety;
+                        self.print_preview()
+                    }
+                },
+                ["dither", enabled] => match enabled.parse() {
+                    Ok(dither) => {
+                        self.dither = dither;
+                        self.print_preview()
+                    }
+                    Err(_) => Err(anyhow!("expected `true` or `false`")),
+                },
+                ["set", option, values @ ..] => self.set(option, values),
+                ["unset", option] => {
+                    let count = self.options.len();
+                    self.options.retain(|(name, _)| name != option);
+                    if self.options.len() == count {
+                        Err(anyhow!("`{option}` was not set"))
+                    } else {
+                        Ok(())
+                    }
+                }
+                ["show"] => {
+                    println!("{}", self.command_line());
+                    Ok(())
+                }
+                ["preview"] => self.print_preview(),
+                ["render"] => crate::run(Arguments::from_vec(self.args())),
+                ["help"] => {
+                    println!("{COMMANDS}");
+                    Ok(())
+                }
+                ["quit"] => return Ok(()),
+                _ => Err(anyhow!("unknown command; `help` lists commands")),
+            };
+
+            if let Err(error) = result {
+                eprintln!("{error:#}");
+            }
+        }
+    }
+
+    // Passes an option to the full render, replacing its earlier values, if any.
+    fn set(&mut self, option: &str, values: &[&str]) -> Result<()> {
+        if !option.starts_with('-') {
+            bail!("expected an option, such as `--seed`");
+        }
+        if let Some((_, command)) = COMMAND_OPTIONS.iter().find(|&&(name, _)| name == option) {
+            bail!("`{option}` is set with the `{command}` command");
+        }
+        if PROCESS_OPTIONS.contains(&option) {
+            bail!("`{option}` cannot be used in `tessera tui`; run `tessera` with it instead");
+        }
+
+        let values = values.iter().map(|&value| value.to_owned()).collect();
+        match self.options.iter_mut().find(|(name, _)| name == option) {
+            Some((_, old_values)) => *old_values = values,
+            None => self.options.push((option.to_owned(), values)),
+        }
+        Ok(())
+    }
+
+    // Arguments of `tessera` for the full render
+    fn args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        if let Some(path) = &self.palette_dir_path {
+            args.extend([OsString::from("-p"), path.into()]);
+        }
+        if let Some(path) = &self.input_image_path {
+            args.extend([OsString::from("-i"), path.into()]);
+        }
+        if let Some(path) = &self.output_image_path {
+            args.extend([OsString::from("-o"), path.into()]);
+        }
+        let TileSize { width, height } = self.tile_size;
+        args.extend(["-s".into(), format!("{width}x{height}").into()]);
+        if self.variety > 1 {
+            args.extend(["--variety".into(), self.variety.to_string().into()]);
+        }
+        if !self.dither {
+            args.extend(["-d".into(), "false".into()]);
+        }
+        for (option, values) in &self.options {
+            args.push(option.into());
+            args.extend(values.iter().map(OsString::from));
+        }
+        args
+    }
+
+    // The full render as a command line, quoting arguments that contain spaces
+    fn command_line(&self) -> String {
+        let mut line = "tessera".to_owned();
+        for arg in self.args() {
+            let arg = arg.to_string_lossy();
+            if arg.contains(char::is_whitespace) {
+                write!(line, " {arg:?}").unwrap();
+            } else {
+                write!(line, " {arg}").unwrap();
+            }
+        }
+        line
+    }
+
+    // Matches a downscaled input image against the palette with the options that change which palette images are
+    // placed, and prints the result in 24-bit color, each cell drawn as a tile of `PREVIEW_TILE_SIZE` pixels.
+    // Tiles are previewed as squares whatever the tile size of the full render.
+    fn print_preview(&mut self) -> Result<()> {
+        let (Some(palette_dir_path), Some(input_image_path)) =
+            (&self.palette_dir_path, &self.input_image_path)
+        else {
+            bail!(
+                "previews need a palette directory and an input image; set them with `palette` and `input`"
+            );
+        };
+
+        // The palette is loaded again only when its directory changes, since resizing palette images takes most of
+        // the time of a preview
+        if self
+            .preview_palette
+            .as_ref()
+            .is_none_or(|(path, _)| path != palette_dir_path)
+        {
+            self.preview_palette = None;
+            let tile_size = TileSize::square(PREVIEW_TILE_SIZE);
+            let spans = vec![1];
+            let load_options = LoadOptions {
+                tile_size,
+                spans: &spans,
+                min_tile_source: None,
+                allow_upscale: false,
+                quality_filters: QualityFilters::default(),
+                upscale_policy: UpscalePolicy::default(),
+                animated_frames: AnimatedFrames::First,
+                max_source_pixels: None,
+                cache: None,
+            };
+            let LoadedPalette {
+                paths, tile_sets, ..
+            } = load_images(
+                find_images(palette_dir_path, &default_extensions())?,
+                &load_options,
+            )?;
+            let palette = Palette::new(paths, tile_size, spans, tile_sets, false, None, None)?;
+            self.preview_palette = Some((palette_dir_path.clone(), palette));
+        }
+        let (_, palette) = self.preview_palette.as_ref().unwrap();
+
+        // Each pixel of the input image is one cell, as in the full render without `--grid` or `--fit`
+        let input_image: InputImage = ImageReader::open(input_image_path)?.decode()?.into_rgb8();
+        let (input_width, input_height) = input_image.dimensions();
+        let width = input_width.min(self.preview_width / PREVIEW_TILE_SIZE);
+        let height = (u64::from(input_height) * u64::from(width) / u64::from(input_width)).max(1);
+        let mut input_image = resize(
+            &input_image,
+            width,
+            u32::try_from(height)?,
+            FilterType::Triangle,
+        );
+        if self.dither {
+            input_image = dither(
+                &input_image,
+                Quantizer::default(),
+                MAX_QUANTIZED_COLOR_COUNT,
+            )?;
+        }
+        let (width, height) = input_image.dimensions();
+        let options = MatchOptions {
+            variety: self.variety,
+            ..MatchOptions::default()
+        };
+        let (preview, _) = render_image(
+            palette,
+            &grid_cells(&input_image),
+            width,
+            height,
+            options,
+            Records::default(),
+        )?;
+
+        // Each character is an upper half block, in the color of the upper pixel on the color of the lower one
+        let mut text = String::new();
+        for y in (0..preview.height()).step_by(2) {
+            for x in 0..preview.width() {
+                let [r, g, b] = preview.get_pixel(x, y).0;
+                write!(text, "\x1b[38;2;{r};{g};{b}m").unwrap();
+                if y + 1 < preview.height() {
+                    let [r, g, b] = preview.get_pixel(x, y + 1).0;
+                    write!(text, "\x1b[48;2;{r};{g};{b}m").unwrap();
+                }
+                text.push('\u{2580}');
+            }
+            text.push_str("\x1b[0m\n");
+        }
+        print!("{text}");
+        stdout().flush()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{
+    TilePixel,
+    color::{linear_srgb_to_oklab, oklab_to_linear_srgb},
+    environment::fingerprint,
+    mosaic::cell_oklab,
+    palette::average_color,
+};
+use anyhow::{Result, bail};
+use image::Rgb;
+use kiddo::{ImmutableKdTree, SquaredEuclidean};
+use std::num::NonZero;
+
+// Largest deviation from a reference Oklab value allowed for a conversion or an average. The reference values are given
+// to 6 decimal places, and the conversions are computed with 32-bit floats.
+const OKLAB_TOLERANCE: f64 = 1e-5;
+
+// Largest deviation allowed when converting to Oklab and back
+const ROUND_TRIP_TOLERANCE: f64 = 1e-4;
+
+// Reference Oklab values of the sRGB primaries, secondaries, and white, as (name, linear sRGB, Oklab), from the CSS
+// Color Module Level 4 sample conversions (https://www.w3.org/TR/css-color-4/#color-conversion-code). Grays, whose
+// lightness is the cube root of their linear value, are checked too.
+const REFERENCES: [(&str, [f32; 3], [f64; 3]); 10] = [
+    ("white", [1., 1., 1.], [1., 0., 0.]),
+    ("red", [1., 0., 0.], [0.627955, 0.224863, 0.125846]),
+    ("green", [0., 1., 0.], [0.866440, -0.233888, 0.179498]),
+    ("blue", [0., 0., 1.], [0.452014, -0.032457, -0.311528]),
+    ("cyan", [0., 1., 1.], [0.905399, -0.149444, -0.039398]),
+    ("magenta", [1., 0., 1.], [0.701674, 0.274566, -0.169156]),
+    ("yellow", [1., 1., 0.], [0.967983, -0.071369, 0.198570]),
+    ("50% gray", [0.5, 0.5, 0.5], [0.793701, 0., 0.]),
+    ("18% gray", [0.18, 0.18, 0.18], [0.564622, 0., 0.]),
+    ("black", [0., 0., 0.], [0., 0., 0.]),
+];
+
+// Runs `--validate`, which checks tessera's color conversions, tile averaging, and color distance metric against
+// built-in reference values and reports how far each result deviates, so a build can be certified before it is used
+// for work that must be colorimetrically accurate. Fails if any check is out of tolerance.
+pub(crate) fn run() -> Result<()> {
+    for (name, value) in fingerprint() {
+        println!("{name}: {value}");
+    }
+
+    let mut checks = Checks::default();
+
+    // Oklab conversion of linear sRGB, as used for palette images, and of 8-bit colors, as used for cells
+    for (name, rgb, expected) in REFERENCES {
+        let [r, g, b] = rgb;
+        checks.compare(
+            &format!("Oklab of linear sRGB {name}"),
+            linear_srgb_to_oklab(r, g, b),
+            expected,
+            OKLAB_TOLERANCE,
+        );
+        // Cells have 8-bit colors, so only the colors with whole components are checked
+        if rgb.iter().all(|c| c.fract() == 0.) {
+            checks.compare(
+                &format!("Oklab of cell color {name}"),
+                cell_oklab(Rgb(rgb.map(|c| (c * 255.) as u8))),
+                expected,
+                OKLAB_TOLERANCE,
+            );
+        }
+        let [l, a, b] = linear_srgb_to_oklab(r, g, b);
+        checks.compare(
+            &format!("linear sRGB {name} to Oklab and back"),
+            oklab_to_linear_srgb(l, a, b),
+            rgb.map(f64::from),
+            ROUND_TRIP_TOLERANCE,
+        );
+    }
+
+    // Averaging of tiles, which happens in linear space before conversion to Oklab
+    let opaque = |rgb: [u8; 3]| [rgb[0], rgb[1], rgb[2], 255];
+    let half_black_half_white = [opaque([0; 3]), opaque([255; 3])].concat();
+    checks.compare(
+        "average of black and white tile",
+        average_color::<TilePixel>(&half_black_half_white, false),
+        REFERENCES[7].2,
+        OKLAB_TOLERANCE,
+    );
+    let primaries = [
+        opaque([255, 0, 0]),
+        opaque([0, 255, 0]),
+        opaque([0, 0, 255]),
+    ]
+    .concat();
+    checks.compare(
+        "average of red, green, and blue tile",
+        average_color::<TilePixel>(&primaries, false),
+        [(1f64 / 3.).cbrt(), 0., 0.],
+        OKLAB_TOLERANCE,
+    );
+    let half_transparent = [[0; 4], opaque([255; 3])].concat();
+    checks.compare(
+        "average of half-transparent tile",
+        average_color::<TilePixel>(&half_transparent, false),
+        REFERENCES[7].2,
+        OKLAB_TOLERANCE,
+    );
+    checks.compare(
+        "opacity-weighted average of half-transparent tile",
+        average_color::<TilePixel>(&half_transparent, true),
+        REFERENCES[0].2,
+        OKLAB_TOLERANCE,
+    );
+
+    // Color distance, as found by the nearest-neighbor search that matches cells with tiles: the nearest other
+    // reference color to each one, and its distance, against the Euclidean distance in Oklab space (ΔEOK) computed
+    // directly from the reference values
+    let colors: Vec<[f32; 3]> = REFERENCES
+        .iter()
+        .map(|(_, [r, g, b], _)| linear_srgb_to_oklab(*r, *g, *b))
+        .collect();
+    let tree = ImmutableKdTree::new_from_slice(&colors);
+    for ((name, _, expected), color) in REFERENCES.iter().zip(&colors) {
+        let reference_distance = REFERENCES
+            .iter()
+            .filter(|(other_name, ..)| other_name != name)
+            .map(|(_, _, other)| {
+                expected
+                    .iter()
+                    .zip(other)
+                    .map(|(a, b)| (a - b).powi(2))
+                    .sum::<f64>()
+                    .sqrt()
+            })
+            .fold(f64::INFINITY, f64::min);
+        // The nearest color is the color itself, so the second nearest is the nearest other color
+        let nearest = tree.nearest_n::<SquaredEuclidean>(color, NonZero::new(2).unwrap());
+        let distance = f64::from(nearest[1].distance).sqrt();
+        checks.check(
+            &format!("ΔEOK from {name} to the nearest other reference color"),
+            (distance - reference_distance).abs(),
+            OKLAB_TOLERANCE,
+        );
+    }
+
+    checks.finish()
+}
+
+// Results of the checks run so far
+#[derive(Default)]
+struct Checks {
+    count: u32,
+    failed: u32,
+    // Largest deviation of any check, relative to its tolerance
+    worst: f64,
+}
+
+impl Checks {
+    // Checks a color against its reference value, by the largest deviation of any component
+    fn compare(&mut self, name: &str, actual: [f32; 3], expected: [f64; 3], tolerance: f64) {
+        let deviation = actual
+            .iter()
+            .zip(expected)
+            .map(|(&actual, expected)| (f64::from(actual) - expected).abs())
+            .fold(0., f64::max);
+        self.check(name, deviation, tolerance);
+    }
+
+    fn check(&mut self, name: &str, deviation: f64, tolerance: f64) {
+        let passed = deviation <= tolerance;
+        println!(
+            "{}: {name}: deviation {deviation:.1e} (tolerance {tolerance:.0e})",
+            if passed { "pass" } else { "FAIL" }
+        );
+        self.count += 1;
+        self.failed += u32::from(!passed);
+        self.worst = self.worst.max(deviation / tolerance);
+    }
+
+    fn finish(self) -> Result<()> {
+        if self.failed > 0 {
+            bail!("{} of {} checks failed", self.failed, self.count);
+        }
+        println!(
+            "all {} checks passed; the largest deviation was {:.0}% of its tolerance",
+            self.count,
+            self.worst * 100.
+        );
+        Ok(())
+    }
+}
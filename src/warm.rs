@@ -0,0 +1,125 @@
+use crate::{
+    animation::AnimatedFrames,
+    cache::{TileCache, format_size, parse_size},
+    default_extensions,
+    geometry::{TileSize, parse_tile_sizes},
+    logging::{format_count, log},
+    palette::{LoadOptions, UpscalePolicy, find_images, warm_image},
+    parse_extensions,
+    quality::QualityFilters,
+};
+use anyhow::{Result, bail};
+use pico_args::Arguments;
+use rayon::{ThreadPoolBuilder, prelude::*};
+use std::{path::PathBuf, time::Instant};
+
+// Runs `tessera warm`, which resizes every palette image into a tile cache for each of a list of tile sizes,
+// so later runs with `--cache-dir` don't need to decode and resize palette images.
+pub(crate) fn run(mut args: Arguments) -> Result<()> {
+    if args.contains(["-h", "--help"]) {
+        println!(
+            "tessera warm: resize palette images into a tile cache ahead of time, for --cache-dir
+-h, --help           print this message
+-p, --palette-dir    path to directory containing palette images
+-s, --tile-size      comma-separated list of tile sizes to resize palette images to, e.g. \"32,64,64x96\";
+                     may be given more than once
+--cache-dir          directory of the tile cache to add to
+--cache-max-size     maximum size of the tile cache, e.g. \"2G\"; the least recently used entries are evicted
+-x, --extensions     comma-separated list of file extensions of palette images to process, replacing the default list;
+                     prefix the list with \"+\" to add to the default list instead; default is \"{}\"
+--upscale-policy     how to handle palette images smaller than a tile: \"bilinear\", \"nearest\", \"lanczos\", or \"skip\";
+                     default is \"bilinear\"
+--depth              also resize palette images for the larger tiles used with --depth-map
+-t, --threads        number of threads to use; default is the number of logical CPUs",
+            default_extensions().join(",")
+        );
+        return Ok(());
+    }
+
+    let palette_dir_path: PathBuf = args.value_from_str(["-p", "--palette-dir"])?;
+    let tile_sizes: Vec<TileSize> = args
+        .values_from_fn(["-s", "--tile-size"], parse_tile_sizes)?
+        .into_iter()
+        .flatten()
+        .collect();
+    let cache_dir_path: PathBuf = args.value_from_str("--cache-dir")?;
+    let cache_max_size: Option<u64> = args.opt_value_from_fn("--cache-max-size", parse_size)?;
+    let extensions = args
+        .opt_value_from_fn(["-x", "--extensions"], parse_extensions)?
+        .unwrap_or_else(default_extensions);
+    let upscale_policy: UpscalePolicy = args
+        .opt_value_from_str("--upscale-policy")?
+        .unwrap_or_default();
+    let depth = args.contains("--depth");
+    let thread_count: Option<usize> = args.opt_value_from_str(["-t", "--threads"])?;
+
+    if !palette_dir_path.is_dir() {
+        bail!("`-p`/`--palette-dir`: path does not point to a directory");
+    }
+    if tile_sizes.is_empty() {
+        bail!("`-s`/`--tile-size`: at least one tile size is required");
+    }
+    if tile_sizes
+        .iter()
+        .any(|tile_size| tile_size.width == 0 || tile_size.height == 0)
+    {
+        bail!("`-s`/`--tile-size`: tile size cannot be zero");
+    }
+    if thread_count == Some(0) {
+        bail!("`-t`/`--threads`: thread count cannot be zero");
+    }
+
+    if let Some(thread_count) = thread_count {
+        ThreadPoolBuilder::new()
+            .num_threads(thread_count)
+            .build_global()?;
+    }
+
+    let cache = TileCache::new(cache_dir_path)?;
+    let palette_paths = find_images(&palette_dir_path, &extensions)?;
+    let spans = if depth { vec![1, 2] } else { vec![1] };
+
+    for tile_size in tile_sizes {
+        let load_options = LoadOptions {
+            tile_size,
+            spans: &spans,
+            min_tile_source: None,
+            allow_upscale: false,
+            quality_filters: QualityFilters::default(),
+            upscale_policy,
+            animated_frames: AnimatedFrames::First,
+            max_source_pixels: None,
+            cache: Some(&cache),
+        };
+
+        let start = Instant::now();
+        let warmed = palette_paths
+            .par_iter()
+            .map(|path| warm_image(path, &load_options))
+            .collect::<Result<Vec<_>>>()?;
+
+        let resized_count = warmed.iter().filter(|&&w| w == Some(true)).count();
+        let cached_count = warmed.iter().filter(|&&w| w == Some(false)).count();
+        let rejected_count = warmed.iter().filter(|w| w.is_none()).count();
+        log!(
+            "tile size {}x{}: resized {} palette images, {} already cached, {} rejected in {:.2} s",
+            tile_size.width,
+            tile_size.height,
+            format_count(resized_count as u64),
+            format_count(cached_count as u64),
+            format_count(rejected_count as u64),
+            start.elapsed().as_secs_f64()
+        );
+    }
+
+    if let Some(cache_max_size) = cache_max_size {
+        let (removed_count, removed_size) = cache.evict(cache_max_size)?;
+        log!(
+            "evicted {} tile cache entries ({})",
+            format_count(removed_count as u64),
+            format_size(removed_size)
+        );
+    }
+
+    Ok(())
+}
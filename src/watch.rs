@@ -0,0 +1,125 @@
+use crate::{hot::HotPalette, logging::log, with_suffix};
+use anyhow::{Result, bail};
+use pico_args::Arguments;
+use std::{
+    fs::copy,
+    path::{Path, PathBuf},
+    thread::sleep,
+    time::{Duration, SystemTime},
+};
+use walkdir::WalkDir;
+
+// Keeps a mosaic up to date with a growing palette directory, for `--watch`: after each render, the palette directory
+// is checked every `interval` for added, removed, or changed files, and the mosaic is rendered again once it changes.
+// Meant for events such as a photobooth, where the mosaic improves as new photos arrive. Palette images stay loaded
+// between renders, so each render only loads the palette images that arrived (or changed) since the last one.
+#[derive(Default)]
+pub(crate) struct Watch {
+    pub(crate) interval: Duration,
+    // Whether the outputs of each render are also copied to numbered snapshots, for `--watch-snapshots`
+    pub(crate) snapshots: bool,
+    pub(crate) palette: HotPalette,
+}
+
+// Path, size, and modification time of each file in the palette directory
+type PaletteState = Vec<(PathBuf, u64, Option<SystemTime>)>;
+
+impl Watch {
+    // Renders the mosaic with `render`, then renders it again whenever the palette directory changes, until stopped.
+    // `render` loads palette images through the given palette and returns the paths it wrote.
+    // A failed render is reported and left for the next change to fix, except for the first one, which ends the run.
+    pub(crate) fn run(
+        &mut self,
+        args: &Arguments,
+        mut render: impl FnMut(Arguments, &mut HotPalette) -> Result<Vec<PathBuf>>,
+    ) -> Result<()> {
+        let palette_dir = palette_dir(args)?;
+
+        let mut state = palette_state(&palette_dir)?;
+        let written_paths = render(args.clone(), &mut self.palette)?;
+        let mut render_count = 1;
+        self.snapshot(&written_paths, render_count)?;
+
+        loop {
+            log!(
+                "watching {} for palette changes every {} s",
+                palette_dir.display(),
+                self.interval.as_secs_f64()
+            );
+            // Wait for the palette to change, then for it to stop changing, so that images still being copied into
+            // the palette directory aren't read half-written
+            loop {
+                sleep(self.interval);
+                let new_state = palette_state(&palette_dir)?;
+                if new_state != state {
+                    state = new_state;
+                    break;
+                }
+            }
+            loop {
+                sleep(self.interval);
+                let new_state = palette_state(&palette_dir)?;
+                if new_state == state {
+                    break;
+                }
+                state = new_state;
+            }
+
+            log!("the palette changed; rendering again");
+            match render(args.clone(), &mut self.palette) {
+                Ok(written_paths) => {
+                    render_count += 1;
+                    self.snapshot(&written_paths, render_count)?;
+                }
+                Err(err) => {
+                    log!(
+                        "warning: rendering failed: {err:#}; waiting for the palette to change again"
+                    );
+                }
+            }
+        }
+    }
+
+    // Copies each path written by a render to a numbered snapshot, e.g. "out.png" to "out.0003.png" for the third
+    // render. With several tile sizes, these are the paths suffixed with each tile size.
+    fn snapshot(&self, written_paths: &[PathBuf], render_count: u32) -> Result<()> {
+        if self.snapshots {
+            for path in written_paths {
+                copy(path, with_suffix(path, &format!("{render_count:04}")))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Finds the palette directory among the options of a run, failing if the run can't be watched
+fn palette_dir(args: &Arguments) -> Result<PathBuf> {
+    let mut args = args.clone();
+    let palette_dir: Option<PathBuf> = args.opt_value_from_str(["-p", "--palette-dir"])?;
+    let stop_after: Option<String> = args.opt_value_from_str("--stop-after")?;
+    let resume: Option<String> = args.opt_value_from_str("--resume-from")?;
+
+    if stop_after.is_some() || resume.is_some() {
+        bail!(
+            "`--watch` renders output images, so it cannot be used with `--stop-after` or `--resume-from`"
+        );
+    }
+    match palette_dir {
+        Some(dir) if dir.is_dir() => Ok(dir),
+        _ => bail!(
+            "`--watch` needs a palette directory to watch, so it cannot be used with a palette pack or `--glyphs`"
+        ),
+    }
+}
+
+fn palette_state(dir: &Path) -> Result<PaletteState> {
+    let mut state = Vec::new();
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let metadata = entry.metadata()?;
+            state.push((entry.into_path(), metadata.len(), metadata.modified().ok()));
+        }
+    }
+    Ok(state)
+}
@@ -0,0 +1,50 @@
+use crate::{logging::log, paths::decode_relative_path};
+use anyhow::{Context, Result, bail};
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+// Reads a weights file assigning a desirability weight to palette images, returning the weight of each palette image.
+// Each non-empty line has a path (relative to the palette directory) and a non-negative weight, separated by a comma,
+// e.g. "sub/hero.jpg,3". Lines starting with "#" are comments. Palette images not listed keep their weight in `weights`.
+pub(crate) fn load_weights(
+    path: &Path,
+    palette_dir: &Path,
+    palette_paths: &[PathBuf],
+    mut weights: Vec<f32>,
+) -> Result<Vec<f32>> {
+    let contents = read_to_string(path)?;
+
+    for (line, line_number) in contents.lines().zip(1..) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((image_path, weight)) = line.rsplit_once(',') else {
+            bail!(
+                "`--weights`: line {line_number}: expected a path and a weight separated by a comma"
+            );
+        };
+        let weight: f32 = weight
+            .trim()
+            .parse()
+            .with_context(|| format!("`--weights`: line {line_number}: invalid weight"))?;
+        if !weight.is_finite() || weight < 0. {
+            bail!("`--weights`: line {line_number}: weight must be a non-negative number");
+        }
+
+        let image_path = decode_relative_path(palette_dir, image_path.trim())
+            .with_context(|| format!("`--weights`: line {line_number}: invalid path"))?;
+        match palette_paths.iter().position(|path| *path == image_path) {
+            Some(idx) => weights[idx] = weight,
+            None => log!(
+                "`--weights`: line {line_number}: {} is not in the palette",
+                image_path.display()
+            ),
+        }
+    }
+
+    Ok(weights)
+}
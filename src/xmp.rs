@@ -0,0 +1,100 @@
+use crate::{mosaic::RenderStats, paths::encode_path};
+use anyhow::Result;
+use std::{
+    fmt::Write as _,
+    fs::write,
+    path::{Path, PathBuf},
+};
+
+// Number of most-used palette images listed in the sidecar
+const TOP_SOURCE_COUNT: usize = 10;
+
+// Namespace of the fields written to the sidecar
+const NAMESPACE: &str = "https://github.com/astral4/tessera/ns/1.0/";
+
+// Returns the path of the XMP sidecar for an output image, e.g. "out.xmp" for "out.png".
+pub(crate) fn sidecar_path(output_path: &Path) -> PathBuf {
+    output_path.with_extension("xmp")
+}
+
+// Writes an XMP sidecar summarizing how the output image was produced, so digital asset management systems can index it:
+// - `tessera:Palette`: the palette directory
+// - `tessera:PaletteSize`: the number of images in the palette
+// - `tessera:TileCount`: the number of cells drawn with a palette image (as opposed to solid color)
+// - `tessera:TopSources`: the most-used palette images and the number of cells each was placed in, most used first
+pub(crate) fn write_sidecar(
+    path: &Path,
+    palette_dir: &Path,
+    palette_paths: &[PathBuf],
+    stats: &RenderStats,
+) -> Result<()> {
+    let tile_count: u32 = stats.tile_uses.iter().sum();
+
+    // Ties are broken by palette order so the sidecar is deterministic
+    let mut top_sources: Vec<_> = stats
+        .tile_uses
+        .iter()
+        .zip(palette_paths)
+        .filter(|&(&uses, _)| uses > 0)
+        .collect();
+    top_sources.sort_by(|(a, _), (b, _)| b.cmp(a));
+    top_sources.truncate(TOP_SOURCE_COUNT);
+
+    let mut xmp = String::new();
+
+    writeln!(
+        xmp,
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>"
+    )?;
+    writeln!(xmp, "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">")?;
+    writeln!(
+        xmp,
+        " <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">"
+    )?;
+    writeln!(xmp, "  <rdf:Description rdf:about=\"\"")?;
+    writeln!(xmp, "    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"")?;
+    writeln!(xmp, "    xmlns:tessera=\"{NAMESPACE}\">")?;
+    writeln!(xmp, "   <xmp:CreatorTool>tessera</xmp:CreatorTool>")?;
+    writeln!(
+        xmp,
+        "   <tessera:Palette>{}</tessera:Palette>",
+        escape(palette_dir)
+    )?;
+    writeln!(
+        xmp,
+        "   <tessera:PaletteSize>{}</tessera:PaletteSize>",
+        palette_paths.len()
+    )?;
+    writeln!(
+        xmp,
+        "   <tessera:TileCount>{tile_count}</tessera:TileCount>"
+    )?;
+    writeln!(xmp, "   <tessera:TopSources>")?;
+    writeln!(xmp, "    <rdf:Seq>")?;
+
+    for (uses, path) in top_sources {
+        writeln!(xmp, "     <rdf:li rdf:parseType=\"Resource\">")?;
+        writeln!(xmp, "      <tessera:Path>{}</tessera:Path>", escape(path))?;
+        writeln!(xmp, "      <tessera:Uses>{uses}</tessera:Uses>")?;
+        writeln!(xmp, "     </rdf:li>")?;
+    }
+
+    writeln!(xmp, "    </rdf:Seq>")?;
+    writeln!(xmp, "   </tessera:TopSources>")?;
+    writeln!(xmp, "  </rdf:Description>")?;
+    writeln!(xmp, " </rdf:RDF>")?;
+    writeln!(xmp, "</x:xmpmeta>")?;
+    writeln!(xmp, "<?xpacket end=\"w\"?>")?;
+
+    write(path, xmp)?;
+
+    Ok(())
+}
+
+// Formats a path as XML character data, escaping characters with special meaning.
+fn escape(path: &Path) -> String {
+    encode_path(path)
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,32 @@
+use std::{
+    fs::remove_dir_all,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+// Command running the tessera binary built for these tests
+pub(crate) fn tessera() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_tessera"))
+}
+
+// Runs a command to completion, failing the test with its error output if it fails
+pub(crate) fn run(command: &mut Command) -> Output {
+    let output = command.output().unwrap();
+    assert!(
+        output.status.success(),
+        "{command:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+// Creates an empty directory for a test, holding the synthetic palette ("palette") and input image ("input.png") of
+// `tessera demo`, and returns its path
+pub(crate) fn fixture(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    if dir.exists() {
+        remove_dir_all(&dir).unwrap();
+    }
+    run(tessera().arg("demo").arg(&dir));
+    dir
+}
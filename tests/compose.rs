@@ -0,0 +1,41 @@
+mod common;
+
+use common::{fixture, run, tessera};
+use std::fs::read;
+
+// Composing a placements file again draws the same output image, including tiles rotated with `--rotate-jitter`
+#[test]
+fn compose_reproduces_rotated_tiles() {
+    let dir = fixture("compose-rotated");
+    run(tessera().current_dir(&dir).args([
+        "-p",
+        "palette",
+        "-i",
+        "input.png",
+        "-s",
+        "8",
+        "--rotate-jitter",
+        "10",
+        "--grout",
+        "#d8d2c4",
+        "-o",
+        "out.png",
+        "--placements",
+        "placements.csv",
+    ]));
+    run(tessera().current_dir(&dir).args([
+        "compose",
+        "-s",
+        "8",
+        "--grout",
+        "#d8d2c4",
+        "-o",
+        "composed.png",
+        "placements.csv",
+    ]));
+
+    assert!(
+        read(dir.join("out.png")).unwrap() == read(dir.join("composed.png")).unwrap(),
+        "composed image differs from the output image"
+    );
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::{fixture, run, tessera};
+use std::fs::read;
+
+// Renders the fixture's input image with the given options on 1 thread and on 4 threads,
+// and checks that both runs write identical output images and placements files
+fn assert_deterministic(name: &str, options: &[&str]) {
+    let dir = fixture(name);
+    let outputs = [1, 4].map(|thread_count| {
+        let output_path = dir.join(format!("out-{thread_count}.png"));
+        let placements_path = dir.join(format!("placements-{thread_count}.csv"));
+        run(tessera()
+            .current_dir(&dir)
+            .args(["-p", "palette", "-i", "input.png", "-s", "8"])
+            .args(options)
+            .arg("-o")
+            .arg(&output_path)
+            .arg("--placements")
+            .arg(&placements_path)
+            .args(["-t", &thread_count.to_string()]));
+        (read(output_path).unwrap(), read(placements_path).unwrap())
+    });
+
+    let [(output_1, placements_1), (output_4, placements_4)] = outputs;
+    assert!(
+        output_1 == output_4,
+        "output images differ between thread counts"
+    );
+    assert!(
+        placements_1 == placements_4,
+        "placements files differ between thread counts"
+    );
+}
+
+#[test]
+fn default_options() {
+    assert_deterministic("determinism-default", &[]);
+}
+
+#[test]
+fn seeded_variety() {
+    assert_deterministic("determinism-variety", &["--variety", "3", "--seed", "7"]);
+}
+
+#[test]
+fn seeded_tie_epsilon() {
+    assert_deterministic(
+        "determinism-tie-epsilon",
+        &["--tie-epsilon", "0.05", "--seed", "7"],
+    );
+}
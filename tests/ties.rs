@@ -0,0 +1,56 @@
+mod common;
+
+use common::{fixture, run, tessera};
+use image::{GenericImageView, ImageReader, imageops::replace};
+use std::{fs::read_to_string, path::Path};
+
+// Renders an input image without dithering, with `--tie-epsilon` and the given options, and returns its placements by
+// row and column
+fn placements(dir: &Path, input: &str, options: &[&str]) -> Vec<((u32, u32), String)> {
+    let placements_path = dir.join(format!("{input}.csv"));
+    run(tessera()
+        .current_dir(dir)
+        .args(["-p", "palette", "-i", input, "-s", "8", "-o", "out.png"])
+        .args(["-d", "false", "--tie-epsilon", "0.05", "--seed", "7"])
+        .args(options)
+        .arg("--placements")
+        .arg(&placements_path));
+    read_to_string(placements_path)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with("row,"))
+        .map(|line| {
+            let mut fields = line.split(',');
+            let row = fields.next().unwrap().parse().unwrap();
+            let column = fields.next().unwrap().parse().unwrap();
+            ((row, column), line.to_owned())
+        })
+        .collect()
+}
+
+// Editing one region of the input image never changes the tiles placed outside it, even when cells share searches
+// with `--match-precision`
+#[test]
+fn editing_a_region_keeps_ties_elsewhere() {
+    let dir = fixture("ties-precision");
+    // Copy the bottom right corner of the input image over its top left corner, so cells of the same colors as cells
+    // further down are matched first
+    let mut input = ImageReader::open(dir.join("input.png"))
+        .unwrap()
+        .decode()
+        .unwrap()
+        .into_rgb8();
+    let corner = input.view(24, 24, 8, 8).to_image();
+    replace(&mut input, &corner, 0, 0);
+    input.save(dir.join("edited.png")).unwrap();
+
+    let options = ["--match-precision", "0.05"];
+    let before = placements(&dir, "input.png", &options);
+    let after = placements(&dir, "edited.png", &options);
+    assert_eq!(before.len(), after.len());
+    for ((cell, before), (_, after)) in before.iter().zip(&after) {
+        if cell.0 >= 8 || cell.1 >= 8 {
+            assert_eq!(before, after, "a cell outside the edited region changed");
+        }
+    }
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::{fixture, run, tessera};
+use std::{
+    fs::read,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+// Runs `tessera tui` in the fixture's directory, reading the given commands from standard input
+fn tui(command: &mut Command, commands: &str) -> String {
+    let mut child = command
+        .arg("tui")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(commands.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "tessera tui failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// A preview is printed once the palette and input image are chosen, and the full render with the options chosen is
+// the same as running `tessera` with them
+#[test]
+fn previews_then_renders() {
+    let dir = fixture("tui");
+    let stdout = tui(
+        tessera().current_dir(&dir),
+        "palette palette\ninput input.png\nvariety 3\noutput tui.png\ntile-size 8\nset --seed 7\nrender\nquit\n",
+    );
+    assert!(
+        stdout.contains("\x1b[38;2;") && stdout.contains('\u{2580}'),
+        "no preview was printed: {stdout}"
+    );
+
+    run(tessera().current_dir(&dir).args([
+        "-p",
+        "palette",
+        "-i",
+        "input.png",
+        "-o",
+        "cli.png",
+        "-s",
+        "8",
+        "--variety",
+        "3",
+        "--seed",
+        "7",
+    ]));
+    assert!(
+        read(dir.join("tui.png")).unwrap() == read(dir.join("cli.png")).unwrap(),
+        "the full render differs from running `tessera` with the same options"
+    );
+}
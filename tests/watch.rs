@@ -0,0 +1,85 @@
+mod common;
+
+use common::{fixture, tessera};
+use std::{
+    fs::copy,
+    path::Path,
+    process::{Child, Stdio},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+// Longest time to wait for a render before giving up
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+// Starts watching the fixture's palette directory with the given options, copying each render to numbered snapshots
+fn watch(dir: &Path, options: &[&str]) -> Child {
+    tessera()
+        .current_dir(dir)
+        .args(["-p", "palette", "-i", "input.png", "-o", "out.png"])
+        .args(["--watch", "0.2", "--watch-snapshots"])
+        .args(options)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+// Waits for the first render, adds a palette image, then waits for the second render. Returns whether it happened,
+// and everything logged, once the run is stopped.
+fn render_twice(dir: &Path, mut child: Child, output_stem: &str) -> (bool, String) {
+    // Each render is copied to a numbered snapshot, so the second render has happened once e.g. "out.0002.png" exists
+    let wait_for = |name: &str| {
+        let start = Instant::now();
+        while !dir.join(name).exists() && start.elapsed() < TIMEOUT {
+            sleep(Duration::from_millis(50));
+        }
+        dir.join(name).exists()
+    };
+    let rendered_again = wait_for(&format!("{output_stem}.0001.png")) && {
+        copy(
+            dir.join("palette/synthetic-0000.png"),
+            dir.join("palette/added.png"),
+        )
+        .unwrap();
+        wait_for(&format!("{output_stem}.0002.png"))
+    };
+
+    child.kill().unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        rendered_again,
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+// With `--watch` and `-t`, every render in the process shares the thread pool set up for the first one
+#[test]
+fn watch_renders_again_with_thread_count() {
+    let dir = fixture("watch-threads");
+    let (rendered_again, log) = render_twice(&dir, watch(&dir, &["-s", "8", "-t", "2"]), "out");
+    assert!(rendered_again, "the mosaic was not rendered again: {log}");
+}
+
+// Palette images stay loaded between renders, so only the added palette image is loaded again
+#[test]
+fn watch_loads_only_new_palette_images() {
+    let dir = fixture("watch-hot");
+    let (rendered_again, log) = render_twice(&dir, watch(&dir, &["-s", "8"]), "out");
+    assert!(rendered_again, "the mosaic was not rendered again: {log}");
+    assert!(
+        log.contains("loaded 1 new or changed palette images, reusing the tiles of the other 64"),
+        "palette images were loaded again: {log}"
+    );
+}
+
+// With a list of tile sizes, the output written for each tile size is snapshotted
+#[test]
+fn watch_snapshots_each_tile_size() {
+    let dir = fixture("watch-sizes");
+    let (rendered_again, log) = render_twice(&dir, watch(&dir, &["-s", "8,16"]), "out.16x16");
+    assert!(rendered_again, "the mosaic was not rendered again: {log}");
+    for name in ["out.8x8.0001.png", "out.8x8.0002.png", "out.16x16.0001.png"] {
+        assert!(dir.join(name).exists(), "{name} was not written: {log}");
+    }
+}